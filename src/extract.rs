@@ -0,0 +1,1319 @@
+//! What is found in fetched pages, stylesheets and sitemaps: links, assets,
+//! forms, metadata and the text of pages.
+
+use html5ever::tokenizer::{
+  states::RawKind, BufferQueue, CharacterTokens, Tag, TagKind, TagToken, Token, TokenSink,
+  TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+
+use super::*;
+
+/// A `<form>` as it appears on a page, with its action resolved.
+#[derive(Debug, Clone)]
+pub(crate) struct Form {
+  pub(crate) action: Url,
+  pub(crate) method: String,
+  pub(crate) inputs: Vec<String>,
+}
+
+/// The most a gzipped sitemap may inflate to, as the sitemap protocol limits
+/// sitemaps to 50 MB.
+pub(crate) const SITEMAP_SIZE_LIMIT: usize = 50 * 1024 * 1024;
+
+/// A `<loc>` of a sitemap. Prefixed ones like `<image:loc>` are not pages.
+pub(crate) static SITEMAP_LOC: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"(?s)<loc>\s*(?:<!\[CDATA\[)?(.*?)(?:\]\]>)?\s*</loc>").unwrap());
+
+/// The URLs listed in a sitemap.
+#[derive(Debug)]
+pub(crate) enum Sitemap {
+  /// The sitemaps of a sitemap index.
+  Index(Vec<String>),
+  /// The pages of a URL set.
+  Pages(Vec<String>),
+}
+
+impl Sitemap {
+  /// Parses a sitemap or sitemap index, gzipped or not.
+  pub(crate) fn parse(url: &Url, body: &[u8]) -> Result<Self> {
+    let inflated;
+    let body = if body.starts_with(&[0x1f, 0x8b]) {
+      inflated = decompress(url, Some("gzip"), body.to_vec(), Some(SITEMAP_SIZE_LIMIT))?;
+      &inflated
+    } else {
+      body
+    };
+    let xml = String::from_utf8_lossy(body);
+    let locs = SITEMAP_LOC
+      .captures_iter(&xml)
+      .map(|c| unescape_xml(c[1].trim()))
+      .collect();
+    Ok(if xml.contains("<sitemapindex") {
+      Sitemap::Index(locs)
+    } else {
+      Sitemap::Pages(locs)
+    })
+  }
+}
+
+/// Replaces the predefined XML entities, which sitemaps must use for `&`, `<`,
+/// `>`, `"` and `'` in URLs.
+pub(crate) fn unescape_xml(text: &str) -> String {
+  text
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&apos;", "'")
+    .replace("&amp;", "&")
+}
+
+/// What the start of a body looks like, whatever its `Content-Type` says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ContentClass {
+  Html,
+  Text,
+  Binary,
+}
+
+impl ContentClass {
+  /// Classifies a body by its start, looking for the tags and binary bytes
+  /// the WHATWG MIME sniffing algorithm looks for.
+  pub(crate) fn of(body: &[u8]) -> Self {
+    let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+    let start = &body[..body.len().min(SNIFF_LENGTH)];
+    let text = String::from_utf8_lossy(start).to_ascii_lowercase();
+    let mut markup = text.trim_start();
+    // XHTML starts with an XML declaration.
+    if markup.starts_with("<?xml") {
+      markup = markup
+        .split_once("?>")
+        .map_or("", |(_, rest)| rest.trim_start());
+    }
+    let html = HTML_SNIFF_TAGS.iter().any(|tag| {
+      markup.strip_prefix(tag).is_some_and(|rest| {
+        tag.ends_with("--") || rest.starts_with([' ', '>', '\t', '\n', '\r', '\x0c'])
+      })
+    });
+    let binary = start
+      .iter()
+      .any(|&b| matches!(b, 0x00..=0x08 | 0x0B | 0x0E..=0x1A | 0x1C..=0x1F));
+    match (html, binary) {
+      (true, _) => ContentClass::Html,
+      (false, true) => ContentClass::Binary,
+      (false, false) => ContentClass::Text,
+    }
+  }
+
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      ContentClass::Html => "HTML",
+      ContentClass::Text => "text",
+      ContentClass::Binary => "binary",
+    }
+  }
+}
+
+/// The `Content-Security-Policy` of a page, from its headers and `<meta>`
+/// tags. A resource has to be allowed by every policy.
+#[derive(Debug, Default)]
+pub(crate) struct ContentSecurityPolicy {
+  /// The source lists of every policy, by directive.
+  pub(crate) policies: Vec<HashMap<String, Vec<String>>>,
+}
+
+impl ContentSecurityPolicy {
+  pub(crate) fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+    // A header may hold several policies, separated by commas.
+    let policies = values
+      .into_iter()
+      .flat_map(|value| value.split(','))
+      .map(|policy| {
+        let mut directives = HashMap::new();
+        for directive in policy.split(';') {
+          let mut tokens = directive.split_ascii_whitespace();
+          let Some(name) = tokens.next() else {
+            continue;
+          };
+          // Repeated directives are ignored.
+          directives
+            .entry(name.to_ascii_lowercase())
+            .or_insert_with(|| tokens.map(str::to_ascii_lowercase).collect());
+        }
+        directives
+      })
+      .filter(|directives| !directives.is_empty())
+      .collect();
+    Self { policies }
+  }
+
+  /// Whether `page` may load `resource` under `directive`, like `img-src`,
+  /// or else `default-src`.
+  pub(crate) fn allows(&self, page: &Url, directive: &str, resource: &Url) -> bool {
+    self.policies.iter().all(|policy| {
+      match policy.get(directive).or_else(|| policy.get("default-src")) {
+        Some(sources) => sources
+          .iter()
+          .any(|source| csp_source_matches(source, page, resource)),
+        None => true,
+      }
+    })
+  }
+}
+
+/// Whether a CSP source expression allows `page` to load `resource`. Nonces,
+/// hashes and other keywords only concern inline content and scripts, so they
+/// match no URL.
+pub(crate) fn csp_source_matches(source: &str, page: &Url, resource: &Url) -> bool {
+  let scheme = resource.scheme();
+  // Upgrades to a secure scheme are always allowed.
+  let scheme_matches = |expected: &str| {
+    expected == scheme
+      || expected == "http" && scheme == "https"
+      || expected == "ws" && scheme == "wss"
+  };
+  match source {
+    "'self'" => {
+      resource.host() == page.host()
+        && scheme_matches(page.scheme())
+        && (resource.port_or_known_default() == page.port_or_known_default()
+          || page.scheme() == "http" && scheme == "https" && resource.port().is_none())
+    }
+    "*" => matches!(scheme, "http" | "https" | "ws" | "wss") || scheme == page.scheme(),
+    _ if source.starts_with('\'') => false,
+    _ if source.ends_with(':') => scheme_matches(source.trim_end_matches(':')),
+    _ => {
+      let (expected_scheme, rest) = match source.split_once("://") {
+        Some((expected, rest)) => (expected, rest),
+        None => (page.scheme(), source),
+      };
+      let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+      let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (authority, None),
+      };
+      let Some(resource_host) = resource.host_str() else {
+        return false;
+      };
+      let host_matches = match host.strip_prefix("*.") {
+        Some(parent) => resource_host
+          .strip_suffix(parent)
+          .is_some_and(|sub| sub.ends_with('.')),
+        None => host == "*" || host == resource_host,
+      };
+      let port_matches = match port {
+        Some("*") => true,
+        Some(port) => {
+          port.parse().ok() == resource.port_or_known_default()
+            || port == "80" && scheme == "https" && resource.port().is_none()
+        }
+        None => resource.port().is_none(),
+      };
+      let path_matches = match path {
+        "" | "/" => true,
+        _ if path.ends_with('/') => resource.path().starts_with(path),
+        _ => resource.path() == path,
+      };
+      scheme_matches(expected_scheme) && host_matches && port_matches && path_matches
+    }
+  }
+}
+
+/// Extracts the `charset` parameter of a `Content-Type` value.
+pub(crate) fn charset_parameter(content_type: &str) -> Option<String> {
+  content_type
+    .split(';')
+    .filter_map(|p| p.split_once('='))
+    .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+    .map(|(_, c)| c.trim().trim_matches('"').to_ascii_lowercase())
+}
+
+pub(crate) fn process_page(url: &Url, page_body: String, depth: u8, contacts: bool) -> Extraction {
+  let mut raw_findings = Aggregate::new(depth);
+  raw_findings.contacts = contacts;
+  // The `<noscript>` fallbacks of lazy loaders are tokenized like the rest of
+  // the page, unless their markup is escaped. Then it is tokenized again on
+  // its own.
+  let mut markup = vec![page_body];
+  while let Some(body) = markup.pop() {
+    let mut tokenizer = Tokenizer::new(&mut raw_findings, TokenizerOpts::default());
+    let mut buffer = BufferQueue::new();
+    buffer.push_back(body.into());
+    let _ = tokenizer.feed(&mut buffer);
+    tokenizer.end();
+    drop(tokenizer);
+    for noscript in std::mem::take(&mut raw_findings.noscript_text) {
+      if noscript.contains('<') {
+        markup.push(noscript);
+      } else {
+        raw_findings.text.push_str(&noscript);
+      }
+    }
+  }
+
+  raw_findings.parse(url)
+}
+
+/// A stylesheet crawled as a page, such as one linked with `<a href>`, only
+/// leads to what it refers to.
+pub(crate) fn process_stylesheet(url: &Url, css: &str, depth: u8) -> Extraction {
+  let mut extraction = Aggregate::new(depth).parse(url);
+  extraction.findings = stylesheet_references(css, url).into_iter().collect();
+  extraction
+}
+
+/// Everything extracted from a single page.
+pub(crate) struct Extraction {
+  pub(crate) findings: HashSet<Finding>,
+  pub(crate) forms: Vec<Form>,
+  pub(crate) endpoints: HashSet<Url>,
+  pub(crate) emails: HashSet<String>,
+  pub(crate) phones: HashSet<String>,
+  pub(crate) meta_charset: Option<String>,
+  pub(crate) html_lang: Option<String>,
+  pub(crate) detected_language: Option<&'static str>,
+  pub(crate) title: Option<String>,
+  pub(crate) description: Option<String>,
+  pub(crate) canonical: Option<Url>,
+  pub(crate) meta_robots: Option<String>,
+  /// Captions of every image that has any.
+  pub(crate) image_captions: HashMap<Url, ImageCaption>,
+  /// Licenses the page declares or mentions, as Creative Commons names or
+  /// license URLs.
+  pub(crate) licenses: BTreeSet<String>,
+  /// The AMP and mobile versions the page announces.
+  pub(crate) variants: HashSet<Url>,
+  /// Targets of `rel=nofollow` links, which are not among the findings.
+  pub(crate) nofollow_links: HashSet<Url>,
+  /// Assets of `<embed>` and `<object>`, which CSP governs with
+  /// `object-src` rather than `img-src`.
+  pub(crate) objects: HashSet<Url>,
+  /// Policies of `<meta http-equiv="Content-Security-Policy">` tags.
+  pub(crate) csp: Vec<String>,
+  /// Hash of the text of the page without digits and with collapsed
+  /// whitespace, which stays the same across pages of a trap that differ only
+  /// in markup, dates or counters. `None` for pages without text.
+  pub(crate) text_hash: Option<u64>,
+}
+
+/// Text describing an image: its alt and title attributes, and the
+/// `<figcaption>` of the `<figure>` it is in.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ImageCaption {
+  pub(crate) alt: Option<String>,
+  pub(crate) title: Option<String>,
+  pub(crate) figcaption: Option<String>,
+}
+
+impl ImageCaption {
+  pub(crate) fn normalized(self) -> Option<Self> {
+    let caption = Self {
+      alt: normalize_whitespace(self.alt),
+      title: normalize_whitespace(self.title),
+      figcaption: normalize_whitespace(self.figcaption),
+    };
+    caption.text().is_some().then_some(caption)
+  }
+
+  /// The most descriptive of the captions.
+  pub(crate) fn text(&self) -> Option<&str> {
+    self
+      .alt
+      .as_deref()
+      .or(self.figcaption.as_deref())
+      .or(self.title.as_deref())
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct Aggregate {
+  pub(crate) depth: u8,
+  /// Scan the text and links for contacts.
+  pub(crate) contacts: bool,
+  pub(crate) page_links: Vec<String>,
+  pub(crate) image_links: Vec<String>,
+  pub(crate) forms: Vec<RawForm>,
+  pub(crate) in_form: bool,
+  pub(crate) in_script: bool,
+  pub(crate) script_text: String,
+  pub(crate) in_noscript: bool,
+  /// The text of every `<noscript>`, which is escaped markup on some pages.
+  pub(crate) noscript_text: Vec<String>,
+  pub(crate) text: String,
+  pub(crate) meta_charset: Option<String>,
+  pub(crate) html_lang: Option<String>,
+  pub(crate) in_title: bool,
+  /// The text of the first `<title>` outside inline SVG, whose `<title>`s
+  /// name its shapes.
+  pub(crate) title: Option<String>,
+  /// How many `<svg>`s are open.
+  pub(crate) svg_depth: usize,
+  pub(crate) description: Option<String>,
+  pub(crate) canonical: Option<String>,
+  pub(crate) meta_robots: Option<String>,
+  /// Source and captions of every image.
+  pub(crate) image_captions: Vec<(String, ImageCaption)>,
+  /// Open `<figure>`s, with the index of their first image and the text of
+  /// their `<figcaption>`.
+  pub(crate) figures: Vec<(usize, Option<String>)>,
+  pub(crate) in_figcaption: bool,
+  /// Sources of the open `<picture>` not yet claimed by its `<img>`.
+  pub(crate) picture: Option<Vec<String>>,
+  /// Targets of `rel=license` links and values of license meta tags.
+  pub(crate) license_refs: Vec<String>,
+  /// Targets of `rel=amphtml` and mobile `rel=alternate` links.
+  pub(crate) variant_links: Vec<String>,
+  /// Targets of `rel=nofollow` links, which are not in `page_links`.
+  pub(crate) nofollow_links: Vec<String>,
+  /// Sources of `<embed>` and `<object>`, also in `image_links`.
+  pub(crate) object_links: Vec<String>,
+  pub(crate) stylesheet_links: Vec<String>,
+  pub(crate) script_links: Vec<String>,
+  pub(crate) font_links: Vec<String>,
+  pub(crate) icon_links: Vec<String>,
+  /// Sources of `<video>`, `<audio>` and the `<source>`s in them.
+  pub(crate) media_links: Vec<String>,
+  pub(crate) in_media: bool,
+  pub(crate) in_style: bool,
+  /// `<style>` elements and `style` attributes.
+  pub(crate) style_text: String,
+  pub(crate) csp: Vec<String>,
+  /// The `href` of the first `<base>`.
+  pub(crate) base: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RawForm {
+  pub(crate) action: Option<String>,
+  pub(crate) method: Option<String>,
+  pub(crate) inputs: Vec<String>,
+}
+
+impl Aggregate {
+  pub(crate) const fn new(depth: u8) -> Self {
+    Self {
+      depth,
+      contacts: false,
+      page_links: Vec::new(),
+      image_links: Vec::new(),
+      forms: Vec::new(),
+      in_form: false,
+      in_script: false,
+      script_text: String::new(),
+      in_noscript: false,
+      noscript_text: Vec::new(),
+      text: String::new(),
+      meta_charset: None,
+      html_lang: None,
+      in_title: false,
+      title: None,
+      svg_depth: 0,
+      description: None,
+      canonical: None,
+      meta_robots: None,
+      image_captions: Vec::new(),
+      figures: Vec::new(),
+      in_figcaption: false,
+      picture: None,
+      license_refs: Vec::new(),
+      variant_links: Vec::new(),
+      nofollow_links: Vec::new(),
+      object_links: Vec::new(),
+      stylesheet_links: Vec::new(),
+      script_links: Vec::new(),
+      font_links: Vec::new(),
+      icon_links: Vec::new(),
+      media_links: Vec::new(),
+      in_media: false,
+      in_style: false,
+      style_text: String::new(),
+      csp: Vec::new(),
+      base: None,
+    }
+  }
+}
+
+impl Aggregate {
+  pub(crate) fn parse(self, url: &Url) -> Extraction {
+    let mut findings = HashSet::new();
+    // Relative URLs resolve against the first `<base href>`, if it is usable.
+    let base = self
+      .base
+      .as_deref()
+      .and_then(|b| url.join(b.trim()).ok())
+      .filter(|b| b.scheme().contains("http") || b.scheme() == "file");
+    let base = base.as_ref().unwrap_or(url);
+    // Links are only not followed for `rel=nofollow`.
+    let all_links: Vec<String> = self
+      .page_links
+      .iter()
+      .chain(&self.nofollow_links)
+      .cloned()
+      .collect();
+
+    let mut endpoints: HashSet<Url> = parse_links(all_links.clone(), base)
+      .into_iter()
+      .chain(parse_links(script_literals(&self.script_text), base))
+      .filter(|u| u.origin() == url.origin() && looks_like_endpoint(u))
+      .collect();
+
+    let forms: Vec<Form> = self
+      .forms
+      .into_iter()
+      .filter_map(|form| {
+        let action = match form.action.as_deref() {
+          None | Some("") => url.clone(),
+          Some(action) => base.join(action).ok()?,
+        };
+        let method = form
+          .method
+          .map(|m| m.to_ascii_uppercase())
+          .unwrap_or_else(|| "GET".into());
+        Some(Form {
+          action,
+          method,
+          inputs: form.inputs,
+        })
+      })
+      .collect();
+    endpoints.extend(
+      forms
+        .iter()
+        .map(|f| f.action.clone())
+        .filter(|a| a.origin() == url.origin()),
+    );
+
+    let (emails, phones) = match self.contacts {
+      true => extract_contacts(&self.text, &all_links),
+      false => Default::default(),
+    };
+    let canonical = self
+      .canonical
+      .as_deref()
+      .and_then(|c| base.join(c.trim()).ok())
+      .filter(|c| c.scheme().contains("http"));
+    let detected_language = detect_language(&self.text);
+
+    let image_captions = self
+      .image_captions
+      .into_iter()
+      .filter_map(|(src, caption)| {
+        let image = parse_links(vec![src], base).into_iter().next()?;
+        Some((image, caption.normalized()?))
+      })
+      .collect();
+    let licenses = detect_licenses(&self.license_refs, &all_links, &self.text, url);
+    let page_links = parse_links(self.page_links, base);
+    let image_links = parse_links(self.image_links, base);
+    let depth = self.depth;
+
+    findings.extend(
+      page_links
+        .into_iter()
+        .map(|u| Finding::Page(u, depth.saturating_add(1))),
+    );
+    // Canonical targets are crawled like any other link so that their status
+    // is known; the archive makes sure they are not visited twice.
+    if let Some(canonical) = &canonical {
+      findings.insert(Finding::Page(canonical.clone(), depth.saturating_add(1)));
+    }
+    findings.extend(image_links.into_iter().map(Finding::Image));
+    findings.extend(stylesheet_references(&self.style_text, base));
+    let stylesheets = parse_links(self.stylesheet_links, base);
+    findings.extend(stylesheets.into_iter().map(Finding::Stylesheet));
+    let scripts = parse_links(self.script_links, base);
+    findings.extend(scripts.into_iter().map(Finding::Script));
+    findings.extend(
+      parse_links(self.font_links, base)
+        .into_iter()
+        .map(Finding::Font),
+    );
+    let mut icons = parse_links(self.icon_links, base);
+    // Browsers ask for the favicon of pages that name no icon.
+    if icons.is_empty() && url.scheme().contains("http") {
+      icons.extend(url.join("/favicon.ico").ok());
+    }
+    findings.extend(icons.into_iter().map(Finding::Icon));
+    findings.extend(
+      parse_links(self.media_links, base)
+        .into_iter()
+        .map(Finding::Media),
+    );
+
+    Extraction {
+      findings,
+      forms,
+      endpoints,
+      emails,
+      phones,
+      meta_charset: self.meta_charset,
+      html_lang: self.html_lang,
+      detected_language,
+      title: normalize_whitespace(self.title),
+      description: normalize_whitespace(self.description),
+      canonical,
+      meta_robots: self.meta_robots,
+      image_captions,
+      licenses,
+      variants: parse_links(self.variant_links, base),
+      nofollow_links: parse_links(self.nofollow_links, base),
+      objects: parse_links(self.object_links, base),
+      csp: self.csp,
+      text_hash: text_hash(&self.text),
+    }
+  }
+}
+
+/// See [`Extraction::text_hash`].
+pub(crate) fn text_hash(text: &str) -> Option<u64> {
+  let text: String = text.chars().filter(|c| !c.is_ascii_digit()).collect();
+  let words: Vec<&str> = text.split_whitespace().collect();
+  (!words.is_empty()).then(|| Deduplicator::content_hash(words.join(" ").as_bytes()))
+}
+
+/// Collapses runs of whitespace, treating blank values as missing.
+pub(crate) fn normalize_whitespace(value: Option<String>) -> Option<String> {
+  let value = value?.split_whitespace().collect::<Vec<_>>().join(" ");
+  (!value.is_empty()).then_some(value)
+}
+
+/// Only a prefix of the page text is inspected, which is plenty for a reliable
+/// guess and keeps detection cheap on large pages.
+#[cfg(feature = "language-detection")]
+pub(crate) const LANGUAGE_DETECTION_SAMPLE: usize = 4096;
+
+#[cfg(feature = "language-detection")]
+pub(crate) fn detect_language(text: &str) -> Option<&'static str> {
+  let end = text
+    .char_indices()
+    .nth(LANGUAGE_DETECTION_SAMPLE)
+    .map_or(text.len(), |(i, _)| i);
+  whatlang::detect(&text[..end])
+    .filter(whatlang::Info::is_reliable)
+    .map(|info| info.lang().code())
+}
+
+#[cfg(not(feature = "language-detection"))]
+pub(crate) fn detect_language(_text: &str) -> Option<&'static str> {
+  None
+}
+
+/// Heuristic for URLs that address an API rather than a document.
+pub(crate) fn looks_like_endpoint(url: &Url) -> bool {
+  let path = url.path();
+  path.ends_with(".json")
+    || path
+      .split('/')
+      .any(|segment| matches!(segment, "api" | "graphql" | "rest"))
+}
+
+pub(crate) static EMAIL_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap()
+});
+
+pub(crate) static PHONE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"(?:\+\d{1,3}[\s.-]?)?\(?\d{2,4}\)?(?:[\s.-]\d{2,4}){2,4}").unwrap()
+});
+
+/// Dates like `12.05.2023` or `2023-05-12`, which look like phone numbers.
+pub(crate) static DATE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"^(?:\d{1,2}([./-])\d{1,2}[./-]\d{2,4}|\d{4}([./-])\d{1,2}[./-]\d{1,2})$").unwrap()
+});
+
+/// Elements whose text continues that of their surroundings.
+pub(crate) const INLINE_ELEMENTS: &[&str] = &[
+  "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "font", "i", "kbd", "mark",
+  "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var", "wbr",
+];
+
+/// Meta tag names and properties whose content names the license of a page.
+pub(crate) const LICENSE_META_NAMES: &[&str] = &[
+  "license",
+  "dc.rights",
+  "dc.license",
+  "dcterms.license",
+  "dcterms.rights",
+  "og:license",
+];
+
+pub(crate) static CC_URL_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"creativecommons\.org/(licenses|publicdomain)/([a-z-]+)(?:/([0-9.]+))?").unwrap()
+});
+
+pub(crate) static CC_TEXT_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r"\bCC(?:[ -]?(0)|[ -](BY(?:-(?:NC|ND|SA))*))(?:[ -]v?([0-9]\.[0-9]))?\b").unwrap()
+});
+
+/// Collects the licenses of a page from `rel=license` links and license meta
+/// tags, plus Creative Commons markers in its links and text. Creative Commons
+/// licenses are reported by name, like `CC BY-SA 4.0`, anything else as given.
+pub(crate) fn detect_licenses(
+  refs: &[String],
+  links: &[String],
+  text: &str,
+  url: &Url,
+) -> BTreeSet<String> {
+  let mut licenses = BTreeSet::new();
+  for reference in refs {
+    let reference = reference.trim();
+    if reference.is_empty() {
+      continue;
+    }
+    let license = cc_license_name(reference).unwrap_or_else(|| match url.join(reference) {
+      Ok(target) if reference.contains('/') => target.to_string(),
+      _ => reference.to_owned(),
+    });
+    licenses.insert(license);
+  }
+  licenses.extend(links.iter().filter_map(|l| cc_license_name(l)));
+  for marker in CC_TEXT_PATTERN.captures_iter(text) {
+    let name = match (marker.get(1), marker.get(2)) {
+      (Some(_), _) => "CC0".to_owned(),
+      (_, Some(terms)) => format!("CC {}", terms.as_str()),
+      _ => continue,
+    };
+    licenses.insert(match marker.get(3) {
+      Some(version) => format!("{} {}", name, version.as_str()),
+      None => name,
+    });
+  }
+  licenses
+}
+
+/// The name of the Creative Commons license or public domain tool a URL points
+/// at.
+pub(crate) fn cc_license_name(url: &str) -> Option<String> {
+  let captures = CC_URL_PATTERN.captures(url)?;
+  let name = match (&captures[1], &captures[2]) {
+    ("publicdomain", "zero") => "CC0".to_owned(),
+    ("publicdomain", "mark") => "Public Domain Mark".to_owned(),
+    ("licenses", terms) => format!("CC {}", terms.to_ascii_uppercase()),
+    _ => return None,
+  };
+  Some(match captures.get(3) {
+    Some(version) => format!("{} {}", name, version.as_str().trim_end_matches('.')),
+    None => name,
+  })
+}
+
+/// Finds email addresses and phone numbers in the page text and in
+/// `mailto:`/`tel:` links.
+pub(crate) fn extract_contacts(text: &str, links: &[String]) -> (HashSet<String>, HashSet<String>) {
+  let mut emails: HashSet<String> = EMAIL_PATTERN
+    .find_iter(text)
+    .map(|m| m.as_str().to_ascii_lowercase())
+    .collect();
+  let mut phones: HashSet<String> = PHONE_PATTERN
+    .find_iter(text)
+    .map(|m| m.as_str())
+    .filter(|m| !is_date_or_years(m))
+    .map(normalize_phone)
+    .filter(|p| p.trim_start_matches('+').len() >= 7)
+    .collect();
+
+  for link in links {
+    let link = link.trim();
+    let (scheme, value) = link.split_once(':').unwrap_or_default();
+    let value = percent_encoding::percent_decode_str(value).decode_utf8_lossy();
+    if scheme.eq_ignore_ascii_case("mailto") {
+      let address = value.split('?').next().unwrap_or_default();
+      emails.extend(address.split(',').map(|a| a.trim().to_ascii_lowercase()));
+    } else if scheme.eq_ignore_ascii_case("tel") {
+      phones.insert(normalize_phone(&value));
+    }
+  }
+  emails.retain(|e| !e.is_empty());
+  phones.retain(|p| !p.is_empty());
+
+  (emails, phones)
+}
+
+/// Whether a match of [`PHONE_PATTERN`] is a date, or a run of years like
+/// `2019-2020-2021`.
+pub(crate) fn is_date_or_years(number: &str) -> bool {
+  let years = number
+    .split(|c: char| !c.is_ascii_digit())
+    .filter(|group| !group.is_empty())
+    .all(|group| group.len() == 4 && (group.starts_with("19") || group.starts_with("20")));
+  years || DATE_PATTERN.is_match(number)
+}
+
+/// Keeps only the digits of a phone number and its leading `+`, so differently
+/// formatted numbers deduplicate.
+pub(crate) fn normalize_phone(number: &str) -> String {
+  let number = number.trim();
+  let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+  if number.starts_with('+') {
+    format!("+{}", digits)
+  } else {
+    digits
+  }
+}
+
+/// Collects quoted string literals from inline scripts that look like URLs.
+pub(crate) fn script_literals(script: &str) -> Vec<String> {
+  let mut literals = Vec::new();
+  let mut rest = script;
+  while let Some(start) = rest.find(['"', '\'', '`']) {
+    let quote = rest[start..].chars().next().unwrap();
+    rest = &rest[start + 1..];
+    let Some(end) = rest.find([quote, '\n']) else {
+      break;
+    };
+    let literal = &rest[..end];
+    if literal.starts_with('/') || literal.starts_with("http") {
+      literals.push(literal.to_owned());
+    }
+    rest = &rest[end + 1..];
+  }
+  literals
+}
+
+pub(crate) fn parse_links(links: Vec<String>, page_url: &Url) -> HashSet<Url> {
+  links
+    .into_iter()
+    .filter_map(|l| match Url::parse(&l) {
+      Err(ParseError::RelativeUrlWithoutBase) => Some(page_url.join(&l).unwrap()),
+      Err(_) => {
+        warn!("Malformed link found: {}", l);
+        None
+      }
+      Ok(url) => Some(url),
+    })
+    .filter(|u| {
+      // Local files may only link to other local files.
+      u.scheme().contains("http") && u.host().is_some()
+        || u.scheme() == "file" && page_url.scheme() == "file"
+    })
+    .collect()
+}
+
+/// `rel`s of `<link>`s to favicons and touch icons, including `shortcut icon`.
+pub(crate) const ICON_RELS: &[&str] = &[
+  "icon",
+  "apple-touch-icon",
+  "apple-touch-icon-precomposed",
+  "mask-icon",
+];
+
+/// Extensions of web fonts, which stylesheets refer to like images.
+pub(crate) const FONT_EXTENSIONS: &[&str] = &["woff", "woff2", "ttf", "otf", "eot"];
+
+pub(crate) static CSS_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r#"(?i)@import\s+(?:url\(\s*)?["']?([^"')\s;]+)|url\(\s*["']?([^"')\s]+)"#).unwrap()
+});
+
+/// The `@import`s and `url()`s of a stylesheet, resolved against `base`:
+/// imports and `.css` files as stylesheets, fonts by their extension and
+/// anything else as an image.
+pub(crate) fn stylesheet_references(css: &str, base: &Url) -> Vec<Finding> {
+  let mut references = Vec::new();
+  for captures in CSS_REFERENCE.captures_iter(css) {
+    let (reference, import) = match (captures.get(1), captures.get(2)) {
+      (Some(import), _) => (import.as_str(), true),
+      (None, Some(reference)) => (reference.as_str(), false),
+      (None, None) => continue,
+    };
+    // Fragments refer to SVG elements of the stylesheet itself.
+    if reference.starts_with('#') {
+      continue;
+    }
+    let Some(url) = parse_links(vec![reference.to_owned()], base)
+      .into_iter()
+      .next()
+    else {
+      continue;
+    };
+    let extension = url
+      .path()
+      .rsplit_once('.')
+      .map(|(_, extension)| extension.to_ascii_lowercase())
+      .unwrap_or_default();
+    references.push(match extension.as_str() {
+      "css" => Finding::Stylesheet(url),
+      _ if import => Finding::Stylesheet(url),
+      font if FONT_EXTENSIONS.contains(&font) => Finding::Font(url),
+      _ => Finding::Image(url),
+    });
+  }
+  references
+}
+
+/// Attributes that lazy loading scripts copy into `src` and `srcset`.
+pub(crate) const LAZY_SRC_ATTRIBUTES: &[&str] = &["data-src", "data-lazy-src", "data-original"];
+
+pub(crate) const LAZY_SRCSET_ATTRIBUTES: &[&str] = &["data-srcset", "data-lazy-srcset"];
+
+/// Every image an `<img>` or `<source>` may load: its `src`, the candidates
+/// of its `srcset` and those of lazy loading attributes. Placeholders in
+/// `src` are usually `data:` URLs, which are never followed.
+pub(crate) fn image_sources(tag: &Tag) -> Vec<String> {
+  let mut sources = Vec::new();
+  for attribute in &tag.attrs {
+    let name = attribute.name.local.as_ref();
+    let value = attribute.value.to_string();
+    if name == "src" || LAZY_SRC_ATTRIBUTES.contains(&name) {
+      sources.push(value.trim().to_owned());
+    } else if name == "srcset" || LAZY_SRCSET_ATTRIBUTES.contains(&name) {
+      sources.extend(srcset_urls(&value));
+    }
+  }
+  sources.retain(|source| !source.is_empty());
+  sources
+}
+
+/// The URLs of the candidates of a `srcset`, without their `2x` or `640w`
+/// descriptors.
+pub(crate) fn srcset_urls(srcset: &str) -> Vec<String> {
+  let mut urls = Vec::new();
+  let mut rest = srcset;
+  loop {
+    rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+    if rest.is_empty() {
+      return urls;
+    }
+    let end = rest
+      .find(|c: char| c.is_ascii_whitespace())
+      .unwrap_or(rest.len());
+    let (url, descriptors) = rest.split_at(end);
+    // A comma right after the URL ends a candidate without descriptors.
+    let trimmed = url.trim_end_matches(',');
+    urls.push(trimmed.to_owned());
+    if trimmed.len() < url.len() {
+      rest = descriptors;
+      continue;
+    }
+    let mut parentheses = 0u32;
+    let end = descriptors
+      .char_indices()
+      .find(|&(_, c)| {
+        match c {
+          '(' => parentheses += 1,
+          ')' => parentheses = parentheses.saturating_sub(1),
+          _ => {}
+        }
+        c == ',' && parentheses == 0
+      })
+      .map_or(descriptors.len(), |(i, _)| i);
+    rest = &descriptors[end..];
+  }
+}
+
+/// Whether the `rel` attribute of `tag` contains `rel`.
+pub(crate) fn has_rel(tag: &Tag, rel: &str) -> bool {
+  attribute_value(tag, "rel")
+    .is_some_and(|r| r.split_whitespace().any(|r| r.eq_ignore_ascii_case(rel)))
+}
+
+pub(crate) fn attribute_value(tag: &Tag, name: &str) -> Option<String> {
+  tag
+    .attrs
+    .iter()
+    .find(|a| a.name.local.as_ref() == name)
+    .map(|a| a.value.to_string())
+}
+
+impl TokenSink for &mut Aggregate {
+  type Handle = ();
+
+  fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<Self::Handle> {
+    // The text of adjacent elements only runs together around inline markup.
+    if let TagToken(Tag { ref name, .. }) = token {
+      let separated = self.text.is_empty() || self.text.ends_with(char::is_whitespace);
+      if !separated && !INLINE_ELEMENTS.contains(&name.as_ref()) {
+        self.text.push(' ');
+      }
+    }
+    match token {
+      CharacterTokens(ref text) if self.in_script => {
+        self.script_text.push_str(text);
+        self.script_text.push('\n');
+      }
+      CharacterTokens(ref text) if self.in_style => {
+        self.style_text.push_str(text);
+        self.style_text.push('\n');
+      }
+      CharacterTokens(ref text) if self.in_noscript => {
+        if let Some(noscript) = self.noscript_text.last_mut() {
+          noscript.push_str(text);
+        }
+      }
+      CharacterTokens(ref text) => {
+        if self.in_title {
+          self.title.get_or_insert_with(String::new).push_str(text);
+        }
+        if let Some((_, caption)) = self.figures.last_mut().filter(|_| self.in_figcaption) {
+          caption.get_or_insert_with(String::new).push_str(text);
+        }
+        self.text.push_str(text);
+      }
+      TagToken(Tag {
+        kind: TagKind::EndTag,
+        ref name,
+        ..
+      }) => match name.as_ref() {
+        "form" => self.in_form = false,
+        "script" => self.in_script = false,
+        "style" => self.in_style = false,
+        "video" | "audio" => self.in_media = false,
+        "noscript" => self.in_noscript = false,
+        "title" => self.in_title = false,
+        "svg" => self.svg_depth = self.svg_depth.saturating_sub(1),
+        "figcaption" => self.in_figcaption = false,
+        "picture" => {
+          if let Some(sources) = self.picture.take() {
+            self.image_links.extend(sources);
+          }
+        }
+        "figure" => {
+          if let Some((first, figcaption)) = self.figures.pop() {
+            for (_, caption) in &mut self.image_captions[first..] {
+              if caption.figcaption.is_none() {
+                caption.figcaption.clone_from(&figcaption);
+              }
+            }
+          }
+        }
+        _ => {}
+      },
+      _ => {}
+    }
+
+    if let TagToken(
+      ref tag @ Tag {
+        kind: TagKind::StartTag,
+        ..
+      },
+    ) = token
+    {
+      if let Some(style) = attribute_value(tag, "style") {
+        self.style_text.push_str(&style);
+        self.style_text.push('\n');
+      }
+      match tag.name.as_ref() {
+        // `xlink:href` for links in inline SVG.
+        "a" => {
+          let links = if has_rel(tag, "nofollow") {
+            &mut self.nofollow_links
+          } else {
+            &mut self.page_links
+          };
+          for attribute in &tag.attrs {
+            if matches!(attribute.name.local.as_ref(), "href" | "xlink:href") {
+              let url_str: &[u8] = attribute.value.borrow();
+              let url_string = String::from_utf8_lossy(url_str).into_owned();
+              links.push(url_string);
+            }
+          }
+          if has_rel(tag, "license") {
+            self.license_refs.extend(attribute_value(tag, "href"));
+          }
+        }
+        "link" => {
+          if has_rel(tag, "license") {
+            self.license_refs.extend(attribute_value(tag, "href"));
+          }
+          let rel = attribute_value(tag, "rel").unwrap_or_default();
+          if rel
+            .split_whitespace()
+            .any(|r| r.eq_ignore_ascii_case("canonical"))
+          {
+            self.canonical = attribute_value(tag, "href");
+          }
+          // Mobile alternates name the screens they are for, unlike those for
+          // other languages or feeds.
+          if has_rel(tag, "amphtml")
+            || has_rel(tag, "alternate") && attribute_value(tag, "media").is_some()
+          {
+            self.variant_links.extend(attribute_value(tag, "href"));
+          }
+          let href = attribute_value(tag, "href");
+          if has_rel(tag, "stylesheet") {
+            self.stylesheet_links.extend(href);
+          } else if ICON_RELS.iter().any(|rel| has_rel(tag, rel)) {
+            self.icon_links.extend(href);
+          } else if has_rel(tag, "modulepreload") {
+            self.script_links.extend(href);
+          } else if has_rel(tag, "preload") {
+            let links = match attribute_value(tag, "as").as_deref().map(str::trim) {
+              Some("style") => &mut self.stylesheet_links,
+              Some("script") => &mut self.script_links,
+              Some("font") => &mut self.font_links,
+              Some("image") => &mut self.image_links,
+              _ => return TokenSinkResult::Continue,
+            };
+            links.extend(href);
+          }
+        }
+        "img" => {
+          let mut sources = image_sources(tag);
+          // The `<source>`s of a `<picture>` are alternatives to its `<img>`.
+          if let Some(picture) = &mut self.picture {
+            sources.append(picture);
+          }
+          let caption = ImageCaption {
+            alt: attribute_value(tag, "alt"),
+            title: attribute_value(tag, "title"),
+            figcaption: None,
+          };
+          for src in &sources {
+            self.image_captions.push((src.clone(), caption.clone()));
+          }
+          self.image_links.extend(sources);
+        }
+        "picture" => self.picture = Some(Vec::new()),
+        "source" => {
+          if let Some(picture) = &mut self.picture {
+            picture.extend(image_sources(tag));
+          } else if self.in_media {
+            self.media_links.extend(attribute_value(tag, "src"));
+          }
+        }
+        "video" | "audio" => {
+          self.media_links.extend(attribute_value(tag, "src"));
+          // The still shown until a video plays.
+          self.image_links.extend(attribute_value(tag, "poster"));
+          self.in_media = true;
+        }
+        // Image maps.
+        "area" => self.page_links.extend(attribute_value(tag, "href")),
+        // Frames are pages of their own, unless they are left empty to be
+        // filled in by a script.
+        "iframe" | "frame" => self
+          .page_links
+          .extend(attribute_value(tag, "src").filter(|src| !src.trim().is_empty())),
+        "embed" | "object" => {
+          let attribute = match tag.name.as_ref() {
+            "embed" => "src",
+            _ => "data",
+          };
+          if let Some(src) = attribute_value(tag, attribute) {
+            self.image_links.push(src.clone());
+            self.object_links.push(src);
+          }
+        }
+        // Resources of inline SVG, once per sprite sheet and not at all if
+        // they refer to the page itself like `<use href="#icon">`.
+        "image" | "use" | "feimage" => {
+          if let Some(href) =
+            attribute_value(tag, "href").or_else(|| attribute_value(tag, "xlink:href"))
+          {
+            let resource = href.split('#').next().unwrap_or_default().trim();
+            if !resource.is_empty() {
+              self.image_links.push(resource.to_owned());
+            }
+          }
+        }
+        "base" if self.base.is_none() => self.base = attribute_value(tag, "href"),
+        "html" => {
+          self.html_lang = attribute_value(tag, "lang").map(|l| l.trim().to_ascii_lowercase());
+        }
+        "meta" => {
+          if let Some(charset) = attribute_value(tag, "charset") {
+            self.meta_charset = Some(charset.trim().to_ascii_lowercase());
+          } else if attribute_value(tag, "http-equiv")
+            .is_some_and(|h| h.eq_ignore_ascii_case("content-type"))
+          {
+            self.meta_charset = attribute_value(tag, "content")
+              .as_deref()
+              .and_then(charset_parameter);
+          } else if attribute_value(tag, "name")
+            .is_some_and(|n| n.eq_ignore_ascii_case("description"))
+          {
+            self.description = attribute_value(tag, "content");
+          } else if attribute_value(tag, "http-equiv")
+            .is_some_and(|h| h.eq_ignore_ascii_case("content-security-policy"))
+          {
+            self.csp.extend(attribute_value(tag, "content"));
+          } else if attribute_value(tag, "name").is_some_and(|n| n.eq_ignore_ascii_case("robots")) {
+            self.meta_robots = attribute_value(tag, "content").map(|c| c.to_ascii_lowercase());
+          } else if attribute_value(tag, "name")
+            .or_else(|| attribute_value(tag, "property"))
+            .is_some_and(|n| LICENSE_META_NAMES.contains(&n.to_ascii_lowercase().as_str()))
+          {
+            self.license_refs.extend(attribute_value(tag, "content"));
+          }
+        }
+        "form" => {
+          self.forms.push(RawForm {
+            action: attribute_value(tag, "action"),
+            method: attribute_value(tag, "method"),
+            inputs: Vec::new(),
+          });
+          self.in_form = true;
+        }
+        "input" | "select" | "textarea" | "button" if self.in_form => {
+          if let (Some(form), Some(name)) = (self.forms.last_mut(), attribute_value(tag, "name")) {
+            form.inputs.push(name);
+          }
+        }
+        "script" => {
+          self.script_links.extend(attribute_value(tag, "src"));
+          self.in_script = true;
+          return TokenSinkResult::RawData(RawKind::ScriptData);
+        }
+        "style" => {
+          self.in_style = true;
+          return TokenSinkResult::RawData(RawKind::Rawtext);
+        }
+        "noscript" => {
+          self.in_noscript = true;
+          self.noscript_text.push(String::new());
+        }
+        "figure" => self.figures.push((self.image_captions.len(), None)),
+        "figcaption" => self.in_figcaption = true,
+        "svg" if !tag.self_closing => self.svg_depth += 1,
+        "title" if self.svg_depth == 0 => {
+          if self.title.is_none() {
+            self.in_title = true;
+            self.title = Some(String::new());
+          }
+          return TokenSinkResult::RawData(RawKind::Rcdata);
+        }
+        _ => {}
+      }
+    }
+    TokenSinkResult::Continue
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn contacts_are_found_in_separate_elements_and_links_only() {
+    let url = Url::parse("https://a.example/").unwrap();
+    let page = "<ul><li>info@a.example</li><li>next</li></ul><p>Call +41 44 123 45 67</p>\
+                <p>Updated 12.05.2023, 2019-2020-2021</p>\
+                <a href='MAILTO:Sales%40a.example?subject=hi'>x</a><a href='TEL:%2B41%2044%20000%2000%2000'>y</a>";
+    let extraction = process_page(&url, page.to_owned(), 0, true);
+    assert_eq!(
+      extraction.emails,
+      HashSet::from(["info@a.example".to_owned(), "sales@a.example".to_owned()])
+    );
+    assert_eq!(
+      extraction.phones,
+      HashSet::from(["+41441234567".to_owned(), "+41440000000".to_owned()])
+    );
+
+    let extraction = process_page(&url, page.to_owned(), 0, false);
+    assert!(extraction.emails.is_empty() && extraction.phones.is_empty());
+  }
+
+  #[test]
+  fn text_hash_ignores_markup_and_digits() {
+    let url = Url::parse("https://a.example/calendar?day=1").unwrap();
+    let page = |day: u32, nonce: &str| {
+      let page = format!(
+        "<div data-nonce='{}'><h1>Events on {}.05.2023</h1><p>No events.</p></div>",
+        nonce, day
+      );
+      process_page(&url, page, 0, false).text_hash
+    };
+    assert!(page(1, "a").is_some());
+    assert_eq!(page(1, "a"), page(2, "b"));
+    assert_ne!(
+      page(1, "a"),
+      process_page(&url, "<p>Other</p>".into(), 0, false).text_hash
+    );
+    assert_eq!(
+      process_page(&url, "<img src=a.png>".into(), 0, false).text_hash,
+      None
+    );
+  }
+
+  #[test]
+  fn charset_parameter_names_are_case_insensitive() {
+    for content_type in [
+      "text/html; charset=UTF-8",
+      "text/html;Charset=\"utf-8\"",
+      "text/html; CHARSET = utf-8",
+    ] {
+      assert_eq!(charset_parameter(content_type).as_deref(), Some("utf-8"));
+    }
+    assert_eq!(charset_parameter("text/html; format=flowed"), None);
+  }
+
+  #[test]
+  fn style_and_script_text_is_not_page_text() {
+    let url = Url::parse("https://a.example/").unwrap();
+    let page = "<style>body { color: red }</style><p>Hallo</p><svg><style>.a { fill: red }</style>\
+                </svg><script>var a = 1;</script><noscript><style>.b {}</style></noscript>";
+    let page = process_page(&url, page.to_owned(), 0, false);
+    assert_eq!(page.text_hash, text_hash("Hallo"));
+  }
+
+  #[test]
+  fn title_is_the_first_outside_svg() {
+    let url = Url::parse("https://a.example/").unwrap();
+    let page = "<svg><title>Menu</title></svg><title>Home</title>\
+                <svg><svg/><title>Close</title></svg><title>Other</title>";
+    let page = process_page(&url, page.to_owned(), 0, false);
+    assert_eq!(page.title.as_deref(), Some("Home"));
+  }
+
+  #[test]
+  fn csp_sources_match_like_browsers() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let page = url("http://a.example/page");
+    let matches = |source: &str, resource: &str| csp_source_matches(source, &page, &url(resource));
+    assert!(matches("'self'", "http://a.example/a.png"));
+    assert!(matches("'self'", "https://a.example/a.png"));
+    assert!(!matches("'self'", "http://b.example/a.png"));
+    assert!(!matches("'self'", "http://a.example:8080/a.png"));
+    assert!(matches("*", "https://cdn.example/a.png"));
+    assert!(!matches("*", "data:image/png,abc"));
+    assert!(matches("data:", "data:image/png,abc"));
+    assert!(matches("https:", "https://cdn.example/a.png"));
+    assert!(!matches("https:", "http://cdn.example/a.png"));
+    assert!(matches("*.example.com", "http://cdn.example.com/a.png"));
+    assert!(!matches("*.example.com", "http://example.com/a.png"));
+    assert!(matches(
+      "https://cdn.example.com",
+      "https://cdn.example.com/a.png"
+    ));
+    assert!(!matches(
+      "https://cdn.example.com",
+      "http://cdn.example.com/a.png"
+    ));
+    assert!(matches(
+      "cdn.example.com:*",
+      "http://cdn.example.com:8080/a.png"
+    ));
+    assert!(!matches("'nonce-abc'", "http://a.example/a.js"));
+  }
+
+  #[test]
+  fn srcset_urls_drop_descriptors() {
+    assert_eq!(srcset_urls("a.png 1x, b.png 2x"), ["a.png", "b.png"]);
+    assert_eq!(
+      srcset_urls(" a.png 640w,b.png, c.png  1280w "),
+      ["a.png", "b.png", "c.png"]
+    );
+    // As in browsers, only commas after a URL end it.
+    assert_eq!(srcset_urls("a.png,b.png 2x"), ["a.png,b.png"]);
+    assert_eq!(srcset_urls("a,1.png 1x, b.png"), ["a,1.png", "b.png"]);
+    assert!(srcset_urls(" , ").is_empty());
+  }
+
+  #[test]
+  fn content_class_sniffs_the_start_of_bodies() {
+    assert_eq!(ContentClass::of(b"<!DOCTYPE html><p>a"), ContentClass::Html);
+    assert_eq!(
+      ContentClass::of(b"\xEF\xBB\xBF  <HTML lang=en>"),
+      ContentClass::Html
+    );
+    assert_eq!(
+      ContentClass::of(b"<?xml version=\"1.0\"?>\n<html xmlns=\"\">"),
+      ContentClass::Html
+    );
+    assert_eq!(ContentClass::of(b"<!-- comment -->"), ContentClass::Html);
+    assert_eq!(ContentClass::of(b"<htmlish>"), ContentClass::Text);
+    assert_eq!(ContentClass::of(b"plain text\n"), ContentClass::Text);
+    assert_eq!(
+      ContentClass::of(b"\x89PNG\r\n\x1a\n\0\0"),
+      ContentClass::Binary
+    );
+    assert_eq!(ContentClass::of(b""), ContentClass::Text);
+  }
+}
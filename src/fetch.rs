@@ -0,0 +1,1925 @@
+//! The backends that fetch resources, from the network, local files or
+//! memory, and the tasks that fetch pages and assets for a crawl.
+
+use super::*;
+
+pub(crate) struct SpiderResponse {
+  pub(crate) depth: u8,
+  pub(crate) content_type: Option<String>,
+  /// Size of the body as received.
+  pub(crate) size: usize,
+  /// Where the page was stored, if it was.
+  pub(crate) saved: Option<PathBuf>,
+  pub(crate) status: u16,
+  /// The URL the page was served from after following redirects.
+  pub(crate) final_url: Url,
+  /// The `Strict-Transport-Security` policy of the page, if it was served
+  /// over HTTPS with a valid one.
+  pub(crate) hsts: Option<StrictTransportSecurity>,
+  pub(crate) content_language: Option<String>,
+  pub(crate) charset: Option<String>,
+  /// Every `X-Robots-Tag` header, lowercased.
+  pub(crate) x_robots_tag: Vec<String>,
+  /// When the page last changed, according to its `Last-Modified` header.
+  pub(crate) last_modified: Option<SystemTime>,
+  /// Hash of the raw body, to tell pages with new content from repeats.
+  pub(crate) content_hash: u64,
+  /// The policy of the page, from its headers and `<meta>` tags.
+  pub(crate) csp: ContentSecurityPolicy,
+  /// What the page looked like if it was sampled first. Only HTML was
+  /// downloaded in full.
+  pub(crate) sampled: Option<ContentClass>,
+  pub(crate) extraction: Extraction,
+}
+
+/// An asset answered with a client or server error.
+#[derive(Debug, thiserror::Error)]
+#[error("status {status} for `{url}`")]
+pub(crate) struct ErrorStatus {
+  pub(crate) url: Url,
+  pub(crate) status: u16,
+}
+
+/// A finding whose host answered 503 without `Retry-After`.
+#[derive(Debug, thiserror::Error)]
+#[error("status 503 without Retry-After for `{}`", .0.url())]
+pub(crate) struct MaintenanceResponse(pub(crate) Finding);
+
+/// A finding whose host answered 429, or 503 with `Retry-After`, asking to be
+/// left alone for a while.
+#[derive(Debug, thiserror::Error)]
+#[error("status {status} for `{}`, retry after {} s", .finding.url(), .retry_after.as_secs())]
+pub(crate) struct RateLimited {
+  pub(crate) finding: Finding,
+  pub(crate) status: u16,
+  pub(crate) retry_after: Duration,
+}
+
+/// A body that arrived slower than [`DispatcherBuilder::min_transfer_rate`],
+/// whether from a broken server or one trying to tie up the crawler.
+#[derive(Debug, thiserror::Error)]
+#[error(
+  "body of `{url}` arrived too slowly: {received} bytes in {} s, below {rate} bytes/s",
+  .window.as_secs()
+)]
+pub(crate) struct SlowTransfer {
+  pub(crate) url: Url,
+  pub(crate) received: usize,
+  pub(crate) rate: usize,
+  pub(crate) window: Duration,
+}
+
+/// Measures the rate at which a body arrives, window by window.
+pub(crate) struct TransferFloor {
+  pub(crate) rate: usize,
+  pub(crate) window: Duration,
+  pub(crate) deadline: Instant,
+  /// Bytes received when the current window started.
+  pub(crate) start: usize,
+}
+
+impl TransferFloor {
+  pub(crate) fn new(rate: usize, window: Duration) -> Self {
+    Self {
+      rate,
+      window,
+      deadline: Instant::now() + window,
+      start: 0,
+    }
+  }
+
+  /// Checks the window that ended at the deadline, once `received` bytes have
+  /// arrived, and starts the next one.
+  pub(crate) fn check(&mut self, url: &Url, received: usize) -> Result<(), SlowTransfer> {
+    let floor = (self.rate as f64 * self.window.as_secs_f64()) as usize;
+    if received - self.start < floor {
+      return Err(SlowTransfer {
+        url: url.clone(),
+        received: received - self.start,
+        rate: self.rate,
+        window: self.window,
+      });
+    }
+    self.start = received;
+    self.deadline += self.window;
+    Ok(())
+  }
+}
+
+/// The status of a response that failed because of it, such as because the
+/// host asked the crawler to slow down.
+pub(crate) fn error_status(error: &eyre::Report) -> Option<u16> {
+  if let Some(limited) = error.downcast_ref::<RateLimited>() {
+    return Some(limited.status);
+  }
+  if error.downcast_ref::<MaintenanceResponse>().is_some() {
+    return Some(503);
+  }
+  error.downcast_ref::<ErrorStatus>().map(|e| e.status)
+}
+
+/// Crawls the page behind `url`, setting `latency` to how long its requests
+/// took.
+pub(crate) async fn spider_page(
+  url: Url,
+  backend: Arc<dyn Fetcher>,
+  depth: u8,
+  saving: Option<PageSaving>,
+  sample: Option<usize>,
+  contacts: bool,
+  latency: &mut Duration,
+) -> Result<SpiderResponse> {
+  info!("crawling url `{}`", &url);
+
+  let requested = Instant::now();
+  let requests = async {
+    Ok(match sample.filter(|_| is_questionable_page(&url)) {
+      Some(bytes) => {
+        let prefix = backend.sample(&url, bytes).await?;
+        match ContentClass::of(&prefix.body) {
+          // All of a short body that ignored the range.
+          ContentClass::Html if prefix.status == 200 && prefix.body.len() < bytes => {
+            (prefix, Some(ContentClass::Html))
+          }
+          ContentClass::Html => (backend.fetch(&url).await?, Some(ContentClass::Html)),
+          class => (prefix, Some(class)),
+        }
+      }
+      None => (backend.fetch(&url).await?, None),
+    })
+  };
+  let requests: Result<_> = requests.await;
+  *latency = requested.elapsed();
+  let (response, sampled) = requests?;
+  // Only the sample of anything but HTML was downloaded.
+  let truncated = sampled.is_some_and(|class| class != ContentClass::Html);
+  let status = response.status;
+  if response.is_maintenance() {
+    return Err(MaintenanceResponse(Finding::Page(url, depth)).into());
+  }
+  if let Some(retry_after) = response.retry_after() {
+    return Err(
+      RateLimited {
+        finding: Finding::Page(url, depth),
+        status,
+        retry_after,
+      }
+      .into(),
+    );
+  }
+  let final_url = response.url.clone();
+  // Only the first header counts, and only over HTTPS.
+  let hsts = (final_url.scheme() == "https")
+    .then(|| {
+      response
+        .headers
+        .get(reqwest::header::STRICT_TRANSPORT_SECURITY)
+    })
+    .flatten()
+    .and_then(|v| v.to_str().ok())
+    .and_then(StrictTransportSecurity::parse);
+  let content_language = response.header(reqwest::header::CONTENT_LANGUAGE);
+  let charset = response
+    .header(reqwest::header::CONTENT_TYPE)
+    .as_deref()
+    .and_then(charset_parameter);
+  let x_robots_tag = response
+    .headers
+    .get_all(HeaderName::from_static("x-robots-tag"))
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .map(|v| v.trim().to_ascii_lowercase())
+    .collect();
+  let last_modified = response
+    .headers
+    .get(reqwest::header::LAST_MODIFIED)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| httpdate::parse_http_date(v).ok());
+  let content_hash = Deduplicator::content_hash(&response.body);
+  let csp_headers: Vec<String> = response
+    .headers
+    .get_all(reqwest::header::CONTENT_SECURITY_POLICY)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .map(str::to_owned)
+    .collect();
+  let content_type = response.header(reqwest::header::CONTENT_TYPE);
+  let stylesheet = response.is_css();
+  let size = response.body.len();
+  let body = match truncated {
+    true => String::new(),
+    false => response.text(),
+  };
+
+  // A page that cannot be stored is still crawled for its links.
+  let saved = match saving.filter(|_| status < 400 && !truncated) {
+    Some(saving) => {
+      let metadata = ResourceMetadata {
+        file_name: page_file_name(&url),
+        final_url: final_url.clone(),
+        status,
+        request_headers: response.request_headers,
+        response_headers: response.headers,
+      };
+      let mut page = saving.rules.apply(&response.body);
+      if let Some(crawl_id) = &saving.provenance {
+        // After the byte order mark, which has to come first.
+        let bom = if page.starts_with(b"\xEF\xBB\xBF") {
+          3
+        } else {
+          0
+        };
+        let mut stamped = page[..bom].to_vec();
+        stamped.extend(provenance_comment(&url, &final_url, crawl_id).into_bytes());
+        stamped.extend_from_slice(&page[bom..]);
+        page = stamped.into();
+      }
+      match saving.storage.store(&url, &page, &metadata).await {
+        Ok(saved) => saved,
+        Err(e) => {
+          warn!("Saving page `{}` failed: {}", url, e);
+          None
+        }
+      }
+    }
+    None => None,
+  };
+
+  let extraction = match stylesheet {
+    true => process_stylesheet(&final_url, &body, depth),
+    false => process_page(&final_url, body, depth, contacts),
+  };
+  let csp = csp_headers
+    .iter()
+    .chain(&extraction.csp)
+    .map(String::as_str);
+  let csp = ContentSecurityPolicy::parse(csp);
+  Ok(SpiderResponse {
+    depth,
+    content_type,
+    size,
+    status,
+    final_url,
+    saved,
+    hsts,
+    content_language,
+    charset,
+    x_robots_tag,
+    last_modified,
+    content_hash,
+    csp,
+    sampled,
+    extraction,
+  })
+}
+
+/// Page URLs that do not look like web pages by their extension.
+pub(crate) fn is_questionable_page(url: &Url) -> bool {
+  let name = url
+    .path_segments()
+    .and_then(|mut s| s.next_back())
+    .unwrap_or_default();
+  match name.rsplit_once('.') {
+    Some((_, extension)) => !PAGE_EXTENSIONS
+      .iter()
+      .any(|page| page.eq_ignore_ascii_case(extension)),
+    None => false,
+  }
+}
+
+/// A successfully fetched resource and where it was saved, if it was.
+pub(crate) struct Fetched {
+  pub(crate) status: u16,
+  pub(crate) content_type: Option<String>,
+  /// Size of the body as received, before any conversion.
+  pub(crate) size: usize,
+  pub(crate) saved: Option<PathBuf>,
+  /// SHA-256 of the saved body, if checksums are enabled.
+  pub(crate) sha256: Option<String>,
+  /// What a stylesheet refers to, see [`stylesheet_references`].
+  pub(crate) subresources: Vec<Finding>,
+}
+
+/// Fetches and saves an asset, setting `latency` to how long its request took.
+pub(crate) async fn fetch(
+  asset: Finding,
+  backend: Arc<dyn Fetcher>,
+  save: SaveOptions,
+  latency: &mut Duration,
+) -> Result<Fetched> {
+  let resource_url = asset.url().clone();
+  info!("fetching `{}`", resource_url);
+
+  // Stylesheets are parsed and images converted in memory; every other body
+  // is streamed to a file.
+  #[cfg(feature = "images")]
+  let conversion = save
+    .convert_images
+    .filter(|_| matches!(asset, Finding::Image(..)));
+  #[cfg(feature = "images")]
+  let in_memory = conversion.is_some();
+  #[cfg(not(feature = "images"))]
+  let in_memory = false;
+  let spool = match in_memory || matches!(asset, Finding::Stylesheet(_)) {
+    true => None,
+    false => Some(
+      SpoolFile::create(&save.spool_dir)
+        .await
+        .wrap_err_with(|| StorageFailure(resource_url.clone()))?,
+    ),
+  };
+  let requested = Instant::now();
+  let response = match &spool {
+    Some(spool) => backend.download(&resource_url, spool.path()).await,
+    None => backend.fetch(&resource_url).await,
+  };
+  *latency = requested.elapsed();
+  let response = response?;
+  let status = response.status;
+  if response.is_maintenance() {
+    return Err(MaintenanceResponse(asset).into());
+  }
+  if let Some(retry_after) = response.retry_after() {
+    return Err(
+      RateLimited {
+        finding: asset,
+        status,
+        retry_after,
+      }
+      .into(),
+    );
+  }
+  if status >= 400 {
+    return Err(
+      ErrorStatus {
+        url: resource_url,
+        status,
+      }
+      .into(),
+    );
+  }
+  let content_type = response.header(reqwest::header::CONTENT_TYPE);
+  let size = match &spool {
+    Some(spool) => tokio::fs::metadata(spool.path()).await?.len() as usize,
+    None => response.body.len(),
+  };
+  let subresources = match (
+    matches!(asset, Finding::Stylesheet(_)) || response.is_css(),
+    &spool,
+  ) {
+    (false, _) => Vec::new(),
+    (true, None) => stylesheet_references(&response.text(), &response.url),
+    // Served as CSS for some other kind of asset.
+    (true, Some(spool)) => {
+      let css = FetchResponse {
+        body: tokio::fs::read(spool.path()).await?.into(),
+        ..response.clone()
+      };
+      stylesheet_references(&css.text(), &css.url)
+    }
+  };
+
+  let Some(file_name) = SaveOptions::response_name(&resource_url, &response) else {
+    return Ok(Fetched {
+      status,
+      content_type,
+      size,
+      saved: None,
+      sha256: None,
+      subresources,
+    });
+  };
+  let (contents, file_name) = match &spool {
+    Some(spool) => (Contents::File(spool.path()), file_name),
+    None => (Contents::Bytes(&response.body), file_name),
+  };
+  #[cfg(feature = "images")]
+  let converted;
+  #[cfg(feature = "images")]
+  let (contents, file_name) = match conversion {
+    Some(conversion) => match conversion
+      .apply(response.body.clone())
+      .await
+      .wrap_err_with(|| StorageFailure(resource_url.clone()))?
+    {
+      Some(bytes) => {
+        converted = bytes;
+        let converted_name = Path::new(&file_name).with_extension(conversion.format.extension());
+        let converted_name = converted_name.to_string_lossy().into_owned();
+        (Contents::Bytes(&converted), converted_name)
+      }
+      None => (contents, file_name),
+    },
+    None => (contents, file_name),
+  };
+  let hash = match contents {
+    Contents::Bytes(bytes) => Deduplicator::content_hash(bytes),
+    Contents::File(path) => file_content_hash(path)
+      .await
+      .wrap_err_with(|| StorageFailure(resource_url.clone()))?,
+  };
+  let file_name = match &save.path_template {
+    Some(template) => template
+      .render(&resource_url, &file_name, Some(hash))
+      .unwrap_or(file_name),
+    None => file_name,
+  };
+  let file_name = save.claim(&resource_url, file_name, hash);
+
+  let metadata = ResourceMetadata {
+    file_name,
+    final_url: response.url.clone(),
+    status,
+    request_headers: response.request_headers.clone(),
+    response_headers: response.headers.clone(),
+  };
+  let stored = match contents {
+    Contents::Bytes(bytes) => save.storage.store(&resource_url, bytes, &metadata).await,
+    Contents::File(path) => {
+      save
+        .storage
+        .store_file(&resource_url, path, &metadata)
+        .await
+    }
+  };
+  let saved = stored.wrap_err_with(|| StorageFailure(resource_url.clone()))?;
+  let sha256 = match (save.checksums, &saved, contents) {
+    (false, _, _) | (_, None, _) => None,
+    (true, Some(_), Contents::Bytes(bytes)) => {
+      let bytes = bytes.to_vec();
+      Some(task::spawn_blocking(move || sha256_hex(&bytes)).await?)
+    }
+    // The download was moved to where it was saved.
+    (true, Some(saved), Contents::File(_)) => Some(file_sha256_hex(saved).await?),
+  };
+  Ok(Fetched {
+    status,
+    content_type,
+    size,
+    saved,
+    sha256,
+    subresources,
+  })
+}
+
+/// A fetched resource, independent of the backend that produced it.
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+  /// The URL the resource was served from, after following redirects.
+  pub url: Url,
+  pub status: u16,
+  pub headers: HeaderMap,
+  /// The headers the backend sent with the request, if it sent any.
+  pub request_headers: HeaderMap,
+  pub body: Bytes,
+}
+
+impl FetchResponse {
+  pub(crate) fn header(&self, name: HeaderName) -> Option<String> {
+    self
+      .headers
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .map(|v| v.trim().to_ascii_lowercase())
+  }
+
+  /// Whether the host is unavailable with no hint for when to come back,
+  /// typically during a brief maintenance window.
+  pub(crate) fn is_maintenance(&self) -> bool {
+    self.status == 503 && self.retry_after_header().is_none()
+  }
+
+  /// The pause `Retry-After` asks for, unless it is neither a number of
+  /// seconds nor an HTTP date. Dates in the past ask for none.
+  pub(crate) fn retry_after_header(&self) -> Option<Duration> {
+    // Not lowercased like `header`, as HTTP dates are case-sensitive.
+    let value = self
+      .headers
+      .get(reqwest::header::RETRY_AFTER)?
+      .to_str()
+      .ok()?;
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+      return Some(Duration::from_secs(value.parse().unwrap_or(u64::MAX)));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+  }
+
+  /// How long the host asks to be left alone, for a 429, or a 503 with a
+  /// valid `Retry-After`. A 429 without one, or with an empty or malformed
+  /// one, gets [`DEFAULT_RETRY_AFTER`].
+  pub(crate) fn retry_after(&self) -> Option<Duration> {
+    let retry_after = match (self.status, self.retry_after_header()) {
+      (429 | 503, Some(retry_after)) => retry_after,
+      (429, None) => DEFAULT_RETRY_AFTER,
+      _ => return None,
+    };
+    Some(retry_after.min(RETRY_AFTER_LIMIT))
+  }
+
+  /// The extension matching the magic bytes of the body, for responses that
+  /// do not declare a specific `Content-Type`.
+  pub(crate) fn sniffed_extension(&self) -> Option<&'static str> {
+    let declared = self.header(reqwest::header::CONTENT_TYPE);
+    let generic = match declared.as_deref().and_then(|t| t.split(';').next()) {
+      None => true,
+      Some(mime) => matches!(
+        mime.trim(),
+        "" | "application/octet-stream" | "binary/octet-stream"
+      ),
+    };
+    generic
+      .then(|| infer::get(&self.body))
+      .flatten()
+      .map(|kind| kind.extension())
+  }
+
+  /// The usual extension of the declared `Content-Type`, for resources whose
+  /// URL has none.
+  pub(crate) fn declared_extension(&self) -> Option<&'static str> {
+    let declared = self.header(reqwest::header::CONTENT_TYPE)?;
+    Some(match declared.split(';').next()?.trim() {
+      "image/png" => "png",
+      "image/jpeg" | "image/jpg" | "image/pjpeg" => "jpg",
+      "image/gif" => "gif",
+      "image/webp" => "webp",
+      "image/avif" => "avif",
+      "image/bmp" => "bmp",
+      "image/tiff" => "tiff",
+      "image/svg+xml" => "svg",
+      "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+      "application/pdf" => "pdf",
+      _ => return None,
+    })
+  }
+
+  /// Whether the response declares a stylesheet, whatever it was linked as.
+  pub(crate) fn is_css(&self) -> bool {
+    self
+      .header(reqwest::header::CONTENT_TYPE)
+      .as_deref()
+      .and_then(|t| t.split(';').next())
+      .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/css"))
+  }
+
+  /// Decodes the body with the charset given in `Content-Type`, falling back
+  /// to UTF-8.
+  pub(crate) fn text(&self) -> String {
+    let encoding = self
+      .header(reqwest::header::CONTENT_TYPE)
+      .as_deref()
+      .and_then(charset_parameter)
+      .and_then(|c| encoding_rs::Encoding::for_label(c.as_bytes()))
+      .unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(&self.body).0.into_owned()
+  }
+}
+
+/// A backend that retrieves the resource behind a URL, registered for a
+/// scheme or host with [`DispatcherBuilder::scheme_fetcher`] and
+/// [`DispatcherBuilder::host_fetcher`]. Only the default HTTP backend records
+/// WARC output.
+pub trait Fetcher: Send + Sync + std::fmt::Debug {
+  fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>>;
+
+  /// Fetches the first `bytes` of the body of `url`, or all of it and cuts it
+  /// short for backends that cannot do less.
+  fn sample<'a>(&'a self, url: &'a Url, bytes: usize) -> BoxFuture<'a, Result<FetchResponse>> {
+    async move {
+      let mut response = self.fetch(url).await?;
+      response.body.truncate(bytes);
+      Ok(response)
+    }
+    .boxed()
+  }
+
+  /// Fetches `url` and writes its body to `file` instead of holding it in
+  /// memory. The response only keeps the first few KiB of the body, enough
+  /// to sniff its type. Backends that cannot stream fetch the body whole.
+  fn download<'a>(&'a self, url: &'a Url, file: &'a Path) -> BoxFuture<'a, Result<FetchResponse>> {
+    download_whole(self, url, file).boxed()
+  }
+}
+
+/// Fetches `url` with `fetcher` into memory and writes the body to `file`.
+pub(crate) async fn download_whole<F: Fetcher + ?Sized>(
+  fetcher: &F,
+  url: &Url,
+  file: &Path,
+) -> Result<FetchResponse> {
+  let mut response = fetcher.fetch(url).await?;
+  tokio::fs::write(file, &response.body).await?;
+  response.body.truncate(DOWNLOAD_PREFIX_LENGTH);
+  Ok(response)
+}
+
+/// The first [`DOWNLOAD_PREFIX_LENGTH`] bytes of `file`.
+pub(crate) async fn read_prefix(file: &Path) -> Result<Bytes> {
+  use tokio::io::AsyncReadExt as _;
+
+  let mut prefix = Vec::with_capacity(DOWNLOAD_PREFIX_LENGTH);
+  File::open(file)
+    .await?
+    .take(DOWNLOAD_PREFIX_LENGTH as u64)
+    .read_to_end(&mut prefix)
+    .await?;
+  Ok(prefix.into())
+}
+
+/// A file an asset is downloaded to before it is stored, removed when it is
+/// dropped unless the storage moved it away.
+#[derive(Debug)]
+pub(crate) struct SpoolFile(pub(crate) PathBuf);
+
+impl SpoolFile {
+  pub(crate) async fn create(dir: &Path) -> Result<Self> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    tokio::fs::create_dir_all(dir).await?;
+    let name = format!(
+      "{}-{}.part",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    Ok(Self(dir.join(name)))
+  }
+
+  pub(crate) fn path(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for SpoolFile {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.0);
+  }
+}
+
+/// The addresses a host resolved to, and until when their TTL allows reusing
+/// them.
+#[cfg(feature = "dns-pinning")]
+pub(crate) type PinnedAddresses = (Vec<IpAddr>, Instant);
+
+/// IPv4 or IPv6.
+#[cfg(feature = "dns-pinning")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AddressFamily {
+  V4,
+  V6,
+}
+
+#[cfg(feature = "dns-pinning")]
+impl AddressFamily {
+  pub(crate) fn of(ip: &IpAddr) -> Self {
+    match ip {
+      IpAddr::V4(_) => AddressFamily::V4,
+      IpAddr::V6(_) => AddressFamily::V6,
+    }
+  }
+
+  pub(crate) fn other(self) -> Self {
+    match self {
+      AddressFamily::V4 => AddressFamily::V6,
+      AddressFamily::V6 => AddressFamily::V4,
+    }
+  }
+
+  pub(crate) fn name(self) -> &'static str {
+    match self {
+      AddressFamily::V4 => "IPv4",
+      AddressFamily::V6 => "IPv6",
+    }
+  }
+}
+
+/// A host that requests reach over one address family after they failed to
+/// connect over the other.
+#[cfg(feature = "dns-pinning")]
+#[derive(Debug, Clone)]
+pub(crate) struct Fallback {
+  pub(crate) family: AddressFamily,
+  /// The error requests over the other family failed with.
+  pub(crate) error: String,
+  /// Whether they timed out, which may be transient: the host is then only
+  /// pinned to `family` until its addresses expire.
+  pub(crate) timed_out: bool,
+}
+
+/// Resolves every host once and reuses its addresses for all requests to it
+/// until their TTL expires. The addresses are kept for the report.
+#[cfg(feature = "dns-pinning")]
+#[derive(Clone)]
+pub(crate) struct PinnedResolver {
+  pub(crate) resolver: hickory_resolver::TokioAsyncResolver,
+  pub(crate) hosts: Arc<Mutex<HashMap<String, PinnedAddresses>>>,
+  /// Hosts that fell back to one address family.
+  pub(crate) fallbacks: Arc<Mutex<BTreeMap<String, Fallback>>>,
+}
+
+#[cfg(feature = "dns-pinning")]
+impl std::fmt::Debug for PinnedResolver {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("PinnedResolver")
+      .field("hosts", &self.hosts)
+      .field("fallbacks", &self.fallbacks)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(feature = "dns-pinning")]
+impl PinnedResolver {
+  pub(crate) fn from_system_conf() -> Result<Self> {
+    Ok(Self {
+      resolver: hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()?,
+      hosts: Default::default(),
+      fallbacks: Default::default(),
+    })
+  }
+
+  /// The addresses of `host`, resolved anew unless they stay pinned for
+  /// `lead`.
+  pub(crate) async fn lookup(
+    &self,
+    host: &str,
+    lead: Duration,
+  ) -> Result<Vec<IpAddr>, hickory_resolver::error::ResolveError> {
+    let pinned = self.hosts.lock().unwrap().get(host).cloned();
+    match pinned {
+      Some((ips, valid_until)) if valid_until > Instant::now() + lead => Ok(ips),
+      _ => {
+        let fallback = self.fallbacks.lock().unwrap().get(host).cloned();
+        let family = fallback.filter(|f| !f.timed_out).map(|f| f.family);
+        let (ips, valid_until) = self.resolve(host, family).await?;
+        debug!("resolved `{}` to {:?}", host, ips);
+        let entry = (ips.clone(), valid_until);
+        self.hosts.lock().unwrap().insert(host.to_owned(), entry);
+        Ok(ips)
+      }
+    }
+  }
+
+  /// The addresses of `host`, only of `family` if given.
+  pub(crate) async fn resolve(
+    &self,
+    host: &str,
+    family: Option<AddressFamily>,
+  ) -> Result<PinnedAddresses, hickory_resolver::error::ResolveError> {
+    Ok(match family {
+      None => {
+        let lookup = self.resolver.lookup_ip(host).await?;
+        (lookup.iter().collect(), lookup.valid_until())
+      }
+      Some(AddressFamily::V4) => {
+        let lookup = self.resolver.ipv4_lookup(host).await?;
+        let ips = lookup.iter().map(|a| IpAddr::V4(a.0)).collect();
+        (ips, lookup.valid_until())
+      }
+      Some(AddressFamily::V6) => {
+        let lookup = self.resolver.ipv6_lookup(host).await?;
+        let ips = lookup.iter().map(|a| IpAddr::V6(a.0)).collect();
+        (ips, lookup.valid_until())
+      }
+    })
+  }
+
+  /// Retries a request to `host` that failed to connect with `error` over
+  /// the other address family than it was resolved to, if it has addresses
+  /// of it, and returns the result of `retry`, unless it was not retried.
+  /// The host falls back to the other family only if the retry succeeds,
+  /// and for good only if `error` is not a timeout.
+  pub(crate) async fn fall_back<T, E>(
+    &self,
+    host: &str,
+    error: &reqwest::Error,
+    retry: impl Future<Output = Result<T, E>>,
+  ) -> Option<Result<T, E>> {
+    let fallen_back = self.fallbacks.lock().unwrap().get(host).cloned();
+    if fallen_back.is_some_and(|fallback| !fallback.timed_out) {
+      return None;
+    }
+    let pinned = self.hosts.lock().unwrap().get(host).cloned()?;
+    let failed = AddressFamily::of(pinned.0.first()?);
+    let family = failed.other();
+    let (ips, valid_until) = self.resolve(host, Some(family)).await.ok()?;
+    if ips.is_empty() {
+      return None;
+    }
+    self
+      .hosts
+      .lock()
+      .unwrap()
+      .insert(host.to_owned(), (ips, valid_until));
+    let result = retry.await;
+    if result.is_err() {
+      self.hosts.lock().unwrap().insert(host.to_owned(), pinned);
+      return Some(result);
+    }
+    warn!(
+      "Request to `{}` over {} failed, falling back to {}: {}",
+      host,
+      failed.name(),
+      family.name(),
+      error
+    );
+    let fallback = Fallback {
+      family,
+      error: error.to_string(),
+      timed_out: error.is_timeout(),
+    };
+    self
+      .fallbacks
+      .lock()
+      .unwrap()
+      .insert(host.to_owned(), fallback);
+    Some(result)
+  }
+
+  /// Writes the hosts that fell back to the other address family.
+  pub(crate) fn render(&self, out: &mut String) {
+    let fallbacks = self.fallbacks.lock().unwrap();
+    if fallbacks.is_empty() {
+      return;
+    }
+    out.push_str("## Address family fallbacks\n\n");
+    for (host, fallback) in fallbacks.iter() {
+      let _ = writeln!(
+        out,
+        "- {}: over {}{} since {} failed with: {}",
+        host,
+        fallback.family.name(),
+        match fallback.timed_out {
+          true => " until its addresses expired",
+          false => "",
+        },
+        fallback.family.other().name(),
+        fallback.error
+      );
+    }
+    out.push('\n');
+  }
+
+  /// Resolves `host` ahead of requests sent within `window`.
+  pub(crate) async fn warm(self, host: String, window: Duration) {
+    if let Err(e) = self.lookup(&host, window).await {
+      debug!("warming up `{}` failed: {}", host, e);
+    }
+  }
+
+  /// The addresses every host was last resolved to.
+  pub(crate) fn addresses(&self) -> HashMap<String, Vec<IpAddr>> {
+    let hosts = self.hosts.lock().unwrap();
+    hosts
+      .iter()
+      .map(|(host, (ips, _))| (host.clone(), ips.clone()))
+      .collect()
+  }
+}
+
+#[cfg(feature = "dns-pinning")]
+impl reqwest::dns::Resolve for PinnedResolver {
+  fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+    let this = self.clone();
+    Box::pin(async move {
+      let ips = this.lookup(name.as_str(), Duration::ZERO).await?;
+      // The connector fills in the port of the URL.
+      let addrs = ips.into_iter().map(|ip| std::net::SocketAddr::new(ip, 0));
+      Ok(Box::new(addrs) as reqwest::dns::Addrs)
+    })
+  }
+}
+
+/// The default backend, fetching over HTTP(S) with reqwest.
+#[derive(Debug)]
+pub(crate) struct HttpFetcher {
+  pub(crate) client: Client,
+  /// The headers `client` sends with every request.
+  pub(crate) default_headers: HeaderMap,
+  /// The resolver of `client`, to retry requests over the other address
+  /// family.
+  #[cfg(feature = "dns-pinning")]
+  pub(crate) dns: Option<PinnedResolver>,
+  /// Maximum size of a body, see [`DispatcherBuilder::max_file_size`].
+  pub(crate) max_body_size: Option<usize>,
+  /// See [`DispatcherBuilder::min_transfer_rate`].
+  pub(crate) min_transfer_rate: Option<(usize, Duration)>,
+  /// The WARC file every exchange but those of samples is recorded in.
+  #[cfg(feature = "warc")]
+  pub(crate) warc: Option<Arc<WarcWriter>>,
+}
+
+impl HttpFetcher {
+  /// Sends the request for `url`, following redirects, asking with `sample`
+  /// for only that many bytes from the start of its body, and returns the
+  /// response with the headers sent for it.
+  pub(crate) async fn send(
+    &self,
+    url: &Url,
+    sample: Option<usize>,
+  ) -> Result<(reqwest::Response, HeaderMap)> {
+    let mut target = url.clone();
+    for _ in 0..=REDIRECT_LIMIT {
+      #[cfg(feature = "warc")]
+      let (date, started) = (SystemTime::now(), Instant::now());
+      let (response, request_headers) = self.send_once(&target, sample).await?;
+      let location = match response.status().as_u16() {
+        301 | 302 | 303 | 307 | 308 => response
+          .headers()
+          .get(reqwest::header::LOCATION)
+          .and_then(|l| l.to_str().ok())
+          .and_then(|l| target.join(l).ok()),
+        _ => None,
+      };
+      let Some(location) = location else {
+        return Ok((response, request_headers));
+      };
+      #[cfg(feature = "warc")]
+      if self.warc.is_some() && sample.is_none() {
+        let mut response = response;
+        let mut body = Vec::new();
+        self.receive(&mut response, None, &mut body).await?;
+        self
+          .record(Exchange {
+            url: target.clone(),
+            date,
+            latency: started.elapsed(),
+            version: response.version(),
+            status: response.status().as_u16(),
+            headers: response.headers().clone(),
+            request_headers,
+            body: body.into(),
+          })
+          .await;
+      }
+      target = location;
+    }
+    eyre::bail!("more than {} redirects from `{}`", REDIRECT_LIMIT, url)
+  }
+
+  /// Records `exchange` in the WARC file. Failing to record it does not fail
+  /// the request.
+  #[cfg(feature = "warc")]
+  pub(crate) async fn record(&self, exchange: Exchange) {
+    let Some(warc) = self.warc.clone() else {
+      return;
+    };
+    let url = exchange.url.clone();
+    let written = task::spawn_blocking(move || warc.record(&exchange));
+    if let Err(e) = written.await.map_err(eyre::Report::from).and_then(|r| r) {
+      warn!("Recording `{}` in the WARC file failed: {}", url, e);
+    }
+  }
+
+  /// Sends a single request for `url`, see [`Self::send`], and returns the
+  /// response with the headers sent: those of the client, unless the
+  /// request overrides them, and those of the request.
+  pub(crate) async fn send_once(
+    &self,
+    url: &Url,
+    sample: Option<usize>,
+  ) -> Result<(reqwest::Response, HeaderMap)> {
+    let mut request = self.client.get(url.clone());
+    request = match sample {
+      // A prefix of a compressed body cannot always be decompressed.
+      Some(bytes) => request
+        .header(reqwest::header::ACCEPT_ENCODING, "identity")
+        .header(
+          reqwest::header::RANGE,
+          format!("bytes=0-{}", bytes.saturating_sub(1)),
+        ),
+      None => request.header(reqwest::header::ACCEPT_ENCODING, "gzip, deflate"),
+    };
+    let request = request.build()?;
+    let mut request_headers = self.default_headers.clone();
+    for (name, value) in request.headers() {
+      request_headers.insert(name, value.clone());
+    }
+    #[cfg(feature = "dns-pinning")]
+    let retry = request.try_clone();
+    let response = match self.client.execute(request).await {
+      Ok(response) => response,
+      #[cfg(feature = "dns-pinning")]
+      Err(e) if e.is_connect() => {
+        let host = url.host_str().unwrap_or_default();
+        let retried = match (&self.dns, retry) {
+          (Some(dns), Some(retry)) => dns.fall_back(host, &e, self.client.execute(retry)).await,
+          _ => None,
+        };
+        match retried {
+          Some(Ok(response)) => response,
+          // The retry failing says no more than the request did.
+          _ => return Err(e.into()),
+        }
+      }
+      Err(e) => return Err(e.into()),
+    };
+    Ok((response, request_headers))
+  }
+
+  /// Writes the body of `response` to `out` as it arrives, or with `sample`
+  /// only that many bytes from its start, and returns its size.
+  pub(crate) async fn receive<W: tokio::io::AsyncWrite + Unpin>(
+    &self,
+    response: &mut reqwest::Response,
+    sample: Option<usize>,
+    out: &mut W,
+  ) -> Result<usize> {
+    let final_url = response.url().clone();
+    // Oversized bodies are given up on as soon as they are known to be, by
+    // their `Content-Length` or mid-stream, before they are held in memory.
+    let limit = self.max_body_size;
+    if let (None, Some(limit), Some(length)) = (sample, limit, response.content_length()) {
+      if length > limit as u64 {
+        eyre::bail!("body exceeds {} bytes", limit);
+      }
+    }
+    // Bodies trickling in below the floor are given up on at the end of the
+    // window they fell short in, even if no chunk arrives at all.
+    let mut floor = self
+      .min_transfer_rate
+      .map(|(rate, window)| TransferFloor::new(rate, window));
+    let mut received = 0;
+    loop {
+      let chunk = match &mut floor {
+        None => response.chunk().await?,
+        Some(floor) => {
+          let wait = floor.deadline.saturating_duration_since(Instant::now());
+          match tokio::time::timeout(wait, response.chunk()).await {
+            Ok(chunk) => chunk?,
+            Err(_) => {
+              floor.check(&final_url, received)?;
+              continue;
+            }
+          }
+        }
+      };
+      let Some(chunk) = chunk else {
+        break;
+      };
+      // Servers ignoring the range are cut off once there is enough.
+      if let Some(bytes) = sample {
+        let taken = chunk.len().min(bytes - received);
+        out.write_all(&chunk[..taken]).await?;
+        received += taken;
+        if received >= bytes {
+          break;
+        }
+        continue;
+      }
+      if let Some(limit) = limit.filter(|&l| received + chunk.len() > l) {
+        eyre::bail!("body exceeds {} bytes", limit);
+      }
+      out.write_all(&chunk).await?;
+      received += chunk.len();
+      if let Some(floor) = &mut floor {
+        if Instant::now() >= floor.deadline {
+          floor.check(&final_url, received)?;
+        }
+      }
+    }
+    out.flush().await?;
+    Ok(received)
+  }
+
+  /// Fetches `url`, or with `sample` only that many bytes from the start of
+  /// its body.
+  pub(crate) async fn get(&self, url: &Url, sample: Option<usize>) -> Result<FetchResponse> {
+    #[cfg(feature = "warc")]
+    let (date, started) = (SystemTime::now(), Instant::now());
+    let (mut response, request_headers) = self.send(url, sample).await?;
+    let final_url = response.url().clone();
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let mut raw = Vec::new();
+    self.receive(&mut response, sample, &mut raw).await?;
+    #[cfg(feature = "warc")]
+    if self.warc.is_some() && sample.is_none() {
+      self
+        .record(Exchange {
+          url: final_url.clone(),
+          date,
+          latency: started.elapsed(),
+          version: response.version(),
+          status,
+          headers: headers.clone(),
+          request_headers: request_headers.clone(),
+          body: raw.clone().into(),
+        })
+        .await;
+    }
+
+    let limit = self.max_body_size;
+    let encoding = content_encoding(&headers);
+    let body = match encoding.as_deref() {
+      None | Some("identity") => raw,
+      Some(_) => {
+        let url = final_url.clone();
+        let decoded = task::spawn_blocking(move || match sample {
+          // Encoded anyway, and decoded as far as the prefix goes.
+          Some(_) => Ok(decompress(&url, encoding.as_deref(), raw.clone(), limit).unwrap_or(raw)),
+          None => decompress(&url, encoding.as_deref(), raw, limit),
+        });
+        decoded.await??
+      }
+    };
+
+    Ok(FetchResponse {
+      url: final_url,
+      status,
+      headers,
+      request_headers,
+      body: body.into(),
+    })
+  }
+
+  /// Fetches `url`, streaming its body to `file` and then decoding it there.
+  pub(crate) async fn get_to_file(&self, url: &Url, file: &Path) -> Result<FetchResponse> {
+    let (mut response, request_headers) = self.send(url, None).await?;
+    let final_url = response.url().clone();
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let mut out = tokio::io::BufWriter::new(File::create(file).await?);
+    self.receive(&mut response, None, &mut out).await?;
+    drop(out);
+
+    if let Some(encoding) = content_encoding(&headers).filter(|e| e != "identity") {
+      let (url, file, limit) = (final_url.clone(), file.to_owned(), self.max_body_size);
+      task::spawn_blocking(move || decompress_file(&url, &encoding, &file, limit)).await??;
+    }
+
+    Ok(FetchResponse {
+      url: final_url,
+      status,
+      headers,
+      request_headers,
+      body: read_prefix(file).await?,
+    })
+  }
+}
+
+impl Fetcher for HttpFetcher {
+  fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>> {
+    self.get(url, None).boxed()
+  }
+
+  fn sample<'a>(&'a self, url: &'a Url, bytes: usize) -> BoxFuture<'a, Result<FetchResponse>> {
+    self.get(url, Some(bytes)).boxed()
+  }
+
+  /// Recorded bodies are held in memory, to be written to the WARC file as
+  /// received.
+  fn download<'a>(&'a self, url: &'a Url, file: &'a Path) -> BoxFuture<'a, Result<FetchResponse>> {
+    #[cfg(feature = "warc")]
+    if self.warc.is_some() {
+      return download_whole(self, url, file).boxed();
+    }
+    self.get_to_file(url, file).boxed()
+  }
+}
+
+/// The `Content-Encoding` of a response, lowercased.
+pub(crate) fn content_encoding(headers: &HeaderMap) -> Option<String> {
+  headers
+    .get(reqwest::header::CONTENT_ENCODING)
+    .and_then(|v| v.to_str().ok())
+    .map(|v| v.trim().to_ascii_lowercase())
+}
+
+/// Undoes the `Content-Encoding` of a body, refusing to inflate it beyond
+/// `max_size` or by more than [`DECOMPRESSION_RATIO_LIMIT`], which is what
+/// decompression bombs look like. `deflate` bodies are zlib streams, or raw
+/// deflate streams from servers that get it wrong.
+pub(crate) fn decompress(
+  url: &Url,
+  encoding: Option<&str>,
+  raw: Vec<u8>,
+  max_size: Option<usize>,
+) -> Result<Vec<u8>> {
+  let Some(encoding) = encoding.filter(|&e| e != "identity") else {
+    return Ok(raw);
+  };
+  let mut body = Vec::new();
+  inflate(
+    url,
+    encoding,
+    raw.as_slice(),
+    raw.len() as u64,
+    max_size,
+    &mut body,
+  )?;
+  Ok(body)
+}
+
+/// Undoes the `Content-Encoding` of a body downloaded to `file` in place,
+/// with the limits of [`decompress`].
+pub(crate) fn decompress_file(
+  url: &Url,
+  encoding: &str,
+  file: &Path,
+  max_size: Option<usize>,
+) -> Result<()> {
+  let raw = std::fs::File::open(file)?;
+  let raw_size = raw.metadata()?.len();
+  let decoded_path = file.with_extension("decoded");
+  let mut decoded = std::io::BufWriter::new(std::fs::File::create(&decoded_path)?);
+  let inflated = inflate(
+    url,
+    encoding,
+    std::io::BufReader::new(raw),
+    raw_size,
+    max_size,
+    &mut decoded,
+  )
+  .and_then(|_| Ok(decoded.flush()?));
+  drop(decoded);
+  match inflated {
+    Ok(()) => Ok(std::fs::rename(&decoded_path, file)?),
+    Err(e) => {
+      let _ = std::fs::remove_file(&decoded_path);
+      Err(e)
+    }
+  }
+}
+
+/// Decodes `raw`, of `raw_size` compressed bytes, into `out`, giving up
+/// beyond `max_size` or [`DECOMPRESSION_RATIO_LIMIT`].
+pub(crate) fn inflate(
+  url: &Url,
+  encoding: &str,
+  mut raw: impl std::io::BufRead,
+  raw_size: u64,
+  max_size: Option<usize>,
+  out: &mut impl std::io::Write,
+) -> Result<()> {
+  use std::io::Read as _;
+
+  let decoder: Box<dyn std::io::Read + '_> = match encoding {
+    "gzip" | "x-gzip" => Box::new(flate2::bufread::GzDecoder::new(raw)),
+    "deflate" if is_zlib_stream(raw.fill_buf()?) => {
+      Box::new(flate2::bufread::ZlibDecoder::new(raw))
+    }
+    "deflate" => Box::new(flate2::bufread::DeflateDecoder::new(raw)),
+    other => eyre::bail!("unsupported content encoding `{}`", other),
+  };
+
+  let limit = raw_size.saturating_mul(DECOMPRESSION_RATIO_LIMIT as u64);
+  let limit = max_size.map_or(limit, |max| limit.min(max as u64));
+  let inflated = std::io::copy(&mut decoder.take(limit + 1), out)?;
+  if inflated > limit {
+    warn!("suspected decompression bomb at `{}`", url);
+    eyre::bail!(
+      "suspected decompression bomb: {} compressed bytes inflate beyond {} bytes",
+      raw_size,
+      limit
+    );
+  }
+  Ok(())
+}
+
+/// Whether `raw` begins with a zlib header: the deflate method and a check
+/// value making the first two bytes a multiple of 31.
+pub(crate) fn is_zlib_stream(raw: &[u8]) -> bool {
+  match raw {
+    [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+    _ => false,
+  }
+}
+
+/// Serves `file://` URLs from the local file system, or, given a root, the
+/// paths of any URL from that directory. Directories serve their
+/// `index.html`.
+#[derive(Debug)]
+pub(crate) struct FileFetcher {
+  pub(crate) root: Option<PathBuf>,
+}
+
+impl Fetcher for FileFetcher {
+  fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>> {
+    async move {
+      let file_url = match &self.root {
+        Some(root) => Url::from_directory_path(std::path::absolute(root)?)
+          .map_err(|()| eyre::eyre!("invalid root `{}`", root.display()))?
+          .join(url.path().trim_start_matches('/'))?,
+        None => url.clone(),
+      };
+      let mut path = file_url
+        .to_file_path()
+        .map_err(|()| eyre::eyre!("`{}` is not a local file", file_url))?;
+      if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_dir()) {
+        path.push("index.html");
+      }
+
+      let mut headers = HeaderMap::new();
+      let (status, body) = match tokio::fs::read(&path).await {
+        Ok(body) => (200, body.into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (404, Bytes::new()),
+        Err(e) => return Err(e.into()),
+      };
+      if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("html" | "htm")
+      ) {
+        headers.insert(
+          reqwest::header::CONTENT_TYPE,
+          HeaderValue::from_static("text/html"),
+        );
+      }
+
+      Ok(FetchResponse {
+        url: url.clone(),
+        status,
+        headers,
+        request_headers: HeaderMap::new(),
+        body,
+      })
+    }
+    .boxed()
+  }
+}
+
+/// Serves responses added to it from memory, for testing crawls without a
+/// network. URLs that were not added are answered with 404.
+#[derive(Debug, Default)]
+pub struct MemoryFetcher {
+  pub(crate) responses: Mutex<HashMap<Url, (u16, HeaderMap, Bytes)>>,
+  pub(crate) requests: Mutex<Vec<Url>>,
+}
+
+impl MemoryFetcher {
+  /// Answers requests for `url` with `status`, `headers` and `body`.
+  pub fn insert(&self, url: Url, status: u16, headers: HeaderMap, body: impl Into<Bytes>) {
+    let response = (status, headers, body.into());
+    self.responses.lock().unwrap().insert(url, response);
+  }
+
+  /// Answers requests for `url` with the HTML page `html`.
+  pub fn insert_page(&self, url: Url, html: impl Into<String>) {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      reqwest::header::CONTENT_TYPE,
+      HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    self.insert(url, 200, headers, html.into());
+  }
+
+  /// Every URL fetched so far, in the order it was requested.
+  pub fn requests(&self) -> Vec<Url> {
+    self.requests.lock().unwrap().clone()
+  }
+}
+
+impl Fetcher for MemoryFetcher {
+  fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>> {
+    self.requests.lock().unwrap().push(url.clone());
+    let (status, headers, body) = match self.responses.lock().unwrap().get(url) {
+      Some(response) => response.clone(),
+      None => (404, HeaderMap::new(), Bytes::new()),
+    };
+    Box::pin(future::ready(Ok(FetchResponse {
+      url: url.clone(),
+      status,
+      headers,
+      request_headers: HeaderMap::new(),
+      body,
+    })))
+  }
+}
+
+/// Wraps a backend and serves repeated requests for the same resource from
+/// memory. A URL is cacheable if it matches one of the rules, and its query
+/// string is ignored for the lookup, so cache-busting variants of a shared
+/// asset are only downloaded once. Concurrent misses on the same key wait for
+/// the first request instead of all going to the network.
+#[derive(Debug)]
+pub(crate) struct CachingFetcher {
+  pub(crate) inner: Arc<dyn Fetcher>,
+  pub(crate) rules: Vec<Regex>,
+  pub(crate) cache: Mutex<ResponseCache>,
+  pub(crate) in_flight: Mutex<HashMap<Url, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ResponseCache {
+  pub(crate) responses: HashMap<Url, FetchResponse>,
+  pub(crate) bytes: usize,
+}
+
+impl CachingFetcher {
+  pub(crate) fn new(inner: Arc<dyn Fetcher>, rules: Vec<Regex>) -> Self {
+    Self {
+      inner,
+      rules,
+      cache: Default::default(),
+      in_flight: Default::default(),
+    }
+  }
+
+  pub(crate) fn cache_key(&self, url: &Url) -> Option<Url> {
+    if !self.rules.iter().any(|r| r.is_match(url.as_str())) {
+      return None;
+    }
+    let mut key = url.clone();
+    key.set_query(None);
+    key.set_fragment(None);
+    Some(key)
+  }
+}
+
+impl Fetcher for CachingFetcher {
+  fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>> {
+    async move {
+      let Some(key) = self.cache_key(url) else {
+        return self.inner.fetch(url).await;
+      };
+      let cached = || self.cache.lock().unwrap().responses.get(&key).cloned();
+      if let Some(cached) = cached() {
+        debug!("serving `{}` from the response cache", url);
+        return Ok(cached);
+      }
+
+      let flight = self
+        .in_flight
+        .lock()
+        .unwrap()
+        .entry(key.clone())
+        .or_default()
+        .clone();
+      let guard = flight.lock().await;
+      if let Some(cached) = cached() {
+        debug!("serving `{}` from the response cache", url);
+        return Ok(cached);
+      }
+
+      let response = self.inner.fetch(url).await;
+      if let Ok(response) = &response {
+        let mut cache = self.cache.lock().unwrap();
+        let size = response.body.len();
+        if response.status < 400 && cache.bytes + size <= RESPONSE_CACHE_LIMIT {
+          cache.bytes += size;
+          cache.responses.insert(key.clone(), response.clone());
+        }
+      }
+      drop(guard);
+      self.in_flight.lock().unwrap().remove(&key);
+      response
+    }
+    .boxed()
+  }
+
+  /// Samples are never cached.
+  fn sample<'a>(&'a self, url: &'a Url, bytes: usize) -> BoxFuture<'a, Result<FetchResponse>> {
+    self.inner.sample(url, bytes)
+  }
+
+  /// Cacheable resources go through the cache, and all others are streamed.
+  fn download<'a>(&'a self, url: &'a Url, file: &'a Path) -> BoxFuture<'a, Result<FetchResponse>> {
+    match self.cache_key(url) {
+      Some(_) => download_whole(self, url, file).boxed(),
+      None => self.inner.download(url, file),
+    }
+  }
+}
+
+/// An HTTP exchange as it went over the wire, for [`WarcWriter::record`].
+#[cfg(feature = "warc")]
+#[derive(Debug)]
+pub(crate) struct Exchange {
+  pub(crate) url: Url,
+  /// When the request was sent.
+  pub(crate) date: SystemTime,
+  /// How long it took until the whole body was received.
+  pub(crate) latency: Duration,
+  pub(crate) version: reqwest::Version,
+  pub(crate) status: u16,
+  pub(crate) headers: HeaderMap,
+  pub(crate) request_headers: HeaderMap,
+  /// The body as received, before undoing its `Content-Encoding`.
+  pub(crate) body: Bytes,
+}
+
+/// How exchanges in WARC files differ from what was sent over the wire.
+#[cfg(feature = "warc")]
+pub(crate) const WARC_DEVIATIONS: &str =
+  "Bodies are de-chunked, so Transfer-Encoding is dropped and \
+                               Content-Length is the length of the recorded body; HTTP/2 \
+                               messages are written in HTTP/1.1 syntax.";
+
+/// A WARC 1.1 file of request, response and metadata records, gzipped record
+/// by record if its name ends in `.gz`. Bodies are recorded as received but
+/// de-chunked, see [`WARC_DEVIATIONS`].
+#[cfg(feature = "warc")]
+#[derive(Debug)]
+pub(crate) struct WarcWriter {
+  pub(crate) file: Mutex<std::io::BufWriter<std::fs::File>>,
+  pub(crate) compress: bool,
+  pub(crate) responses: AtomicUsize,
+}
+
+#[cfg(feature = "warc")]
+impl WarcWriter {
+  /// Creates the file and writes its `warcinfo` record.
+  pub(crate) fn create(path: &Path) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let writer = Self {
+      file: Mutex::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+      compress: path.extension().is_some_and(|e| e == "gz"),
+      responses: AtomicUsize::new(0),
+    };
+    let info = format!(
+      "software: {}/{}\r\nformat: WARC File Format 1.1\r\nconformsTo: \
+       https://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/\r\n\
+       description: {}\r\n",
+      env!("CARGO_PKG_NAME"),
+      env!("CARGO_PKG_VERSION"),
+      WARC_DEVIATIONS,
+    );
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut file = writer.file.lock().unwrap();
+    writer.write_record(
+      &mut *file,
+      "warcinfo",
+      SystemTime::now(),
+      &[
+        ("WARC-Filename", file_name.into_owned()),
+        ("Content-Type", "application/warc-fields".to_owned()),
+      ],
+      &[info.as_bytes()],
+    )?;
+    file.flush()?;
+    drop(file);
+    Ok(writer)
+  }
+
+  /// Records `exchange` as response, request and metadata records.
+  pub(crate) fn record(&self, exchange: &Exchange) -> Result<()> {
+    let url = &exchange.url;
+    let version = http_version(exchange.version);
+    let mut request = format!(
+      "GET {} {}\r\nHost: {}\r\n",
+      &url[url::Position::BeforePath..url::Position::AfterQuery],
+      version,
+      &url[url::Position::BeforeHost..url::Position::AfterPort]
+    );
+    for (name, value) in &exchange.request_headers {
+      let _ = write!(
+        request,
+        "{}: {}\r\n",
+        name,
+        String::from_utf8_lossy(value.as_bytes())
+      );
+    }
+    request.push_str("\r\n");
+
+    let reason = reqwest::StatusCode::from_u16(exchange.status)
+      .ok()
+      .and_then(|status| status.canonical_reason());
+    let mut head = format!(
+      "{} {} {}\r\n",
+      version,
+      exchange.status,
+      reason.unwrap_or_default()
+    );
+    for (name, value) in &exchange.headers {
+      use reqwest::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+      if [CONTENT_LENGTH, TRANSFER_ENCODING].contains(name) {
+        continue;
+      }
+      let _ = write!(
+        head,
+        "{}: {}\r\n",
+        name,
+        String::from_utf8_lossy(value.as_bytes())
+      );
+    }
+    let _ = write!(head, "Content-Length: {}\r\n\r\n", exchange.body.len());
+
+    let metadata = format!("fetchTimeMs: {}\r\n", exchange.latency.as_millis());
+
+    let (target, date) = (("WARC-Target-URI", url.to_string()), exchange.date);
+    let mut file = self.file.lock().unwrap();
+    let response_id = self.write_record(
+      &mut *file,
+      "response",
+      date,
+      &[
+        target.clone(),
+        (
+          "Content-Type",
+          "application/http;msgtype=response".to_owned(),
+        ),
+        ("WARC-Payload-Digest", warc_digest(&[&exchange.body])),
+      ],
+      &[head.as_bytes(), &exchange.body],
+    )?;
+    self.write_record(
+      &mut *file,
+      "request",
+      date,
+      &[
+        target.clone(),
+        ("WARC-Concurrent-To", response_id.clone()),
+        (
+          "Content-Type",
+          "application/http;msgtype=request".to_owned(),
+        ),
+      ],
+      &[request.as_bytes()],
+    )?;
+    self.write_record(
+      &mut *file,
+      "metadata",
+      date,
+      &[
+        target,
+        ("WARC-Refers-To", response_id),
+        ("Content-Type", "application/warc-fields".to_owned()),
+      ],
+      &[metadata.as_bytes()],
+    )?;
+    file.flush()?;
+    self.responses.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Writes a record whose block is the concatenation of `block`, and returns
+  /// its ID.
+  pub(crate) fn write_record(
+    &self,
+    out: &mut impl std::io::Write,
+    record_type: &str,
+    date: SystemTime,
+    fields: &[(&str, String)],
+    block: &[&[u8]],
+  ) -> std::io::Result<String> {
+    let id = format!("<urn:uuid:{}>", uuid::Uuid::new_v4());
+    let mut header = format!(
+      "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: {}\r\nWARC-Date: {}\r\n",
+      record_type,
+      id,
+      humantime::format_rfc3339_seconds(date)
+    );
+    for (name, value) in fields {
+      let _ = write!(header, "{}: {}\r\n", name, value);
+    }
+    let _ = write!(header, "WARC-Block-Digest: {}\r\n", warc_digest(block));
+    let length: usize = block.iter().map(|part| part.len()).sum();
+    let _ = write!(header, "Content-Length: {}\r\n\r\n", length);
+
+    let write = |out: &mut dyn std::io::Write| -> std::io::Result<()> {
+      out.write_all(header.as_bytes())?;
+      for part in block {
+        out.write_all(part)?;
+      }
+      out.write_all(b"\r\n\r\n")
+    };
+    match self.compress {
+      true => {
+        let mut gzip = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+        write(&mut gzip)?;
+        gzip.finish()?;
+      }
+      false => write(out)?,
+    }
+    Ok(id)
+  }
+}
+
+/// The HTTP version as written in a message's start line; HTTP/2 and HTTP/3
+/// messages are written in HTTP/1.1 syntax (see `WARC_DEVIATIONS`).
+#[cfg(feature = "warc")]
+pub(crate) fn http_version(version: reqwest::Version) -> &'static str {
+  match version {
+    reqwest::Version::HTTP_09 => "HTTP/0.9",
+    reqwest::Version::HTTP_10 => "HTTP/1.0",
+    _ => "HTTP/1.1",
+  }
+}
+
+/// The SHA-256 digest of the concatenation of `parts`, as a WARC digest field.
+#[cfg(feature = "warc")]
+pub(crate) fn warc_digest(parts: &[&[u8]]) -> String {
+  use sha2::Digest as _;
+  let mut hasher = sha2::Sha256::new();
+  for part in parts {
+    hasher.update(part);
+  }
+  let digest: String = hasher
+    .finalize()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect();
+  format!("sha256:{}", digest)
+}
+
+/// The backends of a crawl: a default plus overrides per URL scheme and per
+/// host, where host overrides take precedence.
+#[derive(Debug)]
+pub(crate) struct FetcherRegistry {
+  pub(crate) default: Arc<dyn Fetcher>,
+  pub(crate) schemes: HashMap<String, Arc<dyn Fetcher>>,
+  pub(crate) hosts: HashMap<String, Arc<dyn Fetcher>>,
+}
+
+impl FetcherRegistry {
+  pub(crate) fn new(default: Arc<dyn Fetcher>) -> Self {
+    Self {
+      default,
+      schemes: Default::default(),
+      hosts: Default::default(),
+    }
+  }
+
+  pub(crate) fn for_url(&self, url: &Url) -> Arc<dyn Fetcher> {
+    url
+      .host_str()
+      .and_then(|host| self.hosts.get(host))
+      .or_else(|| self.schemes.get(url.scheme()))
+      .unwrap_or(&self.default)
+      .clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug, Default)]
+  struct CountingFetcher {
+    requests: AtomicUsize,
+  }
+
+  impl Fetcher for CountingFetcher {
+    fn fetch<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, Result<FetchResponse>> {
+      async move {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        Ok(FetchResponse {
+          url: url.clone(),
+          status: 200,
+          headers: HeaderMap::new(),
+          request_headers: HeaderMap::new(),
+          body: Bytes::from_static(b"body"),
+        })
+      }
+      .boxed()
+    }
+  }
+
+  #[tokio::test]
+  async fn caching_fetcher_coalesces_concurrent_misses() {
+    let inner = Arc::new(CountingFetcher::default());
+    let fetcher = CachingFetcher::new(inner.clone(), vec![Regex::new(r"\.css$").unwrap()]);
+    let url = Url::parse("https://a.example/site.css").unwrap();
+    let responses = future::join_all((0..4).map(|_| fetcher.fetch(&url))).await;
+    assert!(responses.iter().all(|r| r.is_ok()));
+    assert_eq!(inner.requests.load(Ordering::Relaxed), 1);
+    assert_eq!(fetcher.cache.lock().unwrap().bytes, 4);
+  }
+
+  #[test]
+  fn decompress_accepts_zlib_and_raw_deflate_but_not_bombs() {
+    use flate2::{write, Compression};
+
+    let url = Url::parse("https://a.example/").unwrap();
+    let body = b"hello hello hello".to_vec();
+    let mut zlib = write::ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&body).unwrap();
+    let mut raw = write::DeflateEncoder::new(Vec::new(), Compression::default());
+    raw.write_all(&body).unwrap();
+    for encoded in [zlib.finish().unwrap(), raw.finish().unwrap()] {
+      assert_eq!(
+        decompress(&url, Some("deflate"), encoded, None).unwrap(),
+        body
+      );
+    }
+
+    let mut bomb = write::GzEncoder::new(Vec::new(), Compression::best());
+    bomb.write_all(&vec![0; 1 << 20]).unwrap();
+    assert!(decompress(&url, Some("gzip"), bomb.finish().unwrap(), None).is_err());
+  }
+
+  #[tokio::test]
+  async fn decompress_file_decodes_in_place() {
+    use flate2::{write, Compression};
+
+    let url = Url::parse("https://a.example/").unwrap();
+    let dir = std::env::temp_dir().join(format!("crawler-test-{}", std::process::id()));
+    let spool = SpoolFile::create(&dir).await.unwrap();
+    let body = b"hello hello hello".repeat(100);
+    let mut gzip = write::GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(&body).unwrap();
+    std::fs::write(spool.path(), gzip.finish().unwrap()).unwrap();
+
+    decompress_file(&url, "gzip", spool.path(), None).unwrap();
+    assert_eq!(std::fs::read(spool.path()).unwrap(), body);
+    assert_eq!(&read_prefix(spool.path()).await.unwrap()[..], &body[..]);
+    assert_eq!(
+      file_content_hash(spool.path()).await.unwrap(),
+      Deduplicator::content_hash(&body)
+    );
+    let path = spool.path().to_owned();
+    drop(spool);
+    assert!(!path.exists());
+    let _ = std::fs::remove_dir(&dir);
+  }
+
+  #[cfg(feature = "warc")]
+  #[test]
+  fn warc_records_raw_bodies_and_versions_with_digests() {
+    let path = std::env::temp_dir().join(format!("crawler-warc-{}.warc", std::process::id()));
+    let warc = WarcWriter::create(&path).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      reqwest::header::CONTENT_ENCODING,
+      HeaderValue::from_static("gzip"),
+    );
+    warc
+      .record(&Exchange {
+        url: Url::parse("https://a.example/b").unwrap(),
+        date: SystemTime::now(),
+        latency: Duration::ZERO,
+        version: reqwest::Version::HTTP_10,
+        status: 200,
+        headers,
+        request_headers: HeaderMap::new(),
+        body: Bytes::from_static(b"raw"),
+      })
+      .unwrap();
+    drop(warc);
+
+    let written = String::from_utf8(std::fs::read(&path).unwrap()).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(written.contains("GET /b HTTP/1.0\r\nHost: a.example\r\n"));
+    assert!(
+      written.contains("HTTP/1.0 200 OK\r\ncontent-encoding: gzip\r\nContent-Length: 3\r\n\r\nraw")
+    );
+    assert!(written.contains(&format!(
+      "WARC-Payload-Digest: sha256:{}",
+      sha256_hex(b"raw")
+    )));
+    // The warcinfo record and the three of the exchange.
+    assert_eq!(written.matches("WARC-Block-Digest: sha256:").count(), 4);
+  }
+
+  #[cfg(feature = "warc")]
+  #[test]
+  fn http2_start_lines_use_http11_syntax() {
+    assert_eq!(http_version(reqwest::Version::HTTP_2), "HTTP/1.1");
+    assert_eq!(http_version(reqwest::Version::HTTP_3), "HTTP/1.1");
+    assert_eq!(http_version(reqwest::Version::HTTP_10), "HTTP/1.0");
+  }
+
+  #[test]
+  fn transfer_rate_floor_is_opt_in() {
+    assert_eq!(DispatcherBuilder::default().min_transfer_rate, None);
+    let window = DEFAULT_MIN_TRANSFER_WINDOW;
+    let builder = DispatcherBuilder::default().min_transfer_rate(1024, window);
+    assert_eq!(builder.min_transfer_rate, Some((1024, window)));
+    let builder = builder.min_transfer_rate(0, window);
+    assert_eq!(builder.min_transfer_rate, None);
+  }
+
+  #[test]
+  fn retry_after_falls_back_on_empty_or_malformed_values() {
+    let response = |status: u16, retry_after: Option<&'static str>| {
+      let mut headers = HeaderMap::new();
+      if let Some(value) = retry_after {
+        headers.insert(
+          reqwest::header::RETRY_AFTER,
+          HeaderValue::from_static(value),
+        );
+      }
+      FetchResponse {
+        url: Url::parse("https://a.example/").unwrap(),
+        status,
+        headers,
+        request_headers: HeaderMap::new(),
+        body: Bytes::new(),
+      }
+    };
+    let seconds = |s| Some(Duration::from_secs(s));
+    assert_eq!(response(429, Some("120")).retry_after(), seconds(120));
+    assert_eq!(
+      response(429, Some("")).retry_after(),
+      Some(DEFAULT_RETRY_AFTER)
+    );
+    assert_eq!(
+      response(429, Some("soon")).retry_after(),
+      Some(DEFAULT_RETRY_AFTER)
+    );
+    assert_eq!(response(429, None).retry_after(), Some(DEFAULT_RETRY_AFTER));
+    assert_eq!(
+      response(429, Some("99999999999999999999")).retry_after(),
+      Some(RETRY_AFTER_LIMIT)
+    );
+    assert_eq!(
+      response(503, Some("Sun, 06 Nov 1994 08:49:37 GMT")).retry_after(),
+      seconds(0)
+    );
+    assert_eq!(response(503, Some("")).retry_after(), None);
+    assert!(response(503, Some("")).is_maintenance());
+    assert!(!response(503, Some("5")).is_maintenance());
+    assert_eq!(response(500, Some("5")).retry_after(), None);
+  }
+}
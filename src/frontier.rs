@@ -0,0 +1,2276 @@
+//! What is left to crawl and what may be crawled: findings, the frontier,
+//! scope and URL rewriting, robots.txt and the pacing of requests to hosts.
+
+use super::*;
+
+/// A page to crawl, at its depth from the seeds, or an asset to download.
+#[derive(Debug, Clone)]
+pub enum Finding {
+  Page(Url, u8),
+  Image(Url),
+  /// From `<link rel=stylesheet>` or the `@import` of a stylesheet.
+  Stylesheet(Url),
+  Script(Url),
+  /// From a stylesheet or `<link rel=preload as=font>`.
+  Font(Url),
+  /// Favicons and touch icons, or `/favicon.ico` for pages that name none.
+  Icon(Url),
+  /// From `<video>`, `<audio>` and their `<source>`s.
+  Media(Url),
+}
+
+/// Findings are identified by kind and URL only, so that a page discovered
+/// again at another depth still counts as already seen. URLs that only differ
+/// in equivalent percent-encodings are the same, see [`equivalence_key`].
+impl PartialEq for Finding {
+  fn eq(&self, other: &Self) -> bool {
+    self.kind() == other.kind() && equivalence_key(self.url()) == equivalence_key(other.url())
+  }
+}
+
+impl Eq for Finding {}
+
+impl Hash for Finding {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.kind().hash(state);
+    equivalence_key(self.url()).hash(state);
+  }
+}
+
+/// `url` with equivalent percent-encodings spelled the same: unreserved
+/// characters decoded (`%7Euser` is `~user`), other escapes in upper case and,
+/// in the query, `+` written as `%20`. Only used to tell URLs apart; the URL
+/// that is requested stays as it was found.
+pub(crate) fn equivalence_key(url: &Url) -> std::borrow::Cow<'_, str> {
+  let serialized = url.as_str();
+  if !serialized.contains(['%', '+']) {
+    return serialized.into();
+  }
+  let query = url[..url::Position::BeforeQuery].len();
+  let bytes = serialized.as_bytes();
+  let mut key = String::with_capacity(serialized.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .filter(|_| bytes[i] == b'%')
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match (escaped, bytes[i]) {
+      (Some(byte), _) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+        key.push(byte as char);
+        i += 3;
+      }
+      (Some(byte), _) => {
+        let _ = write!(key, "%{:02X}", byte);
+        i += 3;
+      }
+      (None, b'+') if i >= query => {
+        key.push_str("%20");
+        i += 1;
+      }
+      (None, _) => {
+        let start = i;
+        i += 1;
+        while i < bytes.len() && !matches!(bytes[i], b'%' | b'+') {
+          i += 1;
+        }
+        key.push_str(&serialized[start..i]);
+      }
+    }
+  }
+  key.into()
+}
+
+impl Finding {
+  pub fn url(&self) -> &Url {
+    match self {
+      Finding::Page(url, _)
+      | Finding::Image(url)
+      | Finding::Stylesheet(url)
+      | Finding::Script(url)
+      | Finding::Font(url)
+      | Finding::Icon(url)
+      | Finding::Media(url) => url,
+    }
+  }
+
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Finding::Page(..) => "page",
+      Finding::Image(..) => "image",
+      Finding::Stylesheet(..) => "stylesheet",
+      Finding::Script(..) => "script",
+      Finding::Font(..) => "font",
+      Finding::Icon(..) => "icon",
+      Finding::Media(..) => "media",
+    }
+  }
+
+  /// The asset of `kind` at `url`, the inverse of [`Finding::kind`].
+  pub(crate) fn asset(kind: &str, url: Url) -> Option<Self> {
+    match kind {
+      "image" => Some(Finding::Image(url)),
+      "stylesheet" => Some(Finding::Stylesheet(url)),
+      "script" => Some(Finding::Script(url)),
+      "font" => Some(Finding::Font(url)),
+      "icon" => Some(Finding::Icon(url)),
+      "media" => Some(Finding::Media(url)),
+      _ => None,
+    }
+  }
+
+  /// The class of the asset, or `None` for pages.
+  pub fn asset_class(&self) -> Option<AssetClass> {
+    match self {
+      Finding::Page(..) => None,
+      Finding::Image(..) => Some(AssetClass::Images),
+      Finding::Stylesheet(..) => Some(AssetClass::Stylesheets),
+      Finding::Script(..) => Some(AssetClass::Scripts),
+      Finding::Font(..) => Some(AssetClass::Fonts),
+      Finding::Icon(..) => Some(AssetClass::Icons),
+      Finding::Media(..) => Some(AssetClass::Media),
+    }
+  }
+
+  pub(crate) fn is_asset(&self) -> bool {
+    !matches!(self, Finding::Page(..))
+  }
+
+  /// The depth of a page; assets have none.
+  pub(crate) fn depth(&self) -> Option<u8> {
+    match self {
+      Finding::Page(_, depth) => Some(*depth),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn url_mut(&mut self) -> &mut Url {
+    match self {
+      Finding::Page(url, _)
+      | Finding::Image(url)
+      | Finding::Stylesheet(url)
+      | Finding::Script(url)
+      | Finding::Font(url)
+      | Finding::Icon(url)
+      | Finding::Media(url) => url,
+    }
+  }
+
+  /// Formats the finding as one line of a plan file.
+  pub(crate) fn to_plan_line(&self) -> String {
+    match self {
+      Finding::Page(url, depth) => format!("page {} {}", depth, url),
+      asset => format!("{} {}", asset.kind(), asset.url()),
+    }
+  }
+
+  /// Parses a line written by [`Finding::to_plan_line`].
+  pub(crate) fn from_plan_line(line: &str) -> Result<Self> {
+    let mut fields = line.split_whitespace();
+    let finding = match (fields.next(), fields.next(), fields.next()) {
+      (Some("page"), Some(depth), Some(url)) => Finding::Page(Url::parse(url)?, depth.parse()?),
+      (Some(kind), Some(url), None) => match Finding::asset(kind, Url::parse(url)?) {
+        Some(asset) => asset,
+        None => eyre::bail!("malformed plan line `{}`", line),
+      },
+      _ => eyre::bail!("malformed plan line `{}`", line),
+    };
+    Ok(finding)
+  }
+}
+
+/// The state of a crawl, written periodically with
+/// [`DispatcherBuilder::checkpoint`] so that an interrupted crawl can be
+/// resumed from it with [`DispatcherBuilder::resume`]. Reports of a resumed
+/// crawl only cover the pages crawled after resuming.
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+  pub(crate) seeds: Vec<Url>,
+  /// Findings that were queued, in flight or deferred.
+  pub(crate) frontier: Vec<Finding>,
+  pub(crate) archive: Vec<Finding>,
+  pub(crate) host_visits: Vec<(Host, u32)>,
+  /// Every robots.txt fetched, by origin.
+  pub(crate) robots: Vec<(String, RobotsFile)>,
+}
+
+impl Checkpoint {
+  pub fn read(path: &Path) -> Result<Self> {
+    let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    Self::upgrade(&mut json)
+      .map_err(|e| eyre::eyre!("cannot resume from `{}`: {}", path.display(), e))?;
+    let findings = |field: &str| -> Result<Vec<Finding>> {
+      let lines = json[field]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+      lines
+        .iter()
+        .filter_map(serde_json::Value::as_str)
+        .map(Finding::from_plan_line)
+        .collect()
+    };
+    let seeds = json["seeds"]
+      .as_array()
+      .map(Vec::as_slice)
+      .unwrap_or_default();
+    let host_visits = json["host_visits"].as_object();
+    let robots = json["robots"].as_object();
+    Ok(Self {
+      seeds: seeds
+        .iter()
+        .filter_map(serde_json::Value::as_str)
+        .map(Url::parse)
+        .collect::<Result<_, _>>()?,
+      frontier: findings("frontier")?,
+      archive: findings("archive")?,
+      host_visits: host_visits
+        .into_iter()
+        .flatten()
+        .map(|(host, visits)| Ok((Host::parse(host)?, visits.as_u64().unwrap_or(0) as u32)))
+        .collect::<Result<_>>()?,
+      robots: robots
+        .into_iter()
+        .flatten()
+        .map(|(origin, robots)| (origin.clone(), RobotsFile::from_json(robots)))
+        .collect(),
+    })
+  }
+
+  /// Upgrades the JSON of a checkpoint to the current format, returning the
+  /// version it was written in. Checkpoints from before there were versions
+  /// are version 0. Fields a version does not know are ignored, so a newer
+  /// crawler only has to bump the version when older ones would misread it.
+  pub(crate) fn upgrade(json: &mut serde_json::Value) -> Result<u64> {
+    let version = json["version"].as_u64().unwrap_or(0);
+    if version > CHECKPOINT_VERSION {
+      eyre::bail!(
+        "the checkpoint is of version {}, but this crawler only reads up to version {}",
+        version,
+        CHECKPOINT_VERSION
+      );
+    }
+    // Version 1 only added the version itself.
+    json["version"] = CHECKPOINT_VERSION.into();
+    Ok(version)
+  }
+
+  /// Rewrites the checkpoint at `path` in the current format, returning the
+  /// version it was written in.
+  pub fn migrate(path: &Path) -> Result<u64> {
+    let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let version = Self::upgrade(&mut json)
+      .map_err(|e| eyre::eyre!("cannot migrate `{}`: {}", path.display(), e))?;
+    if version < CHECKPOINT_VERSION {
+      let mut partial = path.as_os_str().to_owned();
+      partial.push(".partial");
+      std::fs::write(&partial, json.to_string())?;
+      std::fs::rename(&partial, path)?;
+    }
+    Ok(version)
+  }
+
+  pub(crate) fn to_json(&self) -> serde_json::Value {
+    let lines = |findings: &[Finding]| -> Vec<String> {
+      findings.iter().map(Finding::to_plan_line).collect()
+    };
+    let host_visits: serde_json::Map<_, _> = self
+      .host_visits
+      .iter()
+      .map(|(host, visits)| (host.to_string(), (*visits).into()))
+      .collect();
+    let robots: serde_json::Map<_, _> = self
+      .robots
+      .iter()
+      .map(|(origin, robots)| (origin.clone(), robots.to_json()))
+      .collect();
+    serde_json::json!({
+      "version": CHECKPOINT_VERSION,
+      "seeds": self.seeds.iter().map(Url::as_str).collect::<Vec<_>>(),
+      "frontier": lines(&self.frontier),
+      "archive": lines(&self.archive),
+      "host_visits": host_visits,
+      "robots": robots,
+    })
+  }
+}
+
+/// A `Strict-Transport-Security` policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StrictTransportSecurity {
+  pub(crate) max_age: u64,
+  pub(crate) include_subdomains: bool,
+}
+
+impl StrictTransportSecurity {
+  /// Parses a header value, which is invalid without a `max-age` or with a
+  /// directive given twice.
+  pub(crate) fn parse(value: &str) -> Option<Self> {
+    let (mut max_age, mut include_subdomains) = (None, false);
+    let mut seen = HashSet::new();
+    for directive in value.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+      let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+      let name = name.trim().to_ascii_lowercase();
+      let value = value.trim().trim_matches('"');
+      if !seen.insert(name.clone()) {
+        return None;
+      }
+      match name.as_str() {
+        "max-age" => max_age = Some(value.parse().ok()?),
+        "includesubdomains" => include_subdomains = true,
+        _ => {}
+      }
+    }
+    Some(Self {
+      max_age: max_age?,
+      include_subdomains,
+    })
+  }
+}
+
+/// Hosts known to be served over HTTPS, and how many discovered `http://`
+/// links have been upgraded for each of them.
+#[derive(Debug, Default)]
+pub(crate) struct HttpsUpgrades {
+  pub(crate) hosts: HashSet<Host>,
+  /// Domains whose subdomains are known to be served over HTTPS too.
+  pub(crate) domains: HashSet<String>,
+  pub(crate) upgraded: BTreeMap<String, u32>,
+}
+
+impl HttpsUpgrades {
+  /// Records the policy `hsts` of the host `url` was served from. A
+  /// `max-age` of zero makes the host forget it.
+  pub(crate) fn record(&mut self, url: &Url, hsts: StrictTransportSecurity) {
+    let Some(host) = url.host().map(|h| h.to_owned()) else {
+      return;
+    };
+    let domain = url.domain().map(str::to_owned);
+    if hsts.max_age == 0 {
+      self.hosts.remove(&host);
+      if let Some(domain) = domain {
+        self.domains.remove(&domain);
+      }
+      return;
+    }
+    self.hosts.insert(host);
+    if let Some(domain) = domain.filter(|_| hsts.include_subdomains) {
+      self.domains.insert(domain);
+    }
+  }
+
+  /// Whether `host` or, with `includeSubDomains`, a superdomain of it is
+  /// known to be served over HTTPS.
+  pub(crate) fn covers(&self, host: &Host) -> bool {
+    if self.hosts.contains(host) {
+      return true;
+    }
+    let Host::Domain(domain) = host else {
+      return false;
+    };
+    let mut rest = domain.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+      if self.domains.contains(parent) {
+        return true;
+      }
+      rest = parent;
+    }
+    false
+  }
+
+  pub(crate) fn upgrade(&mut self, url: &mut Url) {
+    if url.scheme() != "http" {
+      return;
+    }
+    let Some(host) = url.host().map(|h| h.to_owned()) else {
+      return;
+    };
+    if !self.covers(&host) {
+      return;
+    }
+
+    let default_port = url.port().is_none() || url.port() == Some(80);
+    if url.set_scheme("https").is_err() {
+      return;
+    }
+    if default_port {
+      let _ = url.set_port(None);
+    }
+    *self.upgraded.entry(host.to_string()).or_default() += 1;
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.upgraded.is_empty() {
+      return;
+    }
+    out.push_str("## HTTPS upgrades\n\n");
+    for (host, count) in &self.upgraded {
+      let _ = writeln!(out, "- {}: {} link(s)", host, count);
+    }
+    out.push('\n');
+  }
+}
+
+/// Per-host novelty of crawled pages. Hosts whose recent pages mostly share a
+/// URL template and hardly ever serve new content, like endless calendars or
+/// infinite scroll, are considered traps and no longer expanded.
+#[derive(Debug, Default)]
+pub(crate) struct TrapDetector {
+  pub(crate) hosts: HashMap<String, HostNovelty>,
+  pub(crate) traps: BTreeMap<String, Trap>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HostNovelty {
+  pub(crate) pages: usize,
+  pub(crate) text_hashes: HashSet<u64>,
+  /// Template and content novelty of the most recently crawled pages.
+  pub(crate) window: VecDeque<(String, bool)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Trap {
+  pub(crate) pages: usize,
+  pub(crate) template: String,
+  pub(crate) template_share: f64,
+  pub(crate) novelty: f64,
+}
+
+impl TrapDetector {
+  /// Records a crawled page with the `text_hash` of its extraction.
+  pub(crate) fn record(&mut self, page: &Url, text_hash: u64) {
+    let Some(host) = page.host_str() else {
+      return;
+    };
+    if self.traps.contains_key(host) {
+      return;
+    }
+    let novelty = self.hosts.entry(host.to_owned()).or_default();
+    novelty.pages += 1;
+    if novelty.window.len() == TRAP_WINDOW {
+      novelty.window.pop_front();
+    }
+    let new = novelty.text_hashes.insert(text_hash);
+    novelty.window.push_back((url_template(page), new));
+    if novelty.window.len() < TRAP_WINDOW {
+      return;
+    }
+
+    let fresh = novelty.window.iter().filter(|(_, new)| *new).count() as f64 / TRAP_WINDOW as f64;
+    let mut templates: HashMap<&str, usize> = HashMap::new();
+    for (template, _) in &novelty.window {
+      *templates.entry(template).or_default() += 1;
+    }
+    let (template, count) = templates
+      .into_iter()
+      .max_by_key(|&(_, count)| count)
+      .expect("window is full");
+    let template_share = count as f64 / TRAP_WINDOW as f64;
+    if fresh < TRAP_NOVELTY_THRESHOLD && template_share >= TRAP_TEMPLATE_SHARE {
+      warn!(
+        "no longer expanding `{}`: recent pages repeat content under `{}`",
+        host, template
+      );
+      let trap = Trap {
+        pages: novelty.pages,
+        template: template.to_owned(),
+        template_share,
+        novelty: fresh,
+      };
+      self.traps.insert(host.to_owned(), trap);
+    }
+  }
+
+  pub(crate) fn is_trapped(&self, url: &Url) -> bool {
+    url.host_str().is_some_and(|h| self.traps.contains_key(h))
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.traps.is_empty() {
+      return;
+    }
+    out.push_str("## Crawler traps\n\n");
+    for (host, trap) in &self.traps {
+      let _ = writeln!(
+        out,
+        "- {}: stopped expanding after {} pages; of the last {}, {:.0}% had new content and {:.0}% matched `{}`",
+        host,
+        trap.pages,
+        TRAP_WINDOW,
+        trap.novelty * 100.0,
+        trap.template_share * 100.0,
+        trap.template
+      );
+    }
+    out.push('\n');
+  }
+}
+
+pub(crate) static DIGITS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[0-9]+").unwrap());
+
+/// Which `nofollow` hints are respected: `rel="nofollow"` on links, and
+/// `nofollow` in the robots meta tag or `X-Robots-Tag` header of a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum NofollowPolicy {
+  /// Follow neither `rel="nofollow"` links nor any link of a `nofollow` page.
+  #[default]
+  Respect,
+  /// Also follow no link of a `noindex` page.
+  Noindex,
+  /// Follow every link. This is a politeness override.
+  Ignore,
+}
+
+impl NofollowPolicy {
+  /// Whether the links of a page with these robots directives are not
+  /// followed.
+  pub(crate) fn stops_at(
+    self,
+    x_robots_tag: &Option<String>,
+    meta_robots: &Option<String>,
+  ) -> bool {
+    let directive = match self {
+      NofollowPolicy::Respect => "nofollow",
+      NofollowPolicy::Noindex => "noindex",
+      NofollowPolicy::Ignore => return false,
+    };
+    [x_robots_tag, meta_robots].into_iter().any(|directives| {
+      has_robots_directive(directives, "nofollow") || has_robots_directive(directives, directive)
+    })
+  }
+}
+
+/// Whether comma-separated robots `directives` include `directive`, which
+/// `none` stands for as well.
+pub(crate) fn has_robots_directive(directives: &Option<String>, directive: &str) -> bool {
+  directives
+    .iter()
+    .flat_map(|d| d.split(','))
+    .any(|d| matches!(d.trim(), "none") || d.trim() == directive)
+}
+
+/// The directives of `X-Robots-Tag` header values that apply to the crawler
+/// sending `user_agent`: those without an `<agent>:` prefix and those whose
+/// prefix is its product token.
+pub(crate) fn x_robots_directives(values: &[String], user_agent: &str) -> Option<String> {
+  // Directives that take a value after a colon of their own.
+  const VALUED: &[&str] = &[
+    "unavailable_after",
+    "max-snippet",
+    "max-image-preview",
+    "max-video-preview",
+  ];
+  let agent = product_token(user_agent);
+  let directives: Vec<&str> = values
+    .iter()
+    .filter_map(|value| match value.split_once(':') {
+      Some((prefix, directives)) if !prefix.contains(',') && !VALUED.contains(&prefix.trim()) => {
+        (product_token(prefix) == agent).then_some(directives.trim())
+      }
+      _ => Some(value.as_str()),
+    })
+    .collect();
+  (!directives.is_empty()).then(|| directives.join(", "))
+}
+
+/// How considerate the crawler is towards the hosts it visits. The defaults
+/// identify the crawler, respect robots.txt and space out requests to the
+/// same host; anything less is an override that is logged.
+#[derive(Debug, Clone)]
+pub struct Politeness {
+  pub(crate) user_agent: String,
+  pub(crate) respect_robots: bool,
+  pub(crate) nofollow: NofollowPolicy,
+  /// Minimum time between two requests to the same host.
+  pub(crate) delay: Duration,
+  pub(crate) overrides: Vec<String>,
+}
+
+impl Politeness {
+  /// Politeness with `user_agent`, or else the default one announcing
+  /// `contact`. Everything less polite than the defaults is logged as an
+  /// override.
+  pub fn new(
+    user_agent: Option<String>,
+    contact: &str,
+    respect_robots: bool,
+    delay: Duration,
+  ) -> Self {
+    let mut overrides = Vec::new();
+    let user_agent = match user_agent {
+      Some(user_agent) => {
+        overrides.push(format!("custom user agent `{}`", user_agent));
+        user_agent
+      }
+      None => format!(
+        "{}/{} (+{})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        contact
+      ),
+    };
+    if !respect_robots {
+      overrides.push("robots.txt ignored".to_owned());
+    }
+    if delay < Duration::from_millis(DEFAULT_POLITENESS_DELAY_MS) {
+      overrides.push(format!(
+        "per-host delay lowered to {} ms",
+        delay.as_millis()
+      ));
+    }
+    for politeness_override in &overrides {
+      warn!("politeness override: {}", politeness_override);
+    }
+
+    Self {
+      user_agent,
+      respect_robots,
+      nofollow: NofollowPolicy::default(),
+      delay,
+      overrides,
+    }
+  }
+
+  /// Which `nofollow` hints are respected. Ignoring them is an override that
+  /// is logged.
+  pub fn nofollow(mut self, policy: NofollowPolicy) -> Self {
+    if policy == NofollowPolicy::Ignore {
+      let politeness_override = "nofollow hints ignored".to_owned();
+      warn!("politeness override: {}", politeness_override);
+      self.overrides.push(politeness_override);
+    }
+    self.nofollow = policy;
+    self
+  }
+}
+
+impl Default for Politeness {
+  fn default() -> Self {
+    Self::new(
+      None,
+      DEFAULT_CONTACT_URL,
+      true,
+      Duration::from_millis(DEFAULT_POLITENESS_DELAY_MS),
+    )
+  }
+}
+
+/// When the next request to every host may be sent.
+#[derive(Debug, Default)]
+pub(crate) struct HostSchedule {
+  pub(crate) next: HashMap<Host, Instant>,
+}
+
+impl HostSchedule {
+  /// Reserves the earliest slot for a request to the host of `url`, keeping
+  /// `delay` between consecutive requests to it.
+  pub(crate) fn reserve(&mut self, url: &Url, delay: Duration) -> Instant {
+    let now = Instant::now();
+    let Some(host) = url.host() else {
+      return now;
+    };
+    let next = self.next.entry(host.to_owned()).or_insert(now);
+    let slot = (*next).max(now);
+    *next = slot + delay;
+    slot
+  }
+
+  /// Reserves nothing for `host` for `length`, on top of what is reserved
+  /// already, since [`HostPauses`] pushes that back too.
+  pub(crate) fn pause(&mut self, host: Host, length: Duration) {
+    let now = Instant::now();
+    let next = self.next.entry(host).or_insert(now);
+    *next = (*next).max(now) + length;
+  }
+
+  /// How long the slots reserved for `host` so far keep it busy.
+  pub(crate) fn busy_for(&self, host: &Host) -> Duration {
+    self
+      .next
+      .get(host)
+      .map(|next| next.saturating_duration_since(Instant::now()))
+      .unwrap_or_default()
+  }
+}
+
+/// Caps on the number of requests in flight, overall and to every host.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimits {
+  pub(crate) global: Arc<Semaphore>,
+  pub(crate) per_host: usize,
+  pub(crate) hosts: HashMap<Host, Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimits {
+  pub(crate) fn new(global: usize, per_host: usize) -> Self {
+    Self {
+      global: Arc::new(Semaphore::new(global.max(1))),
+      per_host: per_host.max(1),
+      hosts: HashMap::new(),
+    }
+  }
+
+  /// Waits until a request to `url` fits within the limits. The request may
+  /// be sent for as long as the returned permits are held.
+  pub(crate) fn acquire(&mut self, url: &Url) -> impl Future<Output = Vec<OwnedSemaphorePermit>> {
+    let mut semaphores = vec![];
+    if let Some(host) = url.host() {
+      let per_host = self.per_host;
+      let host = self
+        .hosts
+        .entry(host.to_owned())
+        .or_insert_with(|| Arc::new(Semaphore::new(per_host)));
+      semaphores.push(host.clone());
+    }
+    semaphores.push(self.global.clone());
+    async move {
+      let mut permits = Vec::with_capacity(semaphores.len());
+      for semaphore in semaphores {
+        // The semaphores are never closed.
+        permits.push(semaphore.acquire_owned().await.unwrap());
+      }
+      permits
+    }
+  }
+}
+
+/// Everything discovered so far, what is left to crawl and how often every
+/// host was visited. Scheduling policy, like seed interleaving, depth pacing
+/// and politeness, stays with the [`Dispatcher`], which offers findings here,
+/// takes the next ones to dispatch and completes them once they are done, so
+/// a frontier kept on disk or in a shared store only has to keep this state.
+/// Every method takes `&self`; implementations do their own locking.
+pub trait Frontier: Send + Sync + std::fmt::Debug {
+  /// Adds a finding to the archive, returning whether it is new.
+  fn discover<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, bool>;
+
+  /// Queues a finding for dispatch.
+  fn offer(&self, finding: Finding) -> BoxFuture<'_, ()>;
+
+  /// Takes up to `limit` queued findings, in the order they were offered.
+  fn next(&self, limit: usize) -> BoxFuture<'_, Vec<Finding>>;
+
+  /// Counts a request for `finding` against its host and marks it in flight,
+  /// or tells why it may not be sent.
+  fn dispatch<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, Option<SkipReason>>;
+
+  /// Marks a dispatched finding as done.
+  fn complete<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, ()>;
+
+  /// Takes back the visit a completed finding counted against its host, as
+  /// it will be dispatched again.
+  fn release<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, ()>;
+
+  /// Queues a completed finding again without counting its visit, because
+  /// the host asked to be retried later.
+  fn retry(&self, finding: Finding) -> BoxFuture<'_, ()> {
+    Box::pin(async move {
+      self.release(&finding).await;
+      self.offer(finding).await;
+    })
+  }
+
+  /// The findings queued, in the order they were offered.
+  fn queued(&self) -> BoxFuture<'_, Vec<Finding>>;
+
+  /// How many findings are queued.
+  fn len(&self) -> BoxFuture<'_, usize>;
+
+  /// How many findings are queued for every host.
+  fn queued_per_host(&self) -> BoxFuture<'_, HashMap<Host, usize>> {
+    Box::pin(async move { count_per_host(&self.queued().await) })
+  }
+
+  fn is_empty(&self) -> BoxFuture<'_, bool> {
+    Box::pin(async move { self.len().await == 0 })
+  }
+
+  /// Everything discovered so far.
+  fn archive(&self) -> BoxFuture<'_, Vec<Finding>>;
+
+  /// How many requests were sent to every host.
+  fn host_visits(&self) -> BoxFuture<'_, HashMap<Host, u32>>;
+
+  /// The state to resume from.
+  fn checkpoint(&self) -> BoxFuture<'_, FrontierSnapshot>;
+}
+
+/// The part of a [`Checkpoint`] a [`Frontier`] keeps.
+#[derive(Debug, Default)]
+pub struct FrontierSnapshot {
+  /// The findings queued and in flight.
+  pub queued: Vec<Finding>,
+  pub archive: Vec<Finding>,
+  /// Requests sent to every host, not counting those of findings in flight.
+  pub host_visits: Vec<(Host, u32)>,
+}
+
+/// The default frontier, kept in memory behind a lock.
+#[derive(Debug, Default)]
+pub struct MemoryFrontier {
+  pub(crate) state: Mutex<FrontierState>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FrontierState {
+  pub(crate) queue: VecDeque<Finding>,
+  pub(crate) archive: HashSet<Finding>,
+  /// Findings dispatched and not completed yet.
+  pub(crate) in_flight: HashSet<Finding>,
+  pub(crate) host_visits: HashMap<Host, u32>,
+}
+
+impl MemoryFrontier {
+  /// A frontier with the archive and host visits of `checkpoint`. Its queue
+  /// is offered by the dispatcher that resumes it.
+  pub fn resume(checkpoint: &Checkpoint) -> Self {
+    let state = FrontierState {
+      archive: checkpoint.archive.iter().cloned().collect(),
+      host_visits: checkpoint.host_visits.iter().cloned().collect(),
+      ..FrontierState::default()
+    };
+    Self {
+      state: Mutex::new(state),
+    }
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, FrontierState> {
+    // Nothing panics while holding the lock.
+    self.state.lock().unwrap()
+  }
+}
+
+fn count_per_host<'a>(findings: impl IntoIterator<Item = &'a Finding>) -> HashMap<Host, usize> {
+  let mut hosts = HashMap::new();
+  for finding in findings {
+    if let Some(host) = finding.url().host() {
+      *hosts.entry(host.to_owned()).or_default() += 1;
+    }
+  }
+  hosts
+}
+
+/// Takes back the visit `finding` counted against its host.
+fn release_visit(host_visits: &mut HashMap<Host, u32>, finding: &Finding) {
+  if let Some(visits) = finding
+    .url()
+    .host()
+    .and_then(|h| host_visits.get_mut(&h.to_owned()))
+  {
+    *visits = visits.saturating_sub(1);
+  }
+}
+
+impl Frontier for MemoryFrontier {
+  fn discover<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, bool> {
+    let mut state = self.lock();
+    let new = !state.archive.contains(finding);
+    if new {
+      state.archive.insert(finding.clone());
+    }
+    Box::pin(future::ready(new))
+  }
+
+  fn offer(&self, finding: Finding) -> BoxFuture<'_, ()> {
+    self.lock().queue.push_back(finding);
+    Box::pin(future::ready(()))
+  }
+
+  fn next(&self, limit: usize) -> BoxFuture<'_, Vec<Finding>> {
+    let mut state = self.lock();
+    let limit = limit.min(state.queue.len());
+    Box::pin(future::ready(state.queue.drain(..limit).collect()))
+  }
+
+  fn dispatch<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, Option<SkipReason>> {
+    let mut state = self.lock();
+    let url = finding.url();
+    let rejection = match url.host().map(|h| h.to_owned()) {
+      Some(host) => {
+        let visits = state.host_visits.entry(host).or_insert(0);
+        match *visits > HOST_VISIT_LIMIT {
+          true => Some(SkipReason::HostLimit),
+          false => {
+            *visits += 1;
+            None
+          }
+        }
+      }
+      None if url.scheme() == "file" => None,
+      None => Some(SkipReason::NoHost),
+    };
+    if rejection.is_none() {
+      state.in_flight.insert(finding.clone());
+    }
+    Box::pin(future::ready(rejection))
+  }
+
+  fn complete<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, ()> {
+    self.lock().in_flight.remove(finding);
+    Box::pin(future::ready(()))
+  }
+
+  fn release<'a>(&'a self, finding: &'a Finding) -> BoxFuture<'a, ()> {
+    release_visit(&mut self.lock().host_visits, finding);
+    Box::pin(future::ready(()))
+  }
+
+  fn queued(&self) -> BoxFuture<'_, Vec<Finding>> {
+    Box::pin(future::ready(self.lock().queue.iter().cloned().collect()))
+  }
+
+  fn len(&self) -> BoxFuture<'_, usize> {
+    Box::pin(future::ready(self.lock().queue.len()))
+  }
+
+  fn queued_per_host(&self) -> BoxFuture<'_, HashMap<Host, usize>> {
+    Box::pin(future::ready(count_per_host(&self.lock().queue)))
+  }
+
+  fn archive(&self) -> BoxFuture<'_, Vec<Finding>> {
+    Box::pin(future::ready(self.lock().archive.iter().cloned().collect()))
+  }
+
+  fn host_visits(&self) -> BoxFuture<'_, HashMap<Host, u32>> {
+    Box::pin(future::ready(self.lock().host_visits.clone()))
+  }
+
+  fn checkpoint(&self) -> BoxFuture<'_, FrontierSnapshot> {
+    let state = self.lock();
+    let mut host_visits = state.host_visits.clone();
+    for finding in &state.in_flight {
+      release_visit(&mut host_visits, finding);
+    }
+    let snapshot = FrontierSnapshot {
+      queued: state
+        .queue
+        .iter()
+        .chain(&state.in_flight)
+        .cloned()
+        .collect(),
+      archive: state.archive.iter().cloned().collect(),
+      host_visits: host_visits.into_iter().collect(),
+    };
+    Box::pin(future::ready(snapshot))
+  }
+}
+
+/// Which seed every finding descends from, so the frontier can be shared fairly
+/// between seeds.
+#[derive(Debug, Default)]
+pub(crate) struct SeedFrontier {
+  pub(crate) origins: HashMap<Url, usize>,
+  /// Pages dispatched per seed.
+  pub(crate) pages: Vec<u32>,
+  pub(crate) budget: Option<u32>,
+  /// Tags per seed, which every finding descending from it carries.
+  pub(crate) tags: Vec<BTreeSet<String>>,
+}
+
+impl SeedFrontier {
+  pub(crate) fn new(
+    seeds: &HashSet<Url>,
+    tags: &HashMap<Url, BTreeSet<String>>,
+    budget: Option<u32>,
+  ) -> Self {
+    let seeds: BTreeSet<&Url> = seeds.iter().collect();
+    Self {
+      origins: seeds
+        .iter()
+        .enumerate()
+        .map(|(i, &url)| (url.clone(), i))
+        .collect(),
+      pages: vec![0; seeds.len()],
+      budget,
+      tags: seeds
+        .iter()
+        .map(|&url| tags.get(url).cloned().unwrap_or_default())
+        .collect(),
+    }
+  }
+
+  /// Adds a seed without tags, if it is not one yet.
+  pub(crate) fn add(&mut self, seed: &Url) {
+    if !self.origins.contains_key(seed) {
+      self.origins.insert(seed.clone(), self.pages.len());
+      self.pages.push(0);
+      self.tags.push(BTreeSet::new());
+    }
+  }
+
+  /// The tags of the seed `url` descends from.
+  pub(crate) fn tags(&self, url: &Url) -> Vec<&str> {
+    match self.origins.get(url) {
+      Some(&seed) => self.tags[seed].iter().map(String::as_str).collect(),
+      None => Vec::new(),
+    }
+  }
+
+  /// Writes how many seeds and crawled pages every tag has.
+  pub(crate) fn render(&self, crawled: &HashSet<Url>, out: &mut String) {
+    let mut by_tag: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for tag in self.tags.iter().flatten() {
+      by_tag.entry(tag).or_default().0 += 1;
+    }
+    if by_tag.is_empty() {
+      return;
+    }
+    for page in crawled {
+      for tag in self.tags(page) {
+        if let Some((_, pages)) = by_tag.get_mut(tag) {
+          *pages += 1;
+        }
+      }
+    }
+
+    let _ = writeln!(out, "## Tags\n");
+    for (tag, (seeds, pages)) in &by_tag {
+      let _ = writeln!(
+        out,
+        "- {}: {} seed(s), {} page(s) crawled",
+        tag, seeds, pages
+      );
+    }
+    out.push('\n');
+  }
+
+  /// Attributes a finding discovered on `page` to the seed of that page.
+  pub(crate) fn inherit(&mut self, page: &Url, finding: &Url) {
+    if let Some(&seed) = self.origins.get(page) {
+      self.origins.entry(finding.clone()).or_insert(seed);
+    }
+  }
+
+  /// Reorders `queue` to take turns between seeds, so that the per-host delays
+  /// of a seed with a large frontier don't push all other seeds back.
+  pub(crate) fn interleave(&self, queue: Vec<Finding>) -> Vec<Finding> {
+    let mut slices: BTreeMap<Option<usize>, VecDeque<Finding>> = BTreeMap::new();
+    for finding in queue {
+      let seed = self.origins.get(finding.url()).copied();
+      slices.entry(seed).or_default().push_back(finding);
+    }
+    let mut interleaved = Vec::new();
+    while !slices.is_empty() {
+      slices.retain(|_, slice| match slice.pop_front() {
+        Some(finding) => {
+          interleaved.push(finding);
+          true
+        }
+        None => false,
+      });
+    }
+    interleaved
+  }
+
+  /// Counts a page against the budget of its seed, unless that is used up.
+  pub(crate) fn admit(&mut self, finding: &Finding) -> bool {
+    let Finding::Page(url, _) = finding else {
+      return true;
+    };
+    let Some(&seed) = self.origins.get(url) else {
+      return true;
+    };
+    let pages = &mut self.pages[seed];
+    if self.budget.is_some_and(|budget| *pages >= budget) {
+      return false;
+    }
+    *pages += 1;
+    true
+  }
+
+  /// Gives back what [`Self::admit`] counted for a page that was not crawled
+  /// after all.
+  pub(crate) fn refund(&mut self, finding: &Finding) {
+    let Finding::Page(url, _) = finding else {
+      return;
+    };
+    if let Some(&seed) = self.origins.get(url) {
+      self.pages[seed] = self.pages[seed].saturating_sub(1);
+    }
+  }
+}
+
+/// The `Allow`, `Disallow` and `Crawl-delay` rules of a robots.txt that apply
+/// to this crawler: those of the groups naming its product token, or else of
+/// the `*` group.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RobotsRules {
+  pub(crate) rules: Vec<RobotsRule>,
+  pub(crate) crawl_delay: Option<Duration>,
+}
+
+/// An `Allow` or `Disallow` line of a robots.txt.
+#[derive(Debug, Clone)]
+pub(crate) struct RobotsRule {
+  pub(crate) allow: bool,
+  /// The path pattern as written.
+  pub(crate) pattern: String,
+  pub(crate) regex: Regex,
+}
+
+impl RobotsRule {
+  /// The length by which the longest matching rule wins, not counting a
+  /// trailing `$`.
+  pub(crate) fn length(&self) -> usize {
+    self
+      .pattern
+      .strip_suffix('$')
+      .unwrap_or(&self.pattern)
+      .len()
+  }
+
+  pub(crate) fn line(&self) -> String {
+    match self.allow {
+      true => format!("Allow: {}", self.pattern),
+      false => format!("Disallow: {}", self.pattern),
+    }
+  }
+}
+
+impl RobotsRules {
+  /// The rules of an unreachable robots.txt, which disallow everything.
+  pub(crate) fn disallow_all() -> Self {
+    Self {
+      rules: robots_rule(false, "/").into_iter().collect(),
+      crawl_delay: None,
+    }
+  }
+
+  /// The rules for the crawler sending `user_agent`, whose product token,
+  /// e.g. `crawler` of `crawler/0.1 (+https://a.example)`, is what groups
+  /// are matched against, ignoring case.
+  pub(crate) fn parse(robots_txt: &str, user_agent: &str) -> Self {
+    let agent = product_token(user_agent);
+    let (mut specific, mut generic) = (None::<RobotsRules>, None::<RobotsRules>);
+    let mut agents: Vec<String> = Vec::new();
+    let mut in_rules = false;
+    for line in robots_txt.lines() {
+      let line = line.split('#').next().unwrap_or_default().trim();
+      let Some((field, value)) = line.split_once(':') else {
+        continue;
+      };
+      let (field, value) = (field.trim().to_ascii_lowercase(), value.trim());
+      match field.as_str() {
+        "user-agent" => {
+          if in_rules {
+            agents.clear();
+            in_rules = false;
+          }
+          agents.push(match value {
+            "*" => value.to_owned(),
+            _ => product_token(value),
+          });
+        }
+        "allow" | "disallow" => {
+          in_rules = true;
+          let rule = robots_rule(field == "allow", value);
+          for (group, name) in [(&mut specific, agent.as_str()), (&mut generic, "*")] {
+            if agents.iter().any(|a| a == name) {
+              group
+                .get_or_insert_with(RobotsRules::default)
+                .rules
+                .extend(rule.clone());
+            }
+          }
+        }
+        "crawl-delay" => {
+          in_rules = true;
+          let Some(seconds) = value.parse::<f64>().ok().filter(|s| *s >= 0.0) else {
+            continue;
+          };
+          let delay = Duration::from_secs_f64(seconds.min(CRAWL_DELAY_LIMIT.as_secs_f64()));
+          for (group, name) in [(&mut specific, agent.as_str()), (&mut generic, "*")] {
+            if agents.iter().any(|a| a == name) {
+              group.get_or_insert_with(RobotsRules::default).crawl_delay = Some(delay);
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+    specific.or(generic).unwrap_or_default()
+  }
+
+  /// The rule deciding whether `path` may be crawled, if any matches: the
+  /// longest matching rule wins, and `Allow` wins ties.
+  pub(crate) fn matching(&self, path: &str) -> Option<&RobotsRule> {
+    self
+      .rules
+      .iter()
+      .filter(|rule| rule.regex.is_match(path))
+      .max_by_key(|rule| (rule.length(), rule.allow))
+  }
+
+  /// Whether `path` may be crawled.
+  pub(crate) fn allows(&self, path: &str) -> bool {
+    self.matching(path).is_none_or(|rule| rule.allow)
+  }
+}
+
+/// The lowercased product token a user agent string starts with, the name
+/// robots.txt groups address crawlers by.
+pub(crate) fn product_token(user_agent: &str) -> String {
+  user_agent
+    .trim()
+    .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+    .next()
+    .unwrap_or_default()
+    .to_ascii_lowercase()
+}
+
+/// A robots.txt as it was fetched, kept in checkpoints so that a resumed
+/// crawl does not fetch it again.
+#[derive(Debug, Clone)]
+pub(crate) struct RobotsFile {
+  pub(crate) fetched: SystemTime,
+  /// The status it was answered with, if the request succeeded at all.
+  pub(crate) status: Option<u16>,
+  /// The body of a 2xx answer.
+  pub(crate) text: String,
+  pub(crate) rules: RobotsRules,
+  /// Whether it comes from the checkpoint the crawl resumed from.
+  pub(crate) resumed: bool,
+}
+
+impl RobotsFile {
+  pub(crate) fn new(status: Option<u16>, text: String, user_agent: &str) -> Self {
+    Self {
+      fetched: SystemTime::now(),
+      status,
+      rules: Self::rules_for(status, &text, user_agent),
+      text,
+      resumed: false,
+    }
+  }
+
+  /// The rules a robots.txt answered with `status` sets. As RFC 9309 asks, a
+  /// 4xx answer allows everything, while a server error or no answer at all
+  /// disallows everything.
+  pub(crate) fn rules_for(status: Option<u16>, text: &str, user_agent: &str) -> RobotsRules {
+    match status {
+      Some(status) if status < 500 => RobotsRules::parse(text, user_agent),
+      _ => RobotsRules::disallow_all(),
+    }
+  }
+
+  /// Whether the server failed to answer for it, or did not answer at all.
+  pub(crate) fn is_unreachable(&self) -> bool {
+    self.status.is_none_or(|status| status >= 500)
+  }
+
+  /// Whether it is recent enough to be used instead of fetching it again. An
+  /// unreachable one is fetched again by every crawl that resumes.
+  pub(crate) fn is_fresh(&self) -> bool {
+    !self.is_unreachable()
+      && self
+        .fetched
+        .elapsed()
+        .map_or(true, |age| age < ROBOTS_CACHE_LIFETIME)
+  }
+
+  /// Reads it back from a checkpoint. The rules are parsed from the text
+  /// again once the crawl resumes, for the user agent it resumes with; those
+  /// written alongside it are only for reading.
+  pub(crate) fn from_json(json: &serde_json::Value) -> Self {
+    let fetched = Duration::from_secs(json["fetched"].as_u64().unwrap_or_default());
+    Self {
+      fetched: UNIX_EPOCH + fetched,
+      status: json["status"].as_u64().map(|s| s as u16),
+      text: json["text"].as_str().unwrap_or_default().to_owned(),
+      rules: RobotsRules::default(),
+      resumed: true,
+    }
+  }
+
+  pub(crate) fn to_json(&self) -> serde_json::Value {
+    let fetched = self.fetched.duration_since(UNIX_EPOCH).unwrap_or_default();
+    serde_json::json!({
+      "fetched": fetched.as_secs(),
+      "status": self.status,
+      "text": self.text,
+      "rules": self.rules.rules.iter().map(RobotsRule::line).collect::<Vec<_>>(),
+      "crawl_delay_ms": self.rules.crawl_delay.map(|d| d.as_millis() as u64),
+    })
+  }
+}
+
+/// The robots.txt of every visited origin, and the rules that kept the crawl
+/// from URLs.
+#[derive(Debug, Default)]
+pub(crate) struct RobotsCache {
+  /// The user agent the rules are parsed for.
+  pub(crate) user_agent: String,
+  pub(crate) origins: HashMap<String, RobotsFile>,
+  /// The robots.txt being fetched, with the origin of each task. A task also
+  /// returns where and when its request was sent, if it was.
+  pub(crate) fetches: JoinSet<(RobotsFile, Option<(Url, Instant)>)>,
+  pub(crate) fetching: HashMap<task::Id, String>,
+  /// Findings waiting for the robots.txt of their origin, by origin.
+  pub(crate) pending: HashMap<String, Vec<Finding>>,
+  /// URLs skipped by every `Disallow` rule, by origin.
+  pub(crate) blocked: BTreeMap<(String, String), u32>,
+}
+
+impl RobotsCache {
+  /// A cache for `user_agent`, taking over the robots.txt files of the
+  /// checkpoint the crawl resumes from that are still fresh.
+  pub(crate) fn new(user_agent: &str, resume: Option<&Checkpoint>) -> Self {
+    let resumed = resume.into_iter().flat_map(|checkpoint| &checkpoint.robots);
+    Self {
+      user_agent: user_agent.to_owned(),
+      origins: resumed
+        .filter(|(_, robots)| robots.is_fresh())
+        .map(|(origin, robots)| {
+          let rules = RobotsFile::rules_for(robots.status, &robots.text, user_agent);
+          (
+            origin.clone(),
+            RobotsFile {
+              rules,
+              ..robots.clone()
+            },
+          )
+        })
+        .collect(),
+      ..Self::default()
+    }
+  }
+
+  /// Fetches the robots.txt of `origin` in a task, holding `finding` back
+  /// until it arrives. The request takes a slot of its host in `schedule`
+  /// like any other, keeping `delay` to the next one.
+  pub(crate) fn fetch(
+    &mut self,
+    origin: String,
+    finding: Finding,
+    backend: Arc<dyn Fetcher>,
+    schedule: &mut HostSchedule,
+    pauses: &HostPauses,
+    delay: Duration,
+  ) {
+    if let Some(pending) = self.pending.get_mut(&origin) {
+      pending.push(finding);
+      return;
+    }
+    let robots_url = finding.url().join("/robots.txt");
+    let slot = schedule.reserve(finding.url(), delay);
+    let (pauses, reserved) = (pauses.clone(), Instant::now());
+    let user_agent = self.user_agent.clone();
+    let log_origin = origin.clone();
+    let id = self
+      .fetches
+      .spawn(async move {
+        let Ok(robots_url) = robots_url else {
+          return (RobotsFile::new(None, String::new(), &user_agent), None);
+        };
+        pauses.wait(&robots_url, reserved, slot).await;
+        let sent = Instant::now();
+        let robots = match backend.fetch(&robots_url).await {
+          Ok(response) if (200..300).contains(&response.status) => {
+            RobotsFile::new(Some(response.status), response.text(), &user_agent)
+          }
+          Ok(response) if response.status >= 500 => {
+            warn!(
+              "The robots.txt of `{}` failed with status {}, disallowing the origin",
+              log_origin, response.status
+            );
+            RobotsFile::new(Some(response.status), String::new(), &user_agent)
+          }
+          Ok(response) => RobotsFile::new(Some(response.status), String::new(), &user_agent),
+          Err(e) => {
+            warn!(
+              "The robots.txt of `{}` is unreachable, disallowing the origin: {}",
+              log_origin, e
+            );
+            RobotsFile::new(None, String::new(), &user_agent)
+          }
+        };
+        (robots, Some((robots_url, sent)))
+      })
+      .id();
+    self.fetching.insert(id, origin.clone());
+    self.pending.insert(origin, vec![finding]);
+  }
+
+  /// Waits for the next robots.txt to arrive, returning where and when it
+  /// was requested and the findings that waited for it. A lost task counts
+  /// as an unreachable robots.txt.
+  pub(crate) async fn join_next(&mut self) -> Option<(Option<(Url, Instant)>, Vec<Finding>)> {
+    let (origin, (robots, request)) = match self.fetches.join_next_with_id().await? {
+      Ok((id, fetched)) => (self.fetching.remove(&id)?, fetched),
+      Err(e) => {
+        let origin = self.fetching.remove(&e.id())?;
+        warn!("Fetching the robots.txt of `{}` was lost: {}", origin, e);
+        (
+          origin,
+          (RobotsFile::new(None, String::new(), &self.user_agent), None),
+        )
+      }
+    };
+    if let Some(delay) = robots.rules.crawl_delay {
+      info!(
+        "`{}` asks for a crawl delay of {} ms",
+        origin,
+        delay.as_millis()
+      );
+    }
+    self.origins.insert(origin.clone(), robots);
+    Some((request, self.pending.remove(&origin).unwrap_or_default()))
+  }
+
+  pub(crate) fn rules(&self, origin: &str) -> Option<&RobotsRules> {
+    self.origins.get(origin).map(|robots| &robots.rules)
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.origins.is_empty() {
+      return;
+    }
+    let mut origins: Vec<_> = self.origins.iter().collect();
+    origins.sort_by_key(|(origin, _)| *origin);
+    out.push_str("## robots.txt\n\n");
+    out.push_str("| Origin | Fetched | Status | Rules | Crawl delay |\n");
+    out.push_str("| --- | --- | --- | ---: | ---: |\n");
+    for (origin, robots) in origins {
+      let _ = writeln!(
+        out,
+        "| {} | {}{} | {} | {} | {} |",
+        origin,
+        httpdate::fmt_http_date(robots.fetched),
+        match robots.resumed {
+          true => " (from checkpoint)",
+          false => "",
+        },
+        match robots.status {
+          Some(status) => status.to_string(),
+          None => "unreachable".to_owned(),
+        },
+        robots.rules.rules.len(),
+        match robots.rules.crawl_delay {
+          Some(delay) => format!("{} ms", delay.as_millis()),
+          None => "-".to_owned(),
+        }
+      );
+    }
+    out.push('\n');
+    if self.blocked.is_empty() {
+      return;
+    }
+    out.push_str("### Rules that disallowed URLs\n\n");
+    for ((origin, rule), urls) in &self.blocked {
+      let _ = writeln!(out, "- {} `{}`: {} URL(s)", origin, rule, urls);
+    }
+    out.push('\n');
+  }
+}
+
+/// The path and query of `url`, which robots.txt rules are matched against.
+pub(crate) fn robots_path(url: &Url) -> String {
+  match url.query() {
+    Some(query) => format!("{}?{}", url.path(), query),
+    None => url.path().to_owned(),
+  }
+}
+
+/// Compiles a robots.txt path pattern, where `*` matches anything and a
+/// trailing `$` anchors the end. An empty pattern matches nothing.
+pub(crate) fn robots_rule(allow: bool, written: &str) -> Option<RobotsRule> {
+  if written.is_empty() {
+    return None;
+  }
+  let (pattern, anchored) = match written.strip_suffix('$') {
+    Some(pattern) => (pattern, true),
+    None => (written, false),
+  };
+  let mut regex = String::from("^");
+  regex.push_str(
+    &pattern
+      .split('*')
+      .map(regex::escape)
+      .collect::<Vec<_>>()
+      .join(".*"),
+  );
+  if anchored {
+    regex.push('$');
+  }
+  Some(RobotsRule {
+    allow,
+    pattern: written.to_owned(),
+    regex: Regex::new(&regex).ok()?,
+  })
+}
+
+/// Hosts that asked the crawler to back off. Requests to them that were
+/// scheduled before a pause are pushed back by its length, keeping their
+/// spacing; later ones are reserved after it in the [`HostSchedule`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HostPauses {
+  /// The pauses of every host, in order.
+  pub(crate) hosts: Arc<Mutex<HashMap<Host, Vec<Pause>>>>,
+}
+
+/// When a pause began and how long it lasts.
+pub(crate) type Pause = (Instant, Duration);
+
+impl HostPauses {
+  pub(crate) fn pause(&self, host: Host, length: Duration) {
+    let mut hosts = self.hosts.lock().unwrap();
+    hosts
+      .entry(host)
+      .or_default()
+      .push((Instant::now(), length));
+  }
+
+  /// When a request to `url` that was reserved at `reserved` for `slot` may
+  /// be sent, after the pauses of its host since.
+  pub(crate) fn shifted(&self, url: &Url, reserved: Instant, slot: Instant) -> Instant {
+    let hosts = self.hosts.lock().unwrap();
+    let Some(pauses) = url.host().and_then(|h| hosts.get(&h.to_owned())) else {
+      return slot;
+    };
+    pauses
+      .iter()
+      .filter(|(since, _)| *since >= reserved)
+      .fold(slot, |slot, (since, length)| match slot >= *since {
+        true => slot + *length,
+        false => slot,
+      })
+  }
+
+  /// Waits for the slot of a request and for every pause of its host that
+  /// begins before the request is sent.
+  pub(crate) async fn wait(&self, url: &Url, reserved: Instant, slot: Instant) {
+    let mut until = slot;
+    loop {
+      tokio::time::sleep_until(until.into()).await;
+      let shifted = self.shifted(url, reserved, slot);
+      if shifted <= until {
+        return;
+      }
+      until = shifted;
+    }
+  }
+}
+
+/// Hosts that are presumably down for maintenance. Their frontier is put off
+/// until the end of the crawl and then retried once more.
+#[derive(Debug, Default)]
+pub(crate) struct MaintenanceDeferral {
+  pub(crate) hosts: HashSet<Host>,
+  pub(crate) deferred: Vec<Finding>,
+  /// Hosts whose deferred URLs were retried already, which are not deferred
+  /// again.
+  pub(crate) revisited: HashSet<Host>,
+}
+
+impl MaintenanceDeferral {
+  pub(crate) fn is_down(&self, url: &Url) -> bool {
+    url
+      .host()
+      .is_some_and(|h| self.hosts.contains(&h.to_owned()))
+  }
+
+  /// Defers the finding behind a [`MaintenanceResponse`], unless its host was
+  /// revisited already.
+  pub(crate) fn defer(&mut self, error: &eyre::Report) -> bool {
+    let Some(MaintenanceResponse(finding)) = error.downcast_ref() else {
+      return false;
+    };
+    let host = finding.url().host().map(|h| h.to_owned());
+    if host.as_ref().is_some_and(|h| self.revisited.contains(h)) {
+      return false;
+    }
+    if let Some(host) = host {
+      if self.hosts.insert(host.clone()) {
+        info!("`{}` is down for maintenance, deferring its URLs", host);
+      }
+    }
+    self.deferred.push(finding.clone());
+    true
+  }
+
+  pub(crate) fn revisit(&mut self) -> Vec<Finding> {
+    info!(
+      "revisiting {} URLs of hosts that were down for maintenance",
+      self.deferred.len()
+    );
+    self.revisited.extend(self.hosts.drain());
+    std::mem::take(&mut self.deferred)
+  }
+}
+
+/// Whether `/dir`, `/dir/` and `/dir/index.html` (or `index.htm`) are the same
+/// page. This is settled for every directory separately, the first time two
+/// of its forms are crawled successfully, by comparing their contents; until
+/// then, every form is crawled.
+#[derive(Debug, Default)]
+pub(crate) struct IndexEquivalence {
+  /// The first crawled form of every directory and its content hash.
+  pub(crate) crawled: HashMap<String, (Url, u64)>,
+  /// Whether the forms are equivalent, for every directory where they were
+  /// compared.
+  pub(crate) verdicts: HashMap<String, bool>,
+}
+
+impl IndexEquivalence {
+  /// The URL of the directory `url` is a form of, without the trailing slash
+  /// or index document.
+  pub(crate) fn directory(url: &Url) -> String {
+    let path = url.path();
+    let path = INDEX_DOCUMENTS
+      .iter()
+      .find_map(|name| path.strip_suffix(name).filter(|p| p.ends_with('/')))
+      .unwrap_or(path);
+    let mut directory = url[..url::Position::BeforePath].to_owned();
+    directory.push_str(path.trim_end_matches('/'));
+    if let Some(query) = url.query() {
+      directory.push('?');
+      directory.push_str(query);
+    }
+    directory
+  }
+
+  /// Whether `finding` is a form of a directory whose forms are known to be
+  /// equivalent, and one of which was crawled.
+  pub(crate) fn covers(&self, finding: &Finding) -> bool {
+    let Finding::Page(url, _) = finding else {
+      return false;
+    };
+    self.verdicts.get(&Self::directory(url)) == Some(&true)
+  }
+
+  /// Records a crawled page, comparing it to the first crawled form of its
+  /// directory.
+  pub(crate) fn record(&mut self, url: &Url, status: u16, content_hash: u64) {
+    if !(200..300).contains(&status) {
+      return;
+    }
+    let directory = Self::directory(url);
+    match self.crawled.get(&directory) {
+      Some((first, hash)) if first != url && !self.verdicts.contains_key(&directory) => {
+        let equivalent = *hash == content_hash;
+        match equivalent {
+          true => debug!("`{}` and `{}` are the same page", first, url),
+          false => debug!("`{}` and `{}` are different pages", first, url),
+        }
+        self.verdicts.insert(directory, equivalent);
+      }
+      Some(_) => {}
+      None => {
+        self.crawled.insert(directory, (url.clone(), content_hash));
+      }
+    }
+  }
+}
+
+/// The shape of a URL: its path with digit runs replaced by `{n}`, followed by
+/// its sorted query parameter names without values.
+pub(crate) fn url_template(url: &Url) -> String {
+  let mut template = DIGITS.replace_all(url.path(), "{n}").into_owned();
+  let mut params: Vec<_> = url.query_pairs().map(|(name, _)| name).collect();
+  params.sort_unstable();
+  params.dedup();
+  if !params.is_empty() {
+    template.push('?');
+    template.push_str(&params.join("&"));
+  }
+  template
+}
+
+/// The `include` and `exclude` regexes discovered pages are matched against.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UrlFilters {
+  pub(crate) include: Vec<Regex>,
+  pub(crate) exclude: Vec<Regex>,
+}
+
+impl UrlFilters {
+  /// Whether `url` matches an include regex, if there are any, and no exclude
+  /// regex.
+  pub(crate) fn admit(&self, url: &Url) -> bool {
+    let url = url.as_str();
+    (self.include.is_empty() || self.include.iter().any(|r| r.is_match(url)))
+      && !self.exclude.iter().any(|r| r.is_match(url))
+  }
+
+  /// What keeps `url` out, if anything: the exclude regex it matches, or the
+  /// include regexes it matches none of.
+  pub(crate) fn rejection(&self, url: &Url) -> Option<String> {
+    let url = url.as_str();
+    if let Some(exclude) = self.exclude.iter().find(|r| r.is_match(url)) {
+      return Some(format!("matches exclude pattern `{}`", exclude.as_str()));
+    }
+    let included = self.include.is_empty() || self.include.iter().any(|r| r.is_match(url));
+    (!included).then(|| "matches no include pattern".to_owned())
+  }
+}
+
+/// Suffixes under which anyone can register a domain, in the format of the
+/// [Public Suffix List](https://publicsuffix.org/list/): one rule per line,
+/// `*.` for wildcards, `!` for exceptions and `//` for comments.
+#[derive(Debug, Clone)]
+pub struct PublicSuffixList {
+  pub(crate) rules: HashSet<String>,
+  pub(crate) wildcards: HashSet<String>,
+  pub(crate) exceptions: HashSet<String>,
+}
+
+/// The multi-label suffixes most crawls run into, for when no full list is
+/// given, separated by whitespace rather than one per line like in the list
+/// format. Any other top-level domain is a suffix by the default rule.
+pub(crate) const BUILTIN_PUBLIC_SUFFIXES: &str = "\
+ac.uk co.uk gov.uk ltd.uk me.uk net.uk org.uk plc.uk sch.uk
+com.au edu.au gov.au net.au org.au asn.au id.au
+ac.jp co.jp go.jp ne.jp or.jp
+co.nz net.nz org.nz govt.nz ac.nz
+co.za org.za gov.za ac.za
+com.br net.br org.br gov.br
+com.cn net.cn org.cn gov.cn edu.cn
+co.in net.in org.in gov.in ac.in
+co.kr or.kr go.kr ac.kr
+com.tw org.tw gov.tw edu.tw
+com.hk org.hk gov.hk edu.hk
+com.sg org.sg gov.sg edu.sg
+com.mx org.mx gob.mx
+com.ar com.tr co.il org.il ac.il com.ua co.id or.id
+github.io gitlab.io blogspot.com herokuapp.com netlify.app vercel.app pages.dev
+workers.dev appspot.com web.app firebaseapp.com azurewebsites.net cloudfront.net
+s3.amazonaws.com readthedocs.io
+";
+
+impl Default for PublicSuffixList {
+  fn default() -> Self {
+    let rules: Vec<&str> = BUILTIN_PUBLIC_SUFFIXES.split_whitespace().collect();
+    Self::parse(&rules.join("\n"))
+  }
+}
+
+impl PublicSuffixList {
+  pub fn parse(list: &str) -> Self {
+    let mut suffixes = Self {
+      rules: HashSet::new(),
+      wildcards: HashSet::new(),
+      exceptions: HashSet::new(),
+    };
+    for line in list.lines() {
+      let Some(rule) = line.split_whitespace().next() else {
+        continue;
+      };
+      if rule.starts_with("//") {
+        continue;
+      }
+      let rule = rule.to_ascii_lowercase();
+      if let Some(exception) = rule.strip_prefix('!') {
+        suffixes.exceptions.insert(exception.to_owned());
+      } else if let Some(wildcard) = rule.strip_prefix("*.") {
+        suffixes.wildcards.insert(wildcard.to_owned());
+      } else {
+        suffixes.rules.insert(rule);
+      }
+    }
+    suffixes
+  }
+
+  /// Reads a list such as `public_suffix_list.dat`.
+  pub fn read(path: &Path) -> Result<Self> {
+    Ok(Self::parse(&std::fs::read_to_string(path)?))
+  }
+
+  /// The public suffix of `host` and one more label, or the host itself if
+  /// it is a public suffix.
+  pub(crate) fn registrable_domain<'a>(&self, host: &'a str) -> &'a str {
+    let labels: Vec<usize> = std::iter::once(0)
+      .chain(host.match_indices('.').map(|(i, _)| i + 1))
+      .collect();
+    // The longest matching rule wins, and the default rule is the last label.
+    let mut suffix = *labels.last().unwrap();
+    for (n, &start) in labels.iter().enumerate() {
+      let candidate = &host[start..];
+      if self.exceptions.contains(candidate) {
+        suffix = labels.get(n + 1).copied().unwrap_or(start);
+        break;
+      }
+      if self.rules.contains(candidate) {
+        suffix = start;
+        break;
+      }
+      if let Some(&parent) = labels.get(n + 1) {
+        if self.wildcards.contains(&host[parent..]) {
+          suffix = start;
+          break;
+        }
+      }
+    }
+    match labels.iter().position(|&start| start == suffix) {
+      Some(n) if n > 0 => &host[labels[n - 1]..],
+      _ => host,
+    }
+  }
+}
+
+/// The seeds a [`Scope`] is relative to.
+#[derive(Debug, Default)]
+pub(crate) struct CrawlScope {
+  pub(crate) scope: Scope,
+  pub(crate) seeds: HashSet<Url>,
+  pub(crate) hosts: HashSet<String>,
+  pub(crate) domains: HashSet<String>,
+  pub(crate) suffixes: PublicSuffixList,
+}
+
+impl CrawlScope {
+  pub(crate) fn new(scope: Scope, suffixes: PublicSuffixList, seeds: &HashSet<Url>) -> Self {
+    let mut crawl_scope = Self {
+      scope,
+      suffixes,
+      ..Self::default()
+    };
+    for seed in seeds {
+      crawl_scope.add_seed(seed);
+    }
+    crawl_scope
+  }
+
+  /// Makes `url` a seed, such as where a seed redirected to.
+  pub(crate) fn add_seed(&mut self, url: &Url) {
+    self.seeds.insert(url.clone());
+    if let Some(host) = url.host_str() {
+      self.hosts.insert(host.to_owned());
+      self
+        .domains
+        .insert(self.suffixes.registrable_domain(host).to_owned());
+    }
+  }
+
+  pub(crate) fn admits(&self, url: &Url) -> bool {
+    let host = url.host_str();
+    match self.scope {
+      Scope::Page => self.seeds.contains(url),
+      Scope::Host => host.is_none_or(|h| self.hosts.contains(h)),
+      Scope::Domain => match url.host() {
+        Some(Host::Domain(domain)) => self
+          .domains
+          .contains(self.suffixes.registrable_domain(domain)),
+        _ => host.is_none_or(|h| self.hosts.contains(h)),
+      },
+      Scope::All => true,
+    }
+  }
+}
+
+/// Query parameters stripped from URLs before deduplication and fetching,
+/// either for every host or for specific ones. A trailing `*` in a
+/// name matches any parameter with that prefix.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ParamStripRules {
+  pub(crate) global: Vec<String>,
+  pub(crate) per_host: HashMap<String, Vec<String>>,
+}
+
+impl ParamStripRules {
+  pub(crate) fn insert(&mut self, host: Option<String>, name: String) {
+    match host {
+      Some(host) => self.per_host.entry(host).or_default().push(name),
+      None => self.global.push(name),
+    }
+  }
+
+  pub(crate) fn apply(&self, url: &mut Url) {
+    if url.query().is_none() {
+      return;
+    }
+    let host_rules = url
+      .host_str()
+      .and_then(|h| self.per_host.get(h))
+      .into_iter()
+      .flatten();
+    let rules: Vec<&String> = self.global.iter().chain(host_rules).collect();
+    let matches = |param: &str| {
+      rules.iter().any(|rule| match rule.strip_suffix('*') {
+        Some(prefix) => param.starts_with(prefix),
+        None => param == rule.as_str(),
+      })
+    };
+
+    let params: Vec<&str> = url.query().unwrap_or_default().split('&').collect();
+    let kept: Vec<&str> = params
+      .iter()
+      .copied()
+      .filter(|param| !matches(&query_param_name(param)))
+      .collect();
+    if kept.len() == params.len() {
+      return;
+    }
+    let query = kept.join("&");
+    url.set_query((!query.is_empty()).then_some(query.as_str()));
+  }
+}
+
+/// The decoded name of a raw `name=value` query parameter. Only names are
+/// decoded, so that parameters can be matched, dropped or reordered without
+/// re-encoding the ones that stay.
+pub(crate) fn query_param_name(param: &str) -> String {
+  let name = param.split_once('=').map_or(param, |(name, _)| name);
+  percent_encoding::percent_decode_str(&name.replace('+', " "))
+    .decode_utf8_lossy()
+    .into_owned()
+}
+
+/// How URLs are rewritten before they are deduplicated, on top of what parsing
+/// them already does: lowercasing the scheme and host, dropping default ports
+/// and resolving dot segments. Fragments are always removed, the rest is
+/// opt-in because servers may tell the forms apart.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Canonicalization {
+  /// Drops the trailing slash of every path but `/`.
+  pub(crate) strip_trailing_slash: bool,
+  /// Sorts query parameters by name, keeping repeated ones in order.
+  pub(crate) sort_query: bool,
+}
+
+impl Canonicalization {
+  pub(crate) fn apply(self, url: &mut Url) {
+    url.set_fragment(None);
+    if self.strip_trailing_slash && url.path().len() > 1 && url.path().ends_with('/') {
+      let path = url.path().trim_end_matches('/').to_owned();
+      url.set_path(&path);
+    }
+    if self.sort_query && url.query().is_some() {
+      let mut params: Vec<&str> = url.query().unwrap_or_default().split('&').collect();
+      params.sort_by_cached_key(|param| query_param_name(param));
+      let query = params.join("&");
+      url.set_query((!query.is_empty()).then_some(query.as_str()));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn registrable_domain_uses_every_builtin_suffix() {
+    let suffixes = PublicSuffixList::default();
+    assert_eq!(
+      suffixes.registrable_domain("blog.example.co.uk"),
+      "example.co.uk"
+    );
+    assert_eq!(
+      suffixes.registrable_domain("www.example.gov.uk"),
+      "example.gov.uk"
+    );
+    assert_eq!(
+      suffixes.registrable_domain("a.b.example.co.jp"),
+      "example.co.jp"
+    );
+    assert_eq!(suffixes.registrable_domain("me.gitlab.io"), "me.gitlab.io");
+    assert_eq!(
+      suffixes.registrable_domain("site.netlify.app"),
+      "site.netlify.app"
+    );
+    assert_eq!(
+      suffixes.registrable_domain("www.example.com"),
+      "example.com"
+    );
+    assert_eq!(suffixes.registrable_domain("co.uk"), "co.uk");
+  }
+
+  #[test]
+  fn registrable_domain_follows_wildcards_and_exceptions() {
+    let suffixes = PublicSuffixList::parse("// comment\nck\n*.ck\n!www.ck\n");
+    assert_eq!(
+      suffixes.registrable_domain("a.b.example.ck"),
+      "b.example.ck"
+    );
+    assert_eq!(suffixes.registrable_domain("www.ck"), "www.ck");
+    assert_eq!(suffixes.registrable_domain("a.www.ck"), "www.ck");
+  }
+
+  #[test]
+  fn hsts_requires_a_max_age_and_honors_include_subdomains() {
+    let parse = StrictTransportSecurity::parse;
+    assert_eq!(
+      parse("max-age=31536000; includeSubDomains"),
+      Some(StrictTransportSecurity {
+        max_age: 31536000,
+        include_subdomains: true
+      })
+    );
+    assert_eq!(parse("MAX-AGE=\"60\"").map(|h| h.max_age), Some(60));
+    assert_eq!(parse("includeSubDomains"), None);
+    assert_eq!(parse("max-age=soon"), None);
+    assert_eq!(parse("max-age=1; max-age=2"), None);
+
+    let url = |s: &str| Url::parse(s).unwrap();
+    let mut upgrades = HttpsUpgrades::default();
+    upgrades.record(&url("https://www.a.example/"), parse("max-age=0").unwrap());
+    upgrades.record(
+      &url("https://a.example/"),
+      parse("max-age=60; includeSubDomains").unwrap(),
+    );
+    for (link, upgraded) in [
+      ("http://a.example/", "https://a.example/"),
+      ("http://cdn.a.example/x", "https://cdn.a.example/x"),
+      ("http://b.example/", "http://b.example/"),
+      ("http://aa.example/", "http://aa.example/"),
+    ] {
+      let mut link = url(link);
+      upgrades.upgrade(&mut link);
+      assert_eq!(link.as_str(), upgraded);
+    }
+
+    upgrades.record(&url("https://a.example/"), parse("max-age=0").unwrap());
+    let mut link = url("http://cdn.a.example/");
+    upgrades.upgrade(&mut link);
+    assert_eq!(link.scheme(), "http");
+  }
+
+  #[test]
+  fn stripped_and_sorted_queries_keep_their_encoding() {
+    let mut rules = ParamStripRules::default();
+    rules.insert(None, "utm_*".into());
+    let mut url = Url::parse("https://example.com/a?q=a%20b+c&utm_source=x&v=2").unwrap();
+    rules.apply(&mut url);
+    assert_eq!(url.as_str(), "https://example.com/a?q=a%20b+c&v=2");
+
+    let canonicalization = Canonicalization {
+      strip_trailing_slash: false,
+      sort_query: true,
+    };
+    let mut url = Url::parse("https://example.com/?z=%2F&a%20b=1&a=2").unwrap();
+    canonicalization.apply(&mut url);
+    assert_eq!(url.as_str(), "https://example.com/?a=2&a%20b=1&z=%2F");
+  }
+
+  #[test]
+  fn cache_buster_stripping_is_opt_in() {
+    assert!(!DispatcherBuilder::default().default_strip_params);
+    assert!(
+      DispatcherBuilder::default()
+        .default_strip_params(true)
+        .default_strip_params
+    );
+  }
+
+  #[test]
+  fn robots_groups_match_the_product_token() {
+    let robots_txt = "User-agent: *\nDisallow: /\n\nUser-agent: Crawler/2.0\nDisallow: /private\n";
+    let rules = RobotsRules::parse(robots_txt, "crawler/0.1 (+https://a.example)");
+    assert!(rules.allows("/public"));
+    assert!(!rules.allows("/private/a"));
+
+    let rules = RobotsRules::parse(robots_txt, "OtherBot/1.0");
+    assert!(!rules.allows("/public"));
+    assert_eq!(product_token(" Googlebot-Image/1.0"), "googlebot-image");
+  }
+
+  #[tokio::test]
+  async fn released_findings_do_not_count_a_visit() {
+    let frontier = MemoryFrontier::default();
+    let finding = Finding::Page(Url::parse("https://a.example/").unwrap(), 0);
+    assert_eq!(frontier.dispatch(&finding).await, None);
+    frontier.complete(&finding).await;
+    frontier.release(&finding).await;
+    assert_eq!(frontier.dispatch(&finding).await, None);
+    let host = Host::parse("a.example").unwrap();
+    assert_eq!(frontier.host_visits().await[&host], 1);
+  }
+
+  #[tokio::test]
+  async fn frontiers_hand_out_bounded_batches_in_order() {
+    let frontier = MemoryFrontier::default();
+    let pages: Vec<_> = (0..5)
+      .map(|i| Finding::Page(Url::parse(&format!("https://a.example/{}", i)).unwrap(), 0))
+      .collect();
+    for page in &pages {
+      frontier.offer(page.clone()).await;
+    }
+    frontier
+      .offer(Finding::Page(Url::parse("https://b.example/").unwrap(), 0))
+      .await;
+    let hosts = frontier.queued_per_host().await;
+    assert_eq!(hosts[&Host::parse("a.example").unwrap()], 5);
+    assert_eq!(hosts[&Host::parse("b.example").unwrap()], 1);
+
+    assert_eq!(frontier.next(2).await, pages[..2]);
+    assert_eq!(frontier.len().await, 4);
+    assert_eq!(frontier.next(3).await, pages[2..]);
+    assert_eq!(frontier.len().await, 1);
+    frontier.next(10).await;
+    assert!(frontier.is_empty().await);
+    assert!(frontier.next(10).await.is_empty());
+  }
+
+  #[test]
+  fn x_robots_tag_agent_prefixes_scope_directives() {
+    let values =
+      |values: &[&str]| -> Vec<String> { values.iter().map(|v| v.to_string()).collect() };
+    let agent = "crawler/0.1 (+https://a.example)";
+    let directives = x_robots_directives(
+      &values(&["googlebot: noindex", "crawler: nofollow", "noarchive"]),
+      agent,
+    );
+    assert_eq!(directives.as_deref(), Some("nofollow, noarchive"));
+    assert!(!has_robots_directive(&directives, "noindex"));
+    let directives = x_robots_directives(
+      &values(&[
+        "unavailable_after: 25 jun 2010 15:00:00 pst",
+        "otherbot: none",
+      ]),
+      agent,
+    );
+    assert_eq!(
+      directives.as_deref(),
+      Some("unavailable_after: 25 jun 2010 15:00:00 pst")
+    );
+    assert_eq!(x_robots_directives(&[], agent), None);
+  }
+
+  #[test]
+  fn index_equivalence_is_settled_per_directory() {
+    let page = |s: &str| Finding::Page(Url::parse(s).unwrap(), 1);
+    let mut equivalence = IndexEquivalence::default();
+    equivalence.record(page("https://a.example/a").url(), 200, 1);
+    equivalence.record(page("https://a.example/a/").url(), 200, 1);
+    assert!(equivalence.covers(&page("https://a.example/a/index.html")));
+    assert!(!equivalence.covers(&page("https://a.example/b/index.html")));
+
+    equivalence.record(page("https://a.example/b").url(), 200, 2);
+    equivalence.record(page("https://a.example/b/").url(), 200, 3);
+    assert!(!equivalence.covers(&page("https://a.example/b/index.html")));
+    assert!(equivalence.covers(&page("https://a.example/a/index.htm")));
+  }
+
+  #[test]
+  fn unreachable_robots_txt_disallows_everything() {
+    let robots = |status| RobotsFile::new(status, String::new(), "test");
+    assert!(robots(Some(404)).rules.allows("/a"));
+    assert!(!robots(Some(503)).rules.allows("/a"));
+    assert!(!robots(None).rules.allows("/"));
+    assert!(robots(Some(404)).is_fresh());
+    assert!(!robots(Some(503)).is_fresh());
+  }
+
+  #[test]
+  fn maintenance_revisits_every_host_once() {
+    let down = |s: &str| {
+      let finding = Finding::Page(Url::parse(s).unwrap(), 0);
+      eyre::Report::new(MaintenanceResponse(finding))
+    };
+    let mut maintenance = MaintenanceDeferral::default();
+    assert!(maintenance.defer(&down("https://a.example/")));
+    assert_eq!(maintenance.revisit().len(), 1);
+
+    // A revisited host is not deferred again, one that goes down later is.
+    assert!(!maintenance.defer(&down("https://a.example/")));
+    assert!(maintenance.defer(&down("https://b.example/")));
+    assert!(maintenance.is_down(&Url::parse("https://b.example/x").unwrap()));
+    assert_eq!(maintenance.revisit().len(), 1);
+    assert!(!maintenance.defer(&down("https://b.example/")));
+  }
+
+  #[test]
+  fn refunded_pages_do_not_count_against_the_seed_budget() {
+    let seed = Url::parse("https://a.example/").unwrap();
+    let mut seeds = SeedFrontier::new(&HashSet::from([seed.clone()]), &HashMap::new(), Some(1));
+    let page = Finding::Page(seed, 0);
+    assert!(seeds.admit(&page));
+    seeds.refund(&page);
+    assert!(seeds.admit(&page));
+    assert!(!seeds.admit(&page));
+  }
+
+  #[test]
+  fn url_filter_rejections_name_the_pattern() {
+    let filters = UrlFilters {
+      include: vec![Regex::new("/docs/").unwrap()],
+      exclude: vec![Regex::new("/private/").unwrap()],
+    };
+    let url = |s: &str| Url::parse(s).unwrap();
+    assert_eq!(filters.rejection(&url("https://a.example/docs/a")), None);
+    assert_eq!(
+      filters
+        .rejection(&url("https://a.example/docs/private/a"))
+        .as_deref(),
+      Some("matches exclude pattern `/private/`")
+    );
+    assert_eq!(
+      filters
+        .rejection(&url("https://a.example/blog/"))
+        .as_deref(),
+      Some("matches no include pattern")
+    );
+  }
+
+  #[test]
+  fn checkpoints_are_versioned() {
+    let path = std::env::temp_dir().join(format!("crawler-checkpoint-{}.json", std::process::id()));
+    std::fs::write(
+      &path,
+      r#"{"seeds":["https://a.example/"],"frontier":["page 1 https://a.example/b"]}"#,
+    )
+    .unwrap();
+    assert_eq!(Checkpoint::read(&path).unwrap().frontier.len(), 1);
+    assert_eq!(Checkpoint::migrate(&path).unwrap(), 0);
+    assert_eq!(Checkpoint::migrate(&path).unwrap(), CHECKPOINT_VERSION);
+    let migrated = Checkpoint::read(&path).unwrap();
+    assert_eq!(migrated.seeds, [Url::parse("https://a.example/").unwrap()]);
+    assert_eq!(migrated.frontier.len(), 1);
+
+    let mut newer = migrated.to_json();
+    newer["version"] = (CHECKPOINT_VERSION + 1).into();
+    std::fs::write(&path, newer.to_string()).unwrap();
+    assert!(Checkpoint::read(&path).is_err());
+    assert!(Checkpoint::migrate(&path).is_err());
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[test]
+  fn robots_rules_pick_the_longest_match() {
+    let robots_txt = "\
+      User-agent: otherbot\n\
+      User-agent: crawler\n\
+      Disallow: /shop\n\
+      Allow: /shop/public\n\
+      Disallow: /*.pdf$\n\
+      Allow: /page\n\
+      Disallow: /page\n\
+      Crawl-delay: 2.5\n\
+      \n\
+      User-agent: *\n\
+      Disallow: /\n";
+    let rules = RobotsRules::parse(robots_txt, "crawler");
+    assert!(rules.allows("/"));
+    assert!(!rules.allows("/shop/cart"));
+    assert!(rules.allows("/shop/public/a"));
+    assert!(!rules.allows("/docs/a.pdf"));
+    assert!(rules.allows("/docs/a.pdf?download"));
+    assert!(rules.allows("/page"));
+    assert_eq!(rules.crawl_delay, Some(Duration::from_millis(2500)));
+    assert_eq!(
+      rules
+        .matching("/shop/cart")
+        .map(RobotsRule::line)
+        .as_deref(),
+      Some("Disallow: /shop")
+    );
+    assert!(RobotsRules::parse("Disallow: /\n", "crawler").allows("/a"));
+    assert!(RobotsRules::parse("User-agent: *\nDisallow:\n", "crawler").allows("/a"));
+  }
+
+  #[test]
+  fn equivalence_key_normalizes_percent_encodings() {
+    let key = |s: &str| equivalence_key(&Url::parse(s).unwrap()).into_owned();
+    assert_eq!(key("https://a.example/%7Euser"), "https://a.example/~user");
+    assert_eq!(key("https://a.example/a%2fb"), "https://a.example/a%2Fb");
+    assert_eq!(
+      key("https://a.example/a+b?q=a+b"),
+      "https://a.example/a+b?q=a%20b"
+    );
+    assert_eq!(
+      key("https://a.example/?q=a%20b"),
+      key("https://a.example/?q=a+b")
+    );
+    assert_ne!(key("https://a.example/a%20b"), key("https://a.example/a+b"));
+  }
+}
@@ -0,0 +1,2783 @@
+use std::{
+  borrow::Borrow,
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+  fmt::Write as _,
+  hash::{Hash, Hasher},
+  io::Write as _,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, LazyLock, Mutex,
+  },
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{
+  future::{self, BoxFuture},
+  prelude::*,
+};
+use reqwest::Client;
+use tokio::{
+  fs::File,
+  io::AsyncWriteExt,
+  sync::{watch, OwnedSemaphorePermit, Semaphore},
+  task::{self, JoinSet},
+};
+
+use bytes::Bytes;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use url::{Host, ParseError, Url};
+
+use eyre::{Result, WrapErr as _};
+use tracing::{debug, info, warn};
+
+mod extract;
+mod fetch;
+mod frontier;
+mod report;
+mod storage;
+
+use extract::*;
+use fetch::*;
+use frontier::*;
+use report::*;
+use storage::*;
+
+pub use fetch::{FetchResponse, Fetcher, MemoryFetcher};
+pub use frontier::{
+  Checkpoint, Finding, Frontier, FrontierSnapshot, MemoryFrontier, NofollowPolicy, Politeness,
+  PublicSuffixList,
+};
+pub use report::SkipReason;
+#[cfg(feature = "images")]
+pub use storage::ConvertedFormat;
+pub use storage::{
+  DedupLinks, FileStorage, MemoryStorage, NullStorage, PageRules, PathTemplate, ResourceMetadata,
+  Storage,
+};
+
+const TIMEOUT_DURATION: Duration = Duration::from_millis(5000);
+/// How many redirects are followed from a request before it fails.
+const REDIRECT_LIMIT: usize = 10;
+pub const DEFAULT_RECURSION_DEPTH_LIMIT: u8 = 4;
+const HOST_VISIT_LIMIT: u32 = 256;
+/// How many queued findings the dispatcher takes from the frontier at once.
+const DISPATCH_BATCH_SIZE: usize = 256;
+/// How often a request may be put off by `429` or `Retry-After` before it
+/// fails.
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// How long a host is left alone after a `429` without `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// The longest pause a `Retry-After` is honored with.
+const RETRY_AFTER_LIMIT: Duration = Duration::from_secs(3600);
+const PROGRESS_INTERVAL: usize = 25;
+const DISCOVERY_WINDOW: usize = 50;
+const SATURATION_THRESHOLD: f64 = 0.05;
+const TRAP_WINDOW: usize = 32;
+const TRAP_NOVELTY_THRESHOLD: f64 = 0.1;
+const TRAP_TEMPLATE_SHARE: f64 = 0.5;
+/// Query parameters commonly used to bust caches of static assets.
+const DEFAULT_STRIP_PARAMS: &[&str] = &[
+  "v",
+  "ver",
+  "version",
+  "ts",
+  "timestamp",
+  "cb",
+  "cachebust",
+  "_",
+];
+/// Query parameters commonly added to links for analytics.
+const DEFAULT_TRACKING_PARAMS: &[&str] = &["utm_*", "fbclid", "gclid"];
+const RESPONSE_CACHE_LIMIT: usize = 256 * 1024 * 1024;
+/// Default span over which the transfer rate of a body is measured.
+pub const DEFAULT_MIN_TRANSFER_WINDOW: Duration = Duration::from_secs(30);
+/// How much of a body is looked at to tell what it is, as in the WHATWG MIME
+/// sniffing algorithm.
+const SNIFF_LENGTH: usize = 1445;
+/// How much of a body downloaded to a file is kept in memory, for sniffing
+/// its type.
+const DOWNLOAD_PREFIX_LENGTH: usize = 8 * 1024;
+/// Directory of the output directory that assets are downloaded into before
+/// they are stored.
+const SPOOL_DIR_NAME: &str = ".partial";
+/// File in the directory of a [`FileStorage`] recording where every URL was
+/// stored. Saved files never start with a dot, so it cannot clash with one.
+pub const STORAGE_MANIFEST_NAME: &str = ".manifest.jsonl";
+/// Extensions of URLs that are expected to be web pages, and are not sampled
+/// with `sample_pages`.
+const PAGE_EXTENSIONS: &[&str] = &[
+  "html", "htm", "xhtml", "shtml", "php", "asp", "aspx", "jsp", "jspx", "cfm", "cgi", "pl",
+];
+/// Tags that make a body starting with one of them HTML, in lower case.
+const HTML_SNIFF_TAGS: &[&str] = &[
+  "<!doctype html",
+  "<html",
+  "<head",
+  "<script",
+  "<iframe",
+  "<h1",
+  "<div",
+  "<font",
+  "<table",
+  "<a",
+  "<style",
+  "<title",
+  "<b",
+  "<body",
+  "<br",
+  "<p",
+  "<!--",
+];
+/// Maximum factor by which a compressed body may grow when decompressed.
+const DECOMPRESSION_RATIO_LIMIT: usize = 100;
+const EVENT_LOG_COMPRESSION_LEVEL: i32 = 3;
+/// How many events go into one frame of the event log at most.
+const EVENT_LOG_FRAME_EVENTS: u32 = 10_000;
+/// How long a frame of the event log stays open at most.
+const EVENT_LOG_FRAME_INTERVAL: Duration = Duration::from_secs(10);
+#[cfg(feature = "images")]
+pub const DEFAULT_IMAGE_QUALITY: u8 = 85;
+pub const DEFAULT_OUTPUT_DIR: &str = "prey";
+#[cfg(feature = "warc")]
+const DEFAULT_WARC_NAME: &str = "crawl.warc.gz";
+const REPORT_FILE_NAME: &str = "report.md";
+pub const DEFAULT_CONTACT_URL: &str = "https://github.com/luiswirth/crawler";
+pub const DEFAULT_POLITENESS_DELAY_MS: u64 = 500;
+pub const DEFAULT_CONCURRENCY: usize = 64;
+pub const DEFAULT_PER_HOST_CONCURRENCY: usize = 4;
+/// How much earlier than its delay a request may be sent before the politeness
+/// audit counts it against the host, to allow for timer jitter.
+const AUDIT_TIMER_TOLERANCE: Duration = Duration::from_millis(10);
+/// How many queued URLs must go to a host for it to be resolved ahead with
+/// `preconnect`.
+#[cfg(feature = "dns-pinning")]
+const PRECONNECT_QUEUE_THRESHOLD: usize = 8;
+/// The documents treated like their directory with `index_equivalence`.
+const INDEX_DOCUMENTS: &[&str] = &["index.html", "index.htm"];
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+/// The version of the checkpoint format that is written. Older checkpoints
+/// are upgraded as they are read, newer ones are refused.
+pub const CHECKPOINT_VERSION: u64 = 1;
+/// Longest `Crawl-delay` of a robots.txt that is honored as is.
+const CRAWL_DELAY_LIMIT: Duration = Duration::from_secs(60);
+/// How long a robots.txt from a checkpoint is used before it is fetched again,
+/// the longest RFC 9309 recommends caching it for.
+const ROBOTS_CACHE_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl DispatcherBuilder {
+  /// Builds a dispatcher, runs it and writes all of its outputs.
+  pub async fn crawl(self) -> Result<Dispatcher> {
+    let mut dispatcher = self.build()?;
+    dispatcher.run().await;
+    dispatcher.finish().await?;
+    Ok(dispatcher)
+  }
+
+  /// Crawls the seeds of every host with a separate dispatcher whose artifacts
+  /// all go to a directory of its own, and writes an `index.json` describing
+  /// the layout to the output root.
+  pub async fn crawl_per_seed(self) -> Result<()> {
+    if self.frontier.is_some() {
+      eyre::bail!("crawling per seed needs a frontier per seed, not one given to the builder");
+    }
+    let mut groups: BTreeMap<String, (HashSet<Url>, Vec<Url>)> = BTreeMap::new();
+    for url in &self.inital_urls {
+      groups
+        .entry(seed_directory_name(url))
+        .or_default()
+        .0
+        .insert(url.clone());
+    }
+    for url in &self.sitemaps {
+      groups
+        .entry(seed_directory_name(url))
+        .or_default()
+        .1
+        .push(url.clone());
+    }
+
+    let crawls = groups.into_iter().map(|(name, (seeds, sitemaps))| {
+      let directory = self.output_dir.join(&name);
+      let within = |path: &Path| directory.join(path.file_name().unwrap_or(path.as_os_str()));
+      let builder = DispatcherBuilder {
+        inital_urls: seeds.clone(),
+        sitemaps,
+        report_path: self.report_path.as_deref().map(within),
+        plan_path: self.plan_path.as_deref().map(within),
+        graph_path: self.graph_path.as_deref().map(within),
+        sitemap_path: self.sitemap_path.as_deref().map(within),
+        event_log_path: self.event_log_path.as_deref().map(within),
+        findings_export_path: self.findings_export_path.as_deref().map(within),
+        checkpoint_path: self.checkpoint_path.as_deref().map(within),
+        #[cfg(feature = "warc")]
+        warc_output: self.warc_output.as_deref().map(within),
+        asset_dir: self.asset_dir.as_deref().map(|dir| dir.join(&name)),
+        output_dir: directory.clone(),
+        ..self.clone()
+      };
+      async move {
+        let mut entry = serde_json::json!({
+          "directory": name,
+          "seeds": seeds.iter().map(Url::as_str).collect::<BTreeSet<_>>(),
+          "sitemaps": builder.sitemaps.iter().map(Url::as_str).collect::<Vec<_>>(),
+          "report": builder.report_path.clone().unwrap_or_else(|| directory.join(REPORT_FILE_NAME)),
+        });
+        match builder.crawl().await {
+          Ok(dispatcher) => {
+            entry["pages_crawled"] = dispatcher.pages_crawled().into();
+            entry["assets_fetched"] = dispatcher.assets_fetched().into();
+          }
+          Err(e) => {
+            warn!("Crawl of `{}` failed with error: {}", name, e);
+            entry["error"] = e.to_string().into();
+          }
+        }
+        entry
+      }
+    });
+    let entries = future::join_all(crawls).await;
+
+    tokio::fs::create_dir_all(&self.output_dir).await?;
+    let index_path = self.output_dir.join("index.json");
+    let index = serde_json::json!({ "crawls": entries });
+    tokio::fs::write(&index_path, serde_json::to_string_pretty(&index)?).await?;
+    info!("index written to `{}`", index_path.display());
+    Ok(())
+  }
+}
+
+/// Directory name for the outputs of a seed: its host, plus the port if it is
+/// not the scheme's default.
+fn seed_directory_name(url: &Url) -> String {
+  let host = url.host_str().unwrap_or("unknown");
+  match url.port() {
+    Some(port) => format!("{}_{}", host, port),
+    None => host.to_owned(),
+  }
+}
+
+/// What a running crawl task is working on.
+#[derive(Debug, Clone)]
+struct TaskLabel {
+  url: Url,
+  kind: &'static str,
+  /// The depth of the page, if the task crawls one.
+  depth: Option<u8>,
+  spawned: Instant,
+}
+
+impl TaskLabel {
+  fn finding(&self) -> Finding {
+    match self.depth {
+      Some(depth) => Finding::Page(self.url.clone(), depth),
+      None => Finding::asset(self.kind, self.url.clone()).expect("tasks are labeled by kind"),
+    }
+  }
+}
+
+/// When the request of a task was sent, its latency and the result of the
+/// task, or the error of its join handle if it panicked or was cancelled.
+type TaskOutput<T> = Result<(Instant, Duration, Result<T>), task::JoinError>;
+
+/// Running crawl tasks, labeled so that panics and slow tasks can be traced
+/// back to their URL. Every task evaluates to when its request was sent, its
+/// latency and its result.
+struct TaskSet<T> {
+  tasks: JoinSet<(Instant, Duration, Result<T>)>,
+  labels: HashMap<task::Id, TaskLabel>,
+}
+
+impl<T> Default for TaskSet<T> {
+  fn default() -> Self {
+    Self {
+      tasks: JoinSet::new(),
+      labels: HashMap::new(),
+    }
+  }
+}
+
+impl<T> std::fmt::Debug for TaskSet<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TaskSet")
+      .field("labels", &self.labels)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<T: Send + 'static> TaskSet<T> {
+  fn spawn(
+    &mut self,
+    finding: &Finding,
+    task: impl Future<Output = (Instant, Duration, Result<T>)> + Send + 'static,
+  ) {
+    let id = self.tasks.spawn(task).id();
+    let label = TaskLabel {
+      url: finding.url().clone(),
+      kind: finding.kind(),
+      depth: finding.depth(),
+      spawned: Instant::now(),
+    };
+    self.labels.insert(id, label);
+  }
+
+  fn len(&self) -> usize {
+    self.tasks.len()
+  }
+
+  fn is_empty(&self) -> bool {
+    self.tasks.is_empty()
+  }
+
+  /// Waits for the next task to finish.
+  async fn join_next(&mut self) -> Option<(TaskLabel, TaskOutput<T>)> {
+    let (id, output) = match self.tasks.join_next_with_id().await? {
+      Ok((id, output)) => (id, Ok(output)),
+      Err(e) => (e.id(), Err(e)),
+    };
+    let label = self.labels.remove(&id).expect("every task is labeled");
+    Some((label, output))
+  }
+
+  /// The depth of the shallowest page being crawled.
+  fn shallowest(&self) -> Option<u8> {
+    self.labels.values().filter_map(|label| label.depth).min()
+  }
+
+  /// The task that has been running the longest.
+  fn oldest(&self) -> Option<&TaskLabel> {
+    self.labels.values().min_by_key(|label| label.spawned)
+  }
+}
+
+/// Waits for the next [`Reload`], forever if there are none.
+async fn changed(
+  reloads: &mut Option<watch::Receiver<Reload>>,
+) -> Result<(), watch::error::RecvError> {
+  match reloads {
+    Some(reloads) => reloads.changed().await,
+    None => future::pending().await,
+  }
+}
+
+/// Crawls from a set of seeds, built with a [`DispatcherBuilder`].
+#[derive(Debug)]
+pub struct Dispatcher {
+  backends: FetcherRegistry,
+  inital_urls: HashSet<Url>,
+  seed_tags: HashMap<Url, BTreeSet<String>>,
+  /// Sitemaps whose pages are seeds, see [`DispatcherBuilder::sitemap`].
+  sitemaps: Vec<Url>,
+  recursion_depth_limit: u8,
+  asset_depth_limit: u8,
+  mode: CrawlMode,
+  asset_classes: HashSet<AssetClass>,
+  frontier: Arc<dyn Frontier>,
+  /// The frontier of the checkpoint the crawl resumes from.
+  resumed: Option<Vec<Finding>>,
+  /// The checkpoint file and when it was last written.
+  checkpoint: Option<(PathBuf, Instant)>,
+  save: SaveOptions,
+  /// Where the report, plan and other artifacts of the crawl are written.
+  output_dir: PathBuf,
+  fetched_assets: usize,
+  dataset: Option<DatasetManifest>,
+  /// The WARC file every HTTP exchange is recorded in.
+  #[cfg(feature = "warc")]
+  warc: Option<(PathBuf, Arc<WarcWriter>)>,
+  /// Every saved page, if pages are saved at all.
+  saved_pages: Option<Vec<SavedPage>>,
+  page_rules: Arc<PageRules>,
+  page_provenance: bool,
+  /// See [`DispatcherBuilder::sample_pages`].
+  sample_pages: Option<usize>,
+  samples: Samples,
+  /// Random ID of this crawl, recorded in `run.json`.
+  crawl_id: Arc<str>,
+  politeness: Politeness,
+  /// Dispatch shallow pages first and space out requests to a host more the
+  /// shallower the page.
+  depth_paced: bool,
+  /// Resolve hosts with many queued requests ahead of them.
+  #[cfg(feature = "dns-pinning")]
+  preconnect: bool,
+  /// When the hosts of the queue were last resolved ahead.
+  #[cfg(feature = "dns-pinning")]
+  warmed_up: Option<Instant>,
+  /// Stop dispatching on Ctrl-C, so that the outputs are still written.
+  stop_on_interrupt: bool,
+  reloads: Option<watch::Receiver<Reload>>,
+  /// The robots.txt of every visited origin, if they are respected.
+  robots: RobotsCache,
+  schedule: HostSchedule,
+  pauses: HostPauses,
+  /// How often every finding was put off by [`RateLimited`] responses.
+  rate_limit_retries: HashMap<Finding, u32>,
+  /// The resolver of all HTTP requests, unless the system configuration could
+  /// not be read.
+  #[cfg(feature = "dns-pinning")]
+  dns: Option<PinnedResolver>,
+  geo: GeoDatabases,
+  concurrency: ConcurrencyLimits,
+  seeds: SeedFrontier,
+  event_log: Option<EventLog>,
+  findings_export: Option<FindingsExport>,
+  report_path: PathBuf,
+  skips: SkipLog,
+  inventory: Option<Inventory>,
+  contacts: Option<Contacts>,
+  dead_assets: DeadAssets,
+  https_upgrades: HttpsUpgrades,
+  param_strip_rules: ParamStripRules,
+  /// Analytics parameters stripped from every URL found.
+  tracking_params: ParamStripRules,
+  canonicalization: Canonicalization,
+  language_stats: LanguageStats,
+  licenses: Licenses,
+  progress: DiscoveryProgress,
+  traps: TrapDetector,
+  url_filters: UrlFilters,
+  scope: CrawlScope,
+  variant_policy: VariantPolicy,
+  respect_csp: bool,
+  /// Every announced AMP or mobile version, with the page that announced it.
+  variants: HashMap<Url, Url>,
+  index_equivalence: Option<IndexEquivalence>,
+  health: HostHealth,
+  audit: PolitenessAudit,
+  maintenance: MaintenanceDeferral,
+  page_metadata: PageMetadata,
+  canonicals: CanonicalHealth,
+  indexability: Indexability,
+  /// Findings to crawl instead of the seeds, read from a plan file. Links
+  /// discovered while executing a plan are not followed.
+  planned: Option<Vec<Finding>>,
+  on_finding: Option<FindingCallback>,
+  /// When set, nothing is downloaded; the dispatched findings are collected
+  /// and written to this plan file instead.
+  plan: Option<(PathBuf, Vec<Finding>)>,
+  /// The link graph, written to the file in the format its extension names.
+  graph: Option<(PathBuf, GraphFormat, LinkGraph)>,
+  /// The indexable pages fetched successfully and when they last changed,
+  /// written to this sitemap file.
+  sitemap: Option<(PathBuf, BTreeMap<Url, SystemTime>)>,
+
+  spiders: TaskSet<SpiderResponse>,
+  fetchers: TaskSet<Fetched>,
+}
+
+/// Which kinds of findings a crawl follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum CrawlMode {
+  /// Follow pages and download assets.
+  Full,
+  /// Follow pages but skip all downloads, for a fast structural crawl.
+  PagesOnly,
+  /// Download the assets of the seed pages without recursing.
+  AssetsOnly,
+}
+
+/// How the responses of a crawl are archived, besides the assets handed to the
+/// storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+  /// Only the assets are kept, as files by default.
+  Files,
+  /// Every HTTP exchange is also recorded in a WARC 1.1 file, for replay in
+  /// pywb and other archive tooling.
+  #[cfg(feature = "warc")]
+  Warc,
+}
+
+/// Which discovered pages a crawl follows, relative to the seeds. Assets are
+/// fetched wherever they are hosted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Scope {
+  /// Only the seed pages themselves.
+  Page,
+  /// Pages on the hosts of the seeds.
+  #[default]
+  Host,
+  /// Pages on the registrable domains of the seeds, including subdomains,
+  /// like `blog.example.co.uk` for a seed on `www.example.co.uk`.
+  Domain,
+  /// Every page linked to, across the whole web.
+  All,
+}
+
+impl Scope {
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      Scope::Page => "page",
+      Scope::Host => "host",
+      Scope::Domain => "domain",
+      Scope::All => "all",
+    }
+  }
+}
+
+/// The settings a running crawl takes over from [`DispatcherBuilder::reloads`]:
+/// the delay between requests to a host and the URL filters.
+#[derive(Debug, Clone)]
+pub struct Reload {
+  pub(crate) delay: Duration,
+  pub(crate) url_filters: UrlFilters,
+}
+
+impl Reload {
+  /// Like [`Politeness::new`] with `delay`, [`DispatcherBuilder::include`]
+  /// with every regex of `include` and [`DispatcherBuilder::exclude`] with
+  /// those of `exclude`.
+  pub fn new(delay: Duration, include: Vec<Regex>, exclude: Vec<Regex>) -> Self {
+    Self {
+      delay,
+      url_filters: UrlFilters { include, exclude },
+    }
+  }
+}
+
+/// What to do with the AMP and mobile versions of pages, which pages announce
+/// with `<link rel="amphtml">` and `<link rel="alternate" media="...">`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum VariantPolicy {
+  /// Crawl them like linked pages.
+  Crawl,
+  /// Skip links to them.
+  #[default]
+  Skip,
+  /// Follow links to them to the page that announced them instead.
+  Canonical,
+}
+
+impl CrawlMode {
+  fn follows_pages(self) -> bool {
+    self != CrawlMode::AssetsOnly
+  }
+
+  fn fetches_assets(self) -> bool {
+    self != CrawlMode::PagesOnly
+  }
+}
+
+/// The kinds of assets a crawl can download, see [`DispatcherBuilder::assets`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AssetClass {
+  Images,
+  Stylesheets,
+  Scripts,
+  Fonts,
+  Icons,
+  /// Video and audio.
+  Media,
+}
+
+impl Dispatcher {
+  fn new(builder: DispatcherBuilder) -> Result<Self> {
+    let DispatcherBuilder {
+      inital_urls,
+      seed_tags,
+      sitemaps,
+      recursion_depth_limit,
+      asset_depth_limit,
+      mode,
+      asset_classes,
+      output_dir,
+      dedup,
+      save_headers,
+      skip_existing,
+      path_template,
+      #[cfg(feature = "images")]
+      convert_images,
+      dataset_manifest,
+      save_pages,
+      page_rules,
+      page_provenance,
+      sample_pages,
+      max_file_size,
+      min_transfer_rate,
+      #[cfg(feature = "warc")]
+      format,
+      #[cfg(not(feature = "warc"))]
+        format: _,
+      #[cfg(feature = "warc")]
+      warc_output,
+      proxy,
+      politeness,
+      report_path,
+      verbose_report,
+      event_log_path,
+      findings_export_path,
+      inventory_forms,
+      extract_contacts,
+      planned,
+      plan_path,
+      graph_path,
+      sitemap_path,
+      serve_from,
+      scheme_fetchers,
+      host_fetchers,
+      cache_rules,
+      url_filters,
+      scope,
+      public_suffixes,
+      variant_policy,
+      respect_csp,
+      strip_params,
+      default_strip_params,
+      strip_tracking_params,
+      default_tracking_params,
+      canonicalization,
+      per_seed_budget,
+      depth_paced,
+      #[cfg(feature = "dns-pinning")]
+      preconnect,
+      stop_on_interrupt,
+      reloads,
+      index_equivalence,
+      storage,
+      frontier,
+      asset_dir,
+      concurrency,
+      per_host_concurrency,
+      #[cfg(feature = "geoip")]
+      asn_database,
+      #[cfg(feature = "geoip")]
+      country_database,
+      checkpoint_path,
+      resume,
+      on_finding,
+    } = builder;
+    let asset_depth_limit = asset_depth_limit.unwrap_or(recursion_depth_limit);
+    let mut inital_urls = inital_urls;
+    if let Some(resume) = &resume {
+      inital_urls.extend(resume.seeds.iter().cloned());
+    }
+    let canonical = |mut url: Url| {
+      canonicalization.apply(&mut url);
+      url
+    };
+    let inital_urls: HashSet<Url> = inital_urls.into_iter().map(canonical).collect();
+    let mut seed_tags = seed_tags;
+    for (seed, tags) in std::mem::take(&mut seed_tags) {
+      seed_tags.entry(canonical(seed)).or_default().extend(tags);
+    }
+    let report_path = report_path.unwrap_or_else(|| output_dir.join(REPORT_FILE_NAME));
+    let storage = storage.unwrap_or_else(|| {
+      let root = asset_dir.unwrap_or_else(|| output_dir.join("res"));
+      let mut files = FileStorage::new(root).save_headers(save_headers);
+      if let Some(links) = dedup {
+        files = files.dedup(links);
+      }
+      Arc::new(files)
+    });
+
+    let mut param_strip_rules = ParamStripRules::default();
+    if default_strip_params {
+      for param in DEFAULT_STRIP_PARAMS {
+        param_strip_rules.insert(None, param.to_string());
+      }
+    }
+    for (host, param) in strip_params {
+      param_strip_rules.insert(host, param);
+    }
+    let mut tracking_params = ParamStripRules::default();
+    if default_tracking_params {
+      for param in DEFAULT_TRACKING_PARAMS {
+        tracking_params.insert(None, param.to_string());
+      }
+    }
+    for (host, param) in strip_tracking_params {
+      tracking_params.insert(host, param);
+    }
+
+    let robots = RobotsCache::new(&politeness.user_agent, resume.as_ref());
+    // The headers reqwest adds to every request, set explicitly so that they
+    // can be recorded with the others.
+    let mut default_headers = HeaderMap::new();
+    default_headers.insert(
+      reqwest::header::USER_AGENT,
+      HeaderValue::from_str(&politeness.user_agent)?,
+    );
+    default_headers.insert(reqwest::header::ACCEPT, HeaderValue::from_static("*/*"));
+    // Redirects are followed by the fetcher, which can then record them.
+    let mut client = Client::builder()
+      .connect_timeout(TIMEOUT_DURATION)
+      .redirect(reqwest::redirect::Policy::none())
+      .default_headers(default_headers.clone());
+    if let Some(proxy) = &proxy {
+      client = client.proxy(reqwest::Proxy::all(proxy.as_str())?);
+    }
+    #[cfg(feature = "dns-pinning")]
+    let dns = match PinnedResolver::from_system_conf() {
+      Ok(dns) => {
+        client = client.dns_resolver(Arc::new(dns.clone()));
+        Some(dns)
+      }
+      Err(e) => {
+        warn!("falling back to the default resolver: {}", e);
+        None
+      }
+    };
+    let client = client.build()?;
+    #[cfg(feature = "warc")]
+    let warc = match format {
+      OutputFormat::Files => None,
+      OutputFormat::Warc => {
+        let path = warc_output.unwrap_or_else(|| output_dir.join(DEFAULT_WARC_NAME));
+        Some((path.clone(), Arc::new(WarcWriter::create(&path)?)))
+      }
+    };
+    let mut http: Arc<dyn Fetcher> = Arc::new(HttpFetcher {
+      client,
+      default_headers,
+      #[cfg(feature = "dns-pinning")]
+      dns: dns.clone(),
+      max_body_size: max_file_size,
+      min_transfer_rate,
+      #[cfg(feature = "warc")]
+      warc: warc.as_ref().map(|(_, warc)| warc.clone()),
+    });
+    if !cache_rules.is_empty() {
+      http = Arc::new(CachingFetcher::new(http, cache_rules));
+    }
+    let mut backends = FetcherRegistry::new(http);
+    backends
+      .schemes
+      .insert("file".into(), Arc::new(FileFetcher { root: None }));
+    for (host, root) in serve_from {
+      backends
+        .hosts
+        .insert(host, Arc::new(FileFetcher { root: Some(root) }));
+    }
+    backends.schemes.extend(scheme_fetchers);
+    backends.hosts.extend(host_fetchers);
+
+    let https_upgrades = HttpsUpgrades {
+      hosts: inital_urls
+        .iter()
+        .filter(|u| u.scheme() == "https")
+        .filter_map(|u| u.host().map(|h| h.to_owned()))
+        .collect(),
+      ..Default::default()
+    };
+
+    Ok(Self {
+      seeds: SeedFrontier::new(&inital_urls, &seed_tags, per_seed_budget),
+      scope: CrawlScope::new(scope, public_suffixes, &inital_urls),
+      variant_policy,
+      respect_csp,
+      variants: HashMap::new(),
+      backends,
+      inital_urls,
+      seed_tags,
+      sitemaps,
+      recursion_depth_limit,
+      asset_depth_limit,
+      mode,
+      asset_classes,
+      frontier: frontier.unwrap_or_else(|| match &resume {
+        Some(checkpoint) => Arc::new(MemoryFrontier::resume(checkpoint)),
+        None => Arc::new(MemoryFrontier::default()),
+      }),
+      resumed: resume.as_ref().map(|r| r.frontier.clone()),
+      checkpoint: checkpoint_path.map(|path| (path, Instant::now())),
+      save: SaveOptions {
+        storage,
+        skip_existing,
+        path_template,
+        checksums: dataset_manifest,
+        spool_dir: output_dir.join(SPOOL_DIR_NAME),
+        #[cfg(feature = "images")]
+        convert_images,
+        claimed: Default::default(),
+      },
+      output_dir,
+      fetched_assets: 0,
+      dataset: dataset_manifest.then(DatasetManifest::default),
+      #[cfg(feature = "warc")]
+      warc,
+      saved_pages: save_pages.then(Vec::new),
+      page_rules: Arc::new(page_rules),
+      page_provenance,
+      sample_pages,
+      samples: Samples::default(),
+      crawl_id: crawl_id().into(),
+      politeness,
+      depth_paced,
+      #[cfg(feature = "dns-pinning")]
+      preconnect,
+      #[cfg(feature = "dns-pinning")]
+      warmed_up: None,
+      stop_on_interrupt,
+      reloads,
+      robots,
+      schedule: Default::default(),
+      pauses: Default::default(),
+      rate_limit_retries: HashMap::new(),
+      #[cfg(feature = "dns-pinning")]
+      dns,
+      #[cfg(feature = "geoip")]
+      geo: GeoDatabases::open(asn_database.as_deref(), country_database.as_deref())?,
+      #[cfg(not(feature = "geoip"))]
+      geo: GeoDatabases::default(),
+      concurrency: ConcurrencyLimits::new(concurrency, per_host_concurrency),
+      event_log: event_log_path
+        .as_deref()
+        .map(EventLog::create)
+        .transpose()?,
+      findings_export: findings_export_path
+        .as_deref()
+        .map(FindingsExport::create)
+        .transpose()?,
+      report_path,
+      skips: SkipLog {
+        verbose: verbose_report,
+        ..Default::default()
+      },
+      inventory: inventory_forms.then(Inventory::default),
+      contacts: extract_contacts.then(Contacts::default),
+      dead_assets: Default::default(),
+      https_upgrades,
+      param_strip_rules,
+      tracking_params,
+      canonicalization,
+      language_stats: Default::default(),
+      licenses: Default::default(),
+      progress: Default::default(),
+      traps: Default::default(),
+      url_filters,
+      index_equivalence: index_equivalence.then(IndexEquivalence::default),
+      health: Default::default(),
+      audit: Default::default(),
+      maintenance: Default::default(),
+      page_metadata: Default::default(),
+      canonicals: Default::default(),
+      indexability: Default::default(),
+      planned,
+      on_finding,
+      plan: plan_path.map(|path| (path, Vec::new())),
+      sitemap: sitemap_path.map(|path| (path, BTreeMap::new())),
+      graph: match graph_path {
+        Some(path) => Some((path.clone(), GraphFormat::of(&path)?, LinkGraph::default())),
+        None => None,
+      },
+      spiders: Default::default(),
+      fetchers: Default::default(),
+    })
+  }
+
+  /// Crawls until the frontier is exhausted.
+  pub async fn run(&mut self) {
+    let follow_links = self.planned.is_none();
+    let initial: Vec<Finding> = match (self.planned.take(), self.resumed.take()) {
+      (Some(planned), _) => planned,
+      (None, Some(frontier)) => frontier,
+      (None, None) => {
+        self.ingest_sitemaps().await;
+        self
+          .inital_urls
+          .iter()
+          .cloned()
+          .map(|u| Finding::Page(u, 0))
+          .collect()
+      }
+    };
+    for finding in initial {
+      self.frontier.offer(finding).await;
+    }
+
+    let stop_on_interrupt = self.stop_on_interrupt;
+    let interrupt = async move {
+      match stop_on_interrupt {
+        true => tokio::signal::ctrl_c().await,
+        false => future::pending().await,
+      }
+    };
+    tokio::pin!(interrupt);
+
+    self.progress.started = Some(Instant::now());
+    // Work is dispatched as soon as it is found, and every finished task makes
+    // room for the next.
+    loop {
+      if self.frontier.is_empty().await
+        && self.spiders.is_empty()
+        && self.fetchers.is_empty()
+        && self.robots.fetches.is_empty()
+      {
+        if self.maintenance.deferred.is_empty() {
+          break;
+        }
+        for finding in self.maintenance.revisit() {
+          self.frontier.offer(finding).await;
+        }
+      }
+
+      #[cfg(feature = "dns-pinning")]
+      if self.preconnect {
+        self.warm_up().await;
+      }
+      let mut dispatch = self
+        .seeds
+        .interleave(self.frontier.next(DISPATCH_BATCH_SIZE).await);
+      if self.depth_paced {
+        // Pages below the shallowest level still being crawled wait for it to
+        // complete.
+        let queued = dispatch.iter().filter_map(Finding::depth);
+        let level = queued.chain(self.spiders.shallowest()).min();
+        let (ready, held): (Vec<_>, Vec<_>) =
+          dispatch.into_iter().partition(|finding| match finding {
+            Finding::Page(_, depth) => Some(*depth) <= level,
+            _ => true,
+          });
+        dispatch = ready;
+        for finding in held {
+          self.frontier.offer(finding).await;
+        }
+      }
+      for finding in dispatch {
+        let url = finding.url();
+        if self.maintenance.is_down(url) {
+          self.log_event(Event::Deferred(url));
+          self.maintenance.deferred.push(finding);
+          continue;
+        }
+        if finding.is_asset() && self.save.already_saved(url).await {
+          self.log_event(Event::Skipped(url, SkipReason::Existing, None));
+          continue;
+        }
+        match self.robots_allow(&finding) {
+          Some(true) => {}
+          Some(false) => {
+            let detail = self.skip_detail(url, SkipReason::Robots);
+            self.log_event(Event::Skipped(url, SkipReason::Robots, detail.as_deref()));
+            continue;
+          }
+          None => continue,
+        }
+        if !self.seeds.admit(&finding) {
+          self.log_event(Event::Skipped(url, SkipReason::SeedBudget, None));
+          continue;
+        }
+
+        if let Some(reason) = self.frontier.dispatch(&finding).await {
+          self.seeds.refund(&finding);
+          self.log_event(Event::Skipped(url, reason, None));
+          continue;
+        }
+        self.log_event(Event::Dispatched(&finding));
+
+        if let Some((_, plan)) = &mut self.plan {
+          plan.push(finding.clone());
+          if finding.is_asset() {
+            self.frontier.complete(&finding).await;
+            continue;
+          }
+        }
+
+        match finding {
+          Finding::Page(_, depth) => {
+            self.indexability.crawled.insert(url.clone());
+            let (url, backend) = (url.clone(), self.backends.for_url(url));
+            let saving = self.saved_pages.is_some().then(|| PageSaving {
+              storage: self.save.storage.clone(),
+              rules: self.page_rules.clone(),
+              provenance: self.page_provenance.then(|| self.crawl_id.clone()),
+            });
+            let (sample, contacts) = (self.sample_pages, self.contacts.is_some());
+            let delay = self.page_delay(depth).max(self.crawl_delay(&url));
+            let slot = self.schedule.reserve(&url, delay);
+            let permits = self.concurrency.acquire(&url);
+            let (pauses, reserved) = (self.pauses.clone(), Instant::now());
+            self.spiders.spawn(&finding, async move {
+              pauses.wait(&url, reserved, slot).await;
+              let _permits = permits.await;
+              let (started, mut latency) = (Instant::now(), Duration::ZERO);
+              let result =
+                spider_page(url, backend, depth, saving, sample, contacts, &mut latency).await;
+              (started, latency, result)
+            })
+          }
+          _ => {
+            let (asset, backend) = (finding.clone(), self.backends.for_url(url));
+            let save = self.save.clone();
+            let delay = self.politeness.delay.max(self.crawl_delay(url));
+            let slot = self.schedule.reserve(url, delay);
+            let permits = self.concurrency.acquire(url);
+            let (pauses, reserved) = (self.pauses.clone(), Instant::now());
+            self.fetchers.spawn(&finding, async move {
+              pauses.wait(asset.url(), reserved, slot).await;
+              let _permits = permits.await;
+              let (started, mut latency) = (Instant::now(), Duration::ZERO);
+              let result = fetch(asset, backend, save, &mut latency).await;
+              (started, latency, result)
+            })
+          }
+        };
+      }
+
+      if self.spiders.is_empty() && self.fetchers.is_empty() && self.robots.fetches.is_empty() {
+        continue;
+      }
+      tokio::select! {
+        result = &mut interrupt => {
+          match result {
+            Ok(()) => warn!("Interrupted, stopping the crawl"),
+            Err(e) => warn!("Stopping the crawl after failing to listen for Ctrl-C: {}", e),
+          }
+          break;
+        }
+        Some((task, spider)) = self.spiders.join_next(), if !self.spiders.is_empty() => {
+          self.frontier.complete(&task.finding()).await;
+          self.finish_spider(task, spider, follow_links).await;
+        }
+        Some((task, fetcher)) = self.fetchers.join_next(), if !self.fetchers.is_empty() => {
+          self.frontier.complete(&task.finding()).await;
+          self.finish_fetcher(task, fetcher).await;
+        }
+        Ok(()) = changed(&mut self.reloads), if self.reloads.is_some() => {
+          let reload = self.reloads.as_mut().unwrap().borrow_and_update().clone();
+          self.reload(reload);
+        }
+        Some((request, waiting)) = self.robots.join_next(), if !self.robots.fetches.is_empty() => {
+          if let Some((robots_url, sent)) = request {
+            self.audit.record(&robots_url, sent, self.politeness.delay);
+          }
+          for finding in waiting {
+            self.frontier.offer(finding).await;
+          }
+        }
+      }
+
+      if let Some((_, written)) = self.checkpoint {
+        if written.elapsed() >= CHECKPOINT_INTERVAL {
+          self.write_checkpoint().await;
+        }
+      }
+    }
+
+    self.write_checkpoint().await;
+    self.progress.log(0, None);
+  }
+
+  /// Writes the frontier, archive, host visits and robots.txt files to the
+  /// checkpoint file, if there is one. Findings in flight are written as part
+  /// of the frontier.
+  async fn write_checkpoint(&mut self) {
+    let Some((path, written)) = &mut self.checkpoint else {
+      return;
+    };
+    *written = Instant::now();
+
+    let FrontierSnapshot {
+      queued: mut frontier,
+      archive,
+      host_visits,
+    } = self.frontier.checkpoint().await;
+    frontier.extend(self.maintenance.deferred.iter().cloned());
+    frontier.extend(self.robots.pending.values().flatten().cloned());
+    let checkpoint = Checkpoint {
+      seeds: self.inital_urls.iter().cloned().collect(),
+      frontier,
+      archive,
+      host_visits,
+      robots: self
+        .robots
+        .origins
+        .iter()
+        .map(|(origin, robots)| (origin.clone(), robots.clone()))
+        .collect(),
+    };
+
+    // Written next to the checkpoint and renamed over it, so that a crash
+    // while writing leaves the previous checkpoint intact.
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let result = async {
+      if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+      }
+      tokio::fs::write(&partial, checkpoint.to_json().to_string()).await?;
+      tokio::fs::rename(&partial, &path).await
+    };
+    match result.await {
+      Ok(()) => debug!("checkpoint written to `{}`", path.display()),
+      Err(e) => warn!("Writing checkpoint `{}` failed: {}", path.display(), e),
+    }
+  }
+
+  /// Records the outcome of a spider and queues the new findings of its page
+  /// that are followed.
+  async fn finish_spider(
+    &mut self,
+    task: TaskLabel,
+    spider: TaskOutput<SpiderResponse>,
+    follow_links: bool,
+  ) {
+    let finding = task.finding();
+    let url = task.url;
+    let (started, latency, spider) = match spider {
+      Ok(output) => output,
+      Err(e) => {
+        warn!("Spider for `{}` was lost: {}", url, e);
+        self.log_event(Event::Failed(&url, &e.to_string()));
+        self.export(&finding, None, Some(&e.to_string()));
+        self.indexability.failed.insert(url, e.to_string());
+        return;
+      }
+    };
+    let status = match &spider {
+      Ok(response) => Some(response.status),
+      Err(e) => error_status(e),
+    };
+    self.health.record(&url, status, latency);
+    if let Err(e) = &spider {
+      self.health.record_slow(&url, e);
+    }
+    self.audit_request(&url, started);
+
+    match spider {
+      Ok(SpiderResponse {
+        depth,
+        status,
+        final_url,
+        content_type,
+        size,
+        saved,
+        hsts,
+        content_language,
+        charset,
+        x_robots_tag,
+        last_modified,
+        content_hash,
+        csp,
+        sampled,
+        extraction:
+          Extraction {
+            mut findings,
+            forms,
+            endpoints,
+            emails,
+            phones,
+            meta_charset,
+            html_lang,
+            detected_language,
+            title,
+            description,
+            canonical,
+            meta_robots,
+            mut image_captions,
+            licenses,
+            variants,
+            nofollow_links,
+            objects,
+            text_hash,
+            ..
+          },
+      }) => {
+        self.log_event(Event::Fetched(&url, status));
+        self.export(
+          &finding,
+          Some((status, content_type.as_deref(), size)),
+          None,
+        );
+        if let Some(class) = sampled {
+          self.samples.record(&url, class, content_type.clone());
+        }
+        if final_url != url && self.robots_rules_allow(&final_url) == Some(false) {
+          self.audit.disallowed(&final_url);
+        }
+        if depth == 0 && final_url != url {
+          // A seed that redirects, e.g. to `www.`, takes its scope along.
+          self.scope.add_seed(&final_url);
+        }
+        if let (Some(saved_pages), Some(path)) = (&mut self.saved_pages, saved) {
+          saved_pages.push(SavedPage {
+            url: url.clone(),
+            final_url: final_url.clone(),
+            status,
+            content_type: content_type.clone(),
+            path,
+          });
+        }
+        self.traps.record(&url, text_hash.unwrap_or(content_hash));
+        if let Some(equivalence) = &mut self.index_equivalence {
+          equivalence.record(&url, status, content_hash);
+        }
+        if let Some(dataset) = &mut self.dataset {
+          dataset.record_licenses(&url, &licenses);
+        }
+        self.licenses.record(&url, licenses);
+        let x_robots_tag = x_robots_directives(&x_robots_tag, &self.politeness.user_agent);
+        let nofollow = self
+          .politeness
+          .nofollow
+          .stops_at(&x_robots_tag, &meta_robots);
+        if nofollow {
+          self.indexability.nofollow.insert(url.clone());
+        }
+        self
+          .indexability
+          .record(&url, status, x_robots_tag, meta_robots);
+        self.canonicals.record(&url, status, &final_url, canonical);
+        if let Some((_, sitemap)) = &mut self.sitemap {
+          if (200..300).contains(&status) && !self.indexability.noindex.contains_key(&url) {
+            sitemap.insert(
+              final_url.clone(),
+              last_modified.unwrap_or_else(SystemTime::now),
+            );
+          }
+        }
+        self.page_metadata.record(&url, title, description);
+        self.language_stats.record(
+          content_language,
+          html_lang,
+          detected_language,
+          charset.or(meta_charset),
+        );
+        if let Some(inventory) = &mut self.inventory {
+          inventory.record(&url, forms, endpoints);
+        }
+        if let Some(contacts) = &mut self.contacts {
+          contacts.record(&url, emails, phones);
+        }
+        if let Some(hsts) = hsts {
+          self.https_upgrades.record(&final_url, hsts);
+        }
+        for variant in &variants {
+          if *variant != final_url {
+            self
+              .variants
+              .entry(variant.clone())
+              .or_insert_with(|| final_url.clone());
+          }
+        }
+        if self.variant_policy == VariantPolicy::Crawl {
+          findings.extend(
+            variants
+              .into_iter()
+              .map(|variant| Finding::Page(variant, depth.saturating_add(1))),
+          );
+        }
+        if self.politeness.nofollow == NofollowPolicy::Ignore {
+          findings.extend(
+            nofollow_links
+              .into_iter()
+              .map(|link| Finding::Page(link, depth.saturating_add(1))),
+          );
+        } else if nofollow {
+          // The page still needs its assets.
+          findings.retain(Finding::is_asset);
+        }
+        findings = findings
+          .into_iter()
+          .map(|mut finding| {
+            let caption = image_captions.remove(finding.url());
+            if let Finding::Page(page, _) = &mut finding {
+              if self.variant_policy == VariantPolicy::Canonical {
+                if let Some(desktop) = self.variants.get(page) {
+                  *page = desktop.clone();
+                }
+              }
+            }
+            self.https_upgrades.upgrade(finding.url_mut());
+            self.tracking_params.apply(finding.url_mut());
+            self.canonicalization.apply(finding.url_mut());
+            if finding.is_asset() {
+              self.param_strip_rules.apply(finding.url_mut());
+            }
+            if let (Finding::Image(image), Some(dataset)) = (&finding, &mut self.dataset) {
+              dataset.record_image(&url, image, caption);
+            }
+            finding
+          })
+          .collect();
+
+        for finding in findings.iter().filter(|f| f.is_asset()) {
+          self.dead_assets.record_reference(&url, finding.url());
+        }
+        if let Some((_, _, graph)) = &mut self.graph {
+          graph.record(&url, &findings);
+        }
+
+        let discovered = findings.len();
+        let mut new = 0;
+        for finding in findings {
+          let equivalent = self
+            .index_equivalence
+            .as_ref()
+            .is_some_and(|e| e.covers(&finding));
+          // Not discovered, so that pages allowed to load it still can.
+          if finding.is_asset() && self.respect_csp {
+            let asset = finding.url();
+            let directive = match &finding {
+              Finding::Image(..) if objects.contains(asset) => "object-src",
+              Finding::Stylesheet(..) => "style-src",
+              Finding::Script(..) => "script-src",
+              Finding::Font(..) => "font-src",
+              Finding::Media(..) => "media-src",
+              _ => "img-src",
+            };
+            if !csp.allows(&final_url, directive, asset) {
+              self.log_event(Event::Skipped(asset, SkipReason::Csp, None));
+              continue;
+            }
+          }
+          if equivalent || !self.frontier.discover(&finding).await {
+            self.log_event(Event::Skipped(finding.url(), SkipReason::Duplicate, None));
+            continue;
+          }
+          self.seeds.inherit(&url, finding.url());
+          new += 1;
+          self.log_event(Event::Discovered(&finding, &url));
+          if let Some(FindingCallback(on_finding)) = &self.on_finding {
+            on_finding(&finding);
+          }
+
+          if let Some(reason) = self.follow_rejection(&finding, depth, follow_links) {
+            let detail = self.skip_detail(finding.url(), reason);
+            self.log_event(Event::Skipped(finding.url(), reason, detail.as_deref()));
+            continue;
+          }
+          self.frontier.offer(finding).await;
+        }
+
+        self.progress.record(new, discovered);
+        if self.progress.pages.is_multiple_of(PROGRESS_INTERVAL) {
+          let pending = self.frontier.len().await + self.spiders.len() + self.fetchers.len();
+          let eta = self.eta().await;
+          self.progress.log(pending, eta);
+          let oldest = [self.spiders.oldest(), self.fetchers.oldest()];
+          if let Some(task) = oldest.into_iter().flatten().min_by_key(|t| t.spawned) {
+            let running = task.spawned.elapsed();
+            if running > TIMEOUT_DURATION {
+              warn!(
+                "{} `{}` has been in flight for {:.1} s",
+                task.kind,
+                task.url,
+                running.as_secs_f64()
+              );
+            }
+          }
+        }
+      }
+      Err(e) if self.maintenance.defer(&e) => {
+        self.frontier.release(&finding).await;
+        self.log_event(Event::Deferred(&url));
+      }
+      Err(e) if self.back_off(&e) => {
+        self.frontier.retry(finding).await;
+        self.log_event(Event::Deferred(&url));
+      }
+      Err(e) => {
+        warn!("Spider failed with error: {}", e);
+        self.log_event(Event::Failed(&url, &e.to_string()));
+        self.export(&finding, None, Some(&e.to_string()));
+        self.indexability.failed.insert(url, e.to_string());
+      }
+    }
+  }
+
+  /// Records the outcome of a fetcher.
+  async fn finish_fetcher(&mut self, task: TaskLabel, fetcher: TaskOutput<Fetched>) {
+    let finding = task.finding();
+    let url = task.url;
+    let (started, latency, result) = match fetcher {
+      Ok(output) => output,
+      Err(e) => {
+        warn!("Fetcher for `{}` was lost: {}", url, e);
+        self.log_event(Event::Failed(&url, &e.to_string()));
+        self.export(&finding, None, Some(&e.to_string()));
+        return;
+      }
+    };
+    let status = match &result {
+      Ok(fetched) => Some(fetched.status),
+      Err(e) => error_status(e),
+    };
+    self.health.record(&url, status, latency);
+    if let Err(e) = &result {
+      self.health.record_slow(&url, e);
+    }
+    self.audit_request(&url, started);
+    match result {
+      Ok(Fetched {
+        status,
+        content_type,
+        size,
+        saved,
+        sha256,
+        subresources,
+      }) => {
+        self.log_event(Event::Fetched(&url, status));
+        self.export(
+          &finding,
+          Some((status, content_type.as_deref(), size)),
+          None,
+        );
+        self.fetched_assets += 1;
+        // The dataset is of images; other assets are only saved.
+        if let (Some(dataset), Some(path), Finding::Image(..)) =
+          (&mut self.dataset, saved, &finding)
+        {
+          dataset.record_saved(&url, path, sha256);
+        }
+        self.discover_subresources(&url, subresources).await;
+      }
+      Err(e) if self.maintenance.defer(&e) => {
+        self.frontier.release(&finding).await;
+        self.log_event(Event::Deferred(&url));
+      }
+      Err(e) if self.back_off(&e) => {
+        self.frontier.retry(finding).await;
+        self.log_event(Event::Deferred(&url));
+      }
+      Err(e) => {
+        // With its causes, which storage failures wrap.
+        let error = format!("{:#}", e);
+        warn!("Fetcher failed with error: {}", error);
+        self.log_event(Event::Failed(&url, &error));
+        self.export(&finding, None, Some(&error));
+        self.dead_assets.record_failure(&url, &e);
+      }
+    }
+  }
+
+  /// Resolves the hosts that many queued findings go to while their requests
+  /// wait for the politeness delay, unless they stay pinned until the last of
+  /// them is sent, at most once per delay. Connections are not opened ahead:
+  /// reqwest only pools those of requests, and an extra request would take
+  /// one of the politeness slots of the host. Its connection is opened by the
+  /// robots.txt fetch instead and kept alive for the requests after it.
+  #[cfg(feature = "dns-pinning")]
+  async fn warm_up(&mut self) {
+    let Some(dns) = self.dns.clone() else {
+      return;
+    };
+    let delay = self.politeness.delay;
+    if delay.is_zero() || self.warmed_up.is_some_and(|at| at.elapsed() < delay) {
+      return;
+    }
+    self.warmed_up = Some(Instant::now());
+    for (host, count) in self.frontier.queued_per_host().await {
+      if let (Host::Domain(domain), true) = (host, count >= PRECONNECT_QUEUE_THRESHOLD) {
+        let window = delay.saturating_mul(u32::try_from(count).unwrap_or(u32::MAX));
+        tokio::spawn(dns.clone().warm(domain, window));
+      }
+    }
+  }
+
+  /// Delay before a request to the host of a page at `depth`. When depth-paced,
+  /// seeds wait twice the politeness delay and pages at the depth limit the
+  /// plain delay, so a crawl that is cut short has its shallow levels complete
+  /// without having hammered the hosts for them.
+  fn page_delay(&self, depth: u8) -> Duration {
+    let delay = self.politeness.delay;
+    if !self.depth_paced {
+      return delay;
+    }
+    let limit = u32::from(self.recursion_depth_limit.max(1));
+    let remaining = limit.saturating_sub(u32::from(depth));
+    delay + delay * remaining / limit
+  }
+
+  /// Pauses the host behind a [`RateLimited`] response for as long as it asks,
+  /// returning whether its finding is to be retried, unless it was retried
+  /// too often already.
+  fn back_off(&mut self, error: &eyre::Report) -> bool {
+    let Some(RateLimited {
+      finding,
+      retry_after,
+      ..
+    }) = error.downcast_ref()
+    else {
+      return false;
+    };
+    let retries = self.rate_limit_retries.entry(finding.clone()).or_insert(0);
+    if *retries >= RATE_LIMIT_RETRIES {
+      return false;
+    }
+    *retries += 1;
+    if let Some(host) = finding.url().host().map(|h| h.to_owned()) {
+      info!(
+        "`{}` asked to retry after {} s, pausing it",
+        host,
+        retry_after.as_secs()
+      );
+      self.schedule.pause(host.clone(), *retry_after);
+      self.pauses.pause(host, *retry_after);
+    }
+    true
+  }
+
+  /// Estimated time until everything queued and in flight is done: the
+  /// longer of what the politeness delays of the busiest host allow and what
+  /// the throughput so far suggests. Links yet to be discovered are not
+  /// accounted for.
+  async fn eta(&self) -> Option<Duration> {
+    let completed = self.progress.pages + self.fetched_assets;
+    let elapsed = self.progress.started?.elapsed();
+    if completed == 0 {
+      return None;
+    }
+    let queue = self.frontier.queued().await;
+    let pending = queue.len() + self.spiders.len() + self.fetchers.len();
+    let observed = elapsed.mul_f64(pending as f64 / completed as f64);
+
+    let mut hosts: HashMap<Host, Duration> = HashMap::new();
+    for finding in &queue {
+      let url = finding.url();
+      let Some(host) = url.host().map(|h| h.to_owned()) else {
+        continue;
+      };
+      let delay = match finding {
+        Finding::Page(_, depth) => self.page_delay(*depth),
+        _ => self.politeness.delay,
+      };
+      let delay = delay.max(self.crawl_delay(url));
+      *hosts
+        .entry(host)
+        .or_insert_with_key(|host| self.schedule.busy_for(host)) += delay;
+    }
+    // Dispatched requests wait for their slots in the schedule.
+    let scheduled = self.schedule.next.keys().map(|h| self.schedule.busy_for(h));
+    let polite = hosts
+      .into_values()
+      .chain(scheduled)
+      .max()
+      .unwrap_or_default();
+    Some(observed.max(polite))
+  }
+
+  /// The `Crawl-delay` the robots.txt of the origin of `url` asks for, if it
+  /// is respected. It only ever lengthens the politeness delay.
+  fn crawl_delay(&self, url: &Url) -> Duration {
+    let origin = url.origin().ascii_serialization();
+    let rules = self.robots.rules(&origin);
+    rules.and_then(|r| r.crawl_delay).unwrap_or_default()
+  }
+
+  /// Records a request to `url` sent at `started` in the politeness audit,
+  /// along with the delay it had to keep and whether robots.txt allowed it.
+  fn audit_request(&mut self, url: &Url, started: Instant) {
+    let delay = self.politeness.delay.max(self.crawl_delay(url));
+    self.audit.record(url, started, delay);
+    if self.robots_rules_allow(url) == Some(false) {
+      self.audit.disallowed(url);
+    }
+  }
+
+  /// Fetches the sitemaps, and those of sitemap indexes, one after another at
+  /// the politeness delay, and makes every page they list a seed.
+  async fn ingest_sitemaps(&mut self) {
+    let mut queue: VecDeque<Url> = self.sitemaps.iter().cloned().collect();
+    let mut fetched: HashSet<Url> = HashSet::new();
+    let mut pages = 0;
+    while let Some(url) = queue.pop_front() {
+      if !fetched.insert(url.clone()) {
+        continue;
+      }
+      if fetched.len() > 1 {
+        tokio::time::sleep(self.politeness.delay).await;
+      }
+      let response = match self.backends.for_url(&url).fetch(&url).await {
+        Ok(response) if (200..300).contains(&response.status) => response,
+        Ok(response) => {
+          warn!("Sitemap `{}` answered with status {}", url, response.status);
+          continue;
+        }
+        Err(e) => {
+          warn!("Sitemap `{}` could not be fetched: {}", url, e);
+          continue;
+        }
+      };
+      let (index, locs) = match Sitemap::parse(&response.url, &response.body) {
+        Ok(Sitemap::Index(locs)) => (true, locs),
+        Ok(Sitemap::Pages(locs)) => (false, locs),
+        Err(e) => {
+          warn!("Sitemap `{}` could not be read: {}", url, e);
+          continue;
+        }
+      };
+      for loc in locs {
+        let Ok(mut listed) = response.url.join(&loc) else {
+          debug!("invalid URL `{}` in sitemap `{}`", loc, url);
+          continue;
+        };
+        self.tracking_params.apply(&mut listed);
+        self.canonicalization.apply(&mut listed);
+        if index {
+          queue.push_back(listed);
+        } else if self.inital_urls.insert(listed.clone()) {
+          self.seeds.add(&listed);
+          self.scope.add_seed(&listed);
+          pages += 1;
+        }
+      }
+    }
+    if !fetched.is_empty() {
+      info!("{} pages listed in {} sitemap(s)", pages, fetched.len());
+    }
+  }
+
+  /// Takes over the delay and URL filters of `reload`.
+  fn reload(&mut self, reload: Reload) {
+    info!(
+      "reloaded a delay of {} ms, {} include and {} exclude pattern(s)",
+      reload.delay.as_millis(),
+      reload.url_filters.include.len(),
+      reload.url_filters.exclude.len()
+    );
+    self.politeness.delay = reload.delay;
+    self.url_filters = reload.url_filters;
+  }
+
+  /// Whether the robots.txt of the origin of `url` allows crawling it, if it
+  /// has been fetched.
+  fn robots_rules_allow(&self, url: &Url) -> Option<bool> {
+    let rules = self.robots.rules(&url.origin().ascii_serialization())?;
+    Some(rules.allows(&robots_path(url)))
+  }
+
+  /// Whether the robots.txt of the origin of `finding` allows crawling it.
+  /// On first contact with an origin, the finding is held back and `None`
+  /// returned while its robots.txt is fetched.
+  fn robots_allow(&mut self, finding: &Finding) -> Option<bool> {
+    let url = finding.url();
+    if !self.politeness.respect_robots || !url.scheme().starts_with("http") {
+      return Some(true);
+    }
+    let origin = url.origin().ascii_serialization();
+    let Some(robots) = self.robots.origins.get(&origin) else {
+      let backend = self.backends.for_url(url);
+      let delay = self.politeness.delay;
+      let (schedule, pauses) = (&mut self.schedule, &self.pauses);
+      self
+        .robots
+        .fetch(origin, finding.clone(), backend, schedule, pauses, delay);
+      return None;
+    };
+    match robots.rules.matching(&robots_path(url)) {
+      Some(rule) if !rule.allow => {
+        let rule = rule.line();
+        *self.robots.blocked.entry((origin, rule)).or_default() += 1;
+        Some(false)
+      }
+      _ => Some(true),
+    }
+  }
+
+  /// Why a newly discovered finding on a page at `depth` is not followed, if it
+  /// is not.
+  fn follow_rejection(
+    &self,
+    finding: &Finding,
+    depth: u8,
+    follow_links: bool,
+  ) -> Option<SkipReason> {
+    if !follow_links {
+      return Some(SkipReason::Plan);
+    }
+    let child_depth = depth.saturating_add(1);
+    let limit = match finding {
+      Finding::Page(..) => self.recursion_depth_limit,
+      _ => self.asset_depth_limit,
+    };
+    if let Some(reason) = self.asset_rejection(finding) {
+      Some(reason)
+    } else if !finding.is_asset() && !self.mode.follows_pages() {
+      Some(SkipReason::Mode)
+    } else if child_depth > limit {
+      Some(SkipReason::DepthLimit)
+    } else if matches!(finding, Finding::Page(..)) && !self.scope.admits(finding.url()) {
+      Some(SkipReason::OutOfScope)
+    } else if self.variant_policy == VariantPolicy::Skip
+      && matches!(finding, Finding::Page(..))
+      && self.variants.contains_key(finding.url())
+    {
+      Some(SkipReason::Variant)
+    } else if matches!(finding, Finding::Page(..)) && !self.url_filters.admit(finding.url()) {
+      Some(SkipReason::Filtered)
+    } else if matches!(finding, Finding::Page(..)) && self.traps.is_trapped(finding.url()) {
+      Some(SkipReason::Trap)
+    } else {
+      None
+    }
+  }
+
+  /// What made `url` be skipped for `reason`, for the reasons that depend on
+  /// the configuration: the URL filter, the scope or the robots.txt rule.
+  fn skip_detail(&self, url: &Url, reason: SkipReason) -> Option<String> {
+    match reason {
+      SkipReason::Filtered => self.url_filters.rejection(url),
+      SkipReason::OutOfScope => Some(format!("outside the `{}` scope", self.scope.scope.as_str())),
+      SkipReason::Robots => {
+        let rules = self.robots.rules(&url.origin().ascii_serialization())?;
+        let rule = rules.matching(&robots_path(url))?;
+        Some(format!("robots.txt rule `{}`", rule.line()))
+      }
+      _ => None,
+    }
+  }
+
+  /// Why an asset is not downloaded regardless of where it was found, if it
+  /// is not.
+  fn asset_rejection(&self, finding: &Finding) -> Option<SkipReason> {
+    let class = finding.asset_class()?;
+    if !self.mode.fetches_assets() {
+      Some(SkipReason::Mode)
+    } else if !self.asset_classes.contains(&class) {
+      Some(SkipReason::AssetClass)
+    } else {
+      None
+    }
+  }
+
+  /// Discovers the fonts, images and imports of a fetched stylesheet. They
+  /// are not depth limited: the page that needs them already was not.
+  async fn discover_subresources(&mut self, stylesheet: &Url, subresources: Vec<Finding>) {
+    for mut finding in subresources {
+      self.https_upgrades.upgrade(finding.url_mut());
+      self.tracking_params.apply(finding.url_mut());
+      self.canonicalization.apply(finding.url_mut());
+      self.param_strip_rules.apply(finding.url_mut());
+      self.dead_assets.record_reference(stylesheet, finding.url());
+      if !self.frontier.discover(&finding).await {
+        self.log_event(Event::Skipped(finding.url(), SkipReason::Duplicate, None));
+        continue;
+      }
+      self.seeds.inherit(stylesheet, finding.url());
+      self.log_event(Event::Discovered(&finding, stylesheet));
+      if let Some(FindingCallback(on_finding)) = &self.on_finding {
+        on_finding(&finding);
+      }
+      if let Some(reason) = self.asset_rejection(&finding) {
+        self.log_event(Event::Skipped(finding.url(), reason, None));
+        continue;
+      }
+      self.frontier.offer(finding).await;
+    }
+  }
+
+  fn log_event(&mut self, event: Event) {
+    if let Event::Skipped(url, reason, _) = event {
+      self.skips.record(url, reason);
+    }
+    if let (Event::Discovered(finding, referrer), Some(export)) =
+      (&event, &mut self.findings_export)
+    {
+      export
+        .referrers
+        .insert(finding.url().clone(), (*referrer).clone());
+    }
+    if let Some(log) = &mut self.event_log {
+      if let Err(e) = log.append(event) {
+        warn!("Disabling event log after write error: {}", e);
+        self.event_log = None;
+      }
+    }
+  }
+
+  /// Writes the record of `finding` to the findings export, if there is one.
+  fn export(&mut self, finding: &Finding, response: Option<ResponseSummary>, error: Option<&str>) {
+    if let Some(export) = &mut self.findings_export {
+      let tags = self.seeds.tags(finding.url());
+      if let Err(e) = export.write(finding, &tags, response, error) {
+        warn!("Disabling findings export after write error: {}", e);
+        self.findings_export = None;
+      }
+    }
+  }
+
+  /// Writes the outputs of the crawl: the event log, findings export, plan,
+  /// link graph, report, dataset and page manifests and run metadata, as far
+  /// as they are enabled. The WARC file is complete once the crawl has ended.
+  pub async fn finish(&mut self) -> Result<()> {
+    self.finish_event_log()?;
+    // Left behind only if empty.
+    let _ = tokio::fs::remove_dir(&self.save.spool_dir).await;
+    self.write_plan().await?;
+    self.write_link_graph().await?;
+    self.write_sitemap().await?;
+    self.write_report().await?;
+    self.write_dataset_manifest().await?;
+    self.write_page_manifest().await?;
+    #[cfg(feature = "warc")]
+    if let Some((path, warc)) = &self.warc {
+      info!(
+        "WARC with {} responses written to `{}`",
+        warc.responses.load(Ordering::Relaxed),
+        path.display()
+      );
+    }
+    self.write_run_metadata().await?;
+    Ok(())
+  }
+
+  /// Everything discovered so far.
+  pub async fn findings(&self) -> Vec<Finding> {
+    self.frontier.archive().await
+  }
+
+  pub fn pages_crawled(&self) -> usize {
+    self.indexability.crawled.len()
+  }
+
+  pub fn assets_fetched(&self) -> usize {
+    self.fetched_assets
+  }
+
+  /// Flushes the event log and finishes its compressed stream.
+  fn finish_event_log(&mut self) -> Result<()> {
+    if let Some(log) = self.event_log.take() {
+      log.finish()?;
+    }
+    if let Some(mut export) = self.findings_export.take() {
+      export.file.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Writes the collected plan, one finding per line in visiting order.
+  async fn write_plan(&self) -> Result<()> {
+    let Some((path, plan)) = &self.plan else {
+      return Ok(());
+    };
+
+    let mut contents = String::from("# crawl plan: <kind> [depth] <url>\n");
+    for finding in plan {
+      contents.push_str(&finding.to_plan_line());
+      contents.push('\n');
+    }
+    tokio::fs::write(path, contents).await?;
+    info!(
+      "plan with {} entries written to `{}`",
+      plan.len(),
+      path.display()
+    );
+    Ok(())
+  }
+
+  async fn write_link_graph(&self) -> Result<()> {
+    let Some((path, format, graph)) = &self.graph else {
+      return Ok(());
+    };
+    let contents = match format {
+      GraphFormat::Dot => graph.to_dot(),
+      GraphFormat::GraphMl => graph.to_graphml(),
+    };
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, contents).await?;
+    info!(
+      "link graph with {} nodes and {} edges written to `{}`",
+      graph.nodes.len(),
+      graph.edges.len(),
+      path.display()
+    );
+    Ok(())
+  }
+
+  /// Writes the collected sitemap, with the `Last-Modified` time of every page
+  /// or else when it was fetched.
+  async fn write_sitemap(&self) -> Result<()> {
+    let Some((path, pages)) = &self.sitemap else {
+      return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+    }
+    let files = sitemap_files(pages, path, SITEMAP_URL_LIMIT);
+    let sitemaps = files.len() - 1;
+    for (path, contents) in files {
+      tokio::fs::write(path, contents).await?;
+    }
+    match sitemaps {
+      0 => info!(
+        "sitemap with {} pages written to `{}`",
+        pages.len(),
+        path.display()
+      ),
+      _ => info!(
+        "sitemap index of {} sitemaps with {} pages written to `{}`",
+        sitemaps,
+        pages.len(),
+        path.display()
+      ),
+    }
+    Ok(())
+  }
+
+  /// Writes the dataset manifest of all saved images to `dataset.jsonl` in
+  /// the output directory.
+  async fn write_dataset_manifest(&self) -> Result<()> {
+    let Some(dataset) = &self.dataset else {
+      return Ok(());
+    };
+    let path = self.output_dir.join("dataset.jsonl");
+    tokio::fs::create_dir_all(&self.output_dir).await?;
+    tokio::fs::write(&path, dataset.to_jsonl(&self.output_dir, &self.seeds)).await?;
+    info!(
+      "dataset manifest with {} images written to `{}`",
+      dataset.saved.len(),
+      path.display()
+    );
+    Ok(())
+  }
+
+  /// Writes `pages.jsonl` to the output directory, mapping the URL of every
+  /// saved page to its file.
+  async fn write_page_manifest(&self) -> Result<()> {
+    let Some(saved_pages) = &self.saved_pages else {
+      return Ok(());
+    };
+    let mut manifest = String::new();
+    for page in saved_pages {
+      let path = &page.path;
+      let record = serde_json::json!({
+        "url": page.url.as_str(),
+        "final_url": page.final_url.as_str(),
+        "status": page.status,
+        "content_type": page.content_type,
+        "file_name": path.strip_prefix(&self.output_dir).unwrap_or(path).to_string_lossy(),
+        "tags": self.seeds.tags(&page.url),
+      });
+      let _ = writeln!(manifest, "{}", record);
+    }
+    let path = self.output_dir.join("pages.jsonl");
+    tokio::fs::create_dir_all(&self.output_dir).await?;
+    tokio::fs::write(&path, manifest).await?;
+    info!(
+      "page manifest with {} pages written to `{}`",
+      saved_pages.len(),
+      path.display()
+    );
+    Ok(())
+  }
+
+  /// Writes the seeds and politeness settings of the run, including every
+  /// override of the polite defaults, to `run.json` in the output directory.
+  async fn write_run_metadata(&self) -> Result<()> {
+    let seeds: BTreeSet<_> = self.inital_urls.iter().map(Url::as_str).collect();
+    let seed_tags: BTreeMap<_, _> = self
+      .seed_tags
+      .iter()
+      .map(|(seed, tags)| (seed.as_str(), tags))
+      .collect();
+    let metadata = serde_json::json!({
+      "crawl_id": &*self.crawl_id,
+      "seeds": seeds,
+      "seed_tags": seed_tags,
+      "sitemaps": self.sitemaps.iter().map(Url::as_str).collect::<Vec<_>>(),
+      "user_agent": self.politeness.user_agent,
+      "respect_robots": self.politeness.respect_robots,
+      "delay_ms": self.politeness.delay.as_millis() as u64,
+      "depth_paced": self.depth_paced,
+      "politeness_overrides": self.politeness.overrides,
+    });
+    tokio::fs::create_dir_all(&self.output_dir).await?;
+    let path = self.output_dir.join("run.json");
+    tokio::fs::write(&path, serde_json::to_string_pretty(&metadata)?).await?;
+    Ok(())
+  }
+
+  /// Writes the sections collected during the crawl to the report file.
+  /// Nothing is written if no report section was enabled.
+  async fn write_report(&self) -> Result<()> {
+    let mut report = String::new();
+    if let Some(inventory) = &self.inventory {
+      inventory.render(&mut report);
+    }
+    if let Some(contacts) = &self.contacts {
+      contacts.render(&mut report);
+    }
+    self.dead_assets.render(&mut report);
+    self.https_upgrades.render(&mut report);
+    self.language_stats.render(&mut report);
+    self.licenses.render(&mut report);
+    self.page_metadata.render(&mut report);
+    self.canonicals.render(&mut report);
+    self.traps.render(&mut report);
+    self.seeds.render(&self.indexability.crawled, &mut report);
+    #[cfg(feature = "dns-pinning")]
+    if let Some(dns) = &self.dns {
+      dns.render(&mut report);
+    }
+    #[cfg(feature = "dns-pinning")]
+    let addresses = self
+      .dns
+      .as_ref()
+      .map(PinnedResolver::addresses)
+      .unwrap_or_default();
+    #[cfg(not(feature = "dns-pinning"))]
+    let addresses = HashMap::new();
+    self.health.render(
+      &self.frontier.host_visits().await,
+      &self.traps,
+      &addresses,
+      &self.geo,
+      &mut report,
+    );
+    self.audit.render(&self.politeness, &mut report);
+    self.samples.render(&mut report);
+    self.robots.render(&mut report);
+    self
+      .indexability
+      .render(&self.frontier.archive().await, &self.skips, &mut report);
+    self.skips.render(&mut report);
+    if report.is_empty() {
+      return Ok(());
+    }
+
+    if let Some(parent) = self.report_path.parent() {
+      tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&self.report_path, format!("# Crawl report\n\n{}", report)).await?;
+    info!("report written to `{}`", self.report_path.display());
+    Ok(())
+  }
+}
+
+/// Configures a [`Dispatcher`]. Everything but the seeds defaults to what the
+/// command line defaults to.
+#[derive(Debug, Clone)]
+pub struct DispatcherBuilder {
+  inital_urls: HashSet<Url>,
+  seed_tags: HashMap<Url, BTreeSet<String>>,
+  sitemaps: Vec<Url>,
+  recursion_depth_limit: u8,
+  asset_depth_limit: Option<u8>,
+  mode: CrawlMode,
+  asset_classes: HashSet<AssetClass>,
+  output_dir: PathBuf,
+  dedup: Option<DedupLinks>,
+  save_headers: bool,
+  skip_existing: bool,
+  path_template: Option<PathTemplate>,
+  #[cfg(feature = "images")]
+  convert_images: Option<ImageConversion>,
+  dataset_manifest: bool,
+  save_pages: bool,
+  page_rules: PageRules,
+  page_provenance: bool,
+  sample_pages: Option<usize>,
+  max_file_size: Option<usize>,
+  min_transfer_rate: Option<(usize, Duration)>,
+  format: OutputFormat,
+  #[cfg(feature = "warc")]
+  warc_output: Option<PathBuf>,
+  proxy: Option<Url>,
+  politeness: Politeness,
+  report_path: Option<PathBuf>,
+  verbose_report: bool,
+  event_log_path: Option<PathBuf>,
+  findings_export_path: Option<PathBuf>,
+  inventory_forms: bool,
+  extract_contacts: bool,
+  planned: Option<Vec<Finding>>,
+  plan_path: Option<PathBuf>,
+  graph_path: Option<PathBuf>,
+  sitemap_path: Option<PathBuf>,
+  serve_from: Vec<(String, PathBuf)>,
+  scheme_fetchers: Vec<(String, Arc<dyn Fetcher>)>,
+  host_fetchers: Vec<(String, Arc<dyn Fetcher>)>,
+  cache_rules: Vec<Regex>,
+  url_filters: UrlFilters,
+  scope: Scope,
+  public_suffixes: PublicSuffixList,
+  variant_policy: VariantPolicy,
+  respect_csp: bool,
+  strip_params: Vec<(Option<String>, String)>,
+  default_strip_params: bool,
+  strip_tracking_params: Vec<(Option<String>, String)>,
+  default_tracking_params: bool,
+  canonicalization: Canonicalization,
+  per_seed_budget: Option<u32>,
+  depth_paced: bool,
+  #[cfg(feature = "dns-pinning")]
+  preconnect: bool,
+  stop_on_interrupt: bool,
+  reloads: Option<watch::Receiver<Reload>>,
+  index_equivalence: bool,
+  storage: Option<Arc<dyn Storage>>,
+  frontier: Option<Arc<dyn Frontier>>,
+  asset_dir: Option<PathBuf>,
+  concurrency: usize,
+  per_host_concurrency: usize,
+  #[cfg(feature = "geoip")]
+  asn_database: Option<PathBuf>,
+  #[cfg(feature = "geoip")]
+  country_database: Option<PathBuf>,
+  checkpoint_path: Option<PathBuf>,
+  resume: Option<Checkpoint>,
+  on_finding: Option<FindingCallback>,
+}
+
+/// Called with every newly discovered finding.
+#[derive(Clone)]
+struct FindingCallback(Arc<dyn Fn(&Finding) + Send + Sync>);
+
+impl std::fmt::Debug for FindingCallback {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("FindingCallback").finish_non_exhaustive()
+  }
+}
+
+impl Default for DispatcherBuilder {
+  fn default() -> Self {
+    Self {
+      inital_urls: HashSet::new(),
+      seed_tags: HashMap::new(),
+      sitemaps: Vec::new(),
+      recursion_depth_limit: DEFAULT_RECURSION_DEPTH_LIMIT,
+      asset_depth_limit: None,
+      mode: CrawlMode::Full,
+      asset_classes: HashSet::from([AssetClass::Images]),
+      output_dir: DEFAULT_OUTPUT_DIR.into(),
+      dedup: None,
+      save_headers: false,
+      skip_existing: false,
+      path_template: None,
+      #[cfg(feature = "images")]
+      convert_images: None,
+      dataset_manifest: false,
+      save_pages: false,
+      page_rules: PageRules::default(),
+      page_provenance: false,
+      sample_pages: None,
+      max_file_size: None,
+      min_transfer_rate: None,
+      format: OutputFormat::Files,
+      #[cfg(feature = "warc")]
+      warc_output: None,
+      proxy: None,
+      politeness: Politeness::default(),
+      report_path: None,
+      verbose_report: false,
+      event_log_path: None,
+      findings_export_path: None,
+      inventory_forms: false,
+      extract_contacts: false,
+      planned: None,
+      plan_path: None,
+      graph_path: None,
+      sitemap_path: None,
+      serve_from: Vec::new(),
+      scheme_fetchers: Vec::new(),
+      host_fetchers: Vec::new(),
+      cache_rules: Vec::new(),
+      url_filters: UrlFilters::default(),
+      scope: Scope::default(),
+      public_suffixes: PublicSuffixList::default(),
+      variant_policy: VariantPolicy::default(),
+      respect_csp: false,
+      strip_params: Vec::new(),
+      default_strip_params: false,
+      strip_tracking_params: Vec::new(),
+      default_tracking_params: true,
+      canonicalization: Canonicalization::default(),
+      per_seed_budget: None,
+      depth_paced: false,
+      #[cfg(feature = "dns-pinning")]
+      preconnect: false,
+      stop_on_interrupt: false,
+      reloads: None,
+      index_equivalence: false,
+      storage: None,
+      frontier: None,
+      asset_dir: None,
+      concurrency: DEFAULT_CONCURRENCY,
+      per_host_concurrency: DEFAULT_PER_HOST_CONCURRENCY,
+      #[cfg(feature = "geoip")]
+      asn_database: None,
+      #[cfg(feature = "geoip")]
+      country_database: None,
+      checkpoint_path: None,
+      resume: None,
+      on_finding: None,
+    }
+  }
+}
+
+impl DispatcherBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn build(self) -> Result<Dispatcher> {
+    Dispatcher::new(self)
+  }
+
+  /// Adds URLs to start crawling from.
+  pub fn seeds(mut self, seeds: impl IntoIterator<Item = Url>) -> Self {
+    self.inital_urls.extend(seeds);
+    self
+  }
+
+  /// Adds a seed whose pages and assets, and those descending from it, are
+  /// labelled with `tags` in the findings export, the page and dataset
+  /// manifests and the report, to tell apart the parts of a mixed crawl.
+  pub fn tagged_seed(mut self, seed: Url, tags: impl IntoIterator<Item = String>) -> Self {
+    self.seed_tags.entry(seed.clone()).or_default().extend(tags);
+    self.inital_urls.insert(seed);
+    self
+  }
+
+  /// Makes every page listed in the sitemap at `url` a seed before crawling,
+  /// following sitemap indexes and gzipped sitemaps. Can be called multiple
+  /// times.
+  pub fn sitemap(mut self, url: Url) -> Self {
+    self.sitemaps.push(url);
+    self
+  }
+
+  /// How many links deep pages are followed from the seeds.
+  pub fn depth(mut self, depth: u8) -> Self {
+    self.recursion_depth_limit = depth;
+    self
+  }
+
+  /// Depth limit for assets, counted like pages. Defaults to the page depth.
+  pub fn asset_depth(mut self, depth: u8) -> Self {
+    self.asset_depth_limit = Some(depth);
+    self
+  }
+
+  /// Which kinds of findings are followed, all of them by default. A plan
+  /// given with [`Self::planned`] is visited as it is regardless.
+  pub fn mode(mut self, mode: CrawlMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// The kinds of assets that are downloaded, only images by default. Pages
+  /// are still searched for the others, which are skipped.
+  pub fn assets(mut self, classes: impl IntoIterator<Item = AssetClass>) -> Self {
+    self.asset_classes = classes.into_iter().collect();
+    self
+  }
+
+  /// Where assets and other artifacts are saved.
+  pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+    self.output_dir = output_dir.into();
+    self
+  }
+
+  /// Saves identical resources once and links the duplicates to it.
+  pub fn dedup(mut self, links: DedupLinks) -> Self {
+    self.dedup = Some(links);
+    self
+  }
+
+  /// Stores the headers of every saved resource in a `.headers.json` file.
+  pub fn save_headers(mut self, save_headers: bool) -> Self {
+    self.save_headers = save_headers;
+    self
+  }
+
+  /// Skips assets that were already saved to the output directory, by the
+  /// [`STORAGE_MANIFEST_NAME`] manifest of their storage.
+  pub fn skip_existing(mut self, skip_existing: bool) -> Self {
+    self.skip_existing = skip_existing;
+    self
+  }
+
+  /// Gives up on any response with a body larger than `bytes`, before and
+  /// after decompression, so large files cannot exhaust memory. Bodies are not
+  /// limited by default, apart from how far compressed ones may inflate.
+  pub fn max_file_size(mut self, bytes: usize) -> Self {
+    self.max_file_size = Some(bytes);
+    self
+  }
+
+  /// Aborts any download whose body arrives at fewer than `bytes_per_second`
+  /// on average over a `window`, so that servers trickling out bytes cannot
+  /// hold on to a request slot for hours, e.g. 1024 over
+  /// [`DEFAULT_MIN_TRANSFER_WINDOW`]. A rate of 0 never aborts, which is the
+  /// default.
+  pub fn min_transfer_rate(mut self, bytes_per_second: usize, window: Duration) -> Self {
+    self.min_transfer_rate = (bytes_per_second > 0).then_some((bytes_per_second, window));
+    self
+  }
+
+  /// Names saved assets after `template` instead of the last segment of their
+  /// URL.
+  pub fn path_template(mut self, template: PathTemplate) -> Self {
+    self.path_template = Some(template);
+    self
+  }
+
+  /// Transcodes images into `format` before saving them, with `quality` from
+  /// 1 to 100 for JPEG.
+  #[cfg(feature = "images")]
+  pub fn convert_images(mut self, format: ConvertedFormat, quality: u8) -> Self {
+    self.convert_images = Some(ImageConversion { format, quality });
+    self
+  }
+
+  /// Writes a `dataset.jsonl` manifest of the saved images.
+  pub fn dataset_manifest(mut self, dataset_manifest: bool) -> Self {
+    self.dataset_manifest = dataset_manifest;
+    self
+  }
+
+  /// Stores the HTML of every crawled page alongside the assets, under
+  /// `pages/<host>/<path>`, and writes a `pages.jsonl` manifest of them.
+  pub fn save_pages(mut self, save_pages: bool) -> Self {
+    self.save_pages = save_pages;
+    self
+  }
+
+  /// Rewrites every page saved with [`Self::save_pages`] before storing it.
+  pub fn page_rules(mut self, rules: PageRules) -> Self {
+    self.page_rules = rules;
+    self
+  }
+
+  /// Starts every page saved with [`Self::save_pages`] with an HTML comment
+  /// naming its URL, when it was fetched and the ID of the crawl from
+  /// `run.json`.
+  pub fn page_provenance(mut self, page_provenance: bool) -> Self {
+    self.page_provenance = page_provenance;
+    self
+  }
+
+  /// Fetches only the first `bytes` of pages whose extension is not that of
+  /// a web page, like `.zip` or `.txt`, and downloads and parses them in full
+  /// only if they turn out to be HTML. Saves bandwidth on links to large
+  /// files, at the cost of a second request for those that are pages.
+  pub fn sample_pages(mut self, bytes: usize) -> Self {
+    self.sample_pages = Some(bytes.max(SNIFF_LENGTH));
+    self
+  }
+
+  pub fn format(mut self, format: OutputFormat) -> Self {
+    self.format = format;
+    self
+  }
+
+  /// Where the WARC file of [`OutputFormat::Warc`] is written, instead of
+  /// `crawl.warc.gz` in the output directory. It is gzipped record by record
+  /// if its name ends in `.gz`.
+  #[cfg(feature = "warc")]
+  pub fn warc_output(mut self, path: impl Into<PathBuf>) -> Self {
+    self.warc_output = Some(path.into());
+    self
+  }
+
+  /// Sends every request through the HTTP proxy at `proxy`, instead of the
+  /// one in `HTTP_PROXY` or `HTTPS_PROXY`, if any.
+  pub fn proxy(mut self, proxy: Url) -> Self {
+    self.proxy = Some(proxy);
+    self
+  }
+
+  pub fn politeness(mut self, politeness: Politeness) -> Self {
+    self.politeness = politeness;
+    self
+  }
+
+  /// Where the crawl report is written, instead of `report.md` in the output
+  /// directory.
+  pub fn report_path(mut self, path: impl Into<PathBuf>) -> Self {
+    self.report_path = Some(path.into());
+    self
+  }
+
+  /// Lists every URL that was not crawled in the report.
+  pub fn verbose_report(mut self, verbose_report: bool) -> Self {
+    self.verbose_report = verbose_report;
+    self
+  }
+
+  /// Records every decision of the dispatcher to a zstd-compressed event log.
+  pub fn event_log(mut self, path: impl Into<PathBuf>) -> Self {
+    self.event_log_path = Some(path.into());
+    self
+  }
+
+  /// Writes a JSON Lines record of every crawled page and fetched asset, with
+  /// its depth, referrer, status, `Content-Type`, size and when it was done.
+  pub fn findings_export(mut self, path: impl Into<PathBuf>) -> Self {
+    self.findings_export_path = Some(path.into());
+    self
+  }
+
+  /// Reports the forms and API endpoints found on every page.
+  pub fn inventory_forms(mut self, inventory_forms: bool) -> Self {
+    self.inventory_forms = inventory_forms;
+    self
+  }
+
+  /// Reports the email addresses and phone numbers found on every page.
+  pub fn extract_contacts(mut self, extract_contacts: bool) -> Self {
+    self.extract_contacts = extract_contacts;
+    self
+  }
+
+  /// Visits exactly these findings instead of crawling from the seeds,
+  /// regardless of the [`Self::mode`].
+  pub fn planned(mut self, planned: Vec<Finding>) -> Self {
+    self.planned = Some(planned);
+    self
+  }
+
+  /// Downloads nothing and writes the findings that would be visited to a
+  /// plan file instead.
+  pub fn write_plan(mut self, path: impl Into<PathBuf>) -> Self {
+    self.plan_path = Some(path.into());
+    self
+  }
+
+  /// Writes a sitemap listing every page that was fetched successfully and
+  /// may be indexed, with the time it last changed. Crawls of several hosts,
+  /// or of more pages than a sitemap may list, get a sitemap per host next
+  /// to `path`, split as needed, and `path` becomes their sitemap index.
+  pub fn write_sitemap(mut self, path: impl Into<PathBuf>) -> Self {
+    self.sitemap_path = Some(path.into());
+    self
+  }
+
+  /// Writes the directed graph of which page linked to which URL, as DOT if
+  /// `path` ends in `.dot` or `.gv` and as GraphML if it ends in `.graphml`.
+  pub fn link_graph(mut self, path: impl Into<PathBuf>) -> Self {
+    self.graph_path = Some(path.into());
+    self
+  }
+
+  /// Serves the URLs of `host` from a local directory.
+  pub fn serve_from(mut self, host: impl Into<String>, root: impl Into<PathBuf>) -> Self {
+    self.serve_from.push((host.into(), root.into()));
+    self
+  }
+
+  /// Fetches the URLs of `scheme`, e.g. `ftp`, with `fetcher` instead of the
+  /// built-in backends.
+  pub fn scheme_fetcher(mut self, scheme: impl Into<String>, fetcher: Arc<dyn Fetcher>) -> Self {
+    self.scheme_fetchers.push((scheme.into(), fetcher));
+    self
+  }
+
+  /// Fetches the URLs of `host` with `fetcher`, whatever their scheme. Takes
+  /// precedence over [`Self::scheme_fetcher`] and [`Self::serve_from`].
+  pub fn host_fetcher(mut self, host: impl Into<String>, fetcher: Arc<dyn Fetcher>) -> Self {
+    self.host_fetchers.push((host.into(), fetcher));
+    self
+  }
+
+  /// Which discovered pages are followed relative to the seeds. Defaults to
+  /// the hosts of the seeds.
+  pub fn scope(mut self, scope: Scope) -> Self {
+    self.scope = scope;
+    self
+  }
+
+  /// The public suffixes that registrable domains are told apart by for
+  /// [`Scope::Domain`], instead of a built-in list of common ones.
+  pub fn public_suffixes(mut self, suffixes: PublicSuffixList) -> Self {
+    self.public_suffixes = suffixes;
+    self
+  }
+
+  /// What to do with the AMP and mobile versions pages announce. Defaults to
+  /// skipping links to them.
+  pub fn variants(mut self, policy: VariantPolicy) -> Self {
+    self.variant_policy = policy;
+    self
+  }
+
+  /// Skips the assets of a page that its `Content-Security-Policy` would not
+  /// let a browser load, so that saved pages only come with what they show.
+  pub fn respect_csp(mut self, enabled: bool) -> Self {
+    self.respect_csp = enabled;
+    self
+  }
+
+  /// Only follows links to pages whose URL matches `regex`, or any other
+  /// include regex. Seeds and assets are not filtered.
+  pub fn include(mut self, regex: Regex) -> Self {
+    self.url_filters.include.push(regex);
+    self
+  }
+
+  /// Does not follow links to pages whose URL matches `regex`. Exclusion wins
+  /// over [`Self::include`].
+  pub fn exclude(mut self, regex: Regex) -> Self {
+    self.url_filters.exclude.push(regex);
+    self
+  }
+
+  /// Caches responses for URLs matching `rule`, ignoring their query string.
+  pub fn cache_rule(mut self, rule: Regex) -> Self {
+    self.cache_rules.push(rule);
+    self
+  }
+
+  /// Strips a cache-busting query parameter from asset URLs, on all hosts or
+  /// only on `host`.
+  pub fn strip_param(mut self, host: Option<String>, param: impl Into<String>) -> Self {
+    self.strip_params.push((host, param.into()));
+    self
+  }
+
+  /// Whether the built-in list of cache-busting parameters, such as `v`,
+  /// `version` and `_`, is stripped. Off by default, as servers may return
+  /// different bytes for each value.
+  pub fn default_strip_params(mut self, enabled: bool) -> Self {
+    self.default_strip_params = enabled;
+    self
+  }
+
+  /// Strips an analytics query parameter from the URLs of found pages and
+  /// assets, on all hosts or only on `host`.
+  pub fn strip_tracking_param(mut self, host: Option<String>, param: impl Into<String>) -> Self {
+    self.strip_tracking_params.push((host, param.into()));
+    self
+  }
+
+  /// Whether the built-in list of analytics parameters, `utm_*`, `fbclid`
+  /// and `gclid`, is stripped.
+  pub fn default_tracking_params(mut self, enabled: bool) -> Self {
+    self.default_tracking_params = enabled;
+    self
+  }
+
+  /// Treats `/docs/` and `/docs` as the same page, crawling it without the
+  /// trailing slash.
+  pub fn strip_trailing_slash(mut self, enabled: bool) -> Self {
+    self.canonicalization.strip_trailing_slash = enabled;
+    self
+  }
+
+  /// Treats URLs that only differ in the order of their query parameters as
+  /// the same, requesting them with the parameters sorted by name.
+  pub fn sort_query(mut self, enabled: bool) -> Self {
+    self.canonicalization.sort_query = enabled;
+    self
+  }
+
+  /// Crawls at most `pages` pages descending from every seed.
+  pub fn per_seed_budget(mut self, pages: u32) -> Self {
+    self.per_seed_budget = Some(pages);
+    self
+  }
+
+  /// Dispatches shallow pages first and paces requests slower the shallower
+  /// the page, from twice the politeness delay for seeds down to the plain
+  /// delay at the depth limit.
+  pub fn depth_paced(mut self, depth_paced: bool) -> Self {
+    self.depth_paced = depth_paced;
+    self
+  }
+
+  /// Resolves hosts that many queued URLs go to while their requests wait for
+  /// the politeness delay, so that no request waits for DNS. Only DNS is done
+  /// ahead: connections are opened by requests, starting with the robots.txt
+  /// fetch of every host, and kept alive for the requests after it.
+  #[cfg(feature = "dns-pinning")]
+  pub fn preconnect(mut self, preconnect: bool) -> Self {
+    self.preconnect = preconnect;
+    self
+  }
+
+  /// Stops dispatching when the process receives Ctrl-C, leaving the findings
+  /// in flight to the checkpoint, so that [`DispatcherBuilder::crawl`] still
+  /// writes the report, graph, sitemap and other outputs of the partial crawl.
+  pub fn stop_on_interrupt(mut self, stop_on_interrupt: bool) -> Self {
+    self.stop_on_interrupt = stop_on_interrupt;
+    self
+  }
+
+  /// Applies every [`Reload`] sent through `reloads` to the running crawl.
+  /// Requests already scheduled keep their slots, and pages already queued
+  /// are not filtered again.
+  pub fn reloads(mut self, reloads: watch::Receiver<Reload>) -> Self {
+    self.reloads = Some(reloads);
+    self
+  }
+
+  /// Treats `/dir`, `/dir/` and `/dir/index.html` as the same page once two
+  /// of them that are crawled turn out identical, for every directory on its
+  /// own.
+  pub fn index_equivalence(mut self, index_equivalence: bool) -> Self {
+    self.index_equivalence = index_equivalence;
+    self
+  }
+
+  /// Hands fetched resources to `storage` instead of writing them to files in
+  /// the asset directory. Deduplication and saved headers only apply to the
+  /// default file storage.
+  pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+    self.storage = Some(storage);
+    self
+  }
+
+  /// Keeps the archive, queue and host visits in `frontier` instead of in
+  /// memory. It is used as is when resuming: the queue of the checkpoint is
+  /// offered to it, but its archive and host visits are not.
+  pub fn frontier(mut self, frontier: Arc<dyn Frontier>) -> Self {
+    self.frontier = Some(frontier);
+    self
+  }
+
+  /// Where the default file storage saves assets, instead of `res` in the
+  /// output directory.
+  pub fn asset_dir(mut self, asset_dir: impl Into<PathBuf>) -> Self {
+    self.asset_dir = Some(asset_dir.into());
+    self
+  }
+
+  /// How many requests may be in flight at once.
+  pub fn concurrency(mut self, requests: usize) -> Self {
+    self.concurrency = requests;
+    self
+  }
+
+  /// How many requests to the same host may be in flight at once.
+  pub fn per_host_concurrency(mut self, requests: usize) -> Self {
+    self.per_host_concurrency = requests;
+    self
+  }
+
+  /// Annotates every host in the report with its autonomous system, looked up
+  /// in a MaxMind ASN database such as GeoLite2-ASN.
+  #[cfg(feature = "geoip")]
+  pub fn asn_database(mut self, path: impl Into<PathBuf>) -> Self {
+    self.asn_database = Some(path.into());
+    self
+  }
+
+  /// Annotates every host in the report with its country, looked up in a
+  /// MaxMind country database such as GeoLite2-Country.
+  #[cfg(feature = "geoip")]
+  pub fn country_database(mut self, path: impl Into<PathBuf>) -> Self {
+    self.country_database = Some(path.into());
+    self
+  }
+
+  /// Writes a [`Checkpoint`] of the crawl to `path` every 30 seconds and when
+  /// the crawl ends.
+  pub fn checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+    self.checkpoint_path = Some(path.into());
+    self
+  }
+
+  /// Continues the crawl of `checkpoint` instead of starting from the seeds.
+  pub fn resume(mut self, checkpoint: Checkpoint) -> Self {
+    self.resume = Some(checkpoint);
+    self
+  }
+
+  /// Calls `on_finding` with every newly discovered finding.
+  pub fn on_finding(mut self, on_finding: impl Fn(&Finding) + Send + Sync + 'static) -> Self {
+    self.on_finding = Some(FindingCallback(Arc::new(on_finding)));
+    self
+  }
+}
+
+/// Reads a plan file written by [`DispatcherBuilder::write_plan`].
+pub fn read_plan(path: &Path) -> Result<Vec<Finding>> {
+  std::fs::read_to_string(path)?
+    .lines()
+    .map(str::trim)
+    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    .map(Finding::from_plan_line)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn registered_fetchers_serve_their_host() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let fetcher = Arc::new(MemoryFetcher::default());
+    fetcher.insert_page(
+      url("https://a.example/"),
+      "<a href=/two>Two</a><img src=/a.png>",
+    );
+    fetcher.insert_page(url("https://a.example/two"), "<p>Two</p>");
+    fetcher.insert(
+      url("https://a.example/a.png"),
+      200,
+      HeaderMap::new(),
+      &b"\x89PNG\r\n\x1a\n"[..],
+    );
+    let storage = Arc::new(MemoryStorage::default());
+    let output_dir = std::env::temp_dir().join(format!("crawler-fetcher-{}", std::process::id()));
+    let mut dispatcher = DispatcherBuilder::new()
+      .seeds([url("https://a.example/")])
+      .politeness(Politeness::new(None, "test", true, Duration::ZERO))
+      .host_fetcher("a.example", fetcher.clone())
+      .storage(storage.clone())
+      .output_dir(&output_dir)
+      .build()
+      .unwrap();
+    dispatcher.run().await;
+
+    let requests = fetcher.requests();
+    for requested in ["/robots.txt", "/", "/two", "/a.png"] {
+      assert!(requests.contains(&url("https://a.example/").join(requested).unwrap()));
+    }
+    assert_eq!(storage.urls(), [url("https://a.example/a.png")]);
+    // robots.txt is audited along with the three requests it allowed.
+    let audit = &dispatcher.audit.hosts[&Host::Domain("a.example".to_owned())];
+    assert_eq!(audit.sent.len(), 4);
+    std::fs::remove_dir_all(&output_dir).ok();
+  }
+
+  #[test]
+  fn reloads_replace_the_delay_and_url_filters() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let mut dispatcher = DispatcherBuilder::new()
+      .seeds([url("https://a.example/")])
+      .politeness(Politeness::new(None, "test", true, Duration::ZERO))
+      .build()
+      .unwrap();
+    let page = Finding::Page(url("https://a.example/private/"), 1);
+    assert_eq!(dispatcher.follow_rejection(&page, 0, true), None);
+
+    let exclude = vec![Regex::new("/private/").unwrap()];
+    dispatcher.reload(Reload::new(Duration::from_secs(2), Vec::new(), exclude));
+    assert_eq!(dispatcher.politeness.delay, Duration::from_secs(2));
+    assert_eq!(
+      dispatcher.follow_rejection(&page, 0, true),
+      Some(SkipReason::Filtered)
+    );
+  }
+
+  #[tokio::test]
+  async fn crawls_keep_their_state_in_the_given_frontier() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let fetcher = Arc::new(MemoryFetcher::default());
+    fetcher.insert_page(url("https://a.example/"), "<a href=/two>Two</a>");
+    fetcher.insert_page(url("https://a.example/two"), "<p>Two</p>");
+    let frontier = Arc::new(MemoryFrontier::default());
+    let output_dir = std::env::temp_dir().join(format!("crawler-frontier-{}", std::process::id()));
+    let mut dispatcher = DispatcherBuilder::new()
+      .seeds([url("https://a.example/")])
+      .politeness(Politeness::new(None, "test", true, Duration::ZERO))
+      .host_fetcher("a.example", fetcher)
+      .storage(Arc::new(MemoryStorage::default()))
+      .frontier(frontier.clone())
+      .output_dir(&output_dir)
+      .build()
+      .unwrap();
+    dispatcher.run().await;
+
+    let page = Finding::Page(url("https://a.example/two"), 1);
+    assert!(frontier.archive().await.contains(&page));
+    let host = Host::parse("a.example").unwrap();
+    assert_eq!(frontier.host_visits().await[&host], 2);
+    assert!(frontier.is_empty().await);
+    let _ = std::fs::remove_dir_all(&output_dir);
+  }
+}
@@ -1,313 +1,2372 @@
 use std::{
-  borrow::Borrow,
-  collections::{HashMap, HashSet},
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  ffi::OsString,
+  fmt::Write as _,
+  io::Write as _,
+  path::{Path, PathBuf},
+  sync::Arc,
   time::Duration,
 };
 
-use clap::Parser;
-
-use futures::{prelude::*, stream::FuturesUnordered};
-use reqwest::Client;
-use tokio::{
-  fs::File,
-  io::AsyncWriteExt,
-  task::{self, JoinHandle},
-};
-
-use url::{Host, ParseError, Url};
+use clap::{CommandFactory as _, FromArgMatches as _, Parser};
+use regex::Regex;
+use url::Url;
 
 use color_eyre::Result;
-use tracing::{info, warn};
 
-const TIMEOUT_DURATION: Duration = Duration::from_millis(5000);
-const DEFAULT_RECURSION_DEPTH_LIMIT: u8 = 4;
-const HOST_VISIT_LIMIT: u32 = 256;
+use crawler::{
+  read_plan, AssetClass, Checkpoint, CrawlMode, DedupLinks, DispatcherBuilder, MemoryStorage,
+  NofollowPolicy, NullStorage, OutputFormat, PageRules, PathTemplate, Politeness, PublicSuffixList,
+  Reload, Scope, SkipReason, VariantPolicy, CHECKPOINT_VERSION, DEFAULT_CONCURRENCY,
+  DEFAULT_CONTACT_URL, DEFAULT_MIN_TRANSFER_WINDOW, DEFAULT_OUTPUT_DIR,
+  DEFAULT_PER_HOST_CONCURRENCY, DEFAULT_POLITENESS_DELAY_MS, DEFAULT_RECURSION_DEPTH_LIMIT,
+};
+#[cfg(feature = "images")]
+use crawler::{ConvertedFormat, DEFAULT_IMAGE_QUALITY};
+
+const SEED_PATTERN_EXPANSION_LIMIT: usize = 100_000;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-  tracing_subscriber::fmt()
-    .with_max_level(tracing::Level::INFO)
-    .init();
+  // Where the log goes is only known once the options are.
+  let config = tracing::subscriber::with_default(
+    tracing_subscriber::fmt()
+      .with_max_level(tracing::Level::INFO)
+      .finish(),
+    CrawlConfig::load,
+  )?;
+  init_logging(&config.args)?;
+  let (args, workspace) = (config.args, config.workspace);
+  match &args.command {
+    Some(Command::Init { dir }) => return Workspace::init(dir),
+    Some(Command::Explain { event_log, url }) => {
+      let event_log = Workspace::or_latest(event_log, &workspace, Workspace::latest_event_log)?;
+      return explain(&event_log, url, workspace.as_ref());
+    }
+    Some(Command::Index { event_log }) => {
+      let event_log = Workspace::or_latest(event_log, &workspace, Workspace::latest_event_log)?;
+      return build_index(&event_log, workspace.as_ref());
+    }
+    Some(Command::Diff { old, new, json }) => return diff(old, new, *json),
+    Some(Command::Migrate { checkpoint }) => {
+      let checkpoint = Workspace::or_latest(checkpoint, &workspace, |w| Some(w.checkpoint()))?;
+      match Checkpoint::migrate(&checkpoint)? {
+        version if version == CHECKPOINT_VERSION => {
+          println!("`{}` is up to date", checkpoint.display())
+        }
+        version => println!(
+          "migrated `{}` from checkpoint version {} to {}",
+          checkpoint.display(),
+          version,
+          CHECKPOINT_VERSION
+        ),
+      }
+      return Ok(());
+    }
+    Some(Command::View { archive, port }) => {
+      let archive = Workspace::or_latest(archive, &workspace, Workspace::latest_run)?;
+      return view(&archive, *port).await;
+    }
+    None => {}
+  }
 
-  let AppInput {
-    inital_urls,
-    recursion_depth_limit,
-  } = parse_cli_args();
+  let per_seed_output = args.per_seed_output;
+  let storage = args.storage;
+  #[cfg(unix)]
+  let reload = Reload::new(
+    politeness_delay(&args),
+    args.include.clone(),
+    args.exclude.clone(),
+  );
+  let mut builder = parse_cli_args(args)?;
+  #[cfg(unix)]
+  {
+    builder = builder.reloads(reload_on_hangup(reload)?);
+  }
+  let memory = Arc::new(MemoryStorage::default());
+  match storage {
+    StorageKind::Files => {}
+    StorageKind::Memory => builder = builder.storage(memory.clone()),
+    StorageKind::None => builder = builder.storage(Arc::new(NullStorage)),
+  }
 
-  let mut dispatcher = Dispatcher::new(inital_urls, recursion_depth_limit)?;
-  dispatcher.run().await;
+  if per_seed_output {
+    builder.crawl_per_seed().await?;
+  } else {
+    builder.crawl().await?;
+  }
+  if storage == StorageKind::Memory {
+    tracing::info!(
+      "kept {} resources ({} bytes) in memory",
+      memory.urls().len(),
+      memory.total_bytes()
+    );
+  }
 
   Ok(())
 }
 
-type SpiderHandle = JoinHandle<Result<SpiderResponse>>;
-type FetchHandle = JoinHandle<Result<()>>;
+/// What the variables of a config file stand for.
+struct TemplateVariables {
+  /// The `--host`, if one is given.
+  host: Option<String>,
+  /// The current date, like `2024-05-01`.
+  date: String,
+}
 
-#[derive(Debug)]
-struct Dispatcher {
-  client: Client,
-  inital_urls: HashSet<Url>,
-  recursion_depth_limit: u8,
-  archive: HashSet<Finding>,
-  host_visits: HashMap<Host, u32>,
+impl TemplateVariables {
+  /// `value` with `{host}` and `{date}` filled in, keeping any other braces,
+  /// like those of regexes. `None` if it uses `{host}`, but there is none.
+  fn fill(&self, value: &str) -> Option<String> {
+    let value = value.replace("{date}", &self.date);
+    match &self.host {
+      Some(host) => Some(value.replace("{host}", host)),
+      None => (!value.contains("{host}")).then_some(value),
+    }
+  }
+}
 
-  spiders: FuturesUnordered<SpiderHandle>,
-  fetchers: FuturesUnordered<FetchHandle>,
+/// Where the value of an option came from, to point at it in errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ConfigSource {
+  Default,
+  ConfigFile(PathBuf),
+  Environment(String),
+  CommandLine,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-enum Finding {
-  Page(Url, u8),
-  Image(Url),
+impl std::fmt::Display for ConfigSource {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Default => write!(f, "the default"),
+      Self::ConfigFile(path) => write!(f, "config file `{}`", path.display()),
+      Self::Environment(var) => write!(f, "environment variable `{}`", var),
+      Self::CommandLine => write!(f, "the command line"),
+    }
+  }
 }
 
-struct SpiderResponse {
-  findings: HashSet<Finding>,
-  depth: u8,
+/// The options of a crawl, merged from the `--config` file, `CRAWLER_*`
+/// environment variables and the command line, in increasing precedence, and
+/// validated as a whole.
+struct CrawlConfig {
+  args: Args,
+  /// The source of every option by its ID.
+  sources: HashMap<String, ConfigSource>,
+  /// The source of every seed URL, which may come from several at once.
+  seed_sources: HashMap<String, ConfigSource>,
+  workspace: Option<Workspace>,
 }
 
-impl Dispatcher {
-  fn new(inital_urls: HashSet<Url>, recursion_depth_limit: u8) -> Result<Self> {
-    let client = Client::builder()
-      .connect_timeout(TIMEOUT_DURATION)
-      .build()?;
+impl CrawlConfig {
+  fn load() -> Result<Self> {
+    let mut config = Self::read()?;
+    if config.args.command.is_none() {
+      config.apply_workspace()?;
+    }
+    config.validate()?;
+    Ok(config)
+  }
 
-    Ok(Self {
-      client,
-      inital_urls,
-      recursion_depth_limit,
-      archive: Default::default(),
-      host_visits: Default::default(),
-      spiders: Default::default(),
-      fetchers: Default::default(),
-    })
+  /// Reads the options again, for the settings a running crawl takes over.
+  #[cfg(unix)]
+  fn reload() -> Result<Reload> {
+    let config = Self::read()?;
+    config.validate()?;
+    let args = config.args;
+    Ok(Reload::new(
+      politeness_delay(&args),
+      args.include,
+      args.exclude,
+    ))
   }
 
-  async fn run(&mut self) {
-    let mut queue: Vec<Finding> = self
-      .inital_urls
-      .iter()
-      .cloned()
-      .map(|u| Finding::Page(u, 0))
-      .collect();
+  /// Merges the options of every source.
+  fn read() -> Result<Self> {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let command = Args::command().args_override_self(true);
+    let cli_matches = command.clone().get_matches_from(&cli);
 
-    while !queue.is_empty() || !self.spiders.is_empty() || !self.fetchers.is_empty() {
-      for finding in queue.drain(..) {
-        let url = match &finding {
-          Finding::Page(url, _) | Finding::Image(url) => url,
-        };
+    // Options from the file go first and those from the environment next, so
+    // that later sources override them, or add to them for options that can
+    // be given multiple times.
+    let urls_allowed = cli_matches.subcommand().is_none();
+    let env = Self::read_env(&command, urls_allowed)?;
+    let workspace = match cli_matches.get_one::<PathBuf>("workspace") {
+      Some(root) => Some(Workspace::open(root)?),
+      None => match std::env::var_os("CRAWLER_WORKSPACE") {
+        Some(root) => Some(Workspace::open(Path::new(&root))?),
+        None => Workspace::discover(&std::env::current_dir()?),
+      },
+    };
+    // `init` creates a workspace rather than working in one.
+    let workspace = workspace.filter(|_| !matches!(cli_matches.subcommand_name(), Some("init")));
+    let path = match cli_matches.get_one::<PathBuf>("config") {
+      Some(path) => Some(path.clone()),
+      None => std::env::var_os("CRAWLER_CONFIG").map(PathBuf::from),
+    };
+    let path = path.or_else(|| workspace.as_ref().map(Workspace::config));
+    let variables = TemplateVariables {
+      host: match cli_matches.get_one::<String>("host") {
+        Some(host) => Some(host.clone()),
+        None => std::env::var("CRAWLER_HOST").ok(),
+      },
+      date: humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string()[..10]
+        .to_owned(),
+    };
+    let mut options = Vec::new();
+    if let Some(path) = &path {
+      for (id, tokens) in Self::read_file(path, &command, urls_allowed, &variables)? {
+        options.push((id, ConfigSource::ConfigFile(path.clone()), tokens));
+      }
+    }
+    options.extend(env);
 
-        let Some(host) = url.host().map(|h| h.to_owned()) else {
-          continue
+    let mut tokens = cli[..1].to_vec();
+    let mut other_sources = HashMap::new();
+    let mut seed_sources = HashMap::new();
+    for (id, source, option) in options {
+      if id == "urls" {
+        for url in &option {
+          seed_sources.insert(url.to_string_lossy().into_owned(), source.clone());
+        }
+      }
+      tokens.extend(option);
+      other_sources.insert(id, source);
+    }
+    tokens.extend(cli[1..].iter().cloned());
+
+    let matches = command.clone().try_get_matches_from(tokens).map_err(|e| {
+      // Errors that the command line alone did not cause are blamed on the
+      // source of the offending option.
+      let culprit = e
+        .get(clap::error::ContextKind::InvalidArg)
+        .and_then(|arg| {
+          let arg = arg.to_string();
+          let long = arg
+            .trim_start_matches("--")
+            .split([' ', '='])
+            .next()?
+            .to_owned();
+          command
+            .get_arguments()
+            .find(|a| a.get_long() == Some(long.as_str()))
+            .map(|a| a.get_id().to_string())
+        })
+        .and_then(|id| other_sources.get(&id));
+      let message = e.render().to_string();
+      let message = message.trim_start_matches("error: ").trim_end();
+      match culprit {
+        Some(source) => eyre::eyre!("in {}: {}", source, message),
+        None if !other_sources.is_empty() => {
+          let sources: BTreeSet<_> = other_sources.values().map(ToString::to_string).collect();
+          let sources: Vec<_> = sources.into_iter().collect();
+          eyre::eyre!("with options from {}: {}", sources.join(", "), message)
+        }
+        None => e.into(),
+      }
+    })?;
+    let sources = matches
+      .ids()
+      .map(|id| {
+        let source = match cli_matches.value_source(id.as_str()) {
+          Some(clap::parser::ValueSource::CommandLine) => ConfigSource::CommandLine,
+          _ => other_sources
+            .get(id.as_str())
+            .cloned()
+            .unwrap_or(ConfigSource::Default),
         };
-        let visits = self.host_visits.entry(host.clone()).or_insert(0);
-        if *visits > HOST_VISIT_LIMIT {
+        (id.to_string(), source)
+      })
+      .collect();
+    for url in cli_matches.get_many::<String>("urls").into_iter().flatten() {
+      seed_sources.insert(url.clone(), ConfigSource::CommandLine);
+    }
+    Ok(Self {
+      args: Args::from_arg_matches(&matches)?,
+      sources,
+      seed_sources,
+      workspace,
+    })
+  }
+
+  /// Turns the `CRAWLER_*` environment variables into command line arguments,
+  /// by the ID and variable of every option. Every option has a variable named
+  /// after its long name, like `CRAWLER_OUTPUT_DIR`. Flags take `true` or
+  /// `false`, options that can be given multiple times one value per line,
+  /// and `CRAWLER_URLS` whitespace-separated URLs.
+  fn read_env(
+    command: &clap::Command,
+    urls_allowed: bool,
+  ) -> Result<Vec<(String, ConfigSource, Vec<OsString>)>> {
+    let mut options = Vec::new();
+    let mut known = HashSet::from(["CRAWLER_CONFIG".to_owned(), "CRAWLER_WORKSPACE".to_owned()]);
+    for arg in command.get_arguments() {
+      let id = arg.get_id().as_str();
+      let var = match arg.get_long() {
+        Some("help" | "version" | "config" | "workspace") => continue,
+        Some(long) => format!("CRAWLER_{}", long.to_uppercase().replace('-', "_")),
+        None if id == "urls" => "CRAWLER_URLS".to_owned(),
+        None => continue,
+      };
+      known.insert(var.clone());
+      let Some(value) = std::env::var_os(&var) else {
+        continue;
+      };
+      let source = ConfigSource::Environment(var);
+      let Some(value) = value.to_str() else {
+        eyre::bail!("{} is not valid UTF-8", source);
+      };
+
+      let tokens: Vec<OsString> = if id == "urls" {
+        if !urls_allowed {
           continue;
         }
-        *visits += 1;
-
-        match finding {
-          Finding::Page(_, ref depth) => self.spiders.push(task::spawn(spider_page(
-            url.clone(),
-            self.client.clone(),
-            *depth,
-          ))),
-          Finding::Image(..) => self
-            .fetchers
-            .push(task::spawn(fetch(url.clone(), self.client.clone()))),
+        value.split_whitespace().map(Into::into).collect()
+      } else if !arg.get_action().takes_values() {
+        match value.trim().to_ascii_lowercase().as_str() {
+          "1" | "true" | "yes" => vec![format!("--{}", arg.get_long().unwrap()).into()],
+          "0" | "false" | "no" | "" => Vec::new(),
+          _ => eyre::bail!("{} must be `true` or `false`, got `{}`", source, value),
+        }
+      } else {
+        let values: Vec<&str> = match arg.get_action() {
+          clap::ArgAction::Append => value.lines().filter(|l| !l.trim().is_empty()).collect(),
+          _ => vec![value],
         };
+        let long = arg.get_long().unwrap();
+        values
+          .into_iter()
+          .map(|v| format!("--{}={}", long, v).into())
+          .collect()
+      };
+      options.push((id.to_owned(), source, tokens));
+    }
+
+    for (var, _) in std::env::vars_os() {
+      if let Some(var) = var.to_str().filter(|v| v.starts_with("CRAWLER_")) {
+        if !known.contains(var) {
+          tracing::warn!(
+            "ignoring environment variable `{}`, which is not an option",
+            var
+          );
+        }
       }
+    }
+    Ok(options)
+  }
 
-      while let Some(spider) = self.spiders.next().await {
-        let spider = spider.unwrap();
+  /// Turns the options of a config file into command line arguments, by the
+  /// ID of every option, filling in the `variables` of their values.
+  fn read_file(
+    path: &Path,
+    command: &clap::Command,
+    urls_allowed: bool,
+    variables: &TemplateVariables,
+  ) -> Result<Vec<(String, Vec<OsString>)>> {
+    let source = ConfigSource::ConfigFile(path.to_owned());
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)
+      .map_err(|e| eyre::eyre!("{} is not valid JSON: {}", source, e))?;
+    let Some(object) = json.as_object() else {
+      eyre::bail!("{} must contain a JSON object of options", source);
+    };
 
-        match spider {
-          Ok(SpiderResponse {
-            mut findings,
-            depth,
-          }) => {
-            findings = findings.difference(&self.archive).cloned().collect();
-            self.archive.extend(findings.clone());
+    let mut options = Vec::new();
+    for (key, value) in object {
+      let name = key.replace('_', "-");
+      let values = match value {
+        serde_json::Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+      };
+      let fill = |value: &str| {
+        variables.fill(value).ok_or_else(|| {
+          eyre::eyre!(
+            "{} uses `{{host}}` in `{}`, but no `--host` is given",
+            source,
+            key
+          )
+        })
+      };
+      if name == "urls" {
+        if urls_allowed {
+          let urls = values.iter().filter_map(|v| v.as_str());
+          let urls = urls.map(|url| Ok(fill(url)?.into()));
+          options.push(("urls".to_owned(), urls.collect::<Result<_>>()?));
+        }
+        continue;
+      }
+      let Some(arg) = command.get_arguments().find(|a| {
+        a.get_long() == Some(name.as_str())
+          && !matches!(name.as_str(), "config" | "workspace" | "host")
+      }) else {
+        eyre::bail!(
+          "{} sets unknown option `{}`, expected the long name of an option like `output-dir`",
+          source,
+          key
+        );
+      };
 
-            if depth < self.recursion_depth_limit {
-              queue.extend(findings);
-            }
-          }
-          Err(e) => warn!("Spider failed with error: {}", e),
+      let mut tokens = Vec::new();
+      for value in values {
+        match value {
+          serde_json::Value::Bool(true) => tokens.push(format!("--{}", name).into()),
+          serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+          serde_json::Value::String(s) => tokens.push(format!("--{}={}", name, fill(s)?).into()),
+          serde_json::Value::Number(n) => tokens.push(format!("--{}={}", name, n).into()),
+          _ => eyre::bail!(
+            "{} sets `{}` to {}, expected a string, number, boolean or array of them",
+            source,
+            key,
+            value
+          ),
         }
       }
+      options.push((arg.get_id().to_string(), tokens));
+    }
+    Ok(options)
+  }
+
+  /// Puts the crawl into a new run of the workspace, if there is one: its
+  /// output into `runs`, its event log into `logs` and its checkpoint into
+  /// `state`, unless they are given.
+  fn apply_workspace(&mut self) -> Result<()> {
+    let Some(workspace) = &self.workspace else {
+      return Ok(());
+    };
+    let args = &mut self.args;
+    if self
+      .sources
+      .get("output_dir")
+      .is_none_or(|s| *s == ConfigSource::Default)
+    {
+      let (run, dir) = workspace.new_run()?;
+      tracing::info!(
+        "writing run `{}` of workspace `{}`",
+        run,
+        workspace.root.display()
+      );
+      if args.event_log.is_none() {
+        args.event_log = Some(workspace.event_log(&run));
+      }
+      args.output_dir = dir;
+    }
+    if args.checkpoint.is_none() && args.resume.is_none() {
+      args.checkpoint = Some(workspace.checkpoint());
+    }
+    Ok(())
+  }
+
+  fn source(&self, id: &str) -> &ConfigSource {
+    self.sources.get(id).unwrap_or(&ConfigSource::Default)
+  }
+
+  /// An option with its value and where the value came from.
+  fn describe(&self, id: &str, value: impl std::fmt::Display) -> String {
+    format!(
+      "`--{} {}` from {}",
+      id.replace('_', "-"),
+      value,
+      self.source(id)
+    )
+  }
+
+  /// Checks constraints between options that clap cannot express.
+  fn validate(&self) -> Result<()> {
+    let args = &self.args;
+    for url in &args.urls {
+      if let Err(e) = parse_seed(url) {
+        let source = self
+          .seed_sources
+          .get(url)
+          .unwrap_or(&ConfigSource::CommandLine);
+        eyre::bail!("in {}: {}", source, e);
+      }
+    }
+    if args.per_host_concurrency > args.concurrency {
+      eyre::bail!(
+        "{} allows more requests to one host than {} allows in total; lower the former or raise \
+         the latter",
+        self.describe("per_host_concurrency", args.per_host_concurrency),
+        self.describe("concurrency", args.concurrency)
+      );
+    }
+    if args.yolo && *self.source("delay") != ConfigSource::Default {
+      eyre::bail!(
+        "{} has no effect with `--yolo` from {}, which sends requests without delay; drop one \
+         of them",
+        self.describe("delay", args.delay),
+        self.source("yolo")
+      );
+    }
+    #[cfg(feature = "warc")]
+    if let Some(path) = &args.warc_output {
+      if args.format != OutputFormat::Warc {
+        eyre::bail!(
+          "{} needs `--format warc`, but the format is `files` from {}",
+          self.describe("warc_output", path.display()),
+          self.source("format")
+        );
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Logs to stdout, and to the `--log-file` if there is one.
+fn init_logging(args: &Args) -> Result<()> {
+  use tracing_subscriber::{
+    fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter,
+  };
+
+  let file = match &args.log_file {
+    Some(path) => {
+      let log = RotatingLog::open(
+        path,
+        args.log_max_size as u64,
+        args.log_rotate_every,
+        args.log_keep,
+      )?;
+      Some(
+        fmt::layer()
+          .with_ansi(false)
+          .with_writer(std::sync::Mutex::new(log)),
+      )
+    }
+    None => None,
+  };
+  tracing_subscriber::registry()
+    .with(EnvFilter::try_new(&args.log_filter)?)
+    .with(fmt::layer())
+    .with(file)
+    .init();
+  Ok(())
+}
 
-      while let Some(fetcher) = self.fetchers.next().await {
-        let fetcher = fetcher.unwrap();
-        if let Err(e) = fetcher {
-          warn!("Fetcher failed with error: {}", e);
+/// A log file that is moved to `PATH.1` once it is too large or too old,
+/// after moving the earlier ones from `PATH.n` to `PATH.n+1` and deleting
+/// those beyond the ones to keep. Events are written whole, so rotation never
+/// splits one.
+struct RotatingLog {
+  path: PathBuf,
+  max_size: u64,
+  max_age: Option<Duration>,
+  keep: usize,
+  file: std::fs::File,
+  size: u64,
+  opened: std::time::Instant,
+}
+
+impl RotatingLog {
+  /// Opens `path` to append to it, with the size it already has.
+  fn open(path: &Path, max_size: u64, max_age: Option<Duration>, keep: usize) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let file = Self::append(path)?;
+    Ok(Self {
+      path: path.to_owned(),
+      max_size,
+      max_age,
+      keep,
+      size: file.metadata()?.len(),
+      file,
+      opened: std::time::Instant::now(),
+    })
+  }
+
+  fn append(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+  }
+
+  fn rotated(&self, n: usize) -> PathBuf {
+    let mut path = self.path.as_os_str().to_owned();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+  }
+
+  fn rotate(&mut self) -> std::io::Result<()> {
+    if self.keep == 0 {
+      std::fs::remove_file(&self.path)?;
+    } else {
+      // Missing files are gaps left by an earlier `--log-keep`.
+      let _ = std::fs::remove_file(self.rotated(self.keep));
+      for n in (1..self.keep).rev() {
+        let _ = std::fs::rename(self.rotated(n), self.rotated(n + 1));
+      }
+      std::fs::rename(&self.path, self.rotated(1))?;
+    }
+    self.file = Self::append(&self.path)?;
+    self.size = 0;
+    self.opened = std::time::Instant::now();
+    Ok(())
+  }
+}
+
+impl std::io::Write for RotatingLog {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let too_large = self.size + buf.len() as u64 > self.max_size;
+    let too_old = self.max_age.is_some_and(|age| self.opened.elapsed() >= age);
+    if self.size > 0 && (too_large || too_old) {
+      self.rotate()?;
+    }
+    let written = self.file.write(buf)?;
+    self.size += written as u64;
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.file.flush()
+  }
+}
+
+/// The file marking the root of a workspace, with the options of its crawls.
+const WORKSPACE_CONFIG: &str = "crawler-workspace.json";
+
+/// A directory set up by `crawler init` for repeated crawls: the options in
+/// `crawler-workspace.json`, the checkpoint in `state`, indexes of event logs
+/// in `cache`, event logs in `logs` and the output of every crawl in a
+/// directory of `runs` named after when it started.
+#[derive(Debug, Clone)]
+struct Workspace {
+  root: PathBuf,
+}
+
+impl Workspace {
+  const DIRECTORIES: [&'static str; 4] = ["state", "cache", "logs", "runs"];
+
+  fn init(root: &Path) -> Result<()> {
+    for dir in Self::DIRECTORIES {
+      std::fs::create_dir_all(root.join(dir))?;
+    }
+    let config = root.join(WORKSPACE_CONFIG);
+    if config.exists() {
+      println!("`{}` already is a workspace", root.display());
+      return Ok(());
+    }
+    std::fs::write(&config, "{\n  \"urls\": []\n}\n")?;
+    println!(
+      "created workspace `{}`; add seeds and options to `{}`",
+      root.display(),
+      config.display()
+    );
+    Ok(())
+  }
+
+  fn open(root: &Path) -> Result<Self> {
+    if !root.join(WORKSPACE_CONFIG).is_file() {
+      eyre::bail!(
+        "`{}` is not a workspace, create it with `crawler init`",
+        root.display()
+      );
+    }
+    Ok(Self {
+      root: root.to_owned(),
+    })
+  }
+
+  /// The workspace `dir` is in, if any.
+  fn discover(dir: &Path) -> Option<Self> {
+    dir
+      .ancestors()
+      .find(|d| d.join(WORKSPACE_CONFIG).is_file())
+      .map(|root| Self {
+        root: root.to_owned(),
+      })
+  }
+
+  fn config(&self) -> PathBuf {
+    self.root.join(WORKSPACE_CONFIG)
+  }
+
+  fn checkpoint(&self) -> PathBuf {
+    self.root.join("state").join("checkpoint.json")
+  }
+
+  /// Creates the output directory of a new run, named after the current time
+  /// so that runs sort by when they started.
+  fn new_run(&self) -> Result<(String, PathBuf)> {
+    let now = humantime::format_rfc3339_seconds(std::time::SystemTime::now());
+    let stamp = now.to_string().replace(':', "-");
+    let runs = self.root.join("runs");
+    std::fs::create_dir_all(&runs)?;
+    let (mut run, mut n) = (stamp.clone(), 1);
+    loop {
+      match std::fs::create_dir(runs.join(&run)) {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+          n += 1;
+          run = format!("{}-{}", stamp, n);
         }
+        result => break result?,
       }
     }
+    let dir = runs.join(&run);
+    Ok((run, dir))
+  }
+
+  /// The event log of the crawl `run`, unless it was given another.
+  fn event_log(&self, run: &str) -> PathBuf {
+    self.root.join("logs").join(format!("{}.jsonl.zst", run))
+  }
+
+  /// The names of the runs, from the latest.
+  fn runs(&self) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(self.root.join("runs")) else {
+      return Vec::new();
+    };
+    let mut runs: Vec<String> = entries
+      .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+      .collect();
+    runs.sort_unstable_by(|a, b| b.cmp(a));
+    runs
+  }
+
+  fn latest_run(&self) -> Option<PathBuf> {
+    let run = self.runs().into_iter().next()?;
+    Some(self.root.join("runs").join(run))
+  }
+
+  /// The event log of the latest run that wrote one to the workspace.
+  fn latest_event_log(&self) -> Option<PathBuf> {
+    self
+      .runs()
+      .iter()
+      .map(|run| self.event_log(run))
+      .find(|log| log.is_file())
+  }
+
+  /// `path`, or else the latest of the workspace.
+  fn or_latest(
+    path: &Option<PathBuf>,
+    workspace: &Option<Self>,
+    latest: fn(&Self) -> Option<PathBuf>,
+  ) -> Result<PathBuf> {
+    if let Some(path) = path {
+      return Ok(path.clone());
+    }
+    match workspace {
+      Some(workspace) => latest(workspace)
+        .ok_or_else(|| eyre::eyre!("workspace `{}` has no crawls yet", workspace.root.display())),
+      None => eyre::bail!("no path given and not in a workspace; give one or run `crawler init`"),
+    }
+  }
+
+  /// Where the indexes of event logs go.
+  fn index_dir(&self) -> PathBuf {
+    self.root.join("cache")
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum StorageKind {
+  Files,
+  Memory,
+  None,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Option<Command>,
+
+  urls: Vec<String>,
+
+  /// Read options from this JSON file, keyed by their long names, e.g.
+  /// `{"output-dir": "out", "delay": 2000, "include": ["/blog/"]}`. Options
+  /// given on the command line or in `CRAWLER_*` environment variables take
+  /// precedence. Also read from `CRAWLER_CONFIG`. On Unix, a crawl reads its
+  /// options again on SIGHUP and takes over their delay and URL filters.
+  #[arg(long, value_name = "PATH")]
+  config: Option<PathBuf>,
+
+  /// The host that `{host}` stands for in the values of the `--config` file,
+  /// which makes it a template for crawls of many sites, e.g.
+  /// `{"urls": ["https://{host}/"], "output-dir": "crawls/{host}/{date}"}`.
+  /// `{date}` stands for the current date, like `2024-05-01`.
+  #[arg(long, value_name = "HOST")]
+  host: Option<String>,
+
+  /// Work in this workspace created with `crawler init`, instead of the one
+  /// the current directory is in. Crawls then write their output to a new
+  /// directory of `runs`, their event log to `logs` and their checkpoint to
+  /// `state`, and read options from its `crawler-workspace.json`. Also read
+  /// from `CRAWLER_WORKSPACE`.
+  #[arg(long, value_name = "DIR")]
+  workspace: Option<PathBuf>,
+
+  /// Also crawl every page listed in the sitemap at this URL, following
+  /// sitemap indexes and gzipped sitemaps, e.g.
+  /// `https://example.com/sitemap.xml`. Can be given multiple times.
+  #[arg(long, value_name = "URL")]
+  from_sitemap: Vec<Url>,
+
+  /// File of seed URLs, one per line, or `-` to read them from stdin. Blank
+  /// lines and lines starting with `#` are skipped, and a URL may be followed
+  /// by tags like in `--seed-patterns`.
+  #[arg(long, value_name = "PATH")]
+  seeds_file: Option<PathBuf>,
+
+  /// File of seed URL patterns, one per line. `{1..5}` (or zero-padded
+  /// `{01..10}`) expands to a numeric range and `{a,b}` to alternatives. A
+  /// pattern may be followed by comma-separated tags, e.g.
+  /// `https://a.example important,docs`, which label everything crawled from
+  /// its seeds in the findings export, manifests and report.
+  #[arg(long, value_name = "PATH")]
+  seed_patterns: Option<PathBuf>,
+
+  #[arg(short, long, default_value_t = DEFAULT_RECURSION_DEPTH_LIMIT)]
+  recursion_depth_limit: u8,
+
+  /// Depth limit for assets, counted like pages: an asset referenced by a page
+  /// at depth `n` is at depth `n + 1`. Defaults to the recursion depth limit.
+  #[arg(long, value_name = "N")]
+  asset_depth_limit: Option<u8>,
+
+  /// Which kinds of findings are followed: pages and assets, only pages, or
+  /// only the assets of the seed pages. A plan given with `--from-plan` is
+  /// visited as it is, so the two cannot be combined.
+  #[arg(long, value_enum, default_value_t = CrawlMode::Full, conflicts_with = "from_plan")]
+  mode: CrawlMode,
+
+  /// Comma-separated kinds of assets to download: images, stylesheets
+  /// (including what they import and refer to), scripts, fonts, icons and
+  /// media.
+  #[arg(long, value_enum, value_delimiter = ',', default_value = "images")]
+  assets: Vec<AssetClass>,
+
+  /// Also download the video and audio of `<video>` and `<audio>`, like
+  /// `media` in `--assets`. Raise `--max-file-size` for long ones.
+  #[arg(long)]
+  media: bool,
+
+  /// Save identical resources only once and link the duplicates to the first
+  /// copy, with hard links unless `symbolic` is given.
+  #[arg(long, value_enum, value_name = "LINKS", num_args = 0..=1, default_missing_value = "hard")]
+  dedup: Option<DedupLinks>,
+
+  /// Where downloaded resources go: files in the asset directory, memory (to
+  /// measure a crawl without touching the disk) or nowhere.
+  #[arg(long, value_enum, default_value_t = StorageKind::Files)]
+  storage: StorageKind,
+
+  /// Also record every HTTP exchange in a WARC file with `warc`.
+  #[arg(long, value_enum, default_value_t = OutputFormat::Files)]
+  format: OutputFormat,
+
+  /// The WARC file of `--format warc`, gzipped if it ends in `.gz`. Defaults
+  /// to `crawl.warc.gz` in the output directory.
+  #[cfg(feature = "warc")]
+  #[arg(long, value_name = "PATH")]
+  warc_output: Option<PathBuf>,
+
+  /// Directory the report, plan and other artifacts of the crawl are written
+  /// to, and the downloaded resources below `res` in it.
+  #[arg(long, value_name = "DIR", default_value = DEFAULT_OUTPUT_DIR)]
+  output_dir: PathBuf,
+
+  /// Where downloaded resources go below the asset directory, e.g.
+  /// `{host}/{path}` to mirror the sites or `{host}/{hash}.{ext}`. Also
+  /// `{name}`, `{stem}` and `{ext}` of the file name used without a template.
+  #[arg(long, value_name = "TEMPLATE", value_parser = PathTemplate::parse)]
+  path_template: Option<PathTemplate>,
+
+  /// Directory the downloaded resources are saved to, instead of `res` in the
+  /// output directory.
+  #[arg(long, value_name = "PATH")]
+  assets_dir: Option<PathBuf>,
+
+  /// Store the request and response headers of every saved resource in a
+  /// `.headers.json` file next to it.
+  #[arg(long)]
+  save_headers: bool,
+
+  /// Do not download assets that were already saved to the output directory,
+  /// making repeated crawls into it incremental.
+  #[arg(long)]
+  skip_existing: bool,
+
+  /// Give up on downloads larger than this, e.g. `500K` or `2G`, as soon as
+  /// the size is known. Downloads are not limited by default.
+  #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+  max_file_size: Option<usize>,
+
+  /// Abort downloads whose body arrives slower than this many bytes per
+  /// second, e.g. `1K`, over `--min-transfer-window`. Downloads are not held
+  /// to a rate by default, and `0` never aborts either.
+  #[arg(long, value_name = "SIZE", value_parser = parse_transfer_rate)]
+  min_transfer_rate: Option<usize>,
+
+  /// Seconds over which the rate of a download is held to
+  /// `--min-transfer-rate`.
+  #[arg(long, value_name = "SECS", default_value_t = DEFAULT_MIN_TRANSFER_WINDOW.as_secs(), value_parser = clap::value_parser!(u64).range(1..))]
+  min_transfer_window: u64,
+
+  /// Crawl at most this many pages descending from every seed. Seeds always
+  /// take turns, so none has to wait for the frontier of another.
+  #[arg(long, value_name = "PAGES")]
+  per_seed_budget: Option<u32>,
+
+  /// Finish shallow levels before deep ones, and wait longer between requests
+  /// to a host the shallower the page: twice the delay for seeds, down to the
+  /// plain delay at the depth limit. Interrupted crawls keep complete shallow
+  /// levels.
+  #[arg(long)]
+  depth_paced: bool,
+
+  /// Treat `/dir`, `/dir/` and `/dir/index.html` as the same page once the
+  /// first two of them crawled have identical contents, for every directory
+  /// on its own.
+  #[arg(long)]
+  index_equivalence: bool,
+
+  /// Resolve hosts that many queued URLs go to while their requests wait for
+  /// the politeness delay. Connections are not opened ahead, as that would
+  /// take a request of its own; the robots.txt fetch opens one that the
+  /// requests after it reuse.
+  #[cfg(feature = "dns-pinning")]
+  #[arg(long)]
+  preconnect: bool,
+
+  /// Transcode downloaded images into this format before saving them.
+  #[cfg(feature = "images")]
+  #[arg(long, value_enum, value_name = "FORMAT")]
+  convert_images: Option<ConvertedFormat>,
+
+  /// Write a `dataset.jsonl` manifest of all saved images with their captions
+  /// and sources to the output directory, for use as a training dataset.
+  #[arg(long)]
+  dataset_manifest: bool,
+
+  /// Also save the HTML of every crawled page, under `pages/` in the assets
+  /// directory, and write a `pages.jsonl` manifest mapping their URLs to
+  /// their files.
+  #[arg(long)]
+  save_pages: bool,
+
+  /// Apply the search-and-replace rules in this file to every page saved with
+  /// `--save-pages`, one `REGEX => REPLACEMENT` per line.
+  #[arg(long, value_name = "PATH", requires = "save_pages")]
+  page_rules: Option<PathBuf>,
+
+  /// Start every page saved with `--save-pages` with a comment naming its
+  /// URL, fetch time and the crawl ID from `run.json`.
+  #[arg(long, requires = "save_pages")]
+  page_provenance: bool,
+
+  /// Fetch only the first SIZE, e.g. `16K`, of linked files whose extension
+  /// is not that of a web page, and download them in full only if they turn
+  /// out to be HTML.
+  #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+  sample_pages: Option<usize>,
+
+  /// Quality of images converted to JPEG, from 1 to 100.
+  #[cfg(feature = "images")]
+  #[arg(long, default_value_t = DEFAULT_IMAGE_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+  image_quality: u8,
+
+  /// MaxMind ASN database (e.g. GeoLite2-ASN.mmdb) to annotate every host in
+  /// the report with the network it is hosted in.
+  #[cfg(feature = "geoip")]
+  #[arg(long, value_name = "PATH")]
+  asn_db: Option<PathBuf>,
+
+  /// MaxMind country database (e.g. GeoLite2-Country.mmdb) to annotate every
+  /// host in the report with the country it is hosted in.
+  #[cfg(feature = "geoip")]
+  #[arg(long, value_name = "PATH")]
+  country_db: Option<PathBuf>,
+
+  /// Periodically write the frontier, archive and host visits to this file,
+  /// from which an interrupted crawl can be resumed.
+  #[arg(long, value_name = "PATH")]
+  checkpoint: Option<PathBuf>,
+
+  /// Resume the crawl of a checkpoint file, and keep checkpointing to it
+  /// unless `--checkpoint` names another.
+  #[arg(long, value_name = "PATH", conflicts_with = "per_seed_output")]
+  resume: Option<PathBuf>,
+
+  /// Where the crawl report is written. Defaults to `report.md` in the output
+  /// directory.
+  #[arg(long, value_name = "PATH")]
+  report: Option<PathBuf>,
+
+  /// List every URL that was not crawled, with the reason, in the report.
+  #[arg(long)]
+  verbose_report: bool,
+
+  /// Record every crawl decision to a zstd-compressed JSON Lines event log.
+  #[arg(long, value_name = "PATH")]
+  event_log: Option<PathBuf>,
+
+  /// Which events are logged, by module and level, like
+  /// `crawler=debug,reqwest=warn,html5ever=error`. A bare level applies to
+  /// every module.
+  #[arg(long, value_name = "FILTER", default_value = "info", value_parser = parse_log_filter)]
+  log_filter: String,
+
+  /// Also write the log to this file, without colors.
+  #[arg(long, value_name = "PATH")]
+  log_file: Option<PathBuf>,
+
+  /// Rotate the log file once it would grow beyond this size, e.g. `500M`.
+  #[arg(long, value_name = "SIZE", default_value = "100M", value_parser = parse_size)]
+  log_max_size: usize,
+
+  /// Also rotate the log file once it has been written to for this long,
+  /// e.g. `1day` or `12h`.
+  #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+  log_rotate_every: Option<Duration>,
+
+  /// How many rotated log files to keep, as `PATH.1` for the latest up to
+  /// `PATH.N`. `0` keeps none.
+  #[arg(long, value_name = "N", default_value_t = 5)]
+  log_keep: usize,
+
+  /// Write a JSON Lines record of every crawled page and fetched asset, with
+  /// its type, depth, referrer, status, content type, size and timestamp.
+  #[arg(long, value_name = "PATH")]
+  output: Option<PathBuf>,
+
+  /// Record every form and same-origin API endpoint in the report.
+  #[arg(long)]
+  inventory_forms: bool,
+
+  /// Harvest email addresses and phone numbers into the report.
+  #[arg(long)]
+  extract_contacts: bool,
+
+  /// Write the URLs that would be visited to a plan file instead of
+  /// downloading anything. Pages are still fetched to discover links.
+  #[arg(long, value_name = "PATH", conflicts_with = "from_plan")]
+  plan: Option<PathBuf>,
+
+  /// Write a sitemap.xml listing every page that was fetched successfully and
+  /// is not `noindex`, with its `Last-Modified` time or else when it was
+  /// fetched. Several hosts, or more than 50,000 pages, get a sitemap per
+  /// host next to it, and it becomes their sitemap index.
+  #[arg(long, value_name = "PATH")]
+  sitemap: Option<PathBuf>,
+
+  /// Write the directed graph of which page linked to which URL, as DOT
+  /// (`.dot`, `.gv`) or GraphML (`.graphml`) depending on the extension.
+  #[arg(long, value_name = "PATH")]
+  graph: Option<PathBuf>,
+
+  /// Visit exactly the URLs listed in a plan file written by `--plan`.
+  #[arg(long, value_name = "PATH")]
+  from_plan: Option<PathBuf>,
+
+  /// Crawl every seed host separately, writing its assets, report and plan to
+  /// a directory of its own and an `index.json` to the output root.
+  #[arg(long, conflicts_with = "from_plan")]
+  per_seed_output: bool,
+
+  /// Serve the URLs of a host from a local directory instead of the network,
+  /// e.g. `example.com=./mirror`. Can be given multiple times.
+  #[arg(long, value_name = "HOST=DIR", value_parser = parse_serve_from)]
+  serve_from: Vec<(String, PathBuf)>,
+
+  /// Which linked pages to follow: only the seeds, those on the hosts of the
+  /// seeds, on their registrable domains including subdomains, or all.
+  #[arg(long, value_enum, default_value_t = Scope::Host)]
+  scope: Scope,
+
+  /// What to do with the AMP and mobile versions pages announce with
+  /// `rel=amphtml` and mobile `rel=alternate` links: crawl them too, skip
+  /// links to them, or follow links to them to the announcing page instead.
+  #[arg(long, value_enum, default_value_t = VariantPolicy::Skip)]
+  variants: VariantPolicy,
+
+  /// Skip assets that the `Content-Security-Policy` of the page that links
+  /// them would not let a browser load.
+  #[arg(long)]
+  respect_csp: bool,
+
+  /// Tell registrable domains apart for `--scope domain` with this Public
+  /// Suffix List file instead of a built-in list of common suffixes.
+  #[arg(long, value_name = "PATH")]
+  public_suffix_list: Option<PathBuf>,
+
+  /// Only follow links to pages whose URL matches this regex, e.g.
+  /// `/blog/.*`. Can be given multiple times to allow any of them.
+  #[arg(long, value_name = "REGEX")]
+  include: Vec<Regex>,
+
+  /// Do not follow links to pages whose URL matches this regex, e.g.
+  /// `[?&]sort=`. Can be given multiple times, and wins over `--include`.
+  #[arg(long, value_name = "REGEX")]
+  exclude: Vec<Regex>,
+
+  /// Cache responses for URLs matching this regex, ignoring their query
+  /// string, e.g. `\.(png|css|js)(\?|$)`. Can be given multiple times.
+  #[arg(long, value_name = "REGEX")]
+  cache_rule: Vec<Regex>,
+
+  /// Strip a cache-busting query parameter from asset URLs, on all hosts or
+  /// only on HOST. A trailing `*` matches by prefix. Can be given multiple
+  /// times.
+  #[arg(long, value_name = "[HOST=]PARAM", value_parser = parse_strip_param)]
+  strip_param: Vec<(Option<String>, String)>,
+
+  /// Also strip the built-in list of cache-busting parameters, such as `v`,
+  /// `version` and `_`.
+  #[arg(long)]
+  default_strip_params: bool,
+
+  /// Strip an analytics query parameter from found links, on all hosts or
+  /// only on HOST. A trailing `*` matches by prefix. Can be given multiple
+  /// times, in addition to the built-in `utm_*`, `fbclid` and `gclid`.
+  #[arg(long, value_name = "[HOST=]PARAM", value_parser = parse_strip_param)]
+  strip_tracking_param: Vec<(Option<String>, String)>,
+
+  /// Do not strip the built-in list of analytics parameters.
+  #[arg(long)]
+  no_default_tracking_params: bool,
+
+  /// Treat URLs with and without a trailing slash as the same page.
+  #[arg(long)]
+  strip_trailing_slash: bool,
+
+  /// Treat URLs that only differ in the order of their query parameters as
+  /// the same.
+  #[arg(long)]
+  sort_query: bool,
+
+  /// Contact URL announced in the default user agent.
+  #[arg(long, value_name = "URL", default_value = DEFAULT_CONTACT_URL)]
+  contact: String,
+
+  /// Send every request through this HTTP proxy, e.g.
+  /// `http://proxy.internal:3128`, instead of the one in `HTTP_PROXY` or
+  /// `HTTPS_PROXY`.
+  #[arg(long, value_name = "URL")]
+  proxy: Option<Url>,
+
+  /// Send this user agent instead of the default one, which names the crawler
+  /// and the contact URL. This is a politeness override.
+  #[arg(long, value_name = "UA")]
+  user_agent: Option<String>,
+
+  /// Minimum delay between two requests to the same host, in milliseconds.
+  /// Lowering it below the default is a politeness override.
+  #[arg(long, visible_alias = "delay-ms", value_name = "MS", default_value_t = DEFAULT_POLITENESS_DELAY_MS)]
+  delay: u64,
+
+  /// Maximum number of requests per second to the same host, as an
+  /// alternative to `--delay`. More than the default rate is a politeness
+  /// override.
+  #[arg(long, value_name = "RPS", conflicts_with = "delay", value_parser = parse_rate)]
+  max_per_host_rps: Option<f64>,
+
+  /// Maximum number of requests in flight at once.
+  #[arg(long, value_name = "N", default_value_t = DEFAULT_CONCURRENCY, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+  concurrency: usize,
+
+  /// Maximum number of requests to the same host in flight at once.
+  #[arg(long, value_name = "M", default_value_t = DEFAULT_PER_HOST_CONCURRENCY, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+  per_host_concurrency: usize,
+
+  /// Do not respect robots.txt. This is a politeness override.
+  #[arg(long)]
+  ignore_robots: bool,
+
+  /// Which `nofollow` hints to respect: `rel="nofollow"` links and pages
+  /// marked `nofollow` by their robots meta tag or `X-Robots-Tag`, also pages
+  /// marked `noindex`, or none. Ignoring them is a politeness override.
+  #[arg(long, value_enum, default_value_t = NofollowPolicy::Respect)]
+  nofollow: NofollowPolicy,
+
+  /// Disable all politeness: ignore robots.txt and nofollow hints, and send
+  /// requests without delay.
+  /// This is a politeness override.
+  #[arg(long)]
+  yolo: bool,
+}
+
+fn parse_strip_param(value: &str) -> Result<(Option<String>, String), String> {
+  match value.split_once('=') {
+    Some((host, param)) if !host.is_empty() && !param.is_empty() => {
+      Ok((Some(host.to_owned()), param.to_owned()))
+    }
+    None if !value.is_empty() => Ok((None, value.to_owned())),
+    _ => Err(format!("expected [HOST=]PARAM, got `{}`", value)),
   }
 }
 
-async fn spider_page(url: Url, client: Client, depth: u8) -> Result<SpiderResponse> {
-  info!("crawling url `{}`", &url);
+fn parse_size(value: &str) -> Result<usize, String> {
+  let (digits, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+    Some(i) => value.split_at(i),
+    None => (value, ""),
+  };
+  let shift = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 0,
+    "K" | "KB" | "KIB" => 10,
+    "M" | "MB" | "MIB" => 20,
+    "G" | "GB" | "GIB" => 30,
+    _ => {
+      return Err(format!(
+        "expected a size like `500K` or `2G`, got `{}`",
+        value
+      ))
+    }
+  };
+  digits
+    .parse::<usize>()
+    .ok()
+    .and_then(|n| n.checked_mul(1 << shift))
+    .filter(|&n| n > 0)
+    .ok_or_else(|| format!("expected a size like `500K` or `2G`, got `{}`", value))
+}
 
-  let request = client.get(url.clone());
-  let response = request.send().await?;
-  let body = response.text().await?;
+fn parse_transfer_rate(value: &str) -> Result<usize, String> {
+  match value {
+    "0" => Ok(0),
+    _ => parse_size(value),
+  }
+}
 
-  let findings = process_page(&url, body, depth);
-  Ok(SpiderResponse { findings, depth })
+fn parse_log_filter(value: &str) -> Result<String, String> {
+  match tracing_subscriber::EnvFilter::try_new(value) {
+    Ok(_) => Ok(value.to_owned()),
+    Err(e) => Err(format!(
+      "expected directives like `crawler=debug,reqwest=warn`, got `{}`: {}",
+      value, e
+    )),
+  }
 }
 
-fn process_page(page_url: &Url, page_body: String, depth: u8) -> HashSet<Finding> {
-  let mut page_url = page_url.clone();
-  page_url.set_path("");
-  page_url.set_query(None);
+fn parse_rate(value: &str) -> Result<f64, String> {
+  match value.parse::<f64>() {
+    Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+    _ => Err(format!("expected a positive number, got `{}`", value)),
+  }
+}
 
-  let mut raw_findings = Aggregate::new(depth);
-  let mut tokenizer = Tokenizer::new(&mut raw_findings, TokenizerOpts::default());
-  let mut buffer = BufferQueue::new();
-  buffer.push_back(page_body.into());
-  let _ = tokenizer.feed(&mut buffer);
+fn parse_serve_from(value: &str) -> Result<(String, PathBuf), String> {
+  let (host, dir) = value
+    .split_once('=')
+    .ok_or_else(|| format!("expected HOST=DIR, got `{}`", value))?;
+  Ok((host.to_owned(), dir.into()))
+}
 
-  raw_findings.parse(&page_url)
+/// The delay between requests to a host that the options ask for.
+fn politeness_delay(args: &Args) -> Duration {
+  match (args.yolo, args.max_per_host_rps) {
+    (true, _) => Duration::ZERO,
+    (false, Some(rate)) => Duration::from_secs_f64(1.0 / rate),
+    (false, None) => Duration::from_millis(args.delay),
+  }
 }
 
-#[derive(Debug)]
-struct Aggregate {
-  depth: u8,
-  page_links: Vec<String>,
-  image_links: Vec<String>,
+/// Reads the options again on every SIGHUP, for the crawl to take over their
+/// delay and URL filters, starting from `current`.
+#[cfg(unix)]
+fn reload_on_hangup(current: Reload) -> Result<tokio::sync::watch::Receiver<Reload>> {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let mut hangups = signal(SignalKind::hangup())?;
+  let (sender, receiver) = tokio::sync::watch::channel(current);
+  tokio::spawn(async move {
+    while hangups.recv().await.is_some() {
+      match CrawlConfig::reload() {
+        Ok(reload) => {
+          if sender.send(reload).is_err() {
+            break;
+          }
+        }
+        Err(e) => tracing::warn!("keeping the options, since reloading them failed: {}", e),
+      }
+    }
+  });
+  Ok(receiver)
 }
 
-impl Aggregate {
-  const fn new(depth: u8) -> Self {
-    Self {
-      depth,
-      page_links: Vec::new(),
-      image_links: Vec::new(),
+fn parse_cli_args(args: Args) -> Result<DispatcherBuilder> {
+  let mut inital_urls: HashSet<Url> = args
+    .urls
+    .iter()
+    .map(|url| parse_seed(url))
+    .collect::<Result<_>>()?;
+  let mut seeds = Vec::new();
+  if let Some(path) = &args.seeds_file {
+    seeds.extend(read_seeds_file(path)?);
+  }
+  if let Some(path) = &args.seed_patterns {
+    seeds.extend(read_seed_patterns(path)?);
+  }
+  let mut tagged_seeds = Vec::new();
+  for (url, tags) in seeds {
+    if tags.is_empty() {
+      inital_urls.insert(url);
+    } else {
+      tagged_seeds.push((url, tags));
     }
   }
+
+  let delay = politeness_delay(&args);
+  let politeness = Politeness::new(
+    args.user_agent,
+    &args.contact,
+    !(args.yolo || args.ignore_robots),
+    delay,
+  )
+  .nofollow(match args.yolo {
+    true => NofollowPolicy::Ignore,
+    false => args.nofollow,
+  });
+
+  let mut builder = DispatcherBuilder::new()
+    .seeds(inital_urls)
+    .depth(args.recursion_depth_limit)
+    .mode(args.mode)
+    .assets(
+      args
+        .assets
+        .into_iter()
+        .chain(args.media.then_some(AssetClass::Media)),
+    )
+    .save_headers(args.save_headers)
+    .skip_existing(args.skip_existing)
+    .min_transfer_rate(
+      args.min_transfer_rate.unwrap_or_default(),
+      Duration::from_secs(args.min_transfer_window),
+    )
+    .depth_paced(args.depth_paced)
+    .stop_on_interrupt(true)
+    .index_equivalence(args.index_equivalence)
+    .concurrency(args.concurrency)
+    .per_host_concurrency(args.per_host_concurrency)
+    .dataset_manifest(args.dataset_manifest)
+    .save_pages(args.save_pages)
+    .page_provenance(args.page_provenance)
+    .format(args.format)
+    .politeness(politeness)
+    .output_dir(args.output_dir)
+    .verbose_report(args.verbose_report)
+    .inventory_forms(args.inventory_forms)
+    .extract_contacts(args.extract_contacts)
+    .default_strip_params(args.default_strip_params)
+    .default_tracking_params(!args.no_default_tracking_params)
+    .strip_trailing_slash(args.strip_trailing_slash)
+    .sort_query(args.sort_query);
+  if let Some(bytes) = args.sample_pages {
+    builder = builder.sample_pages(bytes);
+  }
+  #[cfg(feature = "dns-pinning")]
+  {
+    builder = builder.preconnect(args.preconnect);
+  }
+  #[cfg(feature = "warc")]
+  if let Some(path) = args.warc_output {
+    builder = builder.warc_output(path);
+  }
+  if let Some(proxy) = args.proxy {
+    builder = builder.proxy(proxy);
+  }
+  if let Some(path) = args.page_rules {
+    builder = builder.page_rules(PageRules::read(&path)?);
+  }
+  if let Some(depth) = args.asset_depth_limit {
+    builder = builder.asset_depth(depth);
+  }
+  if let Some(bytes) = args.max_file_size {
+    builder = builder.max_file_size(bytes);
+  }
+  if let Some(pages) = args.per_seed_budget {
+    builder = builder.per_seed_budget(pages);
+  }
+  if let Some(links) = args.dedup {
+    builder = builder.dedup(links);
+  }
+  if let Some(path) = &args.resume {
+    builder = builder.resume(Checkpoint::read(path)?);
+  }
+  if let Some(path) = args.checkpoint.or(args.resume) {
+    builder = builder.checkpoint(path);
+  }
+  #[cfg(feature = "geoip")]
+  if let Some(path) = args.asn_db {
+    builder = builder.asn_database(path);
+  }
+  #[cfg(feature = "geoip")]
+  if let Some(path) = args.country_db {
+    builder = builder.country_database(path);
+  }
+  if let Some(path) = args.report {
+    builder = builder.report_path(path);
+  }
+  if let Some(template) = args.path_template {
+    builder = builder.path_template(template);
+  }
+  if let Some(path) = args.assets_dir {
+    builder = builder.asset_dir(path);
+  }
+  #[cfg(feature = "images")]
+  if let Some(format) = args.convert_images {
+    builder = builder.convert_images(format, args.image_quality);
+  }
+  if let Some(path) = args.event_log {
+    builder = builder.event_log(path);
+  }
+  if let Some(path) = args.output {
+    builder = builder.findings_export(path);
+  }
+  if let Some(path) = &args.from_plan {
+    builder = builder.planned(read_plan(path)?);
+  }
+  if let Some(path) = args.plan {
+    builder = builder.write_plan(path);
+  }
+  if let Some(path) = args.graph {
+    builder = builder.link_graph(path);
+  }
+  if let Some(path) = args.sitemap {
+    builder = builder.write_sitemap(path);
+  }
+  for (host, root) in args.serve_from {
+    builder = builder.serve_from(host, root);
+  }
+  for (url, tags) in tagged_seeds {
+    builder = builder.tagged_seed(url, tags);
+  }
+  for url in args.from_sitemap {
+    builder = builder.sitemap(url);
+  }
+  builder = builder
+    .scope(args.scope)
+    .variants(args.variants)
+    .respect_csp(args.respect_csp);
+  if let Some(path) = args.public_suffix_list {
+    builder = builder.public_suffixes(PublicSuffixList::read(&path)?);
+  }
+  for regex in args.include {
+    builder = builder.include(regex);
+  }
+  for regex in args.exclude {
+    builder = builder.exclude(regex);
+  }
+  for rule in args.cache_rule {
+    builder = builder.cache_rule(rule);
+  }
+  for (host, param) in args.strip_param {
+    builder = builder.strip_param(host, param);
+  }
+  for (host, param) in args.strip_tracking_param {
+    builder = builder.strip_tracking_param(host, param);
+  }
+  Ok(builder)
 }
 
-impl Aggregate {
-  fn parse(self, page_url: &Url) -> HashSet<Finding> {
-    let mut findings = HashSet::new();
+#[derive(clap::Subcommand)]
+enum Command {
+  /// Create a workspace: a directory with the options, checkpoint, caches,
+  /// logs and outputs of its crawls, which every command run inside it uses.
+  Init { dir: PathBuf },
+  /// Explain from an event log how a URL was discovered, or why it was not
+  /// crawled.
+  Explain {
+    /// Event log written with `--event-log`. Defaults to the latest of the
+    /// workspace.
+    #[arg(long, value_name = "PATH")]
+    event_log: Option<PathBuf>,
+
+    url: String,
+  },
+  /// Build an on-disk index over an event log, so that `explain` does not have
+  /// to replay the whole log for every query.
+  Index {
+    /// Event log written with `--event-log`. Defaults to the latest of the
+    /// workspace.
+    #[arg(long, value_name = "PATH")]
+    event_log: Option<PathBuf>,
+  },
+  /// Compare the findings exports of two crawls, written with `--output`: the
+  /// URLs only one of them has and those whose status or size changed. Exits
+  /// with status 1 if they differ, like `diff`.
+  Diff {
+    old: PathBuf,
+    new: PathBuf,
 
-    let page_links = parse_links(self.page_links, page_url);
-    let image_links = parse_links(self.image_links, page_url);
-    let depth = self.depth;
+    /// Print one JSON object per change instead, for alerting pipelines.
+    #[arg(long)]
+    json: bool,
+  },
+  /// Rewrite a checkpoint written by an older crawler in the format of this
+  /// one, so that it can be resumed from after upgrading.
+  Migrate {
+    /// Checkpoint written with `--checkpoint`. Defaults to that of the
+    /// workspace.
+    checkpoint: Option<PathBuf>,
+  },
+  /// Browse the pages saved with `--save-pages` on localhost, with their links
+  /// rewritten to point into the archive.
+  View {
+    /// Output directory of the crawl. Defaults to the latest run of the
+    /// workspace.
+    archive: Option<PathBuf>,
 
-    findings.extend(page_links.into_iter().map(|u| Finding::Page(u, depth)));
-    findings.extend(image_links.into_iter().map(Finding::Image));
+    /// Local port to serve the archive on.
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+  },
+}
+
+/// Everything the event log knows about a URL.
+#[derive(Debug, Default, Clone)]
+struct UrlHistory {
+  referrer: Option<String>,
+  dispatched: bool,
+  outcome: Option<String>,
+  skips: BTreeSet<String>,
+}
 
-    findings
+impl UrlHistory {
+  fn to_json(&self) -> serde_json::Value {
+    serde_json::json!({
+      "referrer": self.referrer,
+      "dispatched": self.dispatched,
+      "outcome": self.outcome,
+      "skips": self.skips,
+    })
+  }
+
+  fn from_json(value: &serde_json::Value) -> Self {
+    let string = |v: &serde_json::Value| v.as_str().map(String::from);
+    Self {
+      referrer: string(&value["referrer"]),
+      dispatched: value["dispatched"].as_bool().unwrap_or_default(),
+      outcome: string(&value["outcome"]),
+      skips: value["skips"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(string)
+        .collect(),
+    }
   }
 }
 
-fn parse_links(links: Vec<String>, page_url: &Url) -> HashSet<Url> {
-  links
-    .into_iter()
-    .filter_map(|l| match Url::parse(&l) {
-      Err(ParseError::RelativeUrlWithoutBase) => Some(page_url.join(&l).unwrap()),
-      Err(_) => {
-        warn!("Malformed link found: {}", l);
-        None
+/// Replays an event log into the history of every URL it mentions.
+fn read_histories(event_log: &Path) -> Result<HashMap<String, UrlHistory>> {
+  use std::io::BufRead as _;
+
+  let decoder = zstd::Decoder::new(std::fs::File::open(event_log)?)?;
+  let mut histories: HashMap<String, UrlHistory> = HashMap::new();
+  for line in std::io::BufReader::new(decoder).lines() {
+    let record: serde_json::Value = serde_json::from_str(&line?)?;
+    let (Some(event), Some(event_url)) = (record["event"].as_str(), record["url"].as_str()) else {
+      continue;
+    };
+    let history = histories.entry(event_url.to_owned()).or_default();
+    match event {
+      "discovered" => {
+        if history.referrer.is_none() {
+          history.referrer = record["referrer"].as_str().map(Into::into);
+        }
       }
-      Ok(url) => Some(url),
+      "skipped" => {
+        if let Some(reason) = record["reason"].as_str() {
+          let skip = match record["detail"].as_str() {
+            Some(detail) => format!("{} ({})", reason, detail),
+            None => reason.to_owned(),
+          };
+          history.skips.insert(skip);
+        }
+      }
+      "dispatched" => history.dispatched = true,
+      "fetched" => history.outcome = Some(format!("fetched with status {}", record["status"])),
+      "failed" => {
+        history.outcome = Some(format!(
+          "failed: {}",
+          record["error"].as_str().unwrap_or("?")
+        ))
+      }
+      _ => {}
+    }
+  }
+  Ok(histories)
+}
+
+/// Paths of the index files of an event log: an fst map from URL to the offset
+/// of its history in the records file, which holds one JSON object per line,
+/// and the stamp of the event log and records file the map was built from.
+fn index_paths(event_log: &Path, workspace: Option<&Workspace>) -> (PathBuf, PathBuf, PathBuf) {
+  let with_suffix = |suffix: &str| {
+    let mut path = match (workspace, event_log.file_name()) {
+      (Some(workspace), Some(name)) => workspace.index_dir().join(name).into_os_string(),
+      _ => event_log.as_os_str().to_owned(),
+    };
+    path.push(suffix);
+    PathBuf::from(path)
+  };
+  (
+    with_suffix(".idx"),
+    with_suffix(".idx.records"),
+    with_suffix(".idx.stamp"),
+  )
+}
+
+/// The length and modification time of the event log, followed by the length
+/// of the records file, as written next to an index. An index whose stamp does
+/// not match its files is rebuilt. Writing to the event log moves its
+/// modification time, so it does not need to be read.
+fn index_stamp(event_log: &Path, records_len: u64) -> Result<String> {
+  let metadata = std::fs::metadata(event_log)?;
+  let modified = metadata
+    .modified()?
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default();
+  Ok(format!(
+    "{} {} {}",
+    metadata.len(),
+    modified.as_nanos(),
+    records_len
+  ))
+}
+
+fn build_index(event_log: &Path, workspace: Option<&Workspace>) -> Result<()> {
+  let (map_path, indexed) = write_index(event_log, workspace)?;
+  println!("indexed {} URLs into `{}`", indexed, map_path.display());
+  Ok(())
+}
+
+/// Writes the index of an event log, returning the path of its map and the
+/// number of URLs in it.
+fn write_index(event_log: &Path, workspace: Option<&Workspace>) -> Result<(PathBuf, usize)> {
+  let mut histories: Vec<_> = read_histories(event_log)?.into_iter().collect();
+  histories.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+  let (map_path, records_path, stamp_path) = index_paths(event_log, workspace);
+  if let Some(parent) = map_path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut records = std::io::BufWriter::new(std::fs::File::create(&records_path)?);
+  let mut map = fst::MapBuilder::new(std::io::BufWriter::new(std::fs::File::create(&map_path)?))?;
+  let mut offset = 0;
+  for (url, history) in &histories {
+    let line = format!("{}\n", history.to_json());
+    records.write_all(line.as_bytes())?;
+    map.insert(url, offset)?;
+    offset += line.len() as u64;
+  }
+  records.flush()?;
+  map.finish()?;
+  std::fs::write(&stamp_path, index_stamp(event_log, offset)?)?;
+  Ok((map_path, histories.len()))
+}
+
+/// Where `explain` looks up URL histories: replayed from the event log, or
+/// memory-mapped from its index if one was built, which is rebuilt first if
+/// the event log changed since.
+enum HistoryIndex {
+  Replayed(HashMap<String, UrlHistory>),
+  Mapped {
+    urls: fst::Map<memmap2::Mmap>,
+    records: memmap2::Mmap,
+  },
+}
+
+impl HistoryIndex {
+  fn open(event_log: &Path, workspace: Option<&Workspace>) -> Result<Self> {
+    let (map_path, records_path, stamp_path) = index_paths(event_log, workspace);
+    if !map_path.exists() {
+      return Ok(Self::Replayed(read_histories(event_log)?));
+    }
+    let records_len = std::fs::metadata(&records_path).map_or(0, |m| m.len());
+    let fresh = match std::fs::read_to_string(&stamp_path) {
+      Ok(stamp) => stamp == index_stamp(event_log, records_len)?,
+      Err(_) => false,
+    };
+    if !fresh {
+      tracing::info!("rebuilding the outdated index `{}`", map_path.display());
+      write_index(event_log, workspace)?;
+    }
+
+    // SAFETY: the index files are only ever written by `build_index`, and a
+    // crawl rewrites the event log rather than the index, so the mapped files
+    // are not modified while they are in use.
+    let map = unsafe { memmap2::Mmap::map(&std::fs::File::open(&map_path)?)? };
+    let records = unsafe { memmap2::Mmap::map(&std::fs::File::open(&records_path)?)? };
+    Ok(Self::Mapped {
+      urls: fst::Map::new(map)?,
+      records,
     })
-    .filter(|u| u.scheme().contains("http"))
-    .filter(|u| u.host().is_some())
-    .collect()
+  }
+
+  fn get(&self, url: &str) -> Result<Option<UrlHistory>> {
+    match self {
+      Self::Replayed(histories) => Ok(histories.get(url).cloned()),
+      Self::Mapped { urls, records } => {
+        let Some(offset) = urls.get(url) else {
+          return Ok(None);
+        };
+        let line = records
+          .get(offset as usize..)
+          .ok_or_else(|| eyre::eyre!("the index of {} points past its records", url))?;
+        let end = line.iter().position(|&b| b == b'\n').unwrap_or(line.len());
+        Ok(Some(UrlHistory::from_json(&serde_json::from_slice(
+          &line[..end],
+        )?)))
+      }
+    }
+  }
 }
 
-use html5ever::tokenizer::{
-  BufferQueue, Tag, TagKind, TagToken, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
-};
+/// The status and size a findings export records for a URL, neither of which
+/// it has for a request that failed.
+type ExportedResponse = (Option<u64>, Option<u64>);
 
-impl TokenSink for &mut Aggregate {
-  type Handle = ();
+/// Reads a findings export, keeping the last record of every URL.
+fn read_findings_export(path: &Path) -> Result<BTreeMap<String, ExportedResponse>> {
+  use std::io::BufRead as _;
 
-  fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<Self::Handle> {
-    if let TagToken(
-      ref tag @ Tag {
-        kind: TagKind::StartTag,
-        ..
-      },
-    ) = token
-    {
-      match tag.name.as_ref() {
-        "a" => {
-          for attribute in &tag.attrs {
-            if attribute.name.local.as_ref() == "href" {
-              let url_str: &[u8] = attribute.value.borrow();
-              let url_string = String::from_utf8_lossy(url_str).into_owned();
-              self.page_links.push(url_string);
-            }
-          }
-        }
-        "img" => {
-          for attribute in &tag.attrs {
-            if attribute.name.local.as_ref() == "src" {
-              let url_str: &[u8] = attribute.value.borrow();
-              let url_string = String::from_utf8_lossy(url_str).into_owned();
-              self.image_links.push(url_string);
-            }
-          }
+  let file = std::fs::File::open(path)
+    .map_err(|e| eyre::eyre!("cannot open `{}`: {}", path.display(), e))?;
+  let mut findings = BTreeMap::new();
+  for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let record: serde_json::Value = serde_json::from_str(&line).map_err(|e| {
+      eyre::eyre!(
+        "line {} of `{}` is not a findings record: {}",
+        number + 1,
+        path.display(),
+        e
+      )
+    })?;
+    let Some(url) = record["url"].as_str() else {
+      eyre::bail!("line {} of `{}` has no URL", number + 1, path.display());
+    };
+    let response = (record["status"].as_u64(), record["size"].as_u64());
+    findings.insert(url.to_owned(), response);
+  }
+  Ok(findings)
+}
+
+/// How a URL differs between two crawls.
+#[derive(Debug, PartialEq, Eq)]
+enum FindingChange {
+  Added,
+  Removed,
+  Status(Option<u64>, Option<u64>),
+  Size(Option<u64>, Option<u64>),
+}
+
+impl FindingChange {
+  fn to_json(&self, url: &str) -> serde_json::Value {
+    match self {
+      Self::Added => serde_json::json!({ "url": url, "change": "added" }),
+      Self::Removed => serde_json::json!({ "url": url, "change": "removed" }),
+      Self::Status(old, new) => {
+        serde_json::json!({ "url": url, "change": "status", "old": old, "new": new })
+      }
+      Self::Size(old, new) => {
+        serde_json::json!({ "url": url, "change": "size", "old": old, "new": new })
+      }
+    }
+  }
+}
+
+impl std::fmt::Display for FindingChange {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let or = |value: &Option<u64>, missing: &str| match value {
+      Some(value) => value.to_string(),
+      None => missing.to_owned(),
+    };
+    match self {
+      Self::Added => write!(f, "added"),
+      Self::Removed => write!(f, "removed"),
+      Self::Status(old, new) => write!(f, "status {} -> {}", or(old, "failed"), or(new, "failed")),
+      Self::Size(old, new) => write!(f, "size {} -> {} bytes", or(old, "?"), or(new, "?")),
+    }
+  }
+}
+
+/// The changes from the findings `old` to `new`, in the order of their URLs.
+fn diff_findings<'a>(
+  old: &'a BTreeMap<String, ExportedResponse>,
+  new: &'a BTreeMap<String, ExportedResponse>,
+) -> Vec<(&'a str, FindingChange)> {
+  let urls: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+  let mut changes = Vec::new();
+  for url in urls {
+    match (old.get(url), new.get(url)) {
+      (None, Some(_)) => changes.push((url.as_str(), FindingChange::Added)),
+      (Some(_), None) => changes.push((url.as_str(), FindingChange::Removed)),
+      (Some(&(old_status, old_size)), Some(&(new_status, new_size))) => {
+        // A new status comes with a new body, whose size says nothing more.
+        if old_status != new_status {
+          changes.push((url.as_str(), FindingChange::Status(old_status, new_status)));
+        } else if old_size != new_size {
+          changes.push((url.as_str(), FindingChange::Size(old_size, new_size)));
         }
-        _ => {}
       }
+      (None, None) => {}
     }
-    TokenSinkResult::Continue
   }
+  changes
 }
 
-async fn fetch(resource_url: Url, client: Client) -> Result<()> {
-  info!("fetching `{}`", resource_url);
+fn diff(old: &Path, new: &Path, json: bool) -> Result<()> {
+  let (old, new) = (read_findings_export(old)?, read_findings_export(new)?);
+  let changes = diff_findings(&old, &new);
+  let mut out = std::io::stdout().lock();
+  for (url, change) in &changes {
+    match json {
+      true => writeln!(out, "{}", change.to_json(url))?,
+      false => writeln!(out, "{}: {}", url, change)?,
+    }
+  }
+  if !json {
+    let count = |kind: fn(&FindingChange) -> bool| changes.iter().filter(|(_, c)| kind(c)).count();
+    writeln!(
+      out,
+      "{} added, {} removed, {} with a changed status, {} with a changed size",
+      count(|c| *c == FindingChange::Added),
+      count(|c| *c == FindingChange::Removed),
+      count(|c| matches!(c, FindingChange::Status(..))),
+      count(|c| matches!(c, FindingChange::Size(..)))
+    )?;
+  }
+  out.flush()?;
+  if !changes.is_empty() {
+    std::process::exit(1);
+  }
+  Ok(())
+}
 
-  let request = client.get(resource_url.clone());
-  let response = request.send().await?;
-  let bytes = response.bytes().await?;
+fn explain(event_log: &Path, url: &str, workspace: Option<&Workspace>) -> Result<()> {
+  let url = Url::parse(url)?.to_string();
+  let index = HistoryIndex::open(event_log, workspace)?;
 
-  let Some(url_segments) = resource_url.path_segments() else {
+  let Some(history) = index.get(&url)? else {
+    println!("{} was never discovered", url);
     return Ok(());
   };
-  let file_path = url_segments.last().unwrap();
-  let file_path = format!("prey/res/{}", file_path);
-  let mut file = File::create(&file_path).await?;
 
-  file.write_all(&bytes).await?;
+  if history.dispatched {
+    println!(
+      "{} was crawled and {}",
+      url,
+      history.outcome.as_deref().unwrap_or("did not finish")
+    );
+  } else {
+    let reasons: Vec<_> = history
+      .skips
+      .iter()
+      .filter(|r| r.as_str() != SkipReason::Duplicate.as_str() || history.skips.len() == 1)
+      .map(String::as_str)
+      .collect();
+    println!("{} was not crawled: {}", url, reasons.join(", "));
+  }
 
+  println!("discovery path:");
+  let mut current = url;
+  let mut seen = HashSet::new();
+  while seen.insert(current.clone()) {
+    println!("  {}", current);
+    match index.get(&current)?.and_then(|h| h.referrer) {
+      Some(referrer) => current = referrer,
+      None => {
+        println!("  (seed)");
+        break;
+      }
+    }
+  }
   Ok(())
 }
 
-struct AppInput {
-  inital_urls: HashSet<Url>,
-  recursion_depth_limit: u8,
+/// A crawl saved with `--save-pages`, indexed by the URLs of its pages and
+/// images.
+struct Archive {
+  /// The URL, status, content type and file of every saved page.
+  pages: BTreeMap<String, (u16, String, PathBuf)>,
+  /// The final URL of every page that was redirected, against which its
+  /// relative links resolve.
+  final_urls: HashMap<String, Url>,
+  /// The file of every saved image.
+  assets: HashMap<String, PathBuf>,
 }
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-  urls: Vec<String>,
+impl Archive {
+  const PREFIX: &'static str = "/archive/";
+  /// Sent with every response, so that archived pages cannot run scripts or
+  /// act on the origin of the archive.
+  const CONTENT_SECURITY_POLICY: &'static str = "sandbox; script-src 'none'";
 
-  #[arg(short, long, default_value_t = DEFAULT_RECURSION_DEPTH_LIMIT)]
-  recursion_depth_limit: u8,
+  /// Reads `pages.jsonl` and, if present, `dataset.jsonl` of an output
+  /// directory.
+  fn open(dir: &Path) -> Result<Self> {
+    let manifest = dir.join("pages.jsonl");
+    let pages = std::fs::read_to_string(&manifest).map_err(|e| {
+      eyre::eyre!(
+        "cannot read `{}`, crawl with `--save-pages` first: {}",
+        manifest.display(),
+        e
+      )
+    })?;
+
+    let mut archive = Self {
+      pages: BTreeMap::new(),
+      final_urls: HashMap::new(),
+      assets: HashMap::new(),
+    };
+    for line in pages.lines().filter(|l| !l.is_empty()) {
+      let record: serde_json::Value = serde_json::from_str(line)?;
+      let (Some(url), Some(file_name)) = (record["url"].as_str(), record["file_name"].as_str())
+      else {
+        continue;
+      };
+      let status = record["status"].as_u64().unwrap_or_default() as u16;
+      let content_type = record["content_type"].as_str().unwrap_or("text/html");
+      let page = (status, content_type.to_owned(), dir.join(file_name));
+      archive.pages.insert(url.to_owned(), page.clone());
+      if let Some(final_url) = record["final_url"].as_str().filter(|&f| f != url) {
+        archive
+          .final_urls
+          .insert(url.to_owned(), Url::parse(final_url)?);
+        archive.pages.entry(final_url.to_owned()).or_insert(page);
+      }
+    }
+
+    if let Ok(dataset) = std::fs::read_to_string(dir.join("dataset.jsonl")) {
+      for line in dataset.lines().filter(|l| !l.is_empty()) {
+        let record: serde_json::Value = serde_json::from_str(line)?;
+        if let (Some(url), Some(file_name)) = (record["url"].as_str(), record["file_name"].as_str())
+        {
+          archive.assets.insert(url.to_owned(), dir.join(file_name));
+        }
+      }
+    }
+    Ok(archive)
+  }
+
+  fn contains(&self, url: &str) -> bool {
+    self.pages.contains_key(url) || self.assets.contains_key(url)
+  }
+
+  fn respond(&self, uri: &hyper::Uri) -> hyper::Response<hyper::Body> {
+    if uri.path() == "/" {
+      return Self::response(200, "text/html; charset=utf-8", self.index().into_bytes());
+    }
+    let Some(url) = uri.path().strip_prefix(Self::PREFIX) else {
+      return Self::not_found();
+    };
+    let url = match uri.query() {
+      Some(query) => format!("{}?{}", url, query),
+      None => url.to_owned(),
+    };
+
+    if let Some((status, content_type, path)) = self.pages.get(&url) {
+      let Ok(body) = std::fs::read(path) else {
+        return Self::not_found();
+      };
+      let base = match self.final_urls.get(&url) {
+        Some(final_url) => final_url.clone(),
+        None => match Url::parse(&url) {
+          Ok(url) => url,
+          Err(_) => return Self::not_found(),
+        },
+      };
+      // Rewritten as bytes, so that pages in any encoding stay intact.
+      return Self::response(*status, content_type, self.rewrite(&body, &base));
+    }
+    match self
+      .assets
+      .get(&url)
+      .and_then(|path| std::fs::read(path).ok())
+    {
+      Some(body) => {
+        let mime = infer::get(&body).map_or("application/octet-stream", |t| t.mime_type());
+        Self::response(200, mime, body)
+      }
+      None => Self::not_found(),
+    }
+  }
+
+  /// Lists every saved page with its status.
+  fn index(&self) -> String {
+    let mut rows = String::new();
+    for (url, (status, ..)) in &self.pages {
+      let _ = writeln!(
+        rows,
+        "<tr><td>{}</td><td><a href=\"{}{}\">{}</a></td></tr>",
+        status,
+        Self::PREFIX,
+        html_escape(url),
+        html_escape(url)
+      );
+    }
+    format!(
+      "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Archive</title></head>\n\
+       <body><h1>{} saved pages</h1>\n<table>\n{}</table></body></html>\n",
+      self.pages.len(),
+      rows
+    )
+  }
+
+  /// Points every `href`, `src` and `srcset` candidate of a page that leads
+  /// to a saved page or image into the archive, leaving all other links as
+  /// they are.
+  fn rewrite(&self, page: &[u8], base: &Url) -> Vec<u8> {
+    static ATTRIBUTE: std::sync::OnceLock<regex::bytes::Regex> = std::sync::OnceLock::new();
+    let attribute = ATTRIBUTE.get_or_init(|| {
+      regex::bytes::Regex::new(r#"(?i-u)\b(href|src|data|srcset)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .unwrap()
+    });
+    attribute
+      .replace_all(page, |captures: &regex::bytes::Captures| {
+        let name = String::from_utf8_lossy(&captures[1]);
+        // Links that are not text in any ASCII-compatible encoding lead
+        // nowhere in the archive.
+        let Ok(value) =
+          std::str::from_utf8(captures.get(2).or(captures.get(3)).unwrap().as_bytes())
+        else {
+          return captures[0].to_vec();
+        };
+        if !name.eq_ignore_ascii_case("srcset") {
+          return match self.archived(value, base) {
+            Some(target) => format!("{}=\"{}\"", name, target).into_bytes(),
+            None => captures[0].to_vec(),
+          };
+        }
+        let mut archived = false;
+        let candidates: Vec<String> = value
+          .split(',')
+          .map(|candidate| {
+            let candidate = candidate.trim();
+            let (url, descriptors) = candidate
+              .split_once(char::is_whitespace)
+              .unwrap_or((candidate, ""));
+            match self.archived(url, base) {
+              Some(target) => {
+                archived = true;
+                format!("{} {}", target, descriptors).trim_end().to_owned()
+              }
+              None => html_escape(&candidate.replace("&amp;", "&")),
+            }
+          })
+          .collect();
+        match archived {
+          true => format!("{}=\"{}\"", name, candidates.join(", ")).into_bytes(),
+          false => captures[0].to_vec(),
+        }
+      })
+      .into_owned()
+  }
+
+  /// The escaped archive link to the `href` or `src` value `value`, if it
+  /// leads to a saved page or image.
+  fn archived(&self, value: &str, base: &Url) -> Option<String> {
+    let mut target = base.join(&value.replace("&amp;", "&")).ok()?;
+    let fragment = target.fragment().map(|f| format!("#{}", f));
+    target.set_fragment(None);
+    if !self.contains(target.as_str()) {
+      return None;
+    }
+    Some(format!(
+      "{}{}{}",
+      Self::PREFIX,
+      html_escape(target.as_str()),
+      fragment.as_deref().map(html_escape).unwrap_or_default()
+    ))
+  }
+
+  fn response(status: u16, content_type: &str, body: Vec<u8>) -> hyper::Response<hyper::Body> {
+    hyper::Response::builder()
+      .status(status)
+      .header(hyper::header::CONTENT_TYPE, content_type)
+      .header(
+        hyper::header::CONTENT_SECURITY_POLICY,
+        Self::CONTENT_SECURITY_POLICY,
+      )
+      .body(body.into())
+      .unwrap()
+  }
+
+  fn not_found() -> hyper::Response<hyper::Body> {
+    Self::response(404, "text/plain", b"not in the archive\n".to_vec())
+  }
 }
 
-fn parse_cli_args() -> AppInput {
-  let args = Args::parse();
+fn html_escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('"', "&quot;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
 
-  let recursion_depth_limit = args.recursion_depth_limit;
-  let inital_urls: HashSet<Url> = args
-    .urls
-    .iter()
-    .map(AsRef::as_ref)
-    .map(Url::parse)
-    .map(Result::unwrap)
-    .collect();
-
-  AppInput {
-    inital_urls,
-    recursion_depth_limit,
+async fn view(dir: &Path, port: u16) -> Result<()> {
+  let archive = Arc::new(Archive::open(dir)?);
+  let address = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+  let service = hyper::service::make_service_fn(move |_| {
+    let archive = archive.clone();
+    async move {
+      Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |request| {
+        let response = archive.respond(request.uri());
+        async move { Ok::<_, std::convert::Infallible>(response) }
+      }))
+    }
+  });
+  let server = hyper::Server::try_bind(&address)?.serve(service);
+  tracing::info!("serving `{}` at http://{}/", dir.display(), address);
+  server.await?;
+  Ok(())
+}
+
+/// The seed or pattern of every line of a seed file with the comma-separated
+/// tags following it, skipping blank lines and `#` comments.
+fn seed_lines(text: &str) -> impl Iterator<Item = (&str, Vec<String>)> {
+  text
+    .lines()
+    .map(str::trim)
+    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    .map(|line| {
+      let (seed, tags) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let tags = tags
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(Into::into)
+        .collect();
+      (seed, tags)
+    })
+}
+
+fn parse_seed(url: &str) -> Result<Url> {
+  Url::parse(url).map_err(|e| eyre::eyre!("invalid seed `{}`: {}", url, e))
+}
+
+/// Reads the seeds of a `--seeds-file`, from stdin for `-`.
+fn read_seeds_file(path: &Path) -> Result<Vec<(Url, Vec<String>)>> {
+  let text = if path == Path::new("-") {
+    std::io::read_to_string(std::io::stdin())?
+  } else {
+    std::fs::read_to_string(path)?
+  };
+  seed_lines(&text)
+    .map(|(url, tags)| Ok((parse_seed(url)?, tags)))
+    .collect()
+}
+
+/// Reads the seeds of a `--seed-patterns` file with the tags of their line.
+fn read_seed_patterns(path: &Path) -> Result<Vec<(Url, Vec<String>)>> {
+  let mut seeds = Vec::new();
+  for (pattern, tags) in seed_lines(&std::fs::read_to_string(path)?) {
+    for url in expand_pattern(pattern)? {
+      seeds.push((parse_seed(&url)?, tags.clone()));
+    }
+  }
+  Ok(seeds)
+}
+
+/// Expands every `{start..end}` range and `{a,b,...}` alternation in a pattern
+/// into the cartesian product of URLs.
+fn expand_pattern(pattern: &str) -> Result<Vec<String>> {
+  let Some(open) = pattern.find('{') else {
+    return Ok(vec![pattern.to_owned()]);
+  };
+  let Some(close) = pattern[open..].find('}').map(|i| open + i) else {
+    eyre::bail!("unclosed `{{` in seed pattern `{}`", pattern);
+  };
+  let (prefix, group, rest) = (
+    &pattern[..open],
+    &pattern[open + 1..close],
+    &pattern[close + 1..],
+  );
+
+  // Counted before anything is expanded, so that a huge range fails fast.
+  let range = match group.split_once("..") {
+    Some((start, end)) => {
+      let width = if start.len() > 1 && start.starts_with('0') {
+        start.len()
+      } else {
+        0
+      };
+      let (start, end): (u64, u64) = (start.parse()?, end.parse()?);
+      Some((start.min(end), start.max(end), width))
+    }
+    None => None,
+  };
+  let count = match range {
+    Some((low, high, _)) => (high - low).saturating_add(1),
+    None => group.split(',').count() as u64,
+  };
+  let too_many = || {
+    eyre::eyre!(
+      "seed pattern `{}` expands to more than {} URLs",
+      pattern,
+      SEED_PATTERN_EXPANSION_LIMIT
+    )
+  };
+  if count > SEED_PATTERN_EXPANSION_LIMIT as u64 {
+    return Err(too_many());
+  }
+  let suffixes = expand_pattern(rest)?;
+  if count.saturating_mul(suffixes.len() as u64) > SEED_PATTERN_EXPANSION_LIMIT as u64 {
+    return Err(too_many());
+  }
+
+  let alternatives: Vec<String> = match range {
+    Some((low, high, width)) => (low..=high)
+      .map(|n| format!("{:0width$}", n, width = width))
+      .collect(),
+    None => group.split(',').map(Into::into).collect(),
+  };
+  Ok(
+    alternatives
+      .iter()
+      .flat_map(|a| {
+        suffixes
+          .iter()
+          .map(move |s| format!("{}{}{}", prefix, a, s))
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn expand_pattern_expands_ranges_and_alternatives() {
+    assert_eq!(
+      expand_pattern("https://a.example/{01..03}/{x,y}").unwrap(),
+      [
+        "https://a.example/01/x",
+        "https://a.example/01/y",
+        "https://a.example/02/x",
+        "https://a.example/02/y",
+        "https://a.example/03/x",
+        "https://a.example/03/y",
+      ]
+    );
+    assert_eq!(expand_pattern("https://a.example/{3..1}").unwrap().len(), 3);
+    assert_eq!(
+      expand_pattern("https://a.example/").unwrap(),
+      ["https://a.example/"]
+    );
+    assert!(expand_pattern("https://a.example/{1..2").is_err());
+  }
+
+  #[test]
+  fn parse_size_accepts_units_and_rejects_typos() {
+    assert_eq!(parse_size("500"), Ok(500));
+    assert_eq!(parse_size("500K"), Ok(500 << 10));
+    assert_eq!(parse_size("2gib"), Ok(2 << 30));
+    assert_eq!(parse_size("64MB"), Ok(64 << 20));
+    for typo in ["64KBI", "64I", "64BB", "0", "K", "64X"] {
+      assert!(parse_size(typo).is_err(), "`{}` should not parse", typo);
+    }
+  }
+
+  #[test]
+  fn expand_pattern_rejects_huge_expansions_up_front() {
+    assert!(expand_pattern("https://a.example/{1..1000000000}").is_err());
+    assert!(expand_pattern("https://a.example/{1..1000}/{1..1000}").is_err());
+  }
+
+  #[test]
+  fn invalid_seeds_are_blamed_on_their_source() {
+    let config = CrawlConfig {
+      args: Args::try_parse_from(["crawler", "https://a.example/", "a.example"]).unwrap(),
+      sources: HashMap::new(),
+      seed_sources: HashMap::from([(
+        "a.example".to_owned(),
+        ConfigSource::Environment("CRAWLER_URLS".to_owned()),
+      )]),
+      workspace: None,
+    };
+    let error = config.validate().unwrap_err().to_string();
+    assert!(
+      error.starts_with("in environment variable `CRAWLER_URLS`: invalid seed `a.example`"),
+      "{}",
+      error
+    );
+  }
+
+  #[test]
+  fn findings_diffs_list_url_status_and_size_changes() {
+    let old = BTreeMap::from([
+      ("https://a.example/".to_owned(), (Some(200), Some(10))),
+      ("https://a.example/gone".to_owned(), (Some(200), Some(5))),
+      ("https://a.example/broken".to_owned(), (Some(200), Some(5))),
+    ]);
+    let new = BTreeMap::from([
+      ("https://a.example/".to_owned(), (Some(200), Some(12))),
+      ("https://a.example/broken".to_owned(), (None, None)),
+      ("https://a.example/new".to_owned(), (Some(200), Some(1))),
+    ]);
+    assert_eq!(
+      diff_findings(&old, &new),
+      [
+        (
+          "https://a.example/",
+          FindingChange::Size(Some(10), Some(12))
+        ),
+        (
+          "https://a.example/broken",
+          FindingChange::Status(Some(200), None)
+        ),
+        ("https://a.example/gone", FindingChange::Removed),
+        ("https://a.example/new", FindingChange::Added),
+      ]
+    );
+    assert!(diff_findings(&old, &old).is_empty());
+  }
+
+  #[test]
+  fn config_templates_fill_in_host_and_date() {
+    let variables = TemplateVariables {
+      host: Some("a.example".to_owned()),
+      date: "2024-05-01".to_owned(),
+    };
+    assert_eq!(
+      variables.fill("crawls/{host}/{date}").as_deref(),
+      Some("crawls/a.example/2024-05-01")
+    );
+    assert_eq!(variables.fill("/p{2,3}/").as_deref(), Some("/p{2,3}/"));
+    let hostless = TemplateVariables {
+      host: None,
+      ..variables
+    };
+    assert_eq!(hostless.fill("{date}").as_deref(), Some("2024-05-01"));
+    assert_eq!(hostless.fill("https://{host}/"), None);
+  }
+
+  #[test]
+  fn outdated_history_indexes_are_rebuilt() {
+    let dir = std::env::temp_dir().join(format!("crawler-index-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let event_log = dir.join("events.jsonl.zst");
+    let write_log = |urls: &[&str]| {
+      let lines: String = urls
+        .iter()
+        .map(|url| format!("{{\"event\":\"dispatched\",\"url\":\"{}\"}}\n", url))
+        .collect();
+      std::fs::write(&event_log, zstd::encode_all(lines.as_bytes(), 0).unwrap()).unwrap();
+    };
+
+    write_log(&["https://a.example/"]);
+    build_index(&event_log, None).unwrap();
+    write_log(&["https://a.example/", "https://b.example/"]);
+    let index = HistoryIndex::open(&event_log, None).unwrap();
+    assert!(matches!(index, HistoryIndex::Mapped { .. }));
+    assert!(index.get("https://b.example/").unwrap().unwrap().dispatched);
+
+    drop(index);
+    let (_, records_path, _) = index_paths(&event_log, None);
+    std::fs::write(&records_path, "").unwrap();
+    let index = HistoryIndex::open(&event_log, None).unwrap();
+    assert!(index.get("https://b.example/").unwrap().unwrap().dispatched);
+    std::fs::remove_dir_all(&dir).unwrap();
   }
 }
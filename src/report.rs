@@ -0,0 +1,1514 @@
+//! What a crawl records and writes besides the resources themselves: the
+//! report, event log, findings export, link graph, sitemaps and dataset
+//! manifest.
+
+use super::*;
+
+/// Attack-surface inventory: the forms found on every page and the same-origin
+/// API endpoints that pages reference.
+#[derive(Debug, Default)]
+pub(crate) struct Inventory {
+  pub(crate) forms: BTreeMap<Url, Vec<Form>>,
+  pub(crate) endpoints: BTreeMap<Url, BTreeSet<Url>>,
+}
+
+impl Inventory {
+  pub(crate) fn record(&mut self, page: &Url, forms: Vec<Form>, endpoints: HashSet<Url>) {
+    if !forms.is_empty() {
+      self.forms.insert(page.clone(), forms);
+    }
+    for endpoint in endpoints {
+      self
+        .endpoints
+        .entry(endpoint)
+        .or_default()
+        .insert(page.clone());
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    out.push_str("## Form inventory\n\n");
+    for (page, forms) in &self.forms {
+      let _ = writeln!(out, "### {}\n", page);
+      for form in forms {
+        let _ = writeln!(
+          out,
+          "- {} {} (inputs: {})",
+          form.method,
+          form.action,
+          form.inputs.join(", ")
+        );
+      }
+      out.push('\n');
+    }
+
+    out.push_str("## Endpoints\n\n");
+    for (endpoint, pages) in &self.endpoints {
+      let _ = writeln!(
+        out,
+        "- {} (referenced by {} page(s))",
+        endpoint,
+        pages.len()
+      );
+      for page in pages {
+        let _ = writeln!(out, "  - {}", page);
+      }
+    }
+    out.push('\n');
+  }
+}
+
+/// Email addresses and phone numbers found during the crawl, together with the
+/// pages they appeared on.
+#[derive(Debug, Default)]
+pub(crate) struct Contacts {
+  pub(crate) emails: BTreeMap<String, BTreeSet<Url>>,
+  pub(crate) phones: BTreeMap<String, BTreeSet<Url>>,
+}
+
+impl Contacts {
+  pub(crate) fn record(&mut self, page: &Url, emails: HashSet<String>, phones: HashSet<String>) {
+    for email in emails {
+      self.emails.entry(email).or_default().insert(page.clone());
+    }
+    for phone in phones {
+      self.phones.entry(phone).or_default().insert(page.clone());
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    for (title, entries) in [
+      ("Email addresses", &self.emails),
+      ("Phone numbers", &self.phones),
+    ] {
+      let _ = writeln!(out, "## {}\n", title);
+      for (entry, pages) in entries {
+        let _ = writeln!(out, "- {}", entry);
+        for page in pages {
+          let _ = writeln!(out, "  - {}", page);
+        }
+      }
+      out.push('\n');
+    }
+  }
+}
+
+/// Assets whose fetch failed, cross-referenced with the pages that embed them
+/// when the report is written, so pages found after the failure are listed
+/// too. Failures to store an asset locally are the crawler's, not the site's,
+/// and are left out.
+#[derive(Debug, Default)]
+pub(crate) struct DeadAssets {
+  pub(crate) referrers: HashMap<Url, BTreeSet<Url>>,
+  pub(crate) failures: HashMap<Url, String>,
+}
+
+impl DeadAssets {
+  pub(crate) fn record_reference(&mut self, page: &Url, asset: &Url) {
+    self
+      .referrers
+      .entry(asset.clone())
+      .or_default()
+      .insert(page.clone());
+  }
+
+  pub(crate) fn record_failure(&mut self, asset: &Url, error: &eyre::Report) {
+    if error.downcast_ref::<StorageFailure>().is_none() {
+      self.failures.insert(asset.clone(), error.to_string());
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    let mut broken: BTreeMap<&Url, BTreeMap<&Url, &str>> = BTreeMap::new();
+    for (asset, error) in &self.failures {
+      for page in self.referrers.get(asset).into_iter().flatten() {
+        broken.entry(page).or_default().insert(asset, error);
+      }
+    }
+    if broken.is_empty() {
+      return;
+    }
+    out.push_str("## Broken assets\n\n");
+    for (page, assets) in &broken {
+      let _ = writeln!(out, "### {}\n", page);
+      for (asset, error) in assets {
+        let _ = writeln!(out, "- {}: {}", asset, error);
+      }
+      out.push('\n');
+    }
+  }
+}
+
+/// The pages declaring or mentioning each license.
+#[derive(Debug, Default)]
+pub(crate) struct Licenses {
+  pub(crate) pages: BTreeMap<String, BTreeSet<Url>>,
+}
+
+impl Licenses {
+  pub(crate) fn record(&mut self, page: &Url, licenses: BTreeSet<String>) {
+    for license in licenses {
+      self.pages.entry(license).or_default().insert(page.clone());
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.pages.is_empty() {
+      return;
+    }
+    let _ = writeln!(out, "## Licenses\n");
+    for (license, pages) in &self.pages {
+      let _ = writeln!(out, "- {}: {} page(s)", license, pages.len());
+    }
+    out.push('\n');
+  }
+}
+
+/// Distribution of declared and detected languages and charsets over all
+/// crawled pages.
+#[derive(Debug, Default)]
+pub(crate) struct LanguageStats {
+  pub(crate) content_languages: BTreeMap<String, u32>,
+  pub(crate) html_langs: BTreeMap<String, u32>,
+  pub(crate) detected_languages: BTreeMap<String, u32>,
+  pub(crate) charsets: BTreeMap<String, u32>,
+}
+
+impl LanguageStats {
+  pub(crate) fn record(
+    &mut self,
+    content_language: Option<String>,
+    html_lang: Option<String>,
+    detected_language: Option<&str>,
+    charset: Option<String>,
+  ) {
+    let key = |value: Option<String>| value.unwrap_or_else(|| "(none)".into());
+    *self
+      .content_languages
+      .entry(key(content_language))
+      .or_default() += 1;
+    *self.html_langs.entry(key(html_lang)).or_default() += 1;
+    *self
+      .detected_languages
+      .entry(key(detected_language.map(Into::into)))
+      .or_default() += 1;
+    *self.charsets.entry(key(charset)).or_default() += 1;
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.charsets.is_empty() {
+      return;
+    }
+    out.push_str("## Languages and charsets\n\n");
+    for (title, counts) in [
+      ("Content-Language", &self.content_languages),
+      ("Declared `<html lang>`", &self.html_langs),
+      ("Detected language", &self.detected_languages),
+      ("Charset", &self.charsets),
+    ] {
+      let _ = writeln!(out, "### {}\n", title);
+      for (value, count) in counts {
+        let _ = writeln!(out, "- {}: {} page(s)", value, count);
+      }
+      out.push('\n');
+    }
+  }
+}
+
+/// Ratio of newly discovered to already seen links over the most recently
+/// crawled pages. Once hardly any new links turn up, the crawl has effectively
+/// covered the site.
+#[derive(Debug, Default)]
+pub(crate) struct DiscoveryProgress {
+  pub(crate) pages: usize,
+  pub(crate) window: VecDeque<(usize, usize)>,
+  /// When the crawl started, for the throughput so far.
+  pub(crate) started: Option<Instant>,
+  /// How many quarters of completion have been logged as milestones.
+  pub(crate) milestones: usize,
+}
+
+impl DiscoveryProgress {
+  pub(crate) fn record(&mut self, new: usize, discovered: usize) {
+    self.pages += 1;
+    if self.window.len() == DISCOVERY_WINDOW {
+      self.window.pop_front();
+    }
+    self.window.push_back((new, discovered));
+  }
+
+  pub(crate) fn novelty(&self) -> f64 {
+    let (new, discovered) = self
+      .window
+      .iter()
+      .fold((0, 0), |(n, d), (new, discovered)| {
+        (n + new, d + discovered)
+      });
+    if discovered == 0 {
+      return 0.0;
+    }
+    new as f64 / discovered as f64
+  }
+
+  pub(crate) fn saturated(&self) -> bool {
+    self.window.len() == DISCOVERY_WINDOW && self.novelty() < SATURATION_THRESHOLD
+  }
+
+  pub(crate) fn log(&mut self, pending: usize, eta: Option<Duration>) {
+    let completion = self.pages as f64 / (self.pages + pending) as f64;
+    let remaining = eta
+      .map(|eta| format!(", ~{} remaining", rough_duration(eta)))
+      .unwrap_or_default();
+    info!(
+      "progress: {} pages crawled, {} pending, {:.0}% of recent links new, ~{:.0}% complete{}{}",
+      self.pages,
+      pending,
+      self.novelty() * 100.0,
+      completion * 100.0,
+      remaining,
+      if self.saturated() {
+        " (discovery saturated)"
+      } else {
+        ""
+      }
+    );
+
+    let quarters = (completion * 4.0) as usize;
+    if let (Some(eta), true) = (eta, quarters > self.milestones && quarters < 4) {
+      self.milestones = quarters;
+      info!(
+        "{}% complete, expected to finish in ~{}, around {}",
+        quarters * 25,
+        rough_duration(eta),
+        humantime::format_rfc3339_seconds(SystemTime::now() + eta)
+      );
+    }
+  }
+}
+
+/// A duration rounded to what matters at its scale: seconds under a minute,
+/// minutes under a day and hours beyond.
+pub(crate) fn rough_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  let unit = match secs {
+    0..60 => 1,
+    60..86_400 => 60,
+    _ => 3600,
+  };
+  let rounded = (secs + unit / 2) / unit * unit;
+  humantime::format_duration(Duration::from_secs(rounded.max(1))).to_string()
+}
+
+/// Saved images with the page they were found on and their captions, in the
+/// JSON Lines layout of Hugging Face image datasets.
+#[derive(Debug, Default)]
+pub(crate) struct DatasetManifest {
+  /// The first page every image was found on, and its caption there or on a
+  /// later page.
+  pub(crate) sources: HashMap<Url, (Url, Option<ImageCaption>)>,
+  /// Saved images with the SHA-256 of their file.
+  pub(crate) saved: Vec<(Url, PathBuf, Option<String>)>,
+  /// Licenses of the pages images were found on, as hints for filtering.
+  pub(crate) page_licenses: HashMap<Url, BTreeSet<String>>,
+}
+
+impl DatasetManifest {
+  pub(crate) fn record_image(&mut self, page: &Url, image: &Url, caption: Option<ImageCaption>) {
+    let (_, known) = self
+      .sources
+      .entry(image.clone())
+      .or_insert_with(|| (page.clone(), None));
+    if known.is_none() {
+      *known = caption;
+    }
+  }
+
+  pub(crate) fn record_licenses(&mut self, page: &Url, licenses: &BTreeSet<String>) {
+    if !licenses.is_empty() {
+      self.page_licenses.insert(page.clone(), licenses.clone());
+    }
+  }
+
+  pub(crate) fn record_saved(&mut self, image: &Url, path: PathBuf, sha256: Option<String>) {
+    self.saved.push((image.clone(), path, sha256));
+  }
+
+  /// One JSON object per saved image, with its file name relative to `root`
+  /// and the tags of its seed.
+  pub(crate) fn to_jsonl(&self, root: &Path, seeds: &SeedFrontier) -> String {
+    let mut out = String::new();
+    for (image, path, sha256) in &self.saved {
+      let (page, caption) = match self.sources.get(image) {
+        Some((page, caption)) => (Some(page), caption.clone().unwrap_or_default()),
+        None => (None, ImageCaption::default()),
+      };
+      let licenses = page.and_then(|p| self.page_licenses.get(p));
+      let record = serde_json::json!({
+        "file_name": path.strip_prefix(root).unwrap_or(path).to_string_lossy(),
+        "sha256": sha256,
+        "caption": caption.text(),
+        "alt": caption.alt,
+        "title": caption.title,
+        "figcaption": caption.figcaption,
+        "url": image.as_str(),
+        "page": page.map(Url::as_str),
+        "license": licenses,
+        "tags": seeds.tags(image),
+      });
+      let _ = writeln!(out, "{}", record);
+    }
+    out
+  }
+}
+
+/// Which page linked to which URL, for analyzing the structure of a site in
+/// Graphviz or Gephi. Links are recorded whether or not they were followed.
+#[derive(Debug, Default)]
+pub(crate) struct LinkGraph {
+  /// Every linking page and linked URL with its kind.
+  pub(crate) nodes: BTreeMap<Url, &'static str>,
+  pub(crate) edges: BTreeSet<(Url, Url)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum GraphFormat {
+  Dot,
+  GraphMl,
+}
+
+impl GraphFormat {
+  pub(crate) fn of(path: &Path) -> Result<Self> {
+    let extension = path
+      .extension()
+      .and_then(|e| e.to_str())
+      .unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+      "dot" | "gv" => Ok(Self::Dot),
+      "graphml" => Ok(Self::GraphMl),
+      _ => eyre::bail!(
+        "unknown link graph format of `{}`, expected `.dot`, `.gv` or `.graphml`",
+        path.display()
+      ),
+    }
+  }
+}
+
+impl LinkGraph {
+  pub(crate) fn record(&mut self, page: &Url, findings: &HashSet<Finding>) {
+    self.nodes.entry(page.clone()).or_insert("page");
+    for finding in findings {
+      self
+        .nodes
+        .entry(finding.url().clone())
+        .or_insert(finding.kind());
+      self.edges.insert((page.clone(), finding.url().clone()));
+    }
+  }
+
+  pub(crate) fn to_dot(&self) -> String {
+    let quote = |url: &Url| url.as_str().replace('\\', "\\\\").replace('"', "\\\"");
+    let mut out = String::from("digraph crawl {\n");
+    for (url, kind) in &self.nodes {
+      let shape = match *kind {
+        "page" => "ellipse",
+        _ => "box",
+      };
+      let _ = writeln!(
+        out,
+        "  \"{}\" [kind={}, shape={}];",
+        quote(url),
+        kind,
+        shape
+      );
+    }
+    for (from, to) in &self.edges {
+      let _ = writeln!(out, "  \"{}\" -> \"{}\";", quote(from), quote(to));
+    }
+    out.push_str("}\n");
+    out
+  }
+
+  pub(crate) fn to_graphml(&self) -> String {
+    let escape = |url: &Url| {
+      url
+        .as_str()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+    };
+    let mut out = String::from(
+      "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">
+  <key id=\"url\" for=\"node\" attr.name=\"url\" attr.type=\"string\"/>
+  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>
+  <graph id=\"crawl\" edgedefault=\"directed\">
+",
+    );
+    let ids: HashMap<&Url, usize> = self.nodes.keys().zip(0..).collect();
+    for (url, kind) in &self.nodes {
+      let _ = writeln!(
+        out,
+        "    <node id=\"n{}\"><data key=\"url\">{}</data><data key=\"kind\">{}</data></node>",
+        ids[url],
+        escape(url),
+        kind
+      );
+    }
+    for (from, to) in &self.edges {
+      let _ = writeln!(
+        out,
+        "    <edge source=\"n{}\" target=\"n{}\"/>",
+        ids[from], ids[to]
+      );
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+  }
+}
+
+/// The most URLs a single sitemap may list.
+pub(crate) const SITEMAP_URL_LIMIT: usize = 50_000;
+
+pub(crate) fn escape_xml(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
+}
+
+/// The files of a sitemap of `pages` written to `path`, with their contents.
+/// Pages of a single origin that fit in one sitemap go into `path` itself.
+/// Otherwise there is a sitemap per origin, split every `limit` pages and
+/// named after `path` and the host, and `path` is an index of them, each
+/// listed at the root of its origin, which is where it must be served from.
+pub(crate) fn sitemap_files(
+  pages: &BTreeMap<Url, SystemTime>,
+  path: &Path,
+  limit: usize,
+) -> Vec<(PathBuf, String)> {
+  let urlset = |pages: &[(&Url, &SystemTime)]| {
+    let mut contents = String::from(concat!(
+      "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+      "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for (url, last_modified) in pages {
+      let _ = writeln!(
+        contents,
+        "  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>",
+        escape_xml(url.as_str()),
+        humantime::format_rfc3339_seconds(**last_modified)
+      );
+    }
+    contents.push_str("</urlset>\n");
+    contents
+  };
+
+  let mut origins: BTreeMap<String, Vec<(&Url, &SystemTime)>> = BTreeMap::new();
+  for (url, last_modified) in pages {
+    let origin = url.origin().ascii_serialization();
+    origins
+      .entry(origin)
+      .or_default()
+      .push((url, last_modified));
+  }
+  if origins.len() <= 1 && pages.len() <= limit {
+    let pages: Vec<_> = origins.into_values().flatten().collect();
+    return vec![(path.to_owned(), urlset(&pages))];
+  }
+
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let mut files = Vec::new();
+  let mut index = String::from(concat!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+    "<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+  ));
+  for (origin, pages) in &origins {
+    let url = pages[0].0;
+    // `http` and `https` origins of a host are told apart.
+    let scheme = match url.scheme() {
+      "https" => String::new(),
+      scheme => format!("{}-", scheme),
+    };
+    let chunks: Vec<_> = pages.chunks(limit).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+      let mut name = format!("{}-{}{}", stem, scheme, host_directory(url));
+      if chunks.len() > 1 {
+        let _ = write!(name, "-{}", i + 1);
+      }
+      name.push_str(".xml");
+      let _ = writeln!(
+        index,
+        "  <sitemap>\n    <loc>{}/{}</loc>\n  </sitemap>",
+        escape_xml(origin),
+        escape_xml(&name)
+      );
+      files.push((path.with_file_name(name), urlset(chunk)));
+    }
+  }
+  index.push_str("</sitemapindex>\n");
+  files.push((path.to_owned(), index));
+  files
+}
+
+/// Request outcomes and latencies per host, for spotting struggling hosts.
+#[derive(Debug, Default)]
+pub(crate) struct HostHealth {
+  pub(crate) hosts: HashMap<Host, HostStats>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HostStats {
+  pub(crate) requests: u32,
+  pub(crate) failures: u32,
+  /// Responses asking the crawler to slow down: 429 and 503.
+  pub(crate) throttled: u32,
+  /// Bodies aborted for arriving too slowly.
+  pub(crate) slow: u32,
+  pub(crate) latency: Duration,
+}
+
+impl HostStats {
+  pub(crate) fn success_rate(&self) -> f64 {
+    (self.requests - self.failures) as f64 / self.requests as f64
+  }
+
+  pub(crate) fn average_latency(&self) -> Duration {
+    self.latency / self.requests
+  }
+}
+
+impl HostHealth {
+  /// Records a request that was answered with `status`, or failed outright.
+  pub(crate) fn record(&mut self, url: &Url, status: Option<u16>, latency: Duration) {
+    let Some(host) = url.host() else {
+      return;
+    };
+    let stats = self.hosts.entry(host.to_owned()).or_default();
+    stats.requests += 1;
+    stats.latency += latency;
+    match status {
+      Some(status) if status < 400 => {}
+      Some(status) => {
+        stats.failures += 1;
+        if matches!(status, 429 | 503) {
+          stats.throttled += 1;
+        }
+      }
+      None => stats.failures += 1,
+    }
+  }
+
+  /// Records that the body of a failed request arrived too slowly.
+  pub(crate) fn record_slow(&mut self, url: &Url, error: &eyre::Report) {
+    if error.downcast_ref::<SlowTransfer>().is_none() {
+      return;
+    }
+    if let Some(host) = url.host() {
+      self.hosts.entry(host.to_owned()).or_default().slow += 1;
+    }
+  }
+
+  /// Renders a table of all hosts, least healthy first.
+  pub(crate) fn render(
+    &self,
+    host_visits: &HashMap<Host, u32>,
+    traps: &TrapDetector,
+    addresses: &HashMap<String, Vec<IpAddr>>,
+    geo: &GeoDatabases,
+    out: &mut String,
+  ) {
+    if self.hosts.is_empty() {
+      return;
+    }
+    let mut hosts: Vec<_> = self.hosts.iter().collect();
+    hosts.sort_by(|(a_host, a), (b_host, b)| {
+      a.success_rate()
+        .total_cmp(&b.success_rate())
+        .then(b.average_latency().cmp(&a.average_latency()))
+        .then(a_host.cmp(b_host))
+    });
+
+    out.push_str("## Host health\n\n");
+    let (geo_header, geo_align) = match geo.is_empty() {
+      true => ("", ""),
+      false => (" ASN | Country |", " --- | --- |"),
+    };
+    let _ = writeln!(
+      out,
+      "| Host | Addresses | Requests | Success rate | Avg latency | State | Budget left |{}",
+      geo_header
+    );
+    let _ = writeln!(
+      out,
+      "| --- | --- | ---: | ---: | ---: | --- | ---: |{}",
+      geo_align
+    );
+    for (host, stats) in hosts {
+      let visits = host_visits.get(host).copied().unwrap_or_default();
+      let budget = (HOST_VISIT_LIMIT + 1).saturating_sub(visits);
+      let state = if traps.traps.contains_key(&host.to_string()) {
+        "trap"
+      } else if budget == 0 {
+        "budget exhausted"
+      } else if stats.throttled > 0 {
+        "throttled"
+      } else if stats.slow > 0 {
+        "slow"
+      } else {
+        "ok"
+      };
+      let ips = match host {
+        Host::Ipv4(ip) => vec![IpAddr::from(*ip)],
+        Host::Ipv6(ip) => vec![IpAddr::from(*ip)],
+        Host::Domain(domain) => addresses.get(domain).cloned().unwrap_or_default(),
+      };
+      let _ = write!(
+        out,
+        "| {} | {} | {} | {:.0}% | {} ms | {} | {} |",
+        host,
+        match ips.is_empty() {
+          true => "-".to_owned(),
+          false => ips
+            .iter()
+            .map(IpAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        },
+        stats.requests,
+        stats.success_rate() * 100.0,
+        stats.average_latency().as_millis(),
+        state,
+        budget
+      );
+      if !geo.is_empty() {
+        let ip = ips.first().copied();
+        let _ = write!(
+          out,
+          " {} | {} |",
+          ip.and_then(|ip| geo.asn(ip)).as_deref().unwrap_or("-"),
+          ip.and_then(|ip| geo.country(ip)).as_deref().unwrap_or("-")
+        );
+      }
+      out.push('\n');
+    }
+    out.push('\n');
+  }
+}
+
+/// When every request to a host was sent and whether robots.txt allowed it,
+/// for demonstrating that a crawl kept to its politeness limits. Fetches of
+/// robots.txt count as requests to their host like any other.
+#[derive(Debug, Default)]
+pub(crate) struct PolitenessAudit {
+  pub(crate) hosts: HashMap<Host, HostAudit>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HostAudit {
+  pub(crate) sent: Vec<Instant>,
+  /// The longest delay the requests had to keep, `Crawl-delay` included.
+  pub(crate) delay: Duration,
+  /// Requests to URLs that robots.txt disallows, including redirect targets.
+  pub(crate) disallowed: u32,
+}
+
+impl HostAudit {
+  /// The shortest time between two consecutive requests.
+  pub(crate) fn shortest_gap(&self) -> Option<Duration> {
+    let mut sent = self.sent.clone();
+    sent.sort_unstable();
+    sent.windows(2).map(|pair| pair[1] - pair[0]).min()
+  }
+
+  /// The most requests sent within any one second.
+  pub(crate) fn peak_rate(&self) -> usize {
+    let mut sent = self.sent.clone();
+    sent.sort_unstable();
+    let mut start = 0;
+    (0..sent.len())
+      .map(|end| {
+        while sent[end] - sent[start] >= Duration::from_secs(1) {
+          start += 1;
+        }
+        end - start + 1
+      })
+      .max()
+      .unwrap_or_default()
+  }
+
+  pub(crate) fn within_limits(&self) -> bool {
+    let kept_delay = self
+      .shortest_gap()
+      .is_none_or(|gap| gap + AUDIT_TIMER_TOLERANCE >= self.delay);
+    kept_delay && self.disallowed == 0
+  }
+}
+
+impl PolitenessAudit {
+  pub(crate) fn record(&mut self, url: &Url, sent: Instant, delay: Duration) {
+    let Some(host) = url.host() else {
+      return;
+    };
+    let audit = self.hosts.entry(host.to_owned()).or_default();
+    audit.sent.push(sent);
+    audit.delay = audit.delay.max(delay);
+  }
+
+  pub(crate) fn disallowed(&mut self, url: &Url) {
+    if let Some(host) = url.host() {
+      self.hosts.entry(host.to_owned()).or_default().disallowed += 1;
+    }
+  }
+
+  pub(crate) fn render(&self, politeness: &Politeness, out: &mut String) {
+    if self.hosts.is_empty() {
+      return;
+    }
+    let mut hosts: Vec<_> = self.hosts.iter().collect();
+    hosts.sort_by(|(a_host, a), (b_host, b)| {
+      a.within_limits()
+        .cmp(&b.within_limits())
+        .then(a_host.cmp(b_host))
+    });
+    let exceeded = hosts
+      .iter()
+      .filter(|(_, audit)| !audit.within_limits())
+      .count();
+
+    out.push_str("## Politeness audit\n\n");
+    match exceeded {
+      0 => {
+        let _ = writeln!(
+          out,
+          "All {} host(s) were crawled within the configured limits.\n",
+          hosts.len()
+        );
+      }
+      _ => {
+        let _ = writeln!(
+          out,
+          "{} of {} host(s) were crawled beyond the configured limits.\n",
+          exceeded,
+          hosts.len()
+        );
+      }
+    }
+    let _ = writeln!(
+      out,
+      "| Host | Requests | Delay | Shortest gap | Peak rate | robots.txt | Within limits |"
+    );
+    let _ = writeln!(out, "| --- | ---: | ---: | ---: | ---: | --- | --- |");
+    for (host, audit) in hosts {
+      let robots = match (politeness.respect_robots, audit.disallowed) {
+        (false, _) => "ignored".to_owned(),
+        (true, 0) => "respected".to_owned(),
+        (true, disallowed) => format!("{} disallowed", disallowed),
+      };
+      let _ = writeln!(
+        out,
+        "| {} | {} | {} ms | {} | {} req/s | {} | {} |",
+        host,
+        audit.sent.len(),
+        audit.delay.as_millis(),
+        match audit.shortest_gap() {
+          Some(gap) => format!("{} ms", gap.as_millis()),
+          None => "-".to_owned(),
+        },
+        audit.peak_rate(),
+        robots,
+        match audit.within_limits() {
+          true => "yes",
+          false => "no",
+        }
+      );
+    }
+    out.push('\n');
+  }
+}
+
+/// Local MaxMind databases that annotate hosts with the network and the
+/// country they are hosted in.
+#[derive(Debug, Default)]
+pub(crate) struct GeoDatabases {
+  #[cfg(feature = "geoip")]
+  pub(crate) asn: Option<maxminddb::Reader<Vec<u8>>>,
+  #[cfg(feature = "geoip")]
+  pub(crate) country: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+#[cfg(feature = "geoip")]
+impl GeoDatabases {
+  pub(crate) fn open(asn: Option<&Path>, country: Option<&Path>) -> Result<Self> {
+    let open = |path: &Path| {
+      maxminddb::Reader::open_readfile(path)
+        .map_err(|e| eyre::eyre!("cannot open `{}`: {}", path.display(), e))
+    };
+    Ok(Self {
+      asn: asn.map(open).transpose()?,
+      country: country.map(open).transpose()?,
+    })
+  }
+
+  pub(crate) fn is_empty(&self) -> bool {
+    self.asn.is_none() && self.country.is_none()
+  }
+
+  /// The autonomous system `ip` belongs to, as `AS<number> <organization>`.
+  pub(crate) fn asn(&self, ip: IpAddr) -> Option<String> {
+    let asn: maxminddb::geoip2::Asn = self.asn.as_ref()?.lookup(ip).ok()?;
+    let number = asn.autonomous_system_number?;
+    Some(match asn.autonomous_system_organization {
+      Some(organization) => format!("AS{} {}", number, organization),
+      None => format!("AS{}", number),
+    })
+  }
+
+  /// The ISO code of the country `ip` is located in.
+  pub(crate) fn country(&self, ip: IpAddr) -> Option<String> {
+    let country: maxminddb::geoip2::Country = self.country.as_ref()?.lookup(ip).ok()?;
+    country.country?.iso_code.map(str::to_owned)
+  }
+}
+
+#[cfg(not(feature = "geoip"))]
+impl GeoDatabases {
+  pub(crate) fn is_empty(&self) -> bool {
+    true
+  }
+
+  pub(crate) fn asn(&self, _ip: IpAddr) -> Option<String> {
+    None
+  }
+
+  pub(crate) fn country(&self, _ip: IpAddr) -> Option<String> {
+    None
+  }
+}
+
+/// Titles and meta descriptions of all crawled pages, for finding duplicated
+/// and missing values.
+#[derive(Debug, Default)]
+pub(crate) struct PageMetadata {
+  pub(crate) titles: BTreeMap<String, BTreeSet<Url>>,
+  pub(crate) descriptions: BTreeMap<String, BTreeSet<Url>>,
+  pub(crate) missing_titles: BTreeSet<Url>,
+  pub(crate) missing_descriptions: BTreeSet<Url>,
+}
+
+impl PageMetadata {
+  pub(crate) fn record(&mut self, page: &Url, title: Option<String>, description: Option<String>) {
+    for (value, values, missing) in [
+      (title, &mut self.titles, &mut self.missing_titles),
+      (
+        description,
+        &mut self.descriptions,
+        &mut self.missing_descriptions,
+      ),
+    ] {
+      match value {
+        Some(value) => {
+          values.entry(value).or_default().insert(page.clone());
+        }
+        None => {
+          missing.insert(page.clone());
+        }
+      }
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    for (name, values, missing) in [
+      ("titles", &self.titles, &self.missing_titles),
+      (
+        "meta descriptions",
+        &self.descriptions,
+        &self.missing_descriptions,
+      ),
+    ] {
+      let duplicates: Vec<_> = values.iter().filter(|(_, pages)| pages.len() > 1).collect();
+      if !duplicates.is_empty() {
+        let _ = writeln!(out, "## Duplicate {}\n", name);
+        for (value, pages) in duplicates {
+          let _ = writeln!(out, "- \"{}\" ({} pages)", value, pages.len());
+          for page in pages {
+            let _ = writeln!(out, "  - {}", page);
+          }
+        }
+        out.push('\n');
+      }
+
+      if !missing.is_empty() {
+        let _ = writeln!(out, "## Missing {}\n", name);
+        for page in missing {
+          let _ = writeln!(out, "- {}", page);
+        }
+        out.push('\n');
+      }
+    }
+  }
+}
+
+/// `rel=canonical` declarations and the fetch outcome of every crawled page,
+/// used to find canonicals that chain or point at broken or redirecting URLs.
+#[derive(Debug, Default)]
+pub(crate) struct CanonicalHealth {
+  pub(crate) canonicals: BTreeMap<Url, Url>,
+  pub(crate) outcomes: HashMap<Url, (u16, Option<Url>)>,
+}
+
+impl CanonicalHealth {
+  pub(crate) fn record(
+    &mut self,
+    page: &Url,
+    status: u16,
+    final_url: &Url,
+    canonical: Option<Url>,
+  ) {
+    let redirect = (final_url != page).then(|| final_url.clone());
+    self.outcomes.insert(page.clone(), (status, redirect));
+    if let Some(canonical) = canonical.filter(|c| c != page) {
+      self.canonicals.insert(page.clone(), canonical);
+    }
+  }
+
+  /// Describes what is wrong with the canonical of `page`, if anything.
+  pub(crate) fn problem(&self, page: &Url, canonical: &Url) -> Option<String> {
+    match self.outcomes.get(canonical) {
+      None => return Some("target was not crawled".into()),
+      Some((_, Some(redirect))) => return Some(format!("target redirects to {}", redirect)),
+      Some((status, None)) if *status >= 400 => {
+        return Some(format!("target returns status {}", status))
+      }
+      _ => {}
+    }
+
+    let mut chain = vec![page, canonical];
+    while let Some(next) = self.canonicals.get(*chain.last().unwrap()) {
+      let cycle = chain.contains(&next);
+      chain.push(next);
+      if cycle {
+        break;
+      }
+    }
+    (chain.len() > 2).then(|| {
+      let chain: Vec<_> = chain.iter().map(|u| u.as_str()).collect();
+      format!("canonical chain {}", chain.join(" -> "))
+    })
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.canonicals.is_empty() {
+      return;
+    }
+    let problems: Vec<_> = self
+      .canonicals
+      .iter()
+      .filter_map(|(page, canonical)| Some((page, canonical, self.problem(page, canonical)?)))
+      .collect();
+
+    out.push_str("## Canonical health\n\n");
+    let _ = writeln!(
+      out,
+      "{} page(s) declare a different canonical, {} with problems.\n",
+      self.canonicals.len(),
+      problems.len()
+    );
+    for (page, canonical, problem) in problems {
+      let _ = writeln!(out, "- {} -> {}: {}", page, canonical, problem);
+    }
+    out.push('\n');
+  }
+}
+
+/// The reason every discovered URL was not crawled for. Duplicates are left
+/// out, as they are crawled from wherever they were first discovered.
+#[derive(Debug, Default)]
+pub(crate) struct SkipLog {
+  pub(crate) reasons: HashMap<Url, SkipReason>,
+  /// List every skipped URL in the report instead of only counting them.
+  pub(crate) verbose: bool,
+}
+
+impl SkipLog {
+  pub(crate) fn record(&mut self, url: &Url, reason: SkipReason) {
+    if reason != SkipReason::Duplicate {
+      self.reasons.entry(url.clone()).or_insert(reason);
+    }
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.reasons.is_empty() {
+      return;
+    }
+    let mut by_reason: BTreeMap<SkipReason, BTreeSet<&Url>> = BTreeMap::new();
+    for (url, reason) in &self.reasons {
+      by_reason.entry(*reason).or_default().insert(url);
+    }
+
+    let _ = writeln!(out, "## Skipped URLs\n");
+    for (reason, urls) in &by_reason {
+      let _ = writeln!(out, "- {}: {} URL(s)", reason.as_str(), urls.len());
+    }
+    out.push('\n');
+    if self.verbose {
+      for (reason, urls) in &by_reason {
+        let _ = writeln!(out, "### {}\n", reason.as_str());
+        for url in urls {
+          let _ = writeln!(out, "- {}", url);
+        }
+        out.push('\n');
+      }
+    }
+  }
+}
+
+/// Why discovered pages would not make it into a search index.
+#[derive(Debug, Default)]
+pub(crate) struct Indexability {
+  pub(crate) crawled: HashSet<Url>,
+  pub(crate) noindex: BTreeMap<Url, &'static str>,
+  /// Pages whose links were not followed because of their robots directives.
+  pub(crate) nofollow: BTreeSet<Url>,
+  pub(crate) failed: BTreeMap<Url, String>,
+}
+
+impl Indexability {
+  pub(crate) fn record(
+    &mut self,
+    page: &Url,
+    status: u16,
+    x_robots_tag: Option<String>,
+    meta_robots: Option<String>,
+  ) {
+    if has_robots_directive(&x_robots_tag, "noindex") {
+      self.noindex.insert(page.clone(), "X-Robots-Tag header");
+    } else if has_robots_directive(&meta_robots, "noindex") {
+      self.noindex.insert(page.clone(), "robots meta tag");
+    }
+    if status >= 400 {
+      self
+        .failed
+        .insert(page.clone(), format!("status {}", status));
+    }
+  }
+
+  pub(crate) fn render(&self, archive: &[Finding], skips: &SkipLog, out: &mut String) {
+    let uncrawled: BTreeSet<_> = archive
+      .iter()
+      .filter_map(|f| match f {
+        Finding::Page(url, _) if !self.crawled.contains(url) => Some(url),
+        _ => None,
+      })
+      .collect();
+    if self.noindex.is_empty()
+      && self.nofollow.is_empty()
+      && self.failed.is_empty()
+      && uncrawled.is_empty()
+    {
+      return;
+    }
+
+    out.push_str("## Indexability\n\n");
+    let _ = writeln!(
+      out,
+      "{} page(s) crawled, {} marked noindex, {} with links not followed, {} unreachable, {} \
+       discovered but not crawled.\n",
+      self.crawled.len(),
+      self.noindex.len(),
+      self.nofollow.len(),
+      self.failed.len(),
+      uncrawled.len()
+    );
+    if !self.noindex.is_empty() {
+      out.push_str("### Noindex\n\n");
+      for (page, source) in &self.noindex {
+        let _ = writeln!(out, "- {} ({})", page, source);
+      }
+      out.push('\n');
+    }
+    if !self.nofollow.is_empty() {
+      out.push_str("### Links not followed\n\n");
+      for page in &self.nofollow {
+        let _ = writeln!(out, "- {}", page);
+      }
+      out.push('\n');
+    }
+    if !self.failed.is_empty() {
+      out.push_str("### Unreachable\n\n");
+      for (page, reason) in &self.failed {
+        let _ = writeln!(out, "- {} ({})", page, reason);
+      }
+      out.push('\n');
+    }
+    if !uncrawled.is_empty() {
+      out.push_str("### Discovered but not crawled\n\n");
+      for page in uncrawled {
+        match skips.reasons.get(page) {
+          Some(reason) => {
+            let _ = writeln!(out, "- {} ({})", page, reason.as_str());
+          }
+          None => {
+            let _ = writeln!(out, "- {}", page);
+          }
+        }
+      }
+      out.push('\n');
+    }
+  }
+}
+
+/// A decision of the dispatcher, as recorded in the event log.
+pub(crate) enum Event<'a> {
+  Discovered(&'a Finding, &'a Url),
+  /// With what made the URL be skipped, if the reason alone does not say.
+  Skipped(&'a Url, SkipReason, Option<&'a str>),
+  Dispatched(&'a Finding),
+  Fetched(&'a Url, u16),
+  Failed(&'a Url, &'a str),
+  /// Put off until the end of the crawl because its host is down for
+  /// maintenance.
+  Deferred(&'a Url),
+}
+
+/// A JSON Lines record of every crawled page and fetched asset, for use as a
+/// data source downstream. Findings put off for maintenance are only recorded
+/// once they are retried.
+#[derive(Debug)]
+pub(crate) struct FindingsExport {
+  pub(crate) file: std::io::BufWriter<std::fs::File>,
+  /// The page every finding was first discovered on.
+  pub(crate) referrers: HashMap<Url, Url>,
+}
+
+/// The status, `Content-Type` and size of a response.
+pub(crate) type ResponseSummary<'a> = (u16, Option<&'a str>, usize);
+
+impl FindingsExport {
+  pub(crate) fn create(path: &Path) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    Ok(Self {
+      file: std::io::BufWriter::new(std::fs::File::create(path)?),
+      referrers: HashMap::new(),
+    })
+  }
+
+  /// Writes the record of `finding` with the `tags` of its seed, which was
+  /// answered with `response` or failed with `error`.
+  pub(crate) fn write(
+    &mut self,
+    finding: &Finding,
+    tags: &[&str],
+    response: Option<ResponseSummary>,
+    error: Option<&str>,
+  ) -> Result<()> {
+    let url = finding.url();
+    let (status, content_type, size) = match response {
+      Some((status, content_type, size)) => (Some(status), content_type, Some(size)),
+      None => (None, None, None),
+    };
+    let record = serde_json::json!({
+      "url": url.as_str(),
+      "type": finding.kind(),
+      "depth": finding.depth(),
+      "referrer": self.referrers.get(url).map(Url::as_str),
+      "tags": tags,
+      "status": status,
+      "content_type": content_type,
+      "size": size,
+      "error": error,
+      "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+    });
+    serde_json::to_writer(&mut self.file, &record)?;
+    self.file.write_all(b"\n")?;
+    Ok(())
+  }
+}
+
+/// Why a discovered URL was not crawled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SkipReason {
+  Duplicate,
+  DepthLimit,
+  Mode,
+  Plan,
+  HostLimit,
+  NoHost,
+  Trap,
+  Existing,
+  Robots,
+  SeedBudget,
+  Filtered,
+  OutOfScope,
+  Variant,
+  Csp,
+  AssetClass,
+}
+
+impl SkipReason {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      SkipReason::Duplicate => "duplicate",
+      SkipReason::DepthLimit => "depth-limit",
+      SkipReason::Mode => "mode",
+      SkipReason::Plan => "not-in-plan",
+      SkipReason::HostLimit => "host-limit",
+      SkipReason::NoHost => "no-host",
+      SkipReason::Trap => "trap",
+      SkipReason::Existing => "already-saved",
+      SkipReason::Robots => "robots",
+      SkipReason::SeedBudget => "seed-budget",
+      SkipReason::Filtered => "filtered",
+      SkipReason::OutOfScope => "out-of-scope",
+      SkipReason::Variant => "variant",
+      SkipReason::Csp => "csp",
+      SkipReason::AssetClass => "asset-class",
+    }
+  }
+}
+
+/// Zstd-compressed JSON Lines log of every decision the dispatcher makes. The
+/// log is a sequence of frames, each ended after [`EVENT_LOG_FRAME_EVENTS`]
+/// events or [`EVENT_LOG_FRAME_INTERVAL`], so that all but the last few events
+/// can be read even if the crawler is killed.
+pub(crate) struct EventLog {
+  pub(crate) encoder: Option<zstd::Encoder<'static, std::io::BufWriter<std::fs::File>>>,
+  /// The events in the current frame, and when it began.
+  pub(crate) frame: (u32, Instant),
+}
+
+impl std::fmt::Debug for EventLog {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("EventLog").finish_non_exhaustive()
+  }
+}
+
+impl EventLog {
+  pub(crate) fn create(path: &Path) -> Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let encoder = zstd::Encoder::new(file, EVENT_LOG_COMPRESSION_LEVEL)?;
+    Ok(Self {
+      encoder: Some(encoder),
+      frame: (0, Instant::now()),
+    })
+  }
+
+  /// Ends the current frame and begins the next one.
+  pub(crate) fn end_frame(&mut self) -> Result<()> {
+    if let Some(encoder) = self.encoder.take() {
+      let mut file = encoder.finish()?;
+      file.flush()?;
+      self.encoder = Some(zstd::Encoder::new(file, EVENT_LOG_COMPRESSION_LEVEL)?);
+    }
+    self.frame = (0, Instant::now());
+    Ok(())
+  }
+
+  pub(crate) fn append(&mut self, event: Event) -> Result<()> {
+    use serde_json::json;
+
+    let time = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis() as u64;
+    let mut record = match event {
+      Event::Discovered(finding, referrer) => json!({
+        "event": "discovered",
+        "url": finding.url().as_str(),
+        "kind": finding.kind(),
+        "referrer": referrer.as_str(),
+      }),
+      Event::Skipped(url, reason, _) => json!({
+        "event": "skipped",
+        "url": url.as_str(),
+        "reason": reason.as_str(),
+      }),
+      Event::Dispatched(finding) => json!({
+        "event": "dispatched",
+        "url": finding.url().as_str(),
+        "kind": finding.kind(),
+      }),
+      Event::Fetched(url, status) => json!({
+        "event": "fetched",
+        "url": url.as_str(),
+        "status": status,
+      }),
+      Event::Failed(url, error) => json!({
+        "event": "failed",
+        "url": url.as_str(),
+        "error": error,
+      }),
+      Event::Deferred(url) => json!({
+        "event": "deferred",
+        "url": url.as_str(),
+      }),
+    };
+    record["time"] = time.into();
+    if let Event::Discovered(Finding::Page(_, depth), _)
+    | Event::Dispatched(Finding::Page(_, depth)) = event
+    {
+      record["depth"] = (*depth).into();
+    }
+    if let Event::Skipped(_, _, Some(detail)) = event {
+      record["detail"] = detail.into();
+    }
+
+    let Some(encoder) = &mut self.encoder else {
+      eyre::bail!("event log is closed after an earlier error");
+    };
+    serde_json::to_writer(&mut *encoder, &record)?;
+    encoder.write_all(b"\n")?;
+    self.frame.0 += 1;
+    if self.frame.0 >= EVENT_LOG_FRAME_EVENTS || self.frame.1.elapsed() >= EVENT_LOG_FRAME_INTERVAL
+    {
+      self.end_frame()?;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn finish(self) -> Result<()> {
+    if let Some(encoder) = self.encoder {
+      encoder.finish()?.flush()?;
+    }
+    Ok(())
+  }
+}
+
+/// The pages sampled with [`DispatcherBuilder::sample_pages`] and what they
+/// turned out to be.
+#[derive(Debug, Default)]
+pub(crate) struct Samples {
+  pub(crate) classes: BTreeMap<Url, (ContentClass, Option<String>)>,
+}
+
+impl Samples {
+  pub(crate) fn record(&mut self, url: &Url, class: ContentClass, content_type: Option<String>) {
+    self.classes.insert(url.clone(), (class, content_type));
+  }
+
+  pub(crate) fn render(&self, out: &mut String) {
+    if self.classes.is_empty() {
+      return;
+    }
+    let html = self
+      .classes
+      .values()
+      .filter(|(class, _)| *class == ContentClass::Html)
+      .count();
+    out.push_str("## Sampled pages\n\n");
+    let _ = writeln!(
+      out,
+      "{} page(s) with unusual extensions were sampled, {} of them HTML and downloaded in full.\n",
+      self.classes.len(),
+      html
+    );
+    if html == self.classes.len() {
+      return;
+    }
+    out.push_str("| URL | Content | Declared type |\n| --- | --- | --- |\n");
+    for (url, (class, content_type)) in &self.classes {
+      if *class == ContentClass::Html {
+        continue;
+      }
+      let _ = writeln!(
+        out,
+        "| {} | {} | {} |",
+        url,
+        class.as_str(),
+        content_type.as_deref().unwrap_or("-")
+      );
+    }
+    out.push('\n');
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dead_assets_lists_pages_found_after_the_failure() {
+    let url = |s: &str| Url::parse(s).unwrap();
+    let mut dead = DeadAssets::default();
+    dead.record_reference(
+      &url("https://a.example/1"),
+      &url("https://a.example/logo.png"),
+    );
+    dead.record_failure(
+      &url("https://a.example/logo.png"),
+      &eyre::eyre!("status 404"),
+    );
+    dead.record_reference(
+      &url("https://a.example/2"),
+      &url("https://a.example/logo.png"),
+    );
+    let storage = Err::<(), _>(eyre::eyre!("disk full"))
+      .wrap_err(StorageFailure(url("https://a.example/font.woff")))
+      .unwrap_err();
+    dead.record_reference(
+      &url("https://a.example/1"),
+      &url("https://a.example/font.woff"),
+    );
+    dead.record_failure(&url("https://a.example/font.woff"), &storage);
+
+    let mut report = String::new();
+    dead.render(&mut report);
+    assert!(report.contains("### https://a.example/1\n\n- https://a.example/logo.png: status 404"));
+    assert!(report.contains("### https://a.example/2\n\n- https://a.example/logo.png: status 404"));
+    assert!(!report.contains("font.woff"));
+  }
+
+  #[test]
+  fn sitemaps_are_split_by_origin_and_size() {
+    let date = SystemTime::UNIX_EPOCH;
+    let pages = |urls: &[&str]| -> BTreeMap<Url, SystemTime> {
+      urls
+        .iter()
+        .map(|u| (Url::parse(u).unwrap(), date))
+        .collect()
+    };
+    let path = Path::new("out/sitemap.xml");
+
+    let single = sitemap_files(
+      &pages(&["https://a.example/", "https://a.example/b"]),
+      path,
+      2,
+    );
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].0, path);
+    assert!(single[0].1.contains("<urlset"));
+
+    let files = sitemap_files(
+      &pages(&[
+        "https://a.example/",
+        "https://a.example/b",
+        "https://a.example/c",
+        "http://b.example:8080/",
+      ]),
+      path,
+      2,
+    );
+    let names: Vec<_> = files.iter().map(|(p, _)| p.to_str().unwrap()).collect();
+    assert_eq!(
+      names,
+      [
+        "out/sitemap-http-b.example_8080.xml",
+        "out/sitemap-a.example-1.xml",
+        "out/sitemap-a.example-2.xml",
+        "out/sitemap.xml",
+      ]
+    );
+    let index = &files[3].1;
+    assert!(index.contains("<sitemapindex"));
+    assert!(index.contains("<loc>https://a.example/sitemap-a.example-2.xml</loc>"));
+    assert!(index.contains("<loc>http://b.example:8080/sitemap-http-b.example_8080.xml</loc>"));
+    assert_eq!(files[1].1.matches("<url>").count(), 2);
+  }
+}
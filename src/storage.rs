@@ -0,0 +1,1122 @@
+//! Where fetched resources end up and under which names, with deduplication
+//! and image conversion.
+
+use super::*;
+
+/// A fetched resource that could not be converted or written to the output
+/// directory.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to store `{0}`")]
+pub(crate) struct StorageFailure(pub(crate) Url);
+
+/// A page saved with [`DispatcherBuilder::save_pages`], for `pages.jsonl`.
+#[derive(Debug)]
+pub(crate) struct SavedPage {
+  pub(crate) url: Url,
+  /// The URL it was served from.
+  pub(crate) final_url: Url,
+  pub(crate) status: u16,
+  pub(crate) content_type: Option<String>,
+  /// Where it was stored.
+  pub(crate) path: PathBuf,
+}
+
+/// How crawled pages are stored, with [`DispatcherBuilder::save_pages`].
+#[derive(Debug, Clone)]
+pub(crate) struct PageSaving {
+  pub(crate) storage: Arc<dyn Storage>,
+  pub(crate) rules: Arc<PageRules>,
+  /// The crawl ID to stamp saved pages with, for
+  /// [`DispatcherBuilder::page_provenance`].
+  pub(crate) provenance: Option<Arc<str>>,
+}
+
+/// A random ID for a crawl, or without the `warc` feature, which brings in
+/// `uuid`, the time it started and the process ID.
+#[cfg(feature = "warc")]
+pub(crate) fn crawl_id() -> String {
+  uuid::Uuid::new_v4().to_string()
+}
+
+#[cfg(not(feature = "warc"))]
+pub(crate) fn crawl_id() -> String {
+  format!(
+    "{}-{}",
+    humantime::format_rfc3339_millis(SystemTime::now()),
+    std::process::id()
+  )
+}
+
+/// An HTML comment naming where and when a page was fetched, and by which
+/// crawl, so a saved page stays attributable without `pages.jsonl`.
+pub(crate) fn provenance_comment(url: &Url, final_url: &Url, crawl_id: &str) -> String {
+  // `--` may not appear within a comment.
+  let escape = |url: &Url| url.as_str().replace("--", "%2D%2D");
+  let mut comment = format!("<!-- saved by crawler: url={}", escape(url));
+  if final_url != url {
+    let _ = write!(comment, " final_url={}", escape(final_url));
+  }
+  let _ = writeln!(
+    comment,
+    " fetched={} crawl={} -->",
+    humantime::format_rfc3339_seconds(SystemTime::now()),
+    crawl_id
+  );
+  comment
+}
+
+/// How fetched resources are named and stored.
+#[derive(Debug, Clone)]
+pub(crate) struct SaveOptions {
+  pub(crate) storage: Arc<dyn Storage>,
+  /// Skip assets that are already stored.
+  pub(crate) skip_existing: bool,
+  pub(crate) path_template: Option<PathTemplate>,
+  #[cfg(feature = "images")]
+  pub(crate) convert_images: Option<ImageConversion>,
+  /// Compute the SHA-256 of every stored body, for the dataset manifest.
+  pub(crate) checksums: bool,
+  /// Where assets are downloaded to before they are stored.
+  pub(crate) spool_dir: PathBuf,
+  /// The URL every file name given out during the crawl belongs to.
+  pub(crate) claimed: Arc<Mutex<HashMap<String, Url>>>,
+}
+
+impl SaveOptions {
+  /// The file name of the resource behind `url`, if it can be saved at all.
+  pub(crate) fn resource_name(url: &Url) -> Option<String> {
+    Some(url.path_segments()?.next_back().unwrap().to_owned())
+  }
+
+  /// The file name of a fetched resource, preferring the name the server
+  /// suggests in `Content-Disposition` over the last segment of the URL.
+  pub(crate) fn response_name(url: &Url, response: &FetchResponse) -> Option<String> {
+    let suggested = response
+      .headers
+      .get(reqwest::header::CONTENT_DISPOSITION)
+      .and_then(|v| v.to_str().ok())
+      .and_then(disposition_file_name);
+    let mut name = PathBuf::from(match suggested {
+      Some(name) => name,
+      None => Self::resource_name(url)?,
+    });
+    if let Some(extension) = response.sniffed_extension() {
+      if name
+        .extension()
+        .is_none_or(|e| !e.eq_ignore_ascii_case(extension))
+      {
+        name.set_extension(extension);
+      }
+    } else if name.extension().is_none() {
+      if let Some(extension) = response.declared_extension() {
+        name.set_extension(extension);
+      }
+    }
+    Some(name.to_string_lossy().into_owned())
+  }
+
+  /// Claims `name` for the resource behind `url`. If a different resource
+  /// already has the name, such as the `logo.png` of another host, the `hash`
+  /// of the contents goes before the extension instead, so the same resource
+  /// always ends up under the same name.
+  pub(crate) fn claim(&self, url: &Url, name: String, hash: u64) -> String {
+    let mut claimed = self.claimed.lock().unwrap();
+    let owner = claimed.entry(name.clone()).or_insert_with(|| url.clone());
+    if owner == url {
+      return name;
+    }
+    let path = Path::new(&name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let unique = match path.extension() {
+      Some(extension) => format!("{}-{:016x}.{}", stem, hash, extension.to_string_lossy()),
+      None => format!("{}-{:016x}", stem, hash),
+    };
+    let unique = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+      Some(parent) => parent.join(unique).to_string_lossy().into_owned(),
+      None => unique,
+    };
+    claimed.entry(unique.clone()).or_insert_with(|| url.clone());
+    unique
+  }
+
+  /// Whether the resource behind `url` was stored by an earlier crawl and
+  /// should not be downloaded again.
+  pub(crate) async fn already_saved(&self, url: &Url) -> bool {
+    self.skip_existing && self.storage.contains(url).await
+  }
+}
+
+/// Where saved assets go below the asset directory, such as `{host}/{path}`
+/// to mirror the crawled sites or `{host}/{hash}.{ext}` to store them by
+/// content. The placeholders are:
+///
+/// - `{host}`: the host of the URL, with its port if it has one,
+/// - `{path}`: the directories of the URL path followed by `{name}`,
+/// - `{name}`: the file name the asset would get without a template,
+/// - `{stem}` and `{ext}`: `{name}` without its extension, and the extension
+///   (`bin` if there is none),
+/// - `{hash}`: the xxh3 hash of the saved contents.
+///
+/// Every directory of the rendered path is sanitized, so a template cannot
+/// escape the asset directory.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+  pub(crate) parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TemplatePart {
+  Literal(String),
+  Host,
+  Path,
+  Name,
+  Stem,
+  Ext,
+  Hash,
+}
+
+impl PathTemplate {
+  pub fn parse(template: &str) -> Result<Self> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+      if start > 0 {
+        parts.push(TemplatePart::Literal(rest[..start].to_owned()));
+      }
+      let Some(end) = rest[start..].find('}') else {
+        eyre::bail!("unclosed `{{` in path template `{}`", template);
+      };
+      parts.push(match &rest[start + 1..start + end] {
+        "host" => TemplatePart::Host,
+        "path" => TemplatePart::Path,
+        "name" => TemplatePart::Name,
+        "stem" => TemplatePart::Stem,
+        "ext" => TemplatePart::Ext,
+        "hash" => TemplatePart::Hash,
+        other => eyre::bail!(
+          "unknown placeholder `{{{}}}` in path template, expected one of {{host}}, {{path}}, \
+           {{name}}, {{stem}}, {{ext}} and {{hash}}",
+          other
+        ),
+      });
+      rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+      parts.push(TemplatePart::Literal(rest.to_owned()));
+    }
+    Ok(Self { parts })
+  }
+
+  /// The path of the asset behind `url` that would be named `name`, or `None`
+  /// if the template needs the `hash` of the contents and it is not known
+  /// yet, or renders to nothing.
+  pub(crate) fn render(&self, url: &Url, name: &str, hash: Option<u64>) -> Option<String> {
+    let file = Path::new(name);
+    let mut rendered = String::new();
+    for part in &self.parts {
+      match part {
+        TemplatePart::Literal(literal) => rendered.push_str(literal),
+        TemplatePart::Host => rendered.push_str(&host_directory(url)),
+        TemplatePart::Path => {
+          let segments: Vec<&str> = url.path_segments().into_iter().flatten().collect();
+          for directory in segments.split_last().map(|(_, d)| d).unwrap_or_default() {
+            rendered.push_str(directory);
+            rendered.push('/');
+          }
+          rendered.push_str(name);
+        }
+        TemplatePart::Name => rendered.push_str(name),
+        TemplatePart::Stem => rendered.push_str(
+          &file
+            .file_stem()
+            .unwrap_or(file.as_os_str())
+            .to_string_lossy(),
+        ),
+        TemplatePart::Ext => match file.extension() {
+          Some(extension) => rendered.push_str(&extension.to_string_lossy()),
+          None => rendered.push_str("bin"),
+        },
+        TemplatePart::Hash => {
+          let _ = write!(rendered, "{:016x}", hash?);
+        }
+      }
+    }
+    let components: Vec<String> = rendered.split('/').filter_map(sanitize_file_name).collect();
+    (!components.is_empty()).then(|| components.join("/"))
+  }
+}
+
+/// What is known about a fetched resource besides its body.
+#[derive(Debug, Clone)]
+pub struct ResourceMetadata {
+  /// Name to save the resource under: the last segment of its URL, unless the
+  /// server suggests another, with the extension matching its contents.
+  pub file_name: String,
+  /// The URL the resource was served from, after following redirects.
+  pub final_url: Url,
+  pub status: u16,
+  pub request_headers: HeaderMap,
+  pub response_headers: HeaderMap,
+}
+
+/// Where fetched resources end up.
+pub trait Storage: Send + Sync + std::fmt::Debug {
+  /// Stores the body of the resource behind `url`, returning the path of the
+  /// file it was written to, if it was written to one.
+  fn store<'a>(
+    &'a self,
+    url: &'a Url,
+    bytes: &'a [u8],
+    metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>>;
+
+  /// Stores the body of the resource behind `url` that was downloaded to the
+  /// file at `path`, which may be moved instead of copied. By default, the
+  /// file is read into memory and passed to [`Storage::store`].
+  fn store_file<'a>(
+    &'a self,
+    url: &'a Url,
+    path: &'a Path,
+    metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    Box::pin(async move {
+      let bytes = tokio::fs::read(path).await?;
+      self.store(url, &bytes, metadata).await
+    })
+  }
+
+  /// Whether the resource behind `url` is already stored, possibly by an
+  /// earlier crawl, for [`DispatcherBuilder::skip_existing`].
+  fn contains<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, bool> {
+    let _ = url;
+    Box::pin(future::ready(false))
+  }
+}
+
+/// The default storage, writing every resource to a file in a directory. The
+/// file every URL was stored in is recorded in a manifest in the directory,
+/// [`STORAGE_MANIFEST_NAME`], so that later crawls into it know what is
+/// already there, whatever name it was saved under.
+#[derive(Debug)]
+pub struct FileStorage {
+  pub(crate) root: PathBuf,
+  pub(crate) dedup: Option<Deduplicator>,
+  /// Write the request and response headers next to every saved resource.
+  pub(crate) save_headers: bool,
+  pub(crate) manifest: tokio::sync::OnceCell<StorageManifest>,
+}
+
+/// Where the resources of a [`FileStorage`] were stored: the file name below
+/// its root for every URL.
+#[derive(Debug, Default)]
+pub(crate) struct StorageManifest {
+  pub(crate) files: Mutex<HashMap<Url, String>>,
+}
+
+impl StorageManifest {
+  /// Reads the manifest at `path`, if there is one. Later records win.
+  pub(crate) async fn read(path: PathBuf) -> Self {
+    let manifest = Self::default();
+    let Ok(contents) = tokio::fs::read_to_string(&path).await else {
+      return manifest;
+    };
+    let mut files = manifest.files.lock().unwrap();
+    for line in contents.lines() {
+      let record: serde_json::Value = match serde_json::from_str(line) {
+        Ok(record) => record,
+        Err(e) => {
+          warn!("Skipping invalid line of `{}`: {}", path.display(), e);
+          continue;
+        }
+      };
+      let url = record["url"].as_str().and_then(|u| Url::parse(u).ok());
+      if let (Some(url), Some(file)) = (url, record["file"].as_str()) {
+        files.insert(url, file.to_owned());
+      }
+    }
+    drop(files);
+    manifest
+  }
+}
+
+impl FileStorage {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self {
+      root: root.into(),
+      dedup: None,
+      save_headers: false,
+      manifest: Default::default(),
+    }
+  }
+
+  pub(crate) async fn manifest(&self) -> &StorageManifest {
+    self
+      .manifest
+      .get_or_init(|| StorageManifest::read(self.root.join(STORAGE_MANIFEST_NAME)))
+      .await
+  }
+
+  /// Records that the resource behind `url` is stored in `file_path`.
+  pub(crate) async fn record_stored(&self, url: &Url, file_path: &Path) -> Result<()> {
+    let file = file_path
+      .strip_prefix(&self.root)
+      .unwrap_or(file_path)
+      .to_string_lossy()
+      .into_owned();
+    let mut record = serde_json::json!({ "url": url.as_str(), "file": file }).to_string();
+    record.push('\n');
+    let mut manifest = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(self.root.join(STORAGE_MANIFEST_NAME))
+      .await?;
+    manifest.write_all(record.as_bytes()).await?;
+    self
+      .manifest()
+      .await
+      .files
+      .lock()
+      .unwrap()
+      .insert(url.clone(), file);
+    Ok(())
+  }
+
+  /// Saves identical resources once and links the duplicates to it.
+  pub fn dedup(mut self, links: DedupLinks) -> Self {
+    self.dedup = Some(Deduplicator::new(links));
+    self
+  }
+
+  /// Stores the headers of every saved resource in a `.headers.json` file.
+  pub fn save_headers(mut self, save_headers: bool) -> Self {
+    self.save_headers = save_headers;
+    self
+  }
+}
+
+impl Storage for FileStorage {
+  fn store<'a>(
+    &'a self,
+    url: &'a Url,
+    bytes: &'a [u8],
+    metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    Box::pin(async move {
+      let file_path = self.root.join(&metadata.file_name);
+      tokio::fs::create_dir_all(file_path.parent().unwrap_or(&self.root)).await?;
+
+      if self.save_headers {
+        write_headers_companion(&file_path, url, metadata).await?;
+      }
+
+      let hash = Deduplicator::content_hash(bytes);
+      if let Some(dedup) = &self.dedup {
+        if dedup
+          .link_duplicate(hash, Contents::Bytes(bytes), &file_path)
+          .await?
+        {
+          self.record_stored(url, &file_path).await?;
+          return Ok(Some(file_path));
+        }
+      }
+
+      remove_existing(&file_path).await?;
+      let mut file = File::create(&file_path).await?;
+      file.write_all(bytes).await?;
+
+      if let Some(dedup) = &self.dedup {
+        dedup.record_saved(hash, file_path.clone());
+      }
+      self.record_stored(url, &file_path).await?;
+      Ok(Some(file_path))
+    })
+  }
+
+  /// Moves the downloaded file into place, or copies it if it is on another
+  /// file system.
+  fn store_file<'a>(
+    &'a self,
+    url: &'a Url,
+    path: &'a Path,
+    metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    Box::pin(async move {
+      let file_path = self.root.join(&metadata.file_name);
+      tokio::fs::create_dir_all(file_path.parent().unwrap_or(&self.root)).await?;
+
+      if self.save_headers {
+        write_headers_companion(&file_path, url, metadata).await?;
+      }
+
+      let hash = match &self.dedup {
+        Some(dedup) => {
+          let hash = file_content_hash(path).await?;
+          if dedup
+            .link_duplicate(hash, Contents::File(path), &file_path)
+            .await?
+          {
+            self.record_stored(url, &file_path).await?;
+            return Ok(Some(file_path));
+          }
+          Some(hash)
+        }
+        None => None,
+      };
+
+      // Renaming replaces a link at `file_path` itself, copying writes
+      // through it.
+      if tokio::fs::rename(path, &file_path).await.is_err() {
+        remove_existing(&file_path).await?;
+        tokio::fs::copy(path, &file_path).await?;
+      }
+
+      if let (Some(dedup), Some(hash)) = (&self.dedup, hash) {
+        dedup.record_saved(hash, file_path.clone());
+      }
+      self.record_stored(url, &file_path).await?;
+      Ok(Some(file_path))
+    })
+  }
+
+  fn contains<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, bool> {
+    Box::pin(async move {
+      let file = self
+        .manifest()
+        .await
+        .files
+        .lock()
+        .unwrap()
+        .get(url)
+        .cloned();
+      let Some(file) = file else {
+        return false;
+      };
+      tokio::fs::metadata(self.root.join(file))
+        .await
+        .is_ok_and(|m| m.is_file())
+    })
+  }
+}
+
+/// Keeps every resource in memory, for programs that process the downloads
+/// themselves.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+  pub(crate) resources: Mutex<HashMap<Url, (ResourceMetadata, Vec<u8>)>>,
+}
+
+impl MemoryStorage {
+  /// The metadata and body of the resource behind `url`, if it was stored.
+  pub fn get(&self, url: &Url) -> Option<(ResourceMetadata, Vec<u8>)> {
+    self.resources.lock().unwrap().get(url).cloned()
+  }
+
+  /// The URLs of all stored resources.
+  pub fn urls(&self) -> Vec<Url> {
+    self.resources.lock().unwrap().keys().cloned().collect()
+  }
+
+  /// The total size of all stored bodies.
+  pub fn total_bytes(&self) -> usize {
+    let resources = self.resources.lock().unwrap();
+    resources.values().map(|(_, bytes)| bytes.len()).sum()
+  }
+}
+
+impl Storage for MemoryStorage {
+  fn store<'a>(
+    &'a self,
+    url: &'a Url,
+    bytes: &'a [u8],
+    metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    let resource = (metadata.clone(), bytes.to_vec());
+    self.resources.lock().unwrap().insert(url.clone(), resource);
+    Box::pin(future::ready(Ok(None)))
+  }
+}
+
+/// Discards every resource, for crawls that only map a site or time it.
+#[derive(Debug, Default)]
+pub struct NullStorage;
+
+impl Storage for NullStorage {
+  fn store<'a>(
+    &'a self,
+    _url: &'a Url,
+    _bytes: &'a [u8],
+    _metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    Box::pin(future::ready(Ok(None)))
+  }
+
+  fn store_file<'a>(
+    &'a self,
+    _url: &'a Url,
+    _path: &'a Path,
+    _metadata: &'a ResourceMetadata,
+  ) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    Box::pin(future::ready(Ok(None)))
+  }
+}
+
+/// Search-and-replace rules applied to pages saved with
+/// [`DispatcherBuilder::save_pages`], such as for stripping analytics snippets
+/// or cookie banners from an archive. Links are still extracted from the
+/// original page.
+///
+/// Rules are read one per line as `REGEX => REPLACEMENT`, in order, where the
+/// replacement can refer to groups as `$1` or `${name}` and may be empty.
+/// Blank lines and lines starting with `#` are skipped. Patterns match
+/// across lines only with the `(?s)` flag.
+#[derive(Debug, Clone, Default)]
+pub struct PageRules {
+  pub(crate) rules: Vec<(regex::bytes::Regex, String)>,
+}
+
+impl PageRules {
+  pub fn parse(rules: &str) -> Result<Self> {
+    let mut parsed = Vec::new();
+    for (number, line) in rules.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let Some((pattern, replacement)) = line.split_once(" =>") else {
+        eyre::bail!(
+          "expected `REGEX => REPLACEMENT` on line {} of page rules, got `{}`",
+          number + 1,
+          line
+        );
+      };
+      let pattern = regex::bytes::Regex::new(pattern.trim_end())
+        .map_err(|e| eyre::eyre!("invalid regex on line {} of page rules: {}", number + 1, e))?;
+      parsed.push((pattern, replacement.trim_start().to_owned()));
+    }
+    Ok(Self { rules: parsed })
+  }
+
+  /// Reads the rules from a file.
+  pub fn read(path: &Path) -> Result<Self> {
+    Self::parse(&std::fs::read_to_string(path)?)
+  }
+
+  /// Applies every rule in order to the raw bytes of a page, so pages keep
+  /// their encoding.
+  pub(crate) fn apply<'a>(&self, page: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+    use std::borrow::Cow;
+    let mut page = Cow::Borrowed(page);
+    for (pattern, replacement) in &self.rules {
+      if let Cow::Owned(replaced) = pattern.replace_all(&page, replacement.as_bytes()) {
+        page = Cow::Owned(replaced);
+      }
+    }
+    page
+  }
+}
+
+/// Hex-encoded SHA-256 of `bytes`, for checksums that outlive the crawl.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+  use sha2::Digest as _;
+  sha2::Sha256::digest(bytes)
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Extracts the file name of a `Content-Disposition` header value, preferring
+/// the RFC 5987 `filename*` parameter, and sanitizes it for the local file
+/// system.
+pub(crate) fn disposition_file_name(value: &str) -> Option<String> {
+  let mut plain = None;
+  let mut extended = None;
+  for param in value.split(';').skip(1) {
+    let Some((name, value)) = param.split_once('=') else {
+      continue;
+    };
+    let value = value.trim();
+    match name.trim().to_ascii_lowercase().as_str() {
+      "filename" => plain = Some(value.trim_matches('"').to_owned()),
+      "filename*" => {
+        // `charset'language'percent-encoded`; anything but UTF-8 is decoded
+        // lossily.
+        if let Some(encoded) = value.splitn(3, '\'').nth(2) {
+          let decoded = percent_encoding::percent_decode_str(encoded).decode_utf8_lossy();
+          extended = Some(decoded.into_owned());
+        }
+      }
+      _ => {}
+    }
+  }
+  sanitize_file_name(&extended.or(plain)?)
+}
+
+/// Reduces a file name suggested by a server to a plain name that cannot
+/// escape the output directory.
+pub(crate) fn sanitize_file_name(name: &str) -> Option<String> {
+  let name = name.rsplit(['/', '\\']).next()?;
+  let name: String = name
+    .chars()
+    .map(|c| match c {
+      c if c.is_control() => '_',
+      ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+      c => c,
+    })
+    .collect();
+  let name = name.trim().trim_start_matches('.');
+  (!name.is_empty()).then(|| name.to_owned())
+}
+
+/// The directory for the host of `url`: the host with its port, if it has
+/// one, or `local` for URLs without a host.
+pub(crate) fn host_directory(url: &Url) -> String {
+  match (url.host_str(), url.port()) {
+    (Some(host), Some(port)) => format!("{}_{}", host, port),
+    (Some(host), None) => host.to_owned(),
+    (None, _) => "local".to_owned(),
+  }
+}
+
+/// The name a page is stored under: `pages/<host>/<path>`, with `index.html`
+/// for directories, the hash of the query before the extension and `.html`
+/// appended unless the page already has it, so that no two pages share a file.
+pub(crate) fn page_file_name(url: &Url) -> String {
+  let host = host_directory(url);
+  let mut segments: Vec<&str> = url.path_segments().into_iter().flatten().collect();
+  if segments.last().is_none_or(|s| s.is_empty()) {
+    segments.pop();
+    segments.push("index.html");
+  }
+  let mut path = PathBuf::from("pages");
+  for segment in std::iter::once(host.as_str()).chain(segments) {
+    path.push(sanitize_file_name(segment).unwrap_or_else(|| "_".to_owned()));
+  }
+
+  let name = path
+    .file_name()
+    .unwrap_or_default()
+    .to_string_lossy()
+    .into_owned();
+  let (stem, extension) = match name.rsplit_once('.') {
+    Some((stem, extension))
+      if extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm") =>
+    {
+      (stem, extension)
+    }
+    _ => (name.as_str(), "html"),
+  };
+  let name = match url.query() {
+    Some(query) => format!(
+      "{}-{:016x}.{}",
+      stem,
+      xxhash_rust::xxh3::xxh3_64(query.as_bytes()),
+      extension
+    ),
+    None => format!("{}.{}", stem, extension),
+  };
+  path.set_file_name(name);
+  path.to_string_lossy().into_owned()
+}
+
+/// Writes the provenance of a saved resource to `<file>.headers.json`.
+pub(crate) async fn write_headers_companion(
+  file_path: &Path,
+  url: &Url,
+  metadata: &ResourceMetadata,
+) -> Result<()> {
+  let headers_json = |headers: &HeaderMap| {
+    let mut object = serde_json::Map::new();
+    for name in headers.keys() {
+      let values: Vec<_> = headers
+        .get_all(name)
+        .iter()
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .collect();
+      object.insert(name.to_string(), values.into());
+    }
+    serde_json::Value::Object(object)
+  };
+
+  let companion = serde_json::json!({
+    "url": url.as_str(),
+    "final_url": metadata.final_url.as_str(),
+    "status": metadata.status,
+    "request_headers": headers_json(&metadata.request_headers),
+    "response_headers": headers_json(&metadata.response_headers),
+  });
+  let mut path = file_path.as_os_str().to_owned();
+  path.push(".headers.json");
+  tokio::fs::write(path, serde_json::to_string_pretty(&companion)?).await?;
+  Ok(())
+}
+
+#[cfg(feature = "images")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConvertedFormat {
+  Webp,
+  Png,
+  Jpeg,
+}
+
+#[cfg(feature = "images")]
+impl ConvertedFormat {
+  pub(crate) fn extension(self) -> &'static str {
+    match self {
+      ConvertedFormat::Webp => "webp",
+      ConvertedFormat::Png => "png",
+      ConvertedFormat::Jpeg => "jpg",
+    }
+  }
+
+  pub(crate) fn image_format(self) -> image::ImageFormat {
+    match self {
+      ConvertedFormat::Webp => image::ImageFormat::WebP,
+      ConvertedFormat::Png => image::ImageFormat::Png,
+      ConvertedFormat::Jpeg => image::ImageFormat::Jpeg,
+    }
+  }
+}
+
+/// Transcoding of downloaded images into a common format before saving.
+#[derive(Debug, Clone, Copy)]
+#[cfg(feature = "images")]
+pub(crate) struct ImageConversion {
+  pub(crate) format: ConvertedFormat,
+  /// JPEG quality from 1 to 100. WebP is always encoded losslessly.
+  pub(crate) quality: u8,
+}
+
+#[cfg(feature = "images")]
+impl ImageConversion {
+  /// Transcodes `bytes` on the blocking pool, if they are an image in another
+  /// format.
+  pub(crate) async fn apply(self, bytes: Bytes) -> Result<Option<Bytes>> {
+    task::spawn_blocking(move || {
+      let Ok(source_format) = image::guess_format(&bytes) else {
+        return Ok(None);
+      };
+      if source_format == self.format.image_format() {
+        return Ok(None);
+      }
+      let Ok(image) = image::load_from_memory_with_format(&bytes, source_format) else {
+        return Ok(None);
+      };
+
+      let mut converted = std::io::Cursor::new(Vec::new());
+      match self.format {
+        ConvertedFormat::Jpeg => {
+          let encoder =
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut converted, self.quality);
+          image.to_rgb8().write_with_encoder(encoder)?;
+        }
+        ConvertedFormat::Webp => {
+          let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut converted);
+          image.to_rgba8().write_with_encoder(encoder)?;
+        }
+        ConvertedFormat::Png => image.write_to(&mut converted, image::ImageFormat::Png)?,
+      }
+      Ok(Some(converted.into_inner().into()))
+    })
+    .await?
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DedupLinks {
+  Hard,
+  /// Symbolic links, which on Windows need Developer Mode or the privilege
+  /// to create them.
+  Symbolic,
+}
+
+/// Content-addressed deduplication of saved resources: identical bodies saved
+/// under different paths are written once and linked from the other paths.
+#[derive(Debug)]
+pub(crate) struct Deduplicator {
+  pub(crate) links: DedupLinks,
+  pub(crate) saved: Mutex<HashMap<u64, PathBuf>>,
+}
+
+impl Deduplicator {
+  pub(crate) fn new(links: DedupLinks) -> Self {
+    Self {
+      links,
+      saved: Default::default(),
+    }
+  }
+
+  /// Fast non-cryptographic hash of a body, for comparisons within a crawl.
+  /// Persisted checksums use [`sha256_hex`] instead.
+  pub(crate) fn content_hash(bytes: &[u8]) -> u64 {
+    xxhash_rust::xxh3::xxh3_64(bytes)
+  }
+
+  /// Remembers a file written with contents of `hash` as the original for
+  /// later duplicates.
+  pub(crate) fn record_saved(&self, hash: u64, path: PathBuf) {
+    self.saved.lock().unwrap().entry(hash).or_insert(path);
+  }
+
+  /// Links `path` to an earlier saved file with the same `contents`, of
+  /// `hash`. Returns `false` if there is none and the caller has to write the
+  /// file itself.
+  pub(crate) async fn link_duplicate(
+    &self,
+    hash: u64,
+    contents: Contents<'_>,
+    path: &Path,
+  ) -> Result<bool> {
+    let Some(original) = self.saved.lock().unwrap().get(&hash).cloned() else {
+      return Ok(false);
+    };
+    if original == path {
+      return Ok(false);
+    }
+    // Guard against hash collisions.
+    let identical = match contents {
+      Contents::Bytes(bytes) => tokio::fs::read(&original).await.ok().as_deref() == Some(bytes),
+      Contents::File(file) => {
+        let (original, file) = (original.clone(), file.to_owned());
+        task::spawn_blocking(move || files_equal(&original, &file).unwrap_or(false)).await?
+      }
+    };
+    if !identical {
+      return Ok(false);
+    }
+
+    remove_existing(path).await?;
+    match self.links {
+      DedupLinks::Hard => tokio::fs::hard_link(&original, path).await?,
+      DedupLinks::Symbolic => {
+        let target = match (original.parent(), original.file_name()) {
+          (Some(parent), Some(name)) if Some(parent) == path.parent() => PathBuf::from(name),
+          _ => tokio::fs::canonicalize(&original).await?,
+        };
+        #[cfg(unix)]
+        tokio::fs::symlink(target, path).await?;
+        // Needs Developer Mode or the privilege to create symbolic links.
+        #[cfg(windows)]
+        tokio::fs::symlink_file(target, path).await?;
+      }
+    }
+    info!(
+      "linked duplicate `{}` to `{}`",
+      path.display(),
+      original.display()
+    );
+    Ok(true)
+  }
+}
+
+/// Removes the file or link at `path`, if there is one, so that writing to it
+/// creates a new file instead of writing through a link from deduplication.
+pub(crate) async fn remove_existing(path: &Path) -> Result<()> {
+  if tokio::fs::symlink_metadata(path).await.is_ok() {
+    tokio::fs::remove_file(path).await?;
+  }
+  Ok(())
+}
+
+/// The contents of a resource to store, in memory or downloaded to a file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Contents<'a> {
+  Bytes(&'a [u8]),
+  File(&'a Path),
+}
+
+/// [`Deduplicator::content_hash`] of the contents of `path`, read in chunks.
+pub(crate) async fn file_content_hash(path: &Path) -> Result<u64> {
+  let path = path.to_owned();
+  task::spawn_blocking(move || {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    for_each_chunk(&path, |chunk| hasher.update(chunk))?;
+    Ok(hasher.digest())
+  })
+  .await?
+}
+
+/// [`sha256_hex`] of the contents of `path`, read in chunks.
+pub(crate) async fn file_sha256_hex(path: &Path) -> Result<String> {
+  use sha2::Digest as _;
+
+  let path = path.to_owned();
+  task::spawn_blocking(move || {
+    let mut hasher = sha2::Sha256::new();
+    for_each_chunk(&path, |chunk| hasher.update(chunk))?;
+    Ok(
+      hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect(),
+    )
+  })
+  .await?
+}
+
+/// Calls `f` with the contents of `path`, a chunk at a time.
+pub(crate) fn for_each_chunk(path: &Path, mut f: impl FnMut(&[u8])) -> std::io::Result<()> {
+  use std::io::Read as _;
+
+  let mut file = std::fs::File::open(path)?;
+  let mut buffer = vec![0; 64 * 1024];
+  loop {
+    match file.read(&mut buffer)? {
+      0 => return Ok(()),
+      n => f(&buffer[..n]),
+    }
+  }
+}
+
+/// Whether the files at `a` and `b` have the same contents, compared a chunk
+/// at a time.
+pub(crate) fn files_equal(a: &Path, b: &Path) -> std::io::Result<bool> {
+  use std::io::Read as _;
+
+  let (a, b) = (std::fs::File::open(a)?, std::fs::File::open(b)?);
+  if a.metadata()?.len() != b.metadata()?.len() {
+    return Ok(false);
+  }
+  let (mut a, mut b) = (std::io::BufReader::new(a), std::io::BufReader::new(b));
+  let (mut chunk_a, mut chunk_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+  loop {
+    let n = a.read(&mut chunk_a)?;
+    if n == 0 {
+      return Ok(true);
+    }
+    b.read_exact(&mut chunk_b[..n])?;
+    if chunk_a[..n] != chunk_b[..n] {
+      return Ok(false);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn file_storage_remembers_stored_urls_across_crawls() {
+    let root = std::env::temp_dir().join(format!("crawler-manifest-{}", std::process::id()));
+    let url = Url::parse("https://a.example/download?id=1").unwrap();
+    let metadata = ResourceMetadata {
+      file_name: "report-1.pdf".to_owned(),
+      final_url: url.clone(),
+      status: 200,
+      request_headers: HeaderMap::new(),
+      response_headers: HeaderMap::new(),
+    };
+    FileStorage::new(&root)
+      .store(&url, b"%PDF", &metadata)
+      .await
+      .unwrap();
+
+    let later = FileStorage::new(&root);
+    assert!(later.contains(&url).await);
+    assert!(
+      !later
+        .contains(&Url::parse("https://b.example/download?id=1").unwrap())
+        .await
+    );
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[tokio::test]
+  async fn file_storage_remembers_deduplicated_urls() {
+    let root = std::env::temp_dir().join(format!("crawler-dedup-manifest-{}", std::process::id()));
+    let url = |s: &str| Url::parse(s).unwrap();
+    let metadata = |name: &str| ResourceMetadata {
+      file_name: name.to_owned(),
+      final_url: url("https://a.example/"),
+      status: 200,
+      request_headers: HeaderMap::new(),
+      response_headers: HeaderMap::new(),
+    };
+    let storage = FileStorage::new(&root).dedup(DedupLinks::Hard);
+    let (a, b, c) = (
+      url("https://a.example/a.png"),
+      url("https://a.example/b.png"),
+      url("https://a.example/c.png"),
+    );
+    storage
+      .store(&a, b"same", &metadata("a.png"))
+      .await
+      .unwrap();
+    storage
+      .store(&b, b"same", &metadata("b.png"))
+      .await
+      .unwrap();
+    let downloaded = root.join("c.download");
+    std::fs::write(&downloaded, b"same").unwrap();
+    storage
+      .store_file(&c, &downloaded, &metadata("c.png"))
+      .await
+      .unwrap();
+
+    let later = FileStorage::new(&root).dedup(DedupLinks::Hard);
+    for stored in [&a, &b, &c] {
+      assert!(later.contains(stored).await, "{} is not remembered", stored);
+    }
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[tokio::test]
+  async fn file_storage_does_not_write_through_dedup_links() {
+    let root = std::env::temp_dir().join(format!("crawler-dedup-{}", std::process::id()));
+    let storage = FileStorage::new(&root).dedup(DedupLinks::Hard);
+    let url = |s: &str| Url::parse(s).unwrap();
+    let metadata = |name: &str| ResourceMetadata {
+      file_name: name.to_owned(),
+      final_url: url("https://a.example/"),
+      status: 200,
+      request_headers: HeaderMap::new(),
+      response_headers: HeaderMap::new(),
+    };
+    let (a, b) = (
+      url("https://a.example/a.png"),
+      url("https://a.example/b.png"),
+    );
+    storage
+      .store(&a, b"same", &metadata("a.png"))
+      .await
+      .unwrap();
+    storage
+      .store(&b, b"same", &metadata("b.png"))
+      .await
+      .unwrap();
+    storage
+      .store(&b, b"changed", &metadata("b.png"))
+      .await
+      .unwrap();
+
+    assert_eq!(std::fs::read(root.join("a.png")).unwrap(), b"same");
+    assert_eq!(std::fs::read(root.join("b.png")).unwrap(), b"changed");
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn disposition_file_names_are_sanitized() {
+    assert_eq!(
+      disposition_file_name("attachment; filename=\"report.pdf\"").as_deref(),
+      Some("report.pdf")
+    );
+    assert_eq!(
+      disposition_file_name("attachment; filename=a.txt; FILENAME*=UTF-8''%C3%A9t%C3%A9.txt")
+        .as_deref(),
+      Some("été.txt")
+    );
+    assert_eq!(
+      disposition_file_name("attachment; filename=\"../../etc/passwd\"").as_deref(),
+      Some("passwd")
+    );
+    assert_eq!(disposition_file_name("inline"), None);
+    assert_eq!(sanitize_file_name("..hidden").as_deref(), Some("hidden"));
+    assert_eq!(sanitize_file_name("a:b?.txt").as_deref(), Some("a_b_.txt"));
+    assert_eq!(sanitize_file_name("dir\\x.exe").as_deref(), Some("x.exe"));
+    assert_eq!(sanitize_file_name("a\nb").as_deref(), Some("a_b"));
+    assert_eq!(sanitize_file_name("dir/"), None);
+    assert_eq!(sanitize_file_name(" .. "), None);
+  }
+}